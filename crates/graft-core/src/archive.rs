@@ -1,21 +1,339 @@
 //! Archive creation and constants for self-appending binaries.
 //!
-//! This module handles creating tar.gz archives from patch directories
-//! and defines the magic marker used for self-appending binary detection.
+//! This module handles creating tar archives from patch directories and
+//! defines the magic marker used for self-appending binary detection.
+//!
+//! The tar stream is gzip-compressed by default, exactly as patchers have
+//! always read it. zstd and uncompressed archives start with a one-byte
+//! header naming the codec instead; neither header byte can begin a gzip
+//! stream, so `Codec::detect` never mistakes an old archive for a new one.
+//!
+//! A patch can also be packed as a plain zip archive instead of a tar
+//! stream, for distributors who want to open or host it as an ordinary
+//! `.zip`. Zip archives are recognized by their own magic bytes; read them
+//! with `read_archive_bytes`, `read_archive_file` or `extract_archive`
+//! rather than `open_archive`.
+//!
+//! Archives are reproducible: entries are written in a fixed order with a
+//! fixed header (see `append_file`), so the same patch directory always
+//! yields the same bytes, and so does the patcher built from it. The
+//! manifest comes first, then each diff or added file in the order the
+//! manifest applies them, then everything else sorted by path (see
+//! `stream_order`), so a patcher can apply straight from the tar stream
+//! with `with_archive_fetcher` instead of extracting it first.
 
 use crate::patch;
+use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use std::fs;
-use std::io::{self, Write};
-use std::path::Path;
-use tar::Builder;
+use sha2::{Digest, Sha256};
+use crate::patch::payload::payload_name;
+use crate::utils::manifest::Manifest;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use tar::{Archive, Builder, EntryType, Header};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter};
 
 /// Magic marker at end of self-appending binary.
 /// Used to detect if a binary has patch data appended.
 pub const MAGIC_MARKER: &[u8; 8] = b"GRAFTPCH";
 
-/// Create a tar.gz archive from a patch directory.
+/// Gzip magic bytes, which start every gzip archive.
+const GZIP_MAGIC: &[u8; 2] = &[0x1f, 0x8b];
+/// Header byte for zstd-compressed archives.
+const ZSTD_HEADER: u8 = b'Z';
+/// Header byte for uncompressed archives.
+const STORE_HEADER: u8 = b'S';
+/// Zip local file header magic bytes, which start every zip archive.
+const ZIP_MAGIC: &[u8; 4] = b"PK\x03\x04";
+
+/// How the tar stream of a patch archive is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    /// Gzip, readable by every patcher
+    #[default]
+    Gzip,
+    /// Zstandard: faster, and smaller at high levels
+    Zstd,
+    /// No compression
+    Store,
+    /// A deflated zip archive instead of a tar stream, which Windows
+    /// Explorer and most other tools open natively
+    Zip,
+}
+
+impl Codec {
+    /// Parse a codec name ("gzip", "zstd", "store"/"none" or "zip").
+    pub fn parse(name: &str) -> Option<Codec> {
+        match name.to_lowercase().as_str() {
+            "gzip" | "gz" => Some(Codec::Gzip),
+            "zstd" => Some(Codec::Zstd),
+            "store" | "none" => Some(Codec::Store),
+            "zip" => Some(Codec::Zip),
+            _ => None,
+        }
+    }
+
+    /// Which codec `data` was written with, or `None` if it isn't a patch
+    /// archive.
+    pub fn detect(data: &[u8]) -> Option<Codec> {
+        if data.starts_with(GZIP_MAGIC) {
+            Some(Codec::Gzip)
+        } else if data.starts_with(ZIP_MAGIC) {
+            Some(Codec::Zip)
+        } else {
+            match data.first() {
+                Some(&ZSTD_HEADER) => Some(Codec::Zstd),
+                Some(&STORE_HEADER) => Some(Codec::Store),
+                _ => None,
+            }
+        }
+    }
+
+    /// Highest compression level the codec accepts.
+    pub fn max_level(self) -> u32 {
+        match self {
+            Codec::Gzip | Codec::Zip => 9,
+            Codec::Zstd => 22,
+            Codec::Store => 0,
+        }
+    }
+
+    /// Level used when none is given.
+    pub fn default_level(self) -> u32 {
+        match self {
+            Codec::Gzip | Codec::Zip => 6,
+            Codec::Zstd => 3,
+            Codec::Store => 0,
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Codec::Gzip => write!(f, "gzip"),
+            Codec::Zstd => write!(f, "zstd"),
+            Codec::Store => write!(f, "store"),
+            Codec::Zip => write!(f, "zip"),
+        }
+    }
+}
+
+/// Codec and level for writing a patch archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveCompression {
+    pub codec: Codec,
+    pub level: u32,
+}
+
+impl ArchiveCompression {
+    /// `codec` at `level`, or its default level if `None`. Returns `None` if
+    /// the level is out of range for the codec.
+    pub fn new(codec: Codec, level: Option<u32>) -> Option<ArchiveCompression> {
+        let level = level.unwrap_or(codec.default_level());
+        (level <= codec.max_level()).then_some(ArchiveCompression { codec, level })
+    }
+}
+
+impl Default for ArchiveCompression {
+    fn default() -> Self {
+        ArchiveCompression {
+            codec: Codec::Gzip,
+            level: Codec::Gzip.default_level(),
+        }
+    }
+}
+
+/// Write a tar archive into `buffer` using `compression`; `build` adds the
+/// entries. Zip archives are written by `create_zip` instead.
+fn write_archive<F>(buffer: &mut Vec<u8>, compression: ArchiveCompression, build: F) -> io::Result<()>
+where
+    F: FnOnce(&mut Builder<&mut dyn Write>) -> io::Result<()>,
+{
+    match compression.codec {
+        Codec::Gzip => {
+            let mut encoder = GzEncoder::new(buffer, Compression::new(compression.level));
+            build(&mut Builder::new(&mut encoder))?;
+            encoder.finish()?;
+        }
+        Codec::Zstd => {
+            buffer.push(ZSTD_HEADER);
+            let mut encoder = zstd::Encoder::new(buffer, compression.level as i32)?;
+            build(&mut Builder::new(&mut encoder))?;
+            encoder.finish()?;
+        }
+        Codec::Store => {
+            buffer.push(STORE_HEADER);
+            build(&mut Builder::new(buffer))?;
+        }
+        Codec::Zip => {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "zip archives have no tar stream"));
+        }
+    }
+    Ok(())
+}
+
+/// Open a patch archive for reading, decompressing it with the codec it was
+/// written with.
+///
+/// Fails with `InvalidInput` for zip archives, which aren't a tar stream.
+pub fn open_archive(data: &[u8]) -> io::Result<Archive<Box<dyn Read + '_>>> {
+    let reader: Box<dyn Read + '_> = match Codec::detect(data) {
+        Some(Codec::Gzip) => Box::new(GzDecoder::new(data)),
+        Some(Codec::Zstd) => Box::new(zstd::Decoder::new(&data[1..])?),
+        Some(Codec::Store) => Box::new(&data[1..]),
+        Some(Codec::Zip) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "zip archives can't be opened as a tar stream",
+            ));
+        }
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a patch archive (unknown compression)",
+            ));
+        }
+    };
+    Ok(Archive::new(reader))
+}
+
+/// Open a zip archive for reading.
+fn open_zip(data: &[u8]) -> io::Result<ZipArchive<Cursor<&[u8]>>> {
+    ZipArchive::new(Cursor::new(data)).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Extract every file in a patch archive (tar stream or zip) into `dir`.
+pub fn extract_archive(data: &[u8], dir: &Path) -> io::Result<()> {
+    if Codec::detect(data) == Some(Codec::Zip) {
+        open_zip(data)?
+            .extract(dir)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    } else {
+        open_archive(data)?.unpack(dir)
+    }
+}
+
+/// Read the file at `path` in a patch archive without reading the rest,
+/// or `None` if the archive doesn't contain it.
+pub fn read_archive_file(data: &[u8], path: &str) -> io::Result<Option<Vec<u8>>> {
+    let mut contents = Vec::new();
+    if Codec::detect(data) == Some(Codec::Zip) {
+        let mut archive = open_zip(data)?;
+        let mut file = match archive.by_name(path) {
+            Ok(file) => file,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        file.read_to_end(&mut contents)?;
+        return Ok(Some(contents));
+    }
+
+    let mut archive = open_archive(data)?;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type() == EntryType::Regular && entry.path()? == Path::new(path) {
+            entry.read_to_end(&mut contents)?;
+            return Ok(Some(contents));
+        }
+    }
+    Ok(None)
+}
+
+/// Run `f` with a function that copies single files out of the patch
+/// archive `data` into `dir` as they are asked for, instead of extracting
+/// the whole archive first.
+///
+/// The fetch function takes an archive path, copies that file to the same
+/// path under `dir` and returns where it put it. A tar stream can only be
+/// read forwards, so files are found by reading on from the last one
+/// fetched: files named in `keep` that are passed on the way are copied
+/// too, so fetching them later finds them in `dir`, and any others are
+/// skipped. Fetching in archive order (see `stream_order`) therefore never
+/// keeps more than one file in `dir`. Zip archives are read by name.
+///
+/// Fetching a file that isn't in the archive (or was skipped) fails with
+/// `NotFound`.
+pub fn with_archive_fetcher<T, F>(data: &[u8], dir: &Path, keep: &HashSet<String>, f: F) -> io::Result<T>
+where
+    F: FnOnce(&mut dyn FnMut(&str) -> io::Result<PathBuf>) -> T,
+{
+    let not_found = |path: &str| io::Error::new(io::ErrorKind::NotFound, format!("{} is not in the patch archive", path));
+    let copy_to = |path: &str, data: &mut dyn Read| -> io::Result<PathBuf> {
+        let out = dir.join(path);
+        if let Some(parent) = out.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(data, &mut File::create(&out)?)?;
+        Ok(out)
+    };
+
+    if Codec::detect(data) == Some(Codec::Zip) {
+        let mut archive = open_zip(data)?;
+        return Ok(f(&mut |path| match archive.by_name(path) {
+            Ok(mut file) => copy_to(path, &mut file),
+            Err(zip::result::ZipError::FileNotFound) => Err(not_found(path)),
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        }));
+    }
+
+    let mut archive = open_archive(data)?;
+    let mut entries = archive.entries()?;
+    let mut copied = HashSet::new();
+    Ok(f(&mut |path| {
+        if copied.contains(path) {
+            return Ok(dir.join(path));
+        }
+        for entry in entries.by_ref() {
+            let mut entry = entry?;
+            if entry.header().entry_type() != EntryType::Regular {
+                continue;
+            }
+            let name = entry.path()?.to_string_lossy().into_owned();
+            if name == path {
+                return copy_to(path, &mut entry);
+            }
+            if keep.contains(&name) {
+                copy_to(&name, &mut entry)?;
+                copied.insert(name);
+            }
+        }
+        Err(not_found(path))
+    }))
+}
+
+/// The order files are written to a tar stream, given their archive paths
+/// and the manifest's contents: `manifest.json` first, then the diff or
+/// added file of each entry in manifest order, then the rest sorted by path.
+/// Payloads the manifest names but `paths` lacks are left out; if the
+/// manifest can't be parsed, everything after it is sorted by path. Only
+/// the order depends on it, so the manifest hash isn't checked here.
+fn stream_order<'a>(paths: impl IntoIterator<Item = &'a str>, manifest: Option<&[u8]>) -> Vec<String> {
+    let mut rest: BTreeSet<&str> = paths.into_iter().collect();
+    let mut order = Vec::new();
+    if rest.remove(patch::MANIFEST_FILENAME) {
+        order.push(patch::MANIFEST_FILENAME.to_string());
+    }
+    let manifest = manifest
+        .and_then(|content| std::str::from_utf8(content).ok())
+        .and_then(|content| serde_json::from_str::<Manifest>(content).ok());
+    for entry in manifest.iter().flat_map(|manifest| &manifest.entries) {
+        if let Some(name) = payload_name(entry)
+            && rest.remove(name.as_str())
+        {
+            order.push(name);
+        }
+    }
+    order.extend(rest.into_iter().map(str::to_string));
+    order
+}
+
+/// Create a gzip-compressed archive from a patch directory.
 ///
 /// The archive will contain:
 /// - manifest.json (required)
@@ -25,59 +343,315 @@ pub const MAGIC_MARKER: &[u8; 8] = b"GRAFTPCH";
 ///
 /// Returns the compressed bytes.
 pub fn create_archive_bytes(patch_dir: &Path) -> io::Result<Vec<u8>> {
+    create_archive_bytes_compressed(patch_dir, ArchiveCompression::default())
+}
+
+/// Like `create_archive_bytes`, with the given compression.
+pub fn create_archive_bytes_compressed(
+    patch_dir: &Path,
+    compression: ArchiveCompression,
+) -> io::Result<Vec<u8>> {
+    if compression.codec == Codec::Zip {
+        return create_zip(&read_patch_dir(patch_dir)?, compression.level);
+    }
+
+    // manifest.json is required
+    let manifest = fs::read(patch_dir.join(patch::MANIFEST_FILENAME))?;
+    let mut paths = vec![patch::MANIFEST_FILENAME.to_string()];
+    for dir in [patch::DIFFS_DIR, patch::FILES_DIR, patch::ASSETS_DIR] {
+        let path = patch_dir.join(dir);
+        if path.is_dir() {
+            list_directory_contents(&mut paths, &path, dir)?;
+        }
+    }
+
     let mut buffer = Vec::new();
 
-    {
-        let encoder = GzEncoder::new(&mut buffer, Compression::default());
-        let mut archive = Builder::new(encoder);
+    write_archive(&mut buffer, compression, |archive| {
+        for path in stream_order(paths.iter().map(String::as_str), Some(&manifest)) {
+            append_path(archive, &patch_dir.join(&path), &path)?;
+        }
+        archive.finish()
+    })?;
 
-        // Add manifest.json (required)
-        let manifest_path = patch_dir.join(patch::MANIFEST_FILENAME);
-        archive.append_path_with_name(&manifest_path, patch::MANIFEST_FILENAME)?;
+    Ok(buffer)
+}
 
-        // Add diffs directory if it exists
-        let diffs_path = patch_dir.join(patch::DIFFS_DIR);
-        if diffs_path.is_dir() {
-            add_directory_contents(&mut archive, &diffs_path, patch::DIFFS_DIR)?;
+/// The files of a patch directory that go into its archive, keyed by
+/// archive path.
+fn read_patch_dir(patch_dir: &Path) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let mut contents = BTreeMap::new();
+    let manifest = fs::read(patch_dir.join(patch::MANIFEST_FILENAME))?;
+    contents.insert(patch::MANIFEST_FILENAME.to_string(), manifest);
+    for dir in [patch::DIFFS_DIR, patch::FILES_DIR, patch::ASSETS_DIR] {
+        let path = patch_dir.join(dir);
+        if path.is_dir() {
+            read_directory_contents(&mut contents, &path, dir)?;
         }
+    }
+    Ok(contents)
+}
 
-        // Add files directory if it exists
-        let files_path = patch_dir.join(patch::FILES_DIR);
-        if files_path.is_dir() {
-            add_directory_contents(&mut archive, &files_path, patch::FILES_DIR)?;
+/// Recursively read directory contents into `contents`, as
+/// `list_directory_contents` lists them for a tar archive.
+fn read_directory_contents(
+    contents: &mut BTreeMap<String, Vec<u8>>,
+    dir: &Path,
+    archive_prefix: &str,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let archive_path = format!(
+            "{}/{}",
+            archive_prefix,
+            path.file_name().unwrap_or_default().to_string_lossy()
+        );
+        if path.is_file() {
+            contents.insert(archive_path, fs::read(&path)?);
+        } else if path.is_dir() {
+            read_directory_contents(contents, &path, &archive_path)?;
         }
+    }
+    Ok(())
+}
 
-        // Add assets directory if it exists
-        let assets_path = patch_dir.join(patch::ASSETS_DIR);
-        if assets_path.is_dir() {
-            add_directory_contents(&mut archive, &assets_path, patch::ASSETS_DIR)?;
+/// Write `contents` as a deflated zip archive at `level` (0-9).
+///
+/// Like the tar archives, the output is reproducible: entries are sorted by
+/// path and carry a fixed time and mode.
+fn create_zip(contents: &BTreeMap<String, Vec<u8>>, level: u32) -> io::Result<Vec<u8>> {
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .compression_level(Some(i64::from(level)))
+        .last_modified_time(DateTime::default())
+        .unix_permissions(0o644);
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for (path, data) in contents {
+        writer.start_file(path.as_str(), options).map_err(io::Error::other)?;
+        writer.write_all(data)?;
+    }
+    let cursor = writer.finish().map_err(io::Error::other)?;
+
+    Ok(cursor.into_inner())
+}
+
+/// Read every file in a patch archive into memory, keyed by archive path.
+///
+/// Directory entries are skipped.
+pub fn read_archive_bytes(data: &[u8]) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let mut contents = BTreeMap::new();
+    if Codec::detect(data) == Some(Codec::Zip) {
+        let mut archive = open_zip(data)?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if file.is_dir() {
+                continue;
+            }
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            contents.insert(file.name().to_string(), data);
+        }
+        return Ok(contents);
+    }
+
+    let mut archive = open_archive(data)?;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type() != EntryType::Regular {
+            continue;
         }
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        contents.insert(path, data);
+    }
+
+    Ok(contents)
+}
 
-        // Finish the archive
-        let encoder = archive.into_inner()?;
-        encoder.finish()?;
+/// Create a patch archive from in-memory files.
+///
+/// Counterpart to `read_archive_bytes`; file contents are written unchanged.
+pub fn create_archive_from_entries(
+    contents: &BTreeMap<String, Vec<u8>>,
+    compression: ArchiveCompression,
+) -> io::Result<Vec<u8>> {
+    if compression.codec == Codec::Zip {
+        return create_zip(contents, compression.level);
     }
 
+    let mut buffer = Vec::new();
+
+    let manifest = contents.get(patch::MANIFEST_FILENAME).map(Vec::as_slice);
+    write_archive(&mut buffer, compression, |archive| {
+        for path in stream_order(contents.keys().map(String::as_str), manifest) {
+            let data = &contents[&path];
+            append_file(archive, &path, data.len() as u64, &data[..])?;
+        }
+        archive.finish()
+    })?;
+
     Ok(buffer)
 }
 
-/// Recursively add directory contents to the archive.
-fn add_directory_contents<W: Write>(
-    archive: &mut Builder<W>,
-    dir: &Path,
-    archive_prefix: &str,
-) -> io::Result<()> {
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        let file_name = entry.file_name();
-        let archive_path = format!("{}/{}", archive_prefix, file_name.to_string_lossy());
+/// SHA-256 of each file's contents, keyed by archive path.
+pub(crate) type FileHashes = BTreeMap<String, [u8; 32]>;
+
+/// Hash each of `contents` on its own, for `contents_digest`.
+pub(crate) fn hash_contents(contents: &BTreeMap<String, Vec<u8>>) -> FileHashes {
+    contents
+        .iter()
+        .map(|(path, data)| (path.clone(), Sha256::digest(data).into()))
+        .collect()
+}
+
+/// Stream every file in a patch archive through SHA-256, keyed by archive
+/// path, without holding the decompressed files in memory.
+///
+/// The files named in `keep` (small ones such as the checksum or
+/// signature file) are also returned whole. Directory entries are skipped.
+pub(crate) fn hash_archive_files(
+    data: &[u8],
+    keep: &[&str],
+) -> io::Result<(FileHashes, BTreeMap<String, Vec<u8>>)> {
+    let mut hashes = BTreeMap::new();
+    let mut kept = BTreeMap::new();
+    let mut hash_file = |path: String, file: &mut dyn Read| -> io::Result<()> {
+        let mut hasher = Sha256::new();
+        if keep.contains(&path.as_str()) {
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            hasher.update(&data);
+            kept.insert(path.clone(), data);
+        } else {
+            io::copy(file, &mut hasher)?;
+        }
+        hashes.insert(path, hasher.finalize().into());
+        Ok(())
+    };
+
+    if Codec::detect(data) == Some(Codec::Zip) {
+        let mut archive = open_zip(data)?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if file.is_dir() {
+                continue;
+            }
+            hash_file(file.name().to_string(), &mut file)?;
+        }
+    } else {
+        let mut archive = open_archive(data)?;
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if entry.header().entry_type() != EntryType::Regular {
+                continue;
+            }
+            hash_file(entry.path()?.to_string_lossy().into_owned(), &mut entry)?;
+        }
+    }
+
+    Ok((hashes, kept))
+}
+
+/// SHA-256 over every file's path and contents hash, in path order.
+///
+/// Each file is hashed on its own first (see `hash_contents` and
+/// `hash_archive_files`), so an archive's digest can be taken as it streams.
+pub(crate) fn contents_digest<'a>(hashes: impl IntoIterator<Item = (&'a String, &'a [u8; 32])>) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for (path, hash) in hashes {
+        hasher.update(path.as_bytes());
+        hasher.update([0]);
+        hasher.update(hash);
+    }
+    hasher.finalize().into()
+}
+
+/// The checksum recorded in `CHECKSUM_FILENAME`: the digest of every file
+/// except the checksum and signature files, as hex.
+fn checksum(hashes: &FileHashes) -> String {
+    let files = hashes
+        .iter()
+        .filter(|(path, _)| *path != patch::CHECKSUM_FILENAME && *path != patch::SIGNATURE_FILENAME);
+    contents_digest(files).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Add (or replace) the checksum file in an archive, returning a copy
+/// compressed with `compression`.
+///
+/// The checksum covers the decompressed files, so a patcher can tell a
+/// truncated or corrupted download from a good one before applying it. Add
+/// it before signing: the signature then covers the checksum file too.
+pub fn add_checksum(data: &[u8], compression: ArchiveCompression) -> io::Result<Vec<u8>> {
+    let mut contents = read_archive_bytes(data)?;
+    let checksum = checksum(&hash_contents(&contents));
+    contents.insert(patch::CHECKSUM_FILENAME.to_string(), format!("{}\n", checksum).into_bytes());
+    create_archive_from_entries(&contents, compression)
+}
+
+/// Check an archive against its checksum file.
+///
+/// Returns true if the checksum matches and false if the archive has none
+/// (it was built before checksums were recorded). Fails with `InvalidData`
+/// if the contents don't match, and with the underlying error if the
+/// archive can't be read to the end, as when it was truncated.
+///
+/// The files are hashed as they're decompressed, so memory use doesn't grow
+/// with the size of the archive.
+pub fn verify_checksum(data: &[u8]) -> io::Result<bool> {
+    let (hashes, kept) = hash_archive_files(data, &[patch::CHECKSUM_FILENAME])?;
+    let Some(recorded) = kept.get(patch::CHECKSUM_FILENAME) else {
+        return Ok(false);
+    };
+    if String::from_utf8_lossy(recorded).trim() != checksum(&hashes) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "archive contents don't match the recorded checksum",
+        ));
+    }
+    Ok(true)
+}
 
+/// Append `data` (`size` bytes) as a regular file at `path`.
+///
+/// The header records nothing about the machine the archive was made on:
+/// mode 0644, owner 0 and mtime 0. Patchers set permissions and times from
+/// the manifest, not the archive.
+fn append_file<W: Write>(archive: &mut Builder<W>, path: &str, size: u64, data: impl Read) -> io::Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(0);
+    header.set_cksum();
+    archive.append_data(&mut header, path, data)
+}
+
+/// Append the file at `path` to the archive as `archive_path`.
+fn append_path<W: Write>(archive: &mut Builder<W>, path: &Path, archive_path: &str) -> io::Result<()> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    append_file(archive, archive_path, size, file)
+}
+
+/// Recursively list the files in `dir`, as archive paths under
+/// `archive_prefix`.
+fn list_directory_contents(paths: &mut Vec<String>, dir: &Path, archive_prefix: &str) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let archive_path = format!(
+            "{}/{}",
+            archive_prefix,
+            path.file_name().unwrap_or_default().to_string_lossy()
+        );
         if path.is_file() {
-            archive.append_path_with_name(&path, &archive_path)?;
+            paths.push(archive_path);
         } else if path.is_dir() {
             // Recursively add subdirectories (for nested file structures in files/)
-            add_directory_contents(archive, &path, &archive_path)?;
+            list_directory_contents(paths, &path, &archive_path)?;
         }
     }
     Ok(())
@@ -86,8 +660,6 @@ fn add_directory_contents<W: Write>(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use flate2::read::GzDecoder;
-    use tar::Archive;
     use tempfile::tempdir;
 
     #[test]
@@ -180,4 +752,211 @@ mod tests {
         assert_eq!(MAGIC_MARKER, b"GRAFTPCH");
         assert_eq!(MAGIC_MARKER.len(), 8);
     }
+
+    #[test]
+    fn entries_roundtrip_through_archive() {
+        let mut contents = BTreeMap::new();
+        contents.insert("manifest.json".to_string(), b"{}".to_vec());
+        contents.insert("diffs/file.bin.diff".to_string(), vec![0u8, 1, 2, 255]);
+
+        for level in [0, 9] {
+            let compression = ArchiveCompression::new(Codec::Gzip, Some(level)).unwrap();
+            let data = create_archive_from_entries(&contents, compression).unwrap();
+            assert_eq!(read_archive_bytes(&data).unwrap(), contents);
+        }
+    }
+
+    #[test]
+    fn read_archive_skips_directories() {
+        let patch_dir = tempdir().unwrap();
+        fs::write(
+            patch_dir.path().join("manifest.json"),
+            r#"{"version": 1, "entries": []}"#,
+        )
+        .unwrap();
+        fs::create_dir_all(patch_dir.path().join("files/nested")).unwrap();
+        fs::write(patch_dir.path().join("files/nested/a.bin"), b"a").unwrap();
+
+        let data = create_archive_bytes(patch_dir.path()).unwrap();
+        let contents = read_archive_bytes(&data).unwrap();
+
+        assert_eq!(
+            contents.keys().collect::<Vec<_>>(),
+            vec!["files/nested/a.bin", "manifest.json"]
+        );
+    }
+
+    #[test]
+    fn entries_roundtrip_through_every_codec() {
+        let mut contents = BTreeMap::new();
+        contents.insert("manifest.json".to_string(), b"{}".to_vec());
+        contents.insert("files/strings.txt".to_string(), b"hola ".repeat(1000));
+
+        for codec in [Codec::Gzip, Codec::Zstd, Codec::Store, Codec::Zip] {
+            let compression = ArchiveCompression::new(codec, None).unwrap();
+            let data = create_archive_from_entries(&contents, compression).unwrap();
+            assert_eq!(Codec::detect(&data), Some(codec));
+            assert_eq!(read_archive_bytes(&data).unwrap(), contents, "{}", codec);
+        }
+    }
+
+    #[test]
+    fn default_archive_is_plain_gzip() {
+        // Patchers built before the codec header existed must still read it
+        let patch_dir = tempdir().unwrap();
+        fs::write(
+            patch_dir.path().join("manifest.json"),
+            r#"{"version": 1, "entries": []}"#,
+        )
+        .unwrap();
+
+        let data = create_archive_bytes(patch_dir.path()).unwrap();
+
+        assert!(data.starts_with(GZIP_MAGIC));
+        let mut archive = Archive::new(GzDecoder::new(&data[..]));
+        assert_eq!(archive.entries().unwrap().count(), 1);
+    }
+
+    #[test]
+    fn detects_codecs_and_rejects_unknown_data() {
+        assert_eq!(Codec::detect(&[0x1f, 0x8b, 0x08]), Some(Codec::Gzip));
+        assert_eq!(Codec::detect(b"Z..."), Some(Codec::Zstd));
+        assert_eq!(Codec::detect(b"S..."), Some(Codec::Store));
+        assert_eq!(Codec::detect(b"PK\x03\x04"), Some(Codec::Zip));
+        assert_eq!(Codec::detect(b"PK\x05\x06"), None);
+        assert_eq!(Codec::detect(b""), None);
+        assert!(open_archive(b"not an archive").is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_range_levels() {
+        assert!(ArchiveCompression::new(Codec::Gzip, Some(10)).is_none());
+        assert!(ArchiveCompression::new(Codec::Zstd, Some(22)).is_some());
+        assert!(ArchiveCompression::new(Codec::Store, Some(1)).is_none());
+        assert_eq!(ArchiveCompression::new(Codec::Zstd, None).unwrap().level, 3);
+    }
+
+    #[test]
+    fn checksum_catches_corrupt_and_truncated_archives() {
+        let mut contents = BTreeMap::new();
+        contents.insert("manifest.json".to_string(), br#"{"version": 1, "entries": []}"#.to_vec());
+        contents.insert("files/strings.txt".to_string(), b"Hola".to_vec());
+        let plain = create_archive_from_entries(&contents, ArchiveCompression::default()).unwrap();
+        assert!(!verify_checksum(&plain).unwrap());
+
+        let data = add_checksum(&plain, ArchiveCompression::default()).unwrap();
+        assert!(verify_checksum(&data).unwrap());
+
+        let mut corrupt = read_archive_bytes(&data).unwrap();
+        corrupt.insert("files/strings.txt".to_string(), b"Hol".to_vec());
+        let corrupt = create_archive_from_entries(&corrupt, ArchiveCompression::default()).unwrap();
+        assert_eq!(verify_checksum(&corrupt).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        assert!(verify_checksum(&data[..data.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn same_patch_directory_gives_same_archive() {
+        let first = tempdir().unwrap();
+        let second = tempdir().unwrap();
+        let files = [("manifest.json", "{}"), ("diffs/b.diff", "b"), ("diffs/a.diff", "a"), ("files/x/y.txt", "y")];
+
+        // Written in opposite orders, at different times, with different
+        // permissions
+        for (dir, order) in [(&first, files.to_vec()), (&second, files.iter().rev().copied().collect())] {
+            for (path, data) in order {
+                let path = dir.path().join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, data).unwrap();
+            }
+        }
+        let old = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        File::options()
+            .write(true)
+            .open(second.path().join("diffs/a.diff"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(second.path().join("files/x/y.txt"), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let hash = |dir: &Path| Sha256::digest(create_archive_bytes(dir).unwrap());
+        assert_eq!(hash(first.path()), hash(second.path()));
+
+        let data = create_archive_bytes(first.path()).unwrap();
+        let mut archive = open_archive(&data).unwrap();
+        let paths: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(paths, ["manifest.json", "diffs/a.diff", "diffs/b.diff", "files/x/y.txt"]);
+    }
+
+    #[test]
+    fn payloads_follow_manifest_order_and_can_be_fetched_in_turn() {
+        let patch_dir = tempdir().unwrap();
+        let manifest = r#"{"version": 1, "name": "Test", "entries": [
+            {"operation": "add", "file": "z.bin", "final_hash": "x"},
+            {"operation": "patch", "file": "a.txt", "original_hash": "x", "diff_hash": "x", "final_hash": "x"}
+        ]}"#;
+        for (path, data) in [("manifest.json", manifest), ("files/z.bin", "z"), ("diffs/a.txt.diff", "a"), (".graft_assets/icon.png", "i")] {
+            let path = patch_dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, data).unwrap();
+        }
+
+        let data = create_archive_bytes(patch_dir.path()).unwrap();
+        let mut archive = open_archive(&data).unwrap();
+        let paths: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(paths, ["manifest.json", "files/z.bin", "diffs/a.txt.diff", ".graft_assets/icon.png"]);
+
+        // Fetched out of order: the earlier file is kept while passing it
+        let out = tempdir().unwrap();
+        let keep: HashSet<String> = ["files/z.bin".to_string(), "diffs/a.txt.diff".to_string()].into();
+        with_archive_fetcher(&data, out.path(), &keep, |fetch| {
+            let diff = fetch("diffs/a.txt.diff").unwrap();
+            assert_eq!(fs::read(diff).unwrap(), b"a");
+            let added = fetch("files/z.bin").unwrap();
+            assert_eq!(fs::read(added).unwrap(), b"z");
+            assert_eq!(fetch("missing.bin").unwrap_err().kind(), io::ErrorKind::NotFound);
+        })
+        .unwrap();
+        assert!(!out.path().join(".graft_assets").exists());
+    }
+
+    #[test]
+    fn zip_archive_extracts_and_reads_like_tar() {
+        let patch_dir = tempdir().unwrap();
+        fs::write(patch_dir.path().join("manifest.json"), r#"{"version": 1, "entries": []}"#).unwrap();
+        fs::create_dir_all(patch_dir.path().join("files/nested")).unwrap();
+        fs::write(patch_dir.path().join("files/nested/a.bin"), b"a").unwrap();
+
+        let zip = ArchiveCompression::new(Codec::Zip, Some(9)).unwrap();
+        let data = create_archive_bytes_compressed(patch_dir.path(), zip).unwrap();
+        assert!(data.starts_with(ZIP_MAGIC));
+        assert_eq!(data, create_archive_bytes_compressed(patch_dir.path(), zip).unwrap());
+
+        let tar = create_archive_bytes(patch_dir.path()).unwrap();
+        assert_eq!(read_archive_bytes(&data).unwrap(), read_archive_bytes(&tar).unwrap());
+        assert_eq!(read_archive_file(&data, "files/nested/a.bin").unwrap(), Some(b"a".to_vec()));
+        assert_eq!(read_archive_file(&tar, "files/nested/a.bin").unwrap(), Some(b"a".to_vec()));
+        assert_eq!(read_archive_file(&data, "missing.bin").unwrap(), None);
+        assert!(matches!(open_archive(&data), Err(e) if e.kind() == io::ErrorKind::InvalidInput));
+
+        let out = tempdir().unwrap();
+        extract_archive(&data, out.path()).unwrap();
+        assert_eq!(fs::read(out.path().join("files/nested/a.bin")).unwrap(), b"a");
+
+        let checked = add_checksum(&data, zip).unwrap();
+        assert!(checked.starts_with(ZIP_MAGIC));
+        assert!(verify_checksum(&checked).unwrap());
+    }
 }
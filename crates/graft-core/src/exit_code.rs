@@ -0,0 +1,100 @@
+//! Exit codes shared by graft's binaries (`graft`, the patcher's headless
+//! mode and `graft-icon`), so scripts and launchers can tell why a run
+//! stopped without parsing its output.
+
+use std::io;
+use std::process;
+
+use crate::patch::PatchError;
+
+/// Why a process exited; the discriminant is the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Finished, or the user declined at a prompt
+    Success = 0,
+    /// Any failure not covered by a more specific code
+    Failure = 1,
+    /// The patch, the target or the arguments were rejected before anything
+    /// was changed
+    Validation = 2,
+    /// A file didn't have the expected content, after applying or when
+    /// checked
+    Verification = 3,
+    /// Reading or writing files failed (disk full, permissions, unreadable
+    /// files)
+    Io = 4,
+    /// Stopped because nobody answered a confirmation prompt (no terminal,
+    /// or it timed out)
+    Cancelled = 5,
+}
+
+impl ExitCode {
+    /// The numeric exit code.
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// Exit the process with this code.
+    pub fn exit(self) -> ! {
+        process::exit(self.code())
+    }
+}
+
+impl From<&PatchError> for ExitCode {
+    fn from(error: &PatchError) -> Self {
+        match error {
+            PatchError::ManifestNotFound
+            | PatchError::DiffNotFound(_)
+            | PatchError::FileNotFound(_)
+            | PatchError::ValidationFailed { .. }
+            | PatchError::ManifestError { .. }
+            | PatchError::RestrictedPaths(_)
+            | PatchError::WrongGameVersion { .. }
+            | PatchError::HooksNotAllowed => ExitCode::Validation,
+            PatchError::VerificationFailed { .. } => ExitCode::Verification,
+            PatchError::BackupFailed { .. }
+            | PatchError::ApplyFailed { .. }
+            | PatchError::RollbackFailed { .. }
+            | PatchError::InsufficientSpace { .. }
+            | PatchError::NotWritable { .. } => ExitCode::Io,
+            PatchError::AlreadyInProgress { .. } | PatchError::HookFailed { .. } => ExitCode::Failure,
+        }
+    }
+}
+
+impl From<&io::Error> for ExitCode {
+    /// `Validation` for input that couldn't be parsed, `Io` otherwise.
+    fn from(error: &io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput => ExitCode::Validation,
+            _ => ExitCode::Io,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_map_to_codes() {
+        let validation = PatchError::ValidationFailed {
+            file: "a.bin".to_string(),
+            reason: "hash mismatch".to_string(),
+        };
+        assert_eq!(ExitCode::from(&validation).code(), 2);
+        let verification = PatchError::VerificationFailed {
+            file: "a.bin".to_string(),
+            expected: "x".to_string(),
+            actual: "y".to_string(),
+        };
+        assert_eq!(ExitCode::from(&verification).code(), 3);
+        let full = PatchError::InsufficientSpace { needed: 2, available: 1 };
+        assert_eq!(ExitCode::from(&full).code(), 4);
+
+        let corrupt = io::Error::new(io::ErrorKind::InvalidData, "bad manifest");
+        assert_eq!(ExitCode::from(&corrupt), ExitCode::Validation);
+        let missing = io::Error::new(io::ErrorKind::NotFound, "no such file");
+        assert_eq!(ExitCode::from(&missing), ExitCode::Io);
+    }
+}
@@ -1,4 +1,6 @@
 pub mod archive;
+pub mod exit_code;
 pub mod patch;
 pub mod path_restrictions;
+pub mod signing;
 pub mod utils;
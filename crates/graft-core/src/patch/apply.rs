@@ -1,142 +1,551 @@
-use std::fs;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
 
-use crate::patch::backup::rollback;
-use crate::patch::verify::verify_entry;
-use crate::patch::{PatchError, Progress, ProgressAction, DIFFS_DIR, DIFF_EXTENSION, FILES_DIR};
+use flate2::read::GzDecoder;
+
+use crate::patch::backup::rollback_with;
+use crate::patch::payload::{added_name, decompress, diff_name};
+use crate::patch::validate::in_backup_dir;
+use crate::patch::verify::{verify_entry_with, verify_entry_with_hash};
+use crate::patch::{ByteProgress, PatchError, Progress, ProgressAction};
 use crate::utils::diff::apply_diff;
+use crate::utils::filesystem::{FileSystem, StdFs};
+use crate::utils::hash::{hash_bytes, Algorithm};
 use crate::utils::manifest::ManifestEntry;
 
+/// Default for `set_streaming_threshold`: originals larger than 256 MiB are
+/// patched as a stream.
+pub const DEFAULT_STREAMING_THRESHOLD: u64 = 256 * 1024 * 1024;
+
+/// Size above which patched files are rebuilt as a stream.
+static STREAMING_THRESHOLD: AtomicU64 = AtomicU64::new(DEFAULT_STREAMING_THRESHOLD);
+
+/// Patch original files larger than `bytes` as a stream instead of in memory.
+///
+/// Below the threshold the original, the diff and the result are all held in
+/// memory at once, which is fastest. Above it only a window of each is held
+/// (see `apply_diff_streaming`), so a multi-gigabyte archive can be patched
+/// with little RAM, at the cost of more, smaller reads. Lower it on machines
+/// short of memory. Applies to every patch this process applies afterwards.
+pub fn set_streaming_threshold(bytes: u64) {
+    STREAMING_THRESHOLD.store(bytes, Ordering::Relaxed);
+}
+
+/// The size set by `set_streaming_threshold`.
+pub fn streaming_threshold() -> u64 {
+    STREAMING_THRESHOLD.load(Ordering::Relaxed)
+}
+
+/// How thoroughly `apply_entries` verifies entries after applying them.
+///
+/// Sampled modes trade assurance for speed on very large patches. They do NOT
+/// guarantee every file: an entry that isn't sampled is applied but never
+/// re-hashed, so a bad write to it goes unnoticed. Critical entries and
+/// deletions (a cheap existence check) are always verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Verification {
+    /// Verify every entry (default)
+    #[default]
+    Full,
+    /// Verify every Nth entry, starting with the first
+    EveryNth(usize),
+    /// Verify roughly this percentage (0-100) of entries.
+    ///
+    /// Entries are picked by a hash of their filename rather than a random
+    /// number generator, so the same entries are sampled on every run.
+    Percent(u8),
+}
+
+impl Verification {
+    /// Whether the entry at `index` should be verified under this mode.
+    pub fn should_verify(&self, index: usize, entry: &ManifestEntry) -> bool {
+        if entry.is_critical() || matches!(entry, ManifestEntry::Delete { .. }) {
+            return true;
+        }
+        match self {
+            Verification::Full => true,
+            Verification::EveryNth(n) => index.is_multiple_of((*n).max(1)),
+            Verification::Percent(percent) => {
+                let hash = hash_bytes(entry.file().as_bytes());
+                let bucket = u32::from_str_radix(&hash[..8], 16).unwrap_or(0) % 100;
+                bucket < u32::from(*percent)
+            }
+        }
+    }
+}
+
 /// Apply a single manifest entry to the target directory.
 ///
-/// - Patch: reads original file, applies diff, writes result
+/// - Patch: reads original file, applies diff, writes result (through a temp
+///   file renamed into place, so a crash never leaves it half-written)
 /// - Add: copies file from patch files/ directory
 /// - Delete: removes file from target directory
+/// - Rename: moves file within target directory
+/// - Symlink: replaces (or removes) the symlink at the path
+/// - MkDir: creates the directory (and any missing parents)
+/// - RmDir: removes the directory, failing if anything is left in it
+///
+/// Diffs and added files stored compressed are decompressed on the way.
+/// Patched and added files get the permission bits and modification time
+/// the manifest records for them, if any.
 pub fn apply_entry(
     entry: &ManifestEntry,
     target_dir: &Path,
     patch_dir: &Path,
 ) -> Result<(), PatchError> {
+    apply_entry_with(&StdFs, entry, target_dir, patch_dir)
+}
+
+/// Like `apply_entry`, using the given filesystem.
+pub fn apply_entry_with(
+    fs: &impl FileSystem,
+    entry: &ManifestEntry,
+    target_dir: &Path,
+    patch_dir: &Path,
+) -> Result<(), PatchError> {
+    apply_entry_reporting(fs, entry, target_dir, patch_dir, None, &mut |_| {}).map(|_| ())
+}
+
+/// Like `apply_entry`, also returning the hash (with `algorithm`) of the
+/// file it wrote, so it can be checked with `verify_entry_with_hash` instead
+/// of being read back.
+///
+/// The hash is only available when the whole file was built in memory: for
+/// a patched file below the streaming threshold or a compressed added file.
+/// Otherwise (and for entries that write no file) it is `None`, and the
+/// entry should be checked with `verify_entry`.
+pub fn apply_entry_hashing(
+    entry: &ManifestEntry,
+    target_dir: &Path,
+    patch_dir: &Path,
+    algorithm: Algorithm,
+) -> Result<Option<String>, PatchError> {
+    apply_entry_hashing_with(&StdFs, entry, target_dir, patch_dir, algorithm)
+}
+
+/// Like `apply_entry_hashing`, using the given filesystem.
+pub fn apply_entry_hashing_with(
+    fs: &impl FileSystem,
+    entry: &ManifestEntry,
+    target_dir: &Path,
+    patch_dir: &Path,
+    algorithm: Algorithm,
+) -> Result<Option<String>, PatchError> {
+    apply_entry_reporting(fs, entry, target_dir, patch_dir, Some(algorithm), &mut |_| {})
+}
+
+/// Like `apply_entry_with`, calling `on_written` with the number of bytes of
+/// the patched or added file written so far, as it goes out in chunks.
+///
+/// With `hash_with`, returns the hash of the file written when it was built
+/// in memory (see `apply_entry_hashing`).
+fn apply_entry_reporting(
+    fs: &impl FileSystem,
+    entry: &ManifestEntry,
+    target_dir: &Path,
+    patch_dir: &Path,
+    hash_with: Option<Algorithm>,
+    on_written: &mut dyn FnMut(u64),
+) -> Result<Option<String>, PatchError> {
+    // Also refused by validation; checked again so an entry applied without
+    // it still can't write over the backups
+    let renamed_from = if let ManifestEntry::Rename { from, .. } = entry {
+        Some(from.as_str())
+    } else {
+        None
+    };
+    if let Some(path) = std::iter::once(entry.file()).chain(renamed_from).find(|path| in_backup_dir(path)) {
+        return Err(PatchError::ApplyFailed {
+            file: path.to_string(),
+            reason: "path is inside the backup directory".to_string(),
+        });
+    }
+
+    let mut written_hash = None;
     match entry {
-        ManifestEntry::Patch { file, .. } => {
+        ManifestEntry::Patch { file, mode, mtime, compressed, .. } => {
             let target_path = target_dir.join(file);
-            let diff_path = patch_dir
-                .join(DIFFS_DIR)
-                .join(format!("{}{}", file, DIFF_EXTENSION));
+            let diff_path = patch_dir.join(diff_name(file, *compressed));
 
             // Validate files exist before attempting operations
-            if !target_path.exists() {
+            if !fs.exists(&target_path) {
                 return Err(PatchError::ValidationFailed {
                     file: file.clone(),
                     reason: "target file not found".to_string(),
                 });
             }
-            if !diff_path.exists() {
+            if !fs.exists(&diff_path) {
                 return Err(PatchError::ValidationFailed {
                     file: file.clone(),
                     reason: "diff file not found in patch".to_string(),
                 });
             }
 
-            let original_data = fs::read(&target_path).map_err(|e| PatchError::ApplyFailed {
-                file: file.clone(),
-                reason: format!("failed to read original file: {}", e),
-            })?;
-
-            let diff_data = fs::read(&diff_path).map_err(|e| PatchError::ApplyFailed {
-                file: file.clone(),
-                reason: format!("failed to read diff file: {}", e),
-            })?;
-
-            let patched_data =
-                apply_diff(&original_data, &diff_data).map_err(|e| PatchError::ApplyFailed {
+            // Large originals are patched as a stream so memory stays bounded
+            if fs.file_size(&target_path).is_ok_and(|size| size > streaming_threshold()) {
+                let diff = fs.open(&diff_path).map_err(|e| PatchError::ApplyFailed {
+                    file: file.clone(),
+                    reason: format!("failed to read diff file: {}", e),
+                })?;
+                let mut diff: Box<dyn Read> = if *compressed { Box::new(GzDecoder::new(diff)) } else { diff };
+                fs.patch_streaming(&target_path, &mut diff, on_written).map_err(|e| PatchError::ApplyFailed {
                     file: file.clone(),
                     reason: format!("failed to apply diff: {}", e),
                 })?;
+            } else {
+                let original_data = fs.read(&target_path).map_err(|e| PatchError::ApplyFailed {
+                    file: file.clone(),
+                    reason: format!("failed to read original file: {}", e),
+                })?;
 
-            fs::write(&target_path, patched_data).map_err(|e| PatchError::ApplyFailed {
-                file: file.clone(),
-                reason: format!("failed to write patched file: {}", e),
-            })?;
+                let diff_data = fs.read(&diff_path).map_err(|e| PatchError::ApplyFailed {
+                    file: file.clone(),
+                    reason: format!("failed to read diff file: {}", e),
+                })?;
+                let diff_data = if *compressed {
+                    decompress(&diff_data).map_err(|e| PatchError::ApplyFailed {
+                        file: file.clone(),
+                        reason: format!("failed to decompress diff file: {}", e),
+                    })?
+                } else {
+                    diff_data
+                };
+
+                let patched_data =
+                    apply_diff(&original_data, &diff_data).map_err(|e| PatchError::ApplyFailed {
+                        file: file.clone(),
+                        reason: format!("failed to apply diff: {}", e),
+                    })?;
+
+                fs.write_atomic_reporting(&target_path, &patched_data, on_written).map_err(|e| {
+                    PatchError::ApplyFailed {
+                        file: file.clone(),
+                        reason: format!("failed to write patched file: {}", e),
+                    }
+                })?;
+                written_hash = hash_with.map(|algorithm| algorithm.hash_bytes(&patched_data));
+            }
+            set_mode_with(fs, &target_path, file, *mode)?;
+            set_mtime_with(fs, &target_path, file, *mtime)?;
         }
-        ManifestEntry::Add { file, .. } => {
-            let source_path = patch_dir.join(FILES_DIR).join(file);
+        ManifestEntry::Add { file, mode, mtime, compressed, .. } => {
+            let source_path = patch_dir.join(added_name(file, *compressed));
             let target_path = target_dir.join(file);
 
             // Validate source file exists
-            if !source_path.exists() {
+            if !fs.exists(&source_path) {
                 return Err(PatchError::ValidationFailed {
                     file: file.clone(),
                     reason: "source file not found in patch".to_string(),
                 });
             }
 
-            fs::copy(&source_path, &target_path).map_err(|e| PatchError::ApplyFailed {
-                file: file.clone(),
-                reason: format!("failed to copy new file: {}", e),
-            })?;
+            create_parent_with(fs, &target_path, file)?;
+            if *compressed {
+                let data = fs
+                    .read(&source_path)
+                    .and_then(|data| decompress(&data))
+                    .map_err(|e| PatchError::ApplyFailed {
+                        file: file.clone(),
+                        reason: format!("failed to decompress new file: {}", e),
+                    })?;
+                fs.write_atomic_reporting(&target_path, &data, on_written).map_err(|e| PatchError::ApplyFailed {
+                    file: file.clone(),
+                    reason: format!("failed to write new file: {}", e),
+                })?;
+                written_hash = hash_with.map(|algorithm| algorithm.hash_bytes(&data));
+            } else {
+                fs.copy_atomic_reporting(&source_path, &target_path, on_written).map_err(|e| {
+                    PatchError::ApplyFailed {
+                        file: file.clone(),
+                        reason: format!("failed to copy new file: {}", e),
+                    }
+                })?;
+            }
+            set_mode_with(fs, &target_path, file, *mode)?;
+            set_mtime_with(fs, &target_path, file, *mtime)?;
         }
         ManifestEntry::Delete { file, .. } => {
             let target_path = target_dir.join(file);
 
             // Only delete if file exists (already deleted is not an error)
-            if target_path.exists() {
-                fs::remove_file(&target_path).map_err(|e| PatchError::ApplyFailed {
+            if fs.exists(&target_path) {
+                fs.remove_file(&target_path).map_err(|e| PatchError::ApplyFailed {
                     file: file.clone(),
                     reason: format!("failed to delete file: {}", e),
                 })?;
             }
         }
+        ManifestEntry::Rename { from, to, .. } => {
+            let source_path = target_dir.join(from);
+            let target_path = target_dir.join(to);
+
+            if !fs.exists(&source_path) {
+                return Err(PatchError::ValidationFailed {
+                    file: from.clone(),
+                    reason: "file to rename not found".to_string(),
+                });
+            }
+
+            create_parent_with(fs, &target_path, to)?;
+            fs.rename(&source_path, &target_path).map_err(|e| PatchError::ApplyFailed {
+                file: to.clone(),
+                reason: format!("failed to rename '{}': {}", from, e),
+            })?;
+        }
+        ManifestEntry::Symlink { file, target, .. } => {
+            replace_symlink_with(fs, &target_dir.join(file), target.as_deref()).map_err(|e| {
+                PatchError::ApplyFailed {
+                    file: file.clone(),
+                    reason: format!("failed to update symlink: {}", e),
+                }
+            })?;
+        }
+        ManifestEntry::MkDir { dir } => {
+            fs.create_dir_all(&target_dir.join(dir)).map_err(|e| PatchError::ApplyFailed {
+                file: dir.clone(),
+                reason: format!("failed to create directory: {}", e),
+            })?;
+        }
+        ManifestEntry::RmDir { dir } => {
+            let target_path = target_dir.join(dir);
+
+            // Already removed is not an error, as for Delete
+            if fs.exists(&target_path) {
+                fs.remove_dir(&target_path).map_err(|e| PatchError::ApplyFailed {
+                    file: dir.clone(),
+                    reason: format!("failed to remove directory: {}", e),
+                })?;
+            }
+        }
     }
 
+    Ok(written_hash)
+}
+
+/// Remove the symlink at `link` if there is one, then point it at `target`
+/// if given, creating its directory if needed.
+///
+/// Only a symlink is ever removed: anything else at `link` makes creating
+/// the new one fail.
+pub(crate) fn replace_symlink_with(fs: &impl FileSystem, link: &Path, target: Option<&str>) -> std::io::Result<()> {
+    if fs.read_link(link).is_ok() {
+        fs.remove_file(link)?;
+    }
+    if let Some(target) = target {
+        if let Some(parent) = link.parent()
+            && !fs.exists(parent)
+        {
+            fs.create_dir_all(parent)?;
+        }
+        fs.symlink(Path::new(target), link)?;
+    }
     Ok(())
 }
 
+/// Set the modification time recorded in the manifest, if any.
+fn set_mtime_with(
+    fs: &impl FileSystem,
+    target_path: &Path,
+    file: &str,
+    mtime: Option<i64>,
+) -> Result<(), PatchError> {
+    let Some(mtime) = mtime else {
+        return Ok(());
+    };
+    let time = if mtime >= 0 {
+        UNIX_EPOCH + Duration::from_secs(mtime as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(mtime.unsigned_abs())
+    };
+    fs.set_modified(target_path, time).map_err(|e| PatchError::ApplyFailed {
+        file: file.to_string(),
+        reason: format!("failed to set modification time: {}", e),
+    })
+}
+
+/// Set the permission bits recorded in the manifest, if any.
+fn set_mode_with(
+    fs: &impl FileSystem,
+    target_path: &Path,
+    file: &str,
+    mode: Option<u32>,
+) -> Result<(), PatchError> {
+    match mode {
+        Some(mode) => fs.set_mode(target_path, mode).map_err(|e| PatchError::ApplyFailed {
+            file: file.to_string(),
+            reason: format!("failed to set permissions: {}", e),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Create the directory `target_path` goes in, for files added to (or
+/// renamed into) a subdirectory the target doesn't have yet.
+fn create_parent_with(fs: &impl FileSystem, target_path: &Path, file: &str) -> Result<(), PatchError> {
+    match target_path.parent() {
+        Some(parent) if !fs.exists(parent) => {
+            fs.create_dir_all(parent).map_err(|e| PatchError::ApplyFailed {
+                file: file.to_string(),
+                reason: format!("failed to create directory: {}", e),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
 /// Apply all entries with progress callback and automatic rollback on failure.
 ///
 /// This is a batch operation that:
 /// 1. Iterates over all entries
 /// 2. Calls the progress callback before each entry (if provided)
-/// 3. Applies the entry and verifies the result
+/// 3. Applies the entry and verifies the result (subject to `verification`),
+///    hashing with `algorithm`, the manifest's `hash_algorithm`. Files built
+///    in memory are hashed as they are written rather than read back (see
+///    `apply_entry_hashing`)
 /// 4. On any failure, rolls back all previously applied entries
 ///
+/// `on_bytes`, if provided, is called as patched and added files are
+/// written, several times for large files, so a single big file doesn't
+/// stall a byte-based progress bar.
+///
 /// Note: This assumes backup_entries has already been called to create backups.
-pub fn apply_entries<F>(
+#[allow(clippy::too_many_arguments)]
+pub fn apply_entries<F, G>(
+    entries: &[ManifestEntry],
+    target_dir: &Path,
+    patch_dir: &Path,
+    backup_dir: &Path,
+    verification: Verification,
+    algorithm: Algorithm,
+    on_progress: Option<F>,
+    on_bytes: Option<G>,
+) -> Result<(), PatchError>
+where
+    F: FnMut(Progress),
+    G: FnMut(ByteProgress),
+{
+    apply_entries_with(&StdFs, entries, target_dir, patch_dir, backup_dir, verification, algorithm, on_progress, on_bytes)
+}
+
+/// Like `apply_entries`, using the given filesystem.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_entries_with<F, G>(
+    fs: &impl FileSystem,
+    entries: &[ManifestEntry],
+    target_dir: &Path,
+    patch_dir: &Path,
+    backup_dir: &Path,
+    verification: Verification,
+    algorithm: Algorithm,
+    on_progress: Option<F>,
+    on_bytes: Option<G>,
+) -> Result<(), PatchError>
+where
+    F: FnMut(Progress),
+    G: FnMut(ByteProgress),
+{
+    apply_sequentially(fs, entries, target_dir, patch_dir, backup_dir, verification, algorithm, &mut |_| Ok(()), on_progress, on_bytes)
+}
+
+/// Like `apply_entries`, for a patch whose diffs and added files aren't all
+/// in `patch_dir` up front, e.g. because they are read one at a time from
+/// the patch archive (see `archive::with_archive_fetcher`).
+///
+/// `stage` is called with each entry just before it is applied, to put its
+/// payload (see `payload_name`) in `patch_dir`; it can also remove the
+/// previous entry's, which is no longer needed. If it fails, everything
+/// applied so far is rolled back and its error is returned.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_entries_staged<F, G, S>(
+    entries: &[ManifestEntry],
+    target_dir: &Path,
+    patch_dir: &Path,
+    backup_dir: &Path,
+    verification: Verification,
+    algorithm: Algorithm,
+    mut stage: S,
+    on_progress: Option<F>,
+    on_bytes: Option<G>,
+) -> Result<(), PatchError>
+where
+    F: FnMut(Progress),
+    G: FnMut(ByteProgress),
+    S: FnMut(&ManifestEntry) -> Result<(), PatchError>,
+{
+    apply_sequentially(&StdFs, entries, target_dir, patch_dir, backup_dir, verification, algorithm, &mut stage, on_progress, on_bytes)
+}
+
+/// Apply `entries` one at a time, calling `stage` before each, as
+/// `apply_entries_with` and `apply_entries_staged` describe.
+#[allow(clippy::too_many_arguments)]
+fn apply_sequentially<F, G>(
+    fs: &impl FileSystem,
     entries: &[ManifestEntry],
     target_dir: &Path,
     patch_dir: &Path,
     backup_dir: &Path,
+    verification: Verification,
+    algorithm: Algorithm,
+    stage: &mut dyn FnMut(&ManifestEntry) -> Result<(), PatchError>,
     mut on_progress: Option<F>,
+    mut on_bytes: Option<G>,
 ) -> Result<(), PatchError>
 where
     F: FnMut(Progress),
+    G: FnMut(ByteProgress),
 {
     let total = entries.len();
+    let bytes_total = entries.iter().map(ManifestEntry::expected_size).sum();
+    let mut bytes_done = 0;
     let mut applied = Vec::new();
 
     for (index, entry) in entries.iter().enumerate() {
-        let action = match entry {
-            ManifestEntry::Patch { .. } => ProgressAction::Patching,
-            ManifestEntry::Add { .. } => ProgressAction::Adding,
-            ManifestEntry::Delete { .. } => ProgressAction::Deleting,
-        };
-
         if let Some(ref mut callback) = on_progress {
             callback(Progress {
                 file: entry.file(),
+                label: entry.label(),
                 index,
                 total,
-                action,
+                action: apply_action(entry),
             });
         }
 
-        if let Err(e) = apply_entry(entry, target_dir, patch_dir) {
-            rollback(&applied, target_dir, backup_dir, None::<fn(Progress)>)?;
+        if let Err(e) = stage(entry) {
+            rollback_with(fs, &applied, target_dir, backup_dir, None::<fn(Progress)>)?;
             return Err(e);
         }
 
-        if let Err(e) = verify_entry(entry, target_dir) {
-            rollback(&applied, target_dir, backup_dir, None::<fn(Progress)>)?;
+        let expected = entry.expected_size();
+        let mut on_written = |written: u64| {
+            if let Some(ref mut callback) = on_bytes {
+                callback(ByteProgress {
+                    done: bytes_done + written.min(expected),
+                    total: bytes_total,
+                });
+            }
+        };
+        let hash_with = verification.should_verify(index, entry).then_some(algorithm);
+        let written_hash = match apply_entry_reporting(fs, entry, target_dir, patch_dir, hash_with, &mut on_written) {
+            Ok(hash) => hash,
+            Err(e) => {
+                rollback_with(fs, &applied, target_dir, backup_dir, None::<fn(Progress)>)?;
+                return Err(e);
+            }
+        };
+        bytes_done += expected;
+
+        if hash_with.is_some()
+            && let Err(e) = verify_written(fs, entry, target_dir, algorithm, written_hash.as_deref())
+        {
+            // The entry was written, so it has to be undone too
+            applied.push(entry);
+            rollback_with(fs, &applied, target_dir, backup_dir, None::<fn(Progress)>)?;
             return Err(e);
         }
 
@@ -146,11 +555,292 @@ where
     Ok(())
 }
 
+/// Like `apply_entries`, but applies entries on up to `workers` threads.
+///
+/// Entries that touch the same path (e.g. a rename away from a file and the
+/// add that replaces it, or a new directory and the files in it) always run
+/// in order on one thread. The first failure stops workers from starting
+/// further entries; once in-flight entries finish, everything written so far
+/// (including entries that failed verification) is rolled back once and that
+/// first error is returned. Failures on other threads in the meantime are
+/// not reported.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_entries_parallel<F, G>(
+    entries: &[ManifestEntry],
+    target_dir: &Path,
+    patch_dir: &Path,
+    backup_dir: &Path,
+    verification: Verification,
+    algorithm: Algorithm,
+    workers: usize,
+    on_progress: Option<F>,
+    on_bytes: Option<G>,
+) -> Result<(), PatchError>
+where
+    F: FnMut(Progress) + Send,
+    G: FnMut(ByteProgress) + Send,
+{
+    apply_entries_parallel_then(
+        entries,
+        target_dir,
+        patch_dir,
+        verification,
+        algorithm,
+        workers,
+        on_progress,
+        on_bytes,
+        |applied| rollback_with(&StdFs, applied, target_dir, backup_dir, None::<fn(Progress)>),
+    )
+}
+
+/// `apply_entries_parallel`, with `rollback` called to undo the written
+/// entries if any entry fails.
+#[allow(clippy::too_many_arguments)]
+fn apply_entries_parallel_then<'a, F, G, R>(
+    entries: &'a [ManifestEntry],
+    target_dir: &Path,
+    patch_dir: &Path,
+    verification: Verification,
+    algorithm: Algorithm,
+    workers: usize,
+    on_progress: Option<F>,
+    on_bytes: Option<G>,
+    rollback: R,
+) -> Result<(), PatchError>
+where
+    F: FnMut(Progress) + Send,
+    G: FnMut(ByteProgress) + Send,
+    R: FnOnce(&[&'a ManifestEntry]) -> Result<(), PatchError>,
+{
+    let total = entries.len();
+    let on_progress = on_progress.map(Mutex::new);
+    let bytes_total = entries.iter().map(ManifestEntry::expected_size).sum();
+    let bytes_done = AtomicU64::new(0);
+    let on_bytes = on_bytes.map(Mutex::new);
+
+    apply_in_parallel(
+        entries,
+        workers,
+        |index, entry| {
+            if let Some(callback) = &on_progress {
+                let mut callback = callback.lock().unwrap();
+                callback(Progress {
+                    file: entry.file(),
+                    label: entry.label(),
+                    index,
+                    total,
+                    action: apply_action(entry),
+                });
+            }
+
+            let expected = entry.expected_size();
+            let mut reported = 0;
+            let mut on_written = |written: u64| {
+                if let Some(callback) = &on_bytes {
+                    // Add under the lock so reports arrive in increasing order
+                    let mut callback = callback.lock().unwrap();
+                    let written = written.min(expected);
+                    let done = bytes_done.fetch_add(written - reported, Ordering::SeqCst) + written - reported;
+                    reported = written;
+                    callback(ByteProgress { done, total: bytes_total });
+                }
+            };
+            let hash_with = verification.should_verify(index, entry).then_some(algorithm);
+            let written_hash = apply_entry_reporting(&StdFs, entry, target_dir, patch_dir, hash_with, &mut on_written)?;
+            if hash_with.is_some()
+                && let Err(error) = verify_written(&StdFs, entry, target_dir, algorithm, written_hash.as_deref())
+            {
+                return Err(EntryFailure { error, written: true });
+            }
+            Ok(())
+        },
+        rollback,
+    )
+}
+
+/// An entry that failed in `apply_in_parallel`.
+struct EntryFailure {
+    error: PatchError,
+    /// Whether the entry was written anyway (it failed verification), so
+    /// it has to be rolled back with the others
+    written: bool,
+}
+
+impl From<PatchError> for EntryFailure {
+    fn from(error: PatchError) -> Self {
+        EntryFailure { error, written: false }
+    }
+}
+
+/// Run `apply` for every entry across `workers` threads, then `rollback` the
+/// written entries (in manifest order) if any entry failed.
+///
+/// Entries that share a path (see `entry_groups`) run in manifest order on
+/// one thread. Directory removals run last, on this thread, once every other
+/// entry (and so every file that was in them) is done.
+fn apply_in_parallel<'a, A, R>(
+    entries: &'a [ManifestEntry],
+    workers: usize,
+    apply: A,
+    rollback: R,
+) -> Result<(), PatchError>
+where
+    A: Fn(usize, &'a ManifestEntry) -> Result<(), EntryFailure> + Sync,
+    R: FnOnce(&[&'a ManifestEntry]) -> Result<(), PatchError>,
+{
+    let (groups, removed_dirs) = entry_groups(entries);
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let first_error = Mutex::new(None);
+    let applied = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, groups.len().max(1)) {
+            scope.spawn(|| {
+                while !failed.load(Ordering::SeqCst) {
+                    let Some(group) = groups.get(next.fetch_add(1, Ordering::SeqCst)) else {
+                        break;
+                    };
+                    for &index in group {
+                        if let Err(failure) = apply(index, &entries[index]) {
+                            if failure.written {
+                                applied.lock().unwrap().push(index);
+                            }
+                            // Keep only the first failure; the rest are
+                            // usually the same problem (e.g. disk full)
+                            if !failed.swap(true, Ordering::SeqCst) {
+                                *first_error.lock().unwrap() = Some(failure.error);
+                            }
+                            return;
+                        }
+                        applied.lock().unwrap().push(index);
+                    }
+                }
+            });
+        }
+    });
+
+    let mut first_error = first_error.into_inner().unwrap();
+    let mut applied = applied.into_inner().unwrap();
+    if first_error.is_none() {
+        for index in removed_dirs {
+            if let Err(failure) = apply(index, &entries[index]) {
+                if failure.written {
+                    applied.push(index);
+                }
+                first_error = Some(failure.error);
+                break;
+            }
+            applied.push(index);
+        }
+    }
+
+    let Some(error) = first_error else {
+        return Ok(());
+    };
+
+    applied.sort_unstable();
+    let applied: Vec<_> = applied.into_iter().map(|index| &entries[index]).collect();
+    rollback(&applied)?;
+
+    Err(error)
+}
+
+/// Split `entries` into groups that can be applied in parallel, each in
+/// manifest order, and the directory removals, which run after the rest.
+///
+/// Entries share a group if they touch the same path: a rename touches both
+/// its paths, and an entry inside a directory the patch creates, or under a
+/// path the patch makes a symlink, touches that directory or link too.
+fn entry_groups(entries: &[ManifestEntry]) -> (Vec<Vec<usize>>, Vec<usize>) {
+    let created: HashSet<&str> = entries
+        .iter()
+        .filter(|e| matches!(e, ManifestEntry::MkDir { .. } | ManifestEntry::Symlink { .. }))
+        .map(ManifestEntry::file)
+        .collect();
+
+    // Union-find over entry indices, joined through the paths they touch
+    let mut parent: Vec<usize> = (0..entries.len()).collect();
+    fn root(parent: &mut [usize], mut index: usize) -> usize {
+        while parent[index] != index {
+            parent[index] = parent[parent[index]];
+            index = parent[index];
+        }
+        index
+    }
+    let mut first_toucher: HashMap<&str, usize> = HashMap::new();
+    let mut removed_dirs = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        if matches!(entry, ManifestEntry::RmDir { .. }) {
+            removed_dirs.push(index);
+            continue;
+        }
+        let paths = match entry {
+            ManifestEntry::Rename { from, to, .. } => vec![from.as_str(), to.as_str()],
+            _ => vec![entry.file()],
+        };
+        for path in paths {
+            let parents = path.match_indices('/').map(|(i, _)| &path[..i]);
+            for touched in std::iter::once(path).chain(parents.filter(|p| created.contains(p))) {
+                let other = *first_toucher.entry(touched).or_insert(index);
+                let (a, b) = (root(&mut parent, index), root(&mut parent, other));
+                // Point the later group at the earlier, so each root is its
+                // group's first entry
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_of: HashMap<usize, usize> = HashMap::new();
+    for index in (0..entries.len()).filter(|&i| !matches!(entries[i], ManifestEntry::RmDir { .. })) {
+        let group = *group_of.entry(root(&mut parent, index)).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[group].push(index);
+    }
+    (groups, removed_dirs)
+}
+
+/// Verify an entry just applied, against `written_hash` (the hash of the
+/// file as it was written) if there is one, or else by reading it back.
+fn verify_written(
+    fs: &impl FileSystem,
+    entry: &ManifestEntry,
+    target_dir: &Path,
+    algorithm: Algorithm,
+    written_hash: Option<&str>,
+) -> Result<(), PatchError> {
+    match written_hash {
+        Some(hash) => verify_entry_with_hash(entry, hash),
+        None => verify_entry_with(fs, entry, target_dir, algorithm),
+    }
+}
+
+/// Progress action reported when applying an entry.
+fn apply_action(entry: &ManifestEntry) -> ProgressAction {
+    match entry {
+        ManifestEntry::Patch { .. } => ProgressAction::Patching,
+        ManifestEntry::Add { .. } => ProgressAction::Adding,
+        ManifestEntry::Delete { .. } => ProgressAction::Deleting,
+        ManifestEntry::Rename { .. } => ProgressAction::Renaming,
+        ManifestEntry::Symlink { .. } => ProgressAction::Linking,
+        ManifestEntry::MkDir { .. } => ProgressAction::Creating,
+        ManifestEntry::RmDir { .. } => ProgressAction::Deleting,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::patch::payload::compress;
+    use crate::patch::{DIFFS_DIR, DIFF_EXTENSION, FILES_DIR};
+    use crate::utils::filesystem::MemFs;
+    use std::fs;
     use crate::utils::diff::create_diff;
-    use crate::utils::hash::hash_bytes;
+    use crate::utils::hash::{hash_bytes, Algorithm};
     use tempfile::tempdir;
 
     #[test]
@@ -181,12 +871,71 @@ mod tests {
             original_hash: hash_bytes(original_content),
             diff_hash: hash_bytes(&diff_data),
             final_hash: hash_bytes(new_content),
+            final_hashes: vec![],
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+            line_endings: None,
+            encoding: None,
         };
 
         apply_entry(&entry, target_dir.path(), patch_dir.path()).unwrap();
 
         let result = fs::read(target_dir.path().join("file.bin")).unwrap();
         assert_eq!(result, new_content);
+        // The temp file the result was written through was renamed away
+        assert_eq!(fs::read_dir(target_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn hashing_returns_hash_of_file_built_in_memory() {
+        let target_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+
+        let original_content = b"original content";
+        let new_content = b"modified content";
+        fs::write(target_dir.path().join("file.bin"), original_content).unwrap();
+        let diff_data = create_diff(original_content, new_content).unwrap();
+        fs::create_dir_all(patch_dir.path().join(DIFFS_DIR)).unwrap();
+        fs::write(patch_dir.path().join(DIFFS_DIR).join(format!("file.bin{}", DIFF_EXTENSION)), &diff_data).unwrap();
+        fs::create_dir_all(patch_dir.path().join(FILES_DIR)).unwrap();
+        fs::write(patch_dir.path().join(FILES_DIR).join("new.bin"), b"new").unwrap();
+
+        let patch = ManifestEntry::Patch {
+            file: "file.bin".to_string(),
+            original_hash: hash_bytes(original_content),
+            diff_hash: hash_bytes(&diff_data),
+            final_hash: hash_bytes(new_content),
+            final_hashes: vec![],
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+            line_endings: None,
+            encoding: None,
+        };
+        let hash = apply_entry_hashing(&patch, target_dir.path(), patch_dir.path(), Algorithm::Sha256).unwrap();
+        assert_eq!(hash, Some(hash_bytes(new_content)));
+
+        // Copied straight from the patch, so never in memory to hash
+        let add = ManifestEntry::Add {
+            file: "new.bin".to_string(),
+            final_hash: hash_bytes(b"new"),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+        };
+        let hash = apply_entry_hashing(&add, target_dir.path(), patch_dir.path(), Algorithm::Sha256).unwrap();
+        assert_eq!(hash, None);
     }
 
     #[test]
@@ -203,6 +952,13 @@ mod tests {
         let entry = ManifestEntry::Add {
             file: "new.bin".to_string(),
             final_hash: hash_bytes(content),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
         };
 
         apply_entry(&entry, target_dir.path(), patch_dir.path()).unwrap();
@@ -267,6 +1023,15 @@ mod tests {
             original_hash: "x".to_string(),
             diff_hash: "y".to_string(),
             final_hash: "z".to_string(),
+            final_hashes: vec![],
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+            line_endings: None,
+            encoding: None,
         };
 
         let result = apply_entry(&entry, target_dir.path(), patch_dir.path());
@@ -286,6 +1051,15 @@ mod tests {
             original_hash: "x".to_string(),
             diff_hash: "y".to_string(),
             final_hash: "z".to_string(),
+            final_hashes: vec![],
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+            line_endings: None,
+            encoding: None,
         };
 
         let result = apply_entry(&entry, target_dir.path(), patch_dir.path());
@@ -300,9 +1074,725 @@ mod tests {
         let entry = ManifestEntry::Add {
             file: "missing.bin".to_string(),
             final_hash: "x".to_string(),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
         };
 
         let result = apply_entry(&entry, target_dir.path(), patch_dir.path());
         assert!(matches!(result, Err(PatchError::ValidationFailed { .. })));
     }
+
+    /// Add entries whose payload doesn't match the recorded final_hash,
+    /// so verification of any of them fails.
+    fn setup_bad_adds(patch_dir: &Path, names: &[&str], critical: &[&str]) -> Vec<ManifestEntry> {
+        fs::create_dir_all(patch_dir.join(FILES_DIR)).unwrap();
+        names
+            .iter()
+            .map(|name| {
+                fs::write(patch_dir.join(FILES_DIR).join(name), b"content").unwrap();
+                ManifestEntry::Add {
+                    file: name.to_string(),
+                    final_hash: "wrong".to_string(),
+                    original_hash: None,
+                    size: None,
+                    critical: critical.contains(name),
+                    label: None,
+                    mode: None,
+                    mtime: None,
+                    compressed: false,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn full_verification_catches_every_entry() {
+        let target_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let backup_dir = tempdir().unwrap();
+        let entries = setup_bad_adds(patch_dir.path(), &["a.bin", "b.bin"], &[]);
+
+        let result = apply_entries(
+            &entries,
+            target_dir.path(),
+            patch_dir.path(),
+            backup_dir.path(),
+            Verification::Full,
+            Algorithm::Sha256,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+        );
+        assert!(matches!(result, Err(PatchError::VerificationFailed { .. })));
+    }
+
+    #[test]
+    fn sampled_verification_skips_unsampled_entries() {
+        let target_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let backup_dir = tempdir().unwrap();
+        let mut entries = setup_bad_adds(patch_dir.path(), &["a.bin", "b.bin"], &[]);
+
+        // Only index 0 is sampled, so give it a correct hash; b.bin is never checked
+        entries[0] = ManifestEntry::Add {
+            file: "a.bin".to_string(),
+            final_hash: hash_bytes(b"content"),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+        };
+
+        let result = apply_entries(
+            &entries,
+            target_dir.path(),
+            patch_dir.path(),
+            backup_dir.path(),
+            Verification::EveryNth(2),
+            Algorithm::Sha256,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+        );
+        assert!(result.is_ok());
+        assert!(target_dir.path().join("b.bin").exists());
+    }
+
+    #[test]
+    fn sampled_verification_always_checks_critical_entries() {
+        let target_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let backup_dir = tempdir().unwrap();
+        let entries = setup_bad_adds(patch_dir.path(), &["a.bin", "b.bin"], &["b.bin"]);
+
+        // Nothing is sampled, but b.bin is critical
+        let result = apply_entries(
+            &entries,
+            target_dir.path(),
+            patch_dir.path(),
+            backup_dir.path(),
+            Verification::Percent(0),
+            Algorithm::Sha256,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+        );
+        assert!(matches!(
+            result,
+            Err(PatchError::VerificationFailed { file, .. }) if file == "b.bin"
+        ));
+    }
+
+    #[test]
+    fn percent_sampling_is_deterministic() {
+        let entry = ManifestEntry::Add {
+            file: "data/text.bin".to_string(),
+            final_hash: "x".to_string(),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+        };
+
+        assert!(Verification::Percent(100).should_verify(0, &entry));
+        assert!(!Verification::Percent(0).should_verify(0, &entry));
+        assert_eq!(
+            Verification::Percent(50).should_verify(0, &entry),
+            Verification::Percent(50).should_verify(7, &entry)
+        );
+    }
+
+    #[test]
+    fn deletes_are_always_verified() {
+        let entry = ManifestEntry::Delete {
+            file: "old.bin".to_string(),
+            original_hash: "x".to_string(),
+        };
+
+        assert!(Verification::EveryNth(10).should_verify(3, &entry));
+        assert!(Verification::Percent(0).should_verify(3, &entry));
+    }
+
+    fn mem_add(name: &str, content: &[u8]) -> ManifestEntry {
+        ManifestEntry::Add {
+            file: name.to_string(),
+            final_hash: hash_bytes(content),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+        }
+    }
+
+    #[test]
+    fn mem_fs_apply_adds_files() {
+        let fs = MemFs::new();
+        fs.insert("/patch/files/a.bin", "a");
+        fs.insert("/patch/files/b.bin", "b");
+
+        let entries = vec![mem_add("a.bin", b"a"), mem_add("b.bin", b"b")];
+        apply_entries_with(
+            &fs,
+            &entries,
+            Path::new("/game"),
+            Path::new("/patch"),
+            Path::new("/game/.patch-backup"),
+            Verification::Full,
+            Algorithm::Sha256,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+        )
+        .unwrap();
+
+        assert_eq!(fs.get("/game/a.bin").unwrap(), b"a");
+        assert_eq!(fs.get("/game/b.bin").unwrap(), b"b");
+    }
+
+    #[test]
+    fn mem_fs_apply_reports_bytes_written() {
+        let fs = MemFs::new();
+        fs.insert("/patch/files/a.bin", "aaaa");
+        fs.insert("/patch/files/b.bin", "bb");
+        fs.insert("/patch/files/c.bin", "c");
+
+        let sized = |name: &str, content: &[u8]| match mem_add(name, content) {
+            ManifestEntry::Add { file, final_hash, original_hash, critical, label, mode, mtime, compressed, .. } => ManifestEntry::Add {
+                file,
+                final_hash,
+                original_hash,
+                size: Some(content.len() as u64),
+                critical,
+                label,
+                mode,
+                mtime,
+                compressed,
+            },
+            _ => unreachable!(),
+        };
+        // c.bin has no recorded size, so it counts toward neither figure
+        let entries = vec![sized("a.bin", b"aaaa"), mem_add("c.bin", b"c"), sized("b.bin", b"bb")];
+        let mut reports = Vec::new();
+        apply_entries_with(
+            &fs,
+            &entries,
+            Path::new("/game"),
+            Path::new("/patch"),
+            Path::new("/game/.patch-backup"),
+            Verification::Full,
+            Algorithm::Sha256,
+            None::<fn(Progress)>,
+            Some(|bytes: ByteProgress| reports.push((bytes.done, bytes.total))),
+        )
+        .unwrap();
+
+        assert_eq!(reports, vec![(4, 6), (4, 6), (6, 6)]);
+    }
+
+    #[test]
+    fn mem_fs_apply_sets_recorded_mode() {
+        let fs = MemFs::new();
+        fs.insert("/patch/files/game", "elf");
+        fs.insert("/patch/files/readme.txt", "text");
+
+        let entries = vec![
+            ManifestEntry::Add {
+                file: "game".to_string(),
+                final_hash: hash_bytes(b"elf"),
+                original_hash: None,
+                size: None,
+                critical: false,
+                label: None,
+                mode: Some(0o755),
+                mtime: None,
+                compressed: false,
+            },
+            mem_add("readme.txt", b"text"),
+        ];
+        apply_entries_with(
+            &fs,
+            &entries,
+            Path::new("/game"),
+            Path::new("/patch"),
+            Path::new("/game/.patch-backup"),
+            Verification::Full,
+            Algorithm::Sha256,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+        )
+        .unwrap();
+
+        assert_eq!(fs.mode("/game/game"), Some(0o755));
+        assert_eq!(fs.mode("/game/readme.txt"), None);
+    }
+
+    #[test]
+    fn mem_fs_apply_sets_recorded_mtime() {
+        let fs = MemFs::new();
+        fs.insert("/patch/files/strings.txt", "hola");
+        fs.insert("/patch/files/readme.txt", "text");
+
+        let entries = vec![
+            ManifestEntry::Add {
+                file: "strings.txt".to_string(),
+                final_hash: hash_bytes(b"hola"),
+                original_hash: None,
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: Some(1_500_000_000),
+                compressed: false,
+            },
+            mem_add("readme.txt", b"text"),
+        ];
+        apply_entries_with(
+            &fs,
+            &entries,
+            Path::new("/game"),
+            Path::new("/patch"),
+            Path::new("/game/.patch-backup"),
+            Verification::Full,
+            Algorithm::Sha256,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs.mtime("/game/strings.txt"),
+            Some(UNIX_EPOCH + Duration::from_secs(1_500_000_000))
+        );
+        assert_eq!(fs.mtime("/game/readme.txt"), None);
+    }
+
+    #[test]
+    fn mem_fs_apply_decompresses_payloads() {
+        let fs = MemFs::new();
+        let diff = create_diff(b"hello", b"bonjour").unwrap();
+        fs.insert("/game/greeting.txt", "hello");
+        fs.insert("/patch/diffs/greeting.txt.diff.gz", compress(&diff).unwrap());
+        fs.insert("/patch/files/strings.txt.gz", compress(b"hola").unwrap());
+
+        let entries = vec![
+            ManifestEntry::Patch {
+                file: "greeting.txt".to_string(),
+                original_hash: hash_bytes(b"hello"),
+                diff_hash: String::new(),
+                final_hash: hash_bytes(b"bonjour"),
+                final_hashes: vec![],
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: true,
+                line_endings: None,
+                encoding: None,
+            },
+            ManifestEntry::Add {
+                file: "strings.txt".to_string(),
+                final_hash: hash_bytes(b"hola"),
+                original_hash: None,
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: true,
+            },
+        ];
+        apply_entries_with(
+            &fs,
+            &entries,
+            Path::new("/game"),
+            Path::new("/patch"),
+            Path::new("/game/.patch-backup"),
+            Verification::Full,
+            Algorithm::Sha256,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+        )
+        .unwrap();
+
+        assert_eq!(fs.get("/game/greeting.txt").unwrap(), b"bonjour");
+        assert_eq!(fs.get("/game/strings.txt").unwrap(), b"hola");
+    }
+
+    #[test]
+    fn mem_fs_symlinks_are_retargeted_and_rolled_back() {
+        let fs = MemFs::new();
+        fs.insert("/game/engine.cfg", "cfg");
+        fs.symlink(Path::new("engine.cfg"), Path::new("/game/current.cfg")).unwrap();
+
+        let entries = vec![
+            ManifestEntry::Symlink {
+                file: "current.cfg".to_string(),
+                original_target: Some("engine.cfg".to_string()),
+                target: Some("custom.cfg".to_string()),
+            },
+            ManifestEntry::Symlink {
+                file: "lib/libgame.so".to_string(),
+                original_target: None,
+                target: Some("libgame.so.1".to_string()),
+            },
+        ];
+        apply_entries_with(
+            &fs,
+            &entries,
+            Path::new("/game"),
+            Path::new("/patch"),
+            Path::new("/game/.patch-backup"),
+            Verification::Full,
+            Algorithm::Sha256,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+        )
+        .unwrap();
+        assert_eq!(fs.read_link(Path::new("/game/current.cfg")).unwrap(), Path::new("custom.cfg"));
+        assert_eq!(fs.read_link(Path::new("/game/lib/libgame.so")).unwrap(), Path::new("libgame.so.1"));
+
+        let applied: Vec<_> = entries.iter().collect();
+        rollback_with(&fs, &applied, Path::new("/game"), Path::new("/game/.patch-backup"), None::<fn(Progress)>)
+            .unwrap();
+        assert_eq!(fs.read_link(Path::new("/game/current.cfg")).unwrap(), Path::new("engine.cfg"));
+        assert!(!fs.exists(Path::new("/game/lib/libgame.so")));
+    }
+
+    #[test]
+    fn mem_fs_rename_failure_moves_earlier_renames_back() {
+        let fs = MemFs::new();
+        fs.insert("/game/old_a.bin", "a");
+        fs.insert("/game/old_b.bin", "b");
+        fs.fail_writes("/game/b.bin", std::io::ErrorKind::PermissionDenied);
+
+        let rename = |from: &str, to: &str, data: &[u8]| ManifestEntry::Rename {
+            from: from.to_string(),
+            to: to.to_string(),
+            hash: hash_bytes(data),
+        };
+        let entries = vec![rename("old_a.bin", "a.bin", b"a"), rename("old_b.bin", "b.bin", b"b")];
+        let result = apply_entries_with(
+            &fs,
+            &entries,
+            Path::new("/game"),
+            Path::new("/patch"),
+            Path::new("/game/.patch-backup"),
+            Verification::Full,
+            Algorithm::Sha256,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+        );
+
+        assert!(matches!(result, Err(PatchError::ApplyFailed { file, .. }) if file == "b.bin"));
+        assert_eq!(fs.get("/game/old_a.bin").unwrap(), b"a");
+        assert!(fs.get("/game/a.bin").is_none());
+    }
+
+    #[test]
+    fn mem_fs_disk_full_rolls_back_earlier_entries() {
+        let fs = MemFs::new();
+        fs.insert("/patch/files/a.bin", "a");
+        fs.insert("/patch/files/b.bin", "b");
+        fs.fail_writes("/game/b.bin", std::io::ErrorKind::StorageFull);
+
+        let entries = vec![mem_add("a.bin", b"a"), mem_add("b.bin", b"b")];
+        let result = apply_entries_with(
+            &fs,
+            &entries,
+            Path::new("/game"),
+            Path::new("/patch"),
+            Path::new("/game/.patch-backup"),
+            Verification::Full,
+            Algorithm::Sha256,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+        );
+
+        assert!(matches!(result, Err(PatchError::ApplyFailed { file, .. }) if file == "b.bin"));
+        assert!(fs.get("/game/a.bin").is_none());
+    }
+
+    #[test]
+    fn mem_fs_permission_denied_on_read_fails_patch() {
+        let fs = MemFs::new();
+        fs.insert("/game/file.bin", "original");
+        fs.insert("/patch/diffs/file.bin.diff", "diff");
+        fs.fail_reads("/game/file.bin", std::io::ErrorKind::PermissionDenied);
+
+        let entry = ManifestEntry::Patch {
+            file: "file.bin".to_string(),
+            original_hash: "x".to_string(),
+            diff_hash: "y".to_string(),
+            final_hash: "z".to_string(),
+            final_hashes: vec![],
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+            line_endings: None,
+            encoding: None,
+        };
+        let result = apply_entry_with(&fs, &entry, Path::new("/game"), Path::new("/patch"));
+
+        assert!(matches!(result, Err(PatchError::ApplyFailed { reason, .. }) if reason.contains("failed to read original file")));
+        assert_eq!(fs.get("/game/file.bin").unwrap(), b"original");
+    }
+
+    fn parallel_add(file: &str) -> ManifestEntry {
+        ManifestEntry::Add {
+            file: file.to_string(),
+            final_hash: hash_bytes(file.as_bytes()),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+        }
+    }
+
+    #[test]
+    fn parallel_apply_applies_all_entries() {
+        let target_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let files_dir = patch_dir.path().join(FILES_DIR);
+        fs::create_dir_all(&files_dir).unwrap();
+
+        let names: Vec<String> = (0..20).map(|i| format!("file{}.bin", i)).collect();
+        for name in &names {
+            fs::write(files_dir.join(name), name).unwrap();
+        }
+        let entries: Vec<_> = names.iter().map(|n| parallel_add(n)).collect();
+
+        let mut events = 0;
+        apply_entries_parallel(
+            &entries,
+            target_dir.path(),
+            patch_dir.path(),
+            &target_dir.path().join(".patch-backup"),
+            Verification::Full,
+            Algorithm::Sha256,
+            4,
+            Some(|_: Progress| events += 1),
+            None::<fn(ByteProgress)>,
+        )
+        .unwrap();
+
+        assert_eq!(events, 20);
+        for name in &names {
+            assert_eq!(fs::read(target_dir.path().join(name)).unwrap(), name.as_bytes());
+        }
+    }
+
+    #[test]
+    fn parallel_apply_failures_roll_back_once() {
+        let entries: Vec<_> = (0..40).map(|i| parallel_add(&format!("file{}.bin", i))).collect();
+        let rollbacks = AtomicUsize::new(0);
+        let rolled_back = Mutex::new(Vec::new());
+        let applied = Mutex::new(Vec::new());
+
+        let result = apply_in_parallel(
+            &entries,
+            8,
+            |index, entry| {
+                thread::sleep(std::time::Duration::from_millis(5));
+                // Every 5th entry fails, as if the disk filled up
+                if index % 5 == 4 {
+                    return Err(PatchError::ApplyFailed {
+                        file: entry.file().to_string(),
+                        reason: "disk full".to_string(),
+                    }
+                    .into());
+                }
+                applied.lock().unwrap().push(entry.file().to_string());
+                Ok(())
+            },
+            |entries| {
+                rollbacks.fetch_add(1, Ordering::SeqCst);
+                rolled_back
+                    .lock()
+                    .unwrap()
+                    .extend(entries.iter().map(|e| e.file().to_string()));
+                Ok(())
+            },
+        );
+
+        assert!(matches!(result, Err(PatchError::ApplyFailed { reason, .. }) if reason == "disk full"));
+        assert_eq!(rollbacks.load(Ordering::SeqCst), 1);
+
+        // Everything that was applied is rolled back, and nothing else
+        let mut applied = applied.into_inner().unwrap();
+        let mut rolled_back = rolled_back.into_inner().unwrap();
+        applied.sort();
+        rolled_back.sort();
+        assert_eq!(applied, rolled_back);
+        assert!(applied.len() < 32, "workers should stop after the first failure");
+    }
+
+    #[test]
+    fn parallel_verify_failures_roll_back_once() {
+        let target_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let files_dir = patch_dir.path().join(FILES_DIR);
+        fs::create_dir_all(&files_dir).unwrap();
+
+        let entries: Vec<_> = (0..40)
+            .map(|i| {
+                let name = format!("file{}.bin", i);
+                fs::write(files_dir.join(&name), &name).unwrap();
+                let mut entry = parallel_add(&name);
+                // Every 5th file is written but doesn't match its hash
+                if i % 5 == 4
+                    && let ManifestEntry::Add { final_hash, .. } = &mut entry
+                {
+                    *final_hash = hash_bytes(b"something else");
+                }
+                entry
+            })
+            .collect();
+        let backup_dir = target_dir.path().join(".patch-backup");
+        let rollbacks = AtomicUsize::new(0);
+        let rolled_back = Mutex::new(Vec::new());
+
+        let result = apply_entries_parallel_then(
+            &entries,
+            target_dir.path(),
+            patch_dir.path(),
+            Verification::Full,
+            Algorithm::Sha256,
+            8,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+            |applied| {
+                rollbacks.fetch_add(1, Ordering::SeqCst);
+                rolled_back.lock().unwrap().extend(applied.iter().map(|e| e.file().to_string()));
+                rollback_with(&StdFs, applied, target_dir.path(), &backup_dir, None::<fn(Progress)>)
+            },
+        );
+
+        assert!(matches!(result, Err(PatchError::VerificationFailed { .. })), "{:?}", result);
+        assert_eq!(rollbacks.load(Ordering::SeqCst), 1);
+        // The entry that failed verification was written, so it is rolled
+        // back with the rest, and nothing is left behind
+        let rolled_back = rolled_back.into_inner().unwrap();
+        let Err(PatchError::VerificationFailed { file, .. }) = &result else { unreachable!() };
+        assert!(rolled_back.contains(file), "{:?}", rolled_back);
+        let left: Vec<_> = fs::read_dir(target_dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .filter(|name| name != ".patch-backup")
+            .collect();
+        assert!(left.is_empty(), "{:?}", left);
+    }
+
+    #[test]
+    fn parallel_apply_renames_away_before_adding_at_same_path() {
+        let target_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        fs::create_dir_all(patch_dir.path().join(FILES_DIR)).unwrap();
+        fs::write(patch_dir.path().join(FILES_DIR).join("voice.bin"), "voice.bin").unwrap();
+        fs::write(target_dir.path().join("voice.bin"), b"old voice").unwrap();
+
+        let mut entries = vec![ManifestEntry::Rename {
+            from: "voice.bin".to_string(),
+            to: "voice_old.bin".to_string(),
+            hash: hash_bytes(b"old voice"),
+        }];
+        for i in 0..8 {
+            let name = format!("file{}.bin", i);
+            fs::write(patch_dir.path().join(FILES_DIR).join(&name), &name).unwrap();
+            entries.push(parallel_add(&name));
+        }
+        entries.push(parallel_add("voice.bin"));
+
+        let (groups, _) = entry_groups(&entries);
+        assert!(groups.iter().any(|g| g == &vec![0, 9]), "{:?}", groups);
+
+        apply_entries_parallel(
+            &entries,
+            target_dir.path(),
+            patch_dir.path(),
+            &target_dir.path().join(".patch-backup"),
+            Verification::Full,
+            Algorithm::Sha256,
+            8,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+        )
+        .unwrap();
+
+        assert_eq!(fs::read(target_dir.path().join("voice_old.bin")).unwrap(), b"old voice");
+        assert_eq!(fs::read(target_dir.path().join("voice.bin")).unwrap(), b"voice.bin");
+    }
+
+    #[test]
+    fn entries_inside_created_directories_share_its_group() {
+        let entries = vec![
+            ManifestEntry::MkDir { dir: "dlc".to_string() },
+            parallel_add("other.bin"),
+            parallel_add("dlc/maps/level1.bin"),
+            ManifestEntry::RmDir { dir: "old".to_string() },
+        ];
+
+        let (groups, removed_dirs) = entry_groups(&entries);
+        assert_eq!(groups, vec![vec![0, 2], vec![1]]);
+        assert_eq!(removed_dirs, vec![3]);
+    }
+
+    #[test]
+    fn parallel_apply_keeps_same_file_entries_in_order() {
+        let entries = vec![
+            ManifestEntry::Rename {
+                from: "old.bin".to_string(),
+                to: "new.bin".to_string(),
+                hash: "h".to_string(),
+            },
+            parallel_add("other.bin"),
+            ManifestEntry::Patch {
+                file: "new.bin".to_string(),
+                original_hash: "h".to_string(),
+                diff_hash: "d".to_string(),
+                final_hash: "f".to_string(),
+                final_hashes: vec![],
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: false,
+                line_endings: None,
+                encoding: None,
+            },
+        ];
+        let order = Mutex::new(Vec::new());
+
+        apply_in_parallel(
+            &entries,
+            3,
+            |index, _| {
+                order.lock().unwrap().push(index);
+                Ok(())
+            },
+            |_| Ok(()),
+        )
+        .unwrap();
+
+        let order = order.into_inner().unwrap();
+        let position = |i| order.iter().position(|&x| x == i).unwrap();
+        assert!(position(0) < position(2));
+    }
 }
@@ -1,11 +1,16 @@
 //! Backup and rollback operations for patch application.
 
-use std::fs;
+use std::collections::BTreeMap;
+use std::io;
 use std::path::Path;
 
+use crate::patch::apply::replace_symlink_with;
+use crate::patch::constants::{BACKUP_ENTRIES_FILENAME, BACKUP_HASHES_FILENAME};
 use crate::patch::PatchError;
 use crate::patch::{Progress, ProgressAction};
-use crate::utils::file_ops::{backup_file, restore_file};
+use crate::utils::file_ops::{backup_file_with, restore_file_with};
+use crate::utils::filesystem::{FileSystem, StdFs};
+use crate::utils::hash::Algorithm;
 use crate::utils::manifest::ManifestEntry;
 
 /// Backup all files that will be modified or deleted.
@@ -15,52 +20,211 @@ use crate::utils::manifest::ManifestEntry;
 ///
 /// - Patch entries: backs up the original file
 /// - Delete entries: backs up the file (if it exists)
-/// - Add entries: nothing to backup (new files)
+/// - Add entries: nothing to backup (new files), unless they have an
+///   `original_hash` (upserts) and the file is already there
+/// - Rename entries: nothing to backup, unless a later Patch entry modifies the
+///   renamed file; then the original is backed up under its new name
+/// - MkDir and RmDir entries: nothing to backup
+/// - Symlink entries: nothing to backup (the manifest records the original
+///   target)
+///
+/// The hash (with `algorithm`) of every file copied is recorded in
+/// `BACKUP_HASHES_FILENAME`, alongside any recorded by an earlier run, so
+/// `validate_backup` can tell a damaged or missing backup from a good one.
+/// `entries` themselves are recorded in `BACKUP_ENTRIES_FILENAME`, after any
+/// an earlier run recorded, so a rollback undoes exactly what was applied
+/// (see `applied_entries`).
 pub fn backup_entries<F>(
     entries: &[ManifestEntry],
     target_dir: &Path,
     backup_dir: &Path,
+    algorithm: Algorithm,
+    on_progress: Option<F>,
+) -> Result<(), PatchError>
+where
+    F: FnMut(Progress),
+{
+    backup_entries_with(&StdFs, entries, target_dir, backup_dir, algorithm, on_progress)
+}
+
+/// Like `backup_entries`, using the given filesystem.
+pub fn backup_entries_with<F>(
+    fs: &impl FileSystem,
+    entries: &[ManifestEntry],
+    target_dir: &Path,
+    backup_dir: &Path,
+    algorithm: Algorithm,
     mut on_progress: Option<F>,
 ) -> Result<(), PatchError>
 where
     F: FnMut(Progress),
 {
+    let index_failed = |e: io::Error| PatchError::BackupFailed {
+        file: BACKUP_HASHES_FILENAME.to_string(),
+        reason: e.to_string(),
+    };
+    let mut hashes = load_backup_hashes(fs, backup_dir).map_err(index_failed)?;
+    let mut recorded = load_backup_entries(fs, backup_dir)
+        .map_err(|e| PatchError::BackupFailed {
+            file: BACKUP_ENTRIES_FILENAME.to_string(),
+            reason: e.to_string(),
+        })?
+        .unwrap_or_default();
+    for entry in entries {
+        if !recorded.contains(entry) {
+            recorded.push(entry.clone());
+        }
+    }
+    save_backup_entries(fs, backup_dir, &recorded).map_err(|e| PatchError::BackupFailed {
+        file: BACKUP_ENTRIES_FILENAME.to_string(),
+        reason: e.to_string(),
+    })?;
+
     let total = entries.len();
     for (index, entry) in entries.iter().enumerate() {
         let action = match entry {
-            ManifestEntry::Patch { .. } | ManifestEntry::Delete { .. } => ProgressAction::BackingUp,
-            ManifestEntry::Add { .. } => ProgressAction::Skipping,
+            ManifestEntry::Patch { .. }
+            | ManifestEntry::Delete { .. }
+            | ManifestEntry::Add { original_hash: Some(_), .. } => ProgressAction::BackingUp,
+            ManifestEntry::Add { .. }
+            | ManifestEntry::Symlink { .. }
+            | ManifestEntry::MkDir { .. }
+            | ManifestEntry::RmDir { .. } => ProgressAction::Skipping,
+            ManifestEntry::Rename { to, .. } if is_patched_later(entries, index, to) => {
+                ProgressAction::BackingUp
+            }
+            ManifestEntry::Rename { .. } => ProgressAction::Skipping,
         };
 
         if let Some(ref mut callback) = on_progress {
             callback(Progress {
                 file: entry.file(),
+                label: entry.label(),
                 index,
                 total,
                 action,
             });
         }
         match entry {
-            ManifestEntry::Patch { file, .. } | ManifestEntry::Delete { file, .. } => {
+            ManifestEntry::Patch { file, .. }
+            | ManifestEntry::Delete { file, .. }
+            | ManifestEntry::Add {
+                file,
+                original_hash: Some(_),
+                ..
+            } => {
                 let target_path = target_dir.join(file);
 
-                // Only backup if file exists (delete entries may already be gone)
-                if target_path.exists() {
-                    backup_file(&target_path, backup_dir).map_err(|e| PatchError::BackupFailed {
-                        file: file.clone(),
-                        reason: e.to_string(),
+                // Only backup if file exists (delete entries may already be
+                // gone, and upserts may be creating it)
+                if fs.exists(&target_path) {
+                    let hash = backup_file_with(fs, target_dir, file, backup_dir, algorithm).map_err(|e| {
+                        PatchError::BackupFailed {
+                            file: file.clone(),
+                            reason: e.to_string(),
+                        }
                     })?;
+                    hashes.insert(file.clone(), hash);
                 }
             }
-            ManifestEntry::Add { .. } => {
-                // Nothing to backup for new files
+            ManifestEntry::Add { .. }
+            | ManifestEntry::Symlink { .. }
+            | ManifestEntry::MkDir { .. }
+            | ManifestEntry::RmDir { .. } => {
+                // Nothing to backup for new files, symlinks or directories
+            }
+            ManifestEntry::Rename { from, to, .. } => {
+                // The Patch entry's backup is taken before anything is
+                // renamed, so it would miss the file. Back it up here instead,
+                // under the name the Patch entry restores from.
+                if action == ProgressAction::BackingUp {
+                    let hash = backup_renamed_with(fs, &target_dir.join(from), to, backup_dir, algorithm).map_err(|e| {
+                        PatchError::BackupFailed {
+                            file: to.clone(),
+                            reason: e.to_string(),
+                        }
+                    })?;
+                    hashes.insert(to.clone(), hash);
+                }
             }
         }
     }
 
+    if !hashes.is_empty() {
+        save_backup_hashes(fs, backup_dir, &hashes).map_err(index_failed)?;
+    }
+
     Ok(())
 }
 
+/// The hashes recorded for the files in `backup_dir`, keyed by path; empty if
+/// none were recorded (no backup yet, or one made by an older graft).
+pub(crate) fn load_backup_hashes(fs: &impl FileSystem, backup_dir: &Path) -> io::Result<BTreeMap<String, String>> {
+    let path = backup_dir.join(BACKUP_HASHES_FILENAME);
+    if !fs.exists(&path) {
+        return Ok(BTreeMap::new());
+    }
+    serde_json::from_slice(&fs.read(&path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn save_backup_hashes(fs: &impl FileSystem, backup_dir: &Path, hashes: &BTreeMap<String, String>) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(hashes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs.create_dir_all(backup_dir)?;
+    fs.write_atomic(&backup_dir.join(BACKUP_HASHES_FILENAME), &json)
+}
+
+/// The entries `backup_entries` recorded in `backup_dir`, in the order they
+/// were backed up and applied, or `None` if the backup was made by an older
+/// graft that didn't record them.
+///
+/// These are what a rollback should undo: entries left out at apply time
+/// (matched by the target's `.graftignore`, say, or already applied) were
+/// never backed up, whatever the target looks like now.
+pub fn applied_entries(backup_dir: &Path) -> Result<Option<Vec<ManifestEntry>>, PatchError> {
+    load_backup_entries(&StdFs, backup_dir).map_err(|e| PatchError::RollbackFailed {
+        reason: format!("failed to read {}: {}", BACKUP_ENTRIES_FILENAME, e),
+    })
+}
+
+fn load_backup_entries(fs: &impl FileSystem, backup_dir: &Path) -> io::Result<Option<Vec<ManifestEntry>>> {
+    let path = backup_dir.join(BACKUP_ENTRIES_FILENAME);
+    if !fs.exists(&path) {
+        return Ok(None);
+    }
+    serde_json::from_slice(&fs.read(&path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn save_backup_entries(fs: &impl FileSystem, backup_dir: &Path, entries: &[ManifestEntry]) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(entries).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs.create_dir_all(backup_dir)?;
+    fs.write_atomic(&backup_dir.join(BACKUP_ENTRIES_FILENAME), &json)
+}
+
+/// Whether an entry after `index` patches `file`.
+fn is_patched_later(entries: &[ManifestEntry], index: usize, file: &str) -> bool {
+    entries[index + 1..]
+        .iter()
+        .any(|e| matches!(e, ManifestEntry::Patch { file: f, .. } if f == file))
+}
+
+/// Copy `source` into the backup directory under the path of `name`,
+/// returning the hash of what was copied.
+fn backup_renamed_with(
+    fs: &impl FileSystem,
+    source: &Path,
+    name: &str,
+    backup_dir: &Path,
+    algorithm: Algorithm,
+) -> io::Result<String> {
+    let backup_path = backup_dir.join(name);
+    if let Some(parent) = backup_path.parent() {
+        fs.create_dir_all(parent)?;
+    }
+    let hash = fs.hash(source, algorithm)?;
+    fs.copy(source, &backup_path)?;
+    Ok(hash)
+}
+
 /// Rollback applied changes by restoring from backup and removing added files.
 ///
 /// This should be called when an error occurs during patch application to
@@ -68,8 +232,31 @@ where
 ///
 /// - Patch entries: restores the original file from backup
 /// - Delete entries: restores the file from backup (if backup exists)
-/// - Add entries: removes the newly added file
+/// - Add entries: removes the newly added file, or for an upsert that
+///   replaced a file, restores it from backup
+/// - Rename entries: moves the file back to its original name
+/// - Symlink entries: points the link back at its original target, or
+///   removes it if it didn't exist
+/// - MkDir entries: removes the created directory
+/// - RmDir entries: recreates the removed directory
+///
+/// Entries are undone in reverse order, so a file that was renamed and then
+/// patched is restored before it is moved back.
 pub fn rollback<F>(
+    applied: &[&ManifestEntry],
+    target_dir: &Path,
+    backup_dir: &Path,
+    on_progress: Option<F>,
+) -> Result<(), PatchError>
+where
+    F: FnMut(Progress),
+{
+    rollback_with(&StdFs, applied, target_dir, backup_dir, on_progress)
+}
+
+/// Like `rollback`, using the given filesystem.
+pub fn rollback_with<F>(
+    fs: &impl FileSystem,
     applied: &[&ManifestEntry],
     target_dir: &Path,
     backup_dir: &Path,
@@ -79,16 +266,24 @@ where
     F: FnMut(Progress),
 {
     let total = applied.len();
-    for (index, entry) in applied.iter().enumerate() {
+    for (index, entry) in applied.iter().rev().enumerate() {
         let action = match entry {
             ManifestEntry::Patch { .. } => ProgressAction::Restoring,
+            ManifestEntry::Add { file, original_hash: Some(_), .. } if fs.exists(&backup_dir.join(file)) => {
+                ProgressAction::Restoring
+            }
             ManifestEntry::Add { .. } => ProgressAction::Removing,
             ManifestEntry::Delete { .. } => ProgressAction::Restoring,
+            ManifestEntry::Rename { .. } => ProgressAction::Renaming,
+            ManifestEntry::Symlink { .. } => ProgressAction::Restoring,
+            ManifestEntry::MkDir { .. } => ProgressAction::Removing,
+            ManifestEntry::RmDir { .. } => ProgressAction::Restoring,
         };
 
         if let Some(ref mut callback) = on_progress {
             callback(Progress {
                 file: entry.file(),
+                label: entry.label(),
                 index,
                 total,
                 action,
@@ -97,32 +292,75 @@ where
         match entry {
             ManifestEntry::Patch { file, .. } => {
                 // Patch entries always have backups (validated to exist)
-                let target_path = target_dir.join(file);
-                restore_file(&target_path, backup_dir).map_err(|e| PatchError::RollbackFailed {
+                restore_file_with(fs, target_dir, file, backup_dir).map_err(|e| PatchError::RollbackFailed {
                     reason: format!("failed to restore '{}': {}", file, e),
                 })?;
             }
             ManifestEntry::Delete { file, .. } => {
                 // Only restore if we have a backup (file existed before patch)
                 let backup_path = backup_dir.join(file);
-                if backup_path.exists() {
-                    let target_path = target_dir.join(file);
-                    restore_file(&target_path, backup_dir).map_err(|e| {
+                if fs.exists(&backup_path) {
+                    restore_file_with(fs, target_dir, file, backup_dir).map_err(|e| {
                         PatchError::RollbackFailed {
                             reason: format!("failed to restore '{}': {}", file, e),
                         }
                     })?;
                 }
             }
+            ManifestEntry::Add {
+                file,
+                original_hash: Some(_),
+                ..
+            } if fs.exists(&backup_dir.join(file)) => {
+                // An upsert that replaced a file: put the original back
+                restore_file_with(fs, target_dir, file, backup_dir).map_err(|e| PatchError::RollbackFailed {
+                    reason: format!("failed to restore '{}': {}", file, e),
+                })?;
+            }
             ManifestEntry::Add { file, .. } => {
                 // Remove the newly added file
                 let target_path = target_dir.join(file);
-                if target_path.exists() {
-                    fs::remove_file(&target_path).map_err(|e| PatchError::RollbackFailed {
+                if fs.exists(&target_path) {
+                    fs.remove_file(&target_path).map_err(|e| PatchError::RollbackFailed {
                         reason: format!("failed to remove added file '{}': {}", file, e),
                     })?;
                 }
             }
+            ManifestEntry::Rename { from, to, .. } => {
+                let target_path = target_dir.join(to);
+                if fs.exists(&target_path) {
+                    fs.rename(&target_path, &target_dir.join(from)).map_err(|e| {
+                        PatchError::RollbackFailed {
+                            reason: format!("failed to rename '{}' back to '{}': {}", to, from, e),
+                        }
+                    })?;
+                }
+            }
+            ManifestEntry::Symlink {
+                file,
+                original_target,
+                ..
+            } => {
+                replace_symlink_with(fs, &target_dir.join(file), original_target.as_deref()).map_err(|e| {
+                    PatchError::RollbackFailed {
+                        reason: format!("failed to restore symlink '{}': {}", file, e),
+                    }
+                })?;
+            }
+            ManifestEntry::MkDir { dir } => {
+                // Files rolled back before this have emptied it again
+                let target_path = target_dir.join(dir);
+                if fs.is_dir(&target_path) {
+                    fs.remove_dir(&target_path).map_err(|e| PatchError::RollbackFailed {
+                        reason: format!("failed to remove created directory '{}': {}", dir, e),
+                    })?;
+                }
+            }
+            ManifestEntry::RmDir { dir } => {
+                fs.create_dir_all(&target_dir.join(dir)).map_err(|e| PatchError::RollbackFailed {
+                    reason: format!("failed to recreate directory '{}': {}", dir, e),
+                })?;
+            }
         }
     }
 
@@ -4,11 +4,25 @@ pub const DIFFS_DIR: &str = "diffs";
 pub const FILES_DIR: &str = "files";
 /// File extension for diff files
 pub const DIFF_EXTENSION: &str = ".diff";
+/// Extension appended to gzip-compressed diffs and added files
+pub const COMPRESSED_EXTENSION: &str = ".gz";
 /// Filename for the manifest
 pub const MANIFEST_FILENAME: &str = "manifest.json";
 /// Directory name for backups during patch application
 pub const BACKUP_DIR: &str = ".patch-backup";
+/// Filename of the lock held in the target directory while a patch is applied
+pub const LOCK_FILENAME: &str = ".patch-lock";
 /// Directory name for patcher assets (icons, etc.)
 pub const ASSETS_DIR: &str = ".graft_assets";
 /// Filename for the patcher icon
 pub const ICON_FILENAME: &str = "icon.png";
+/// Filename for ignore patterns (modified dir at create time, target dir at apply time)
+pub const IGNORE_FILENAME: &str = ".graftignore";
+/// Filename for the archive signature, next to the manifest
+pub const SIGNATURE_FILENAME: &str = "manifest.sig";
+/// Filename for the archive checksum, next to the manifest
+pub const CHECKSUM_FILENAME: &str = "manifest.sha256";
+/// Filename, inside the backup directory, recording the hash of each backed-up file
+pub const BACKUP_HASHES_FILENAME: &str = ".graft-backup-hashes.json";
+/// Filename, inside the backup directory, recording the entries backed up for applying
+pub const BACKUP_ENTRIES_FILENAME: &str = ".graft-backup-entries.json";
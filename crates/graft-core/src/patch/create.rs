@@ -0,0 +1,1169 @@
+//! Creating patches by comparing an original and a modified directory.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::patch::constants::{DIFFS_DIR, FILES_DIR, MANIFEST_FILENAME};
+use crate::patch::payload::{added_name, compress, diff_name};
+use crate::patch::{Progress, ProgressAction};
+use crate::utils::diff::create_diff;
+use crate::utils::dir_scan::{
+    load_changes, pair_renames, save_changes, scan_summary_by_content, scan_summary_subset,
+    FileChange, ScanSummary,
+};
+use crate::utils::hash::Algorithm;
+use crate::utils::line_endings::{is_text_file, LineEnding};
+use crate::utils::text_encoding::TextEncoding;
+use crate::utils::manifest::{Manifest, ManifestEntry, VersionFile};
+
+/// Manifest settings for a created patch.
+///
+/// `version_file` names a file in the original directory (e.g. the game's
+/// executable) whose hash is recorded, so the patcher can tell whether the
+/// target is the version the patch was made from. The patch must leave the
+/// file unchanged.
+///
+/// If `allow_restricted` is true, the resulting manifest will allow patching
+/// restricted paths (system directories, executables). Default is false for security.
+///
+/// A file removed from the original directory and one added to the modified
+/// directory with the same content always become a single Rename entry, so
+/// moved assets aren't shipped again. If `match_by_content` is true, the
+/// patcher also looks up patched files missing from the target by their
+/// original hash.
+///
+/// If `case_insensitive` is true, paths that differ only in case are treated
+/// as the same file, as on Windows, so renaming `Data.bin` to `data.bin`
+/// doesn't produce a delete and an add. Use it for patches meant for
+/// case-insensitive targets.
+///
+/// `hash_algorithm` is used for every hash in the manifest and is recorded
+/// in it, so the patcher computes the same one.
+///
+/// If `preserve_mtime` is true, patched and added files record their
+/// modification time in the modified directory, and the patcher gives them
+/// that time instead of the time they were written.
+///
+/// If `compress_payloads` is true, each diff and added file is stored
+/// gzip-compressed (`.gz`), which shrinks patches hosted file by file rather
+/// than as an archive.
+///
+/// Modified files with one of `text_extensions` (e.g. `txt`, `json`, `ini`)
+/// are diffed in text mode: the modified file is converted to the original's
+/// line endings first, so a file that only gained CRLFs isn't patched at all
+/// and one with real edits only diffs those. Empty turns text mode off.
+///
+/// Text-mode files are also converted to the original's character encoding:
+/// the one its byte order mark gives (UTF-16), or else `text_encoding` (a
+/// label such as `shift_jis`) if set. The modified file is read by its own
+/// byte order mark, as UTF-8 if it's valid UTF-8 without NUL bytes, or as
+/// already in the original's encoding otherwise. Entries converted this way
+/// record the encoding, and creating the patch fails if a modified file has
+/// characters the encoding can't represent.
+///
+/// If `files` is set, only the files at those relative paths are compared
+/// (see `categorize_files_subset`), instead of every file in both
+/// directories, so a patch for a few known files is quick to create on a
+/// large install and can't pick up unrelated changes.
+///
+/// `labels` gives patched and added files a label to show players instead
+/// of the file name while the patch applies, by relative path.
+///
+/// Modified files listed in `upsert` (by relative path) are stored whole and
+/// recorded as Add entries with their original's hash, so the patcher
+/// creates them on installs that are missing them and replaces the original
+/// on the rest, instead of refusing to patch a missing file.
+///
+/// If `manifest_only` is true, only manifest.json is written: diffs are
+/// still computed for their hashes, but neither they nor added files are
+/// stored. The manifest can't be applied, but can be shipped on its own to
+/// check an install against with `verify_entry`.
+#[derive(Debug, Clone, Copy)]
+pub struct CreateOptions<'a> {
+    pub version: u32,
+    pub name: &'a str,
+    pub title: Option<&'a str>,
+    pub description: Option<&'a str>,
+    pub author: Option<&'a str>,
+    pub target_game_version: Option<&'a str>,
+    pub version_file: Option<&'a str>,
+    pub allow_restricted: bool,
+    pub match_by_content: bool,
+    pub case_insensitive: bool,
+    pub hash_algorithm: Algorithm,
+    pub preserve_mtime: bool,
+    pub compress_payloads: bool,
+    pub text_extensions: &'a [String],
+    pub text_encoding: Option<&'a str>,
+    pub files: Option<&'a [String]>,
+    pub labels: Option<&'a BTreeMap<String, String>>,
+    pub upsert: &'a [String],
+    pub manifest_only: bool,
+}
+
+impl<'a> CreateOptions<'a> {
+    /// Options for patch `version` of `name`, with everything else off or
+    /// empty and SHA-256 for hashing.
+    pub const fn new(version: u32, name: &'a str) -> Self {
+        CreateOptions {
+            version,
+            name,
+            title: None,
+            description: None,
+            author: None,
+            target_game_version: None,
+            version_file: None,
+            allow_restricted: false,
+            match_by_content: false,
+            case_insensitive: false,
+            hash_algorithm: Algorithm::Sha256,
+            preserve_mtime: false,
+            compress_payloads: false,
+            text_extensions: &[],
+            text_encoding: None,
+            files: None,
+            labels: None,
+            upsert: &[],
+            manifest_only: false,
+        }
+    }
+}
+
+/// Compare two directories, skipping files matched by either directory's
+/// `.graftignore`, and comparing paths without case if `case_insensitive`,
+/// or only the `files` listed if given. Removed and added files with the
+/// same content are paired up as renames.
+///
+/// Modified files with one of `text_extensions` are compared, and their
+/// final hash taken, after converting them to the original's line endings
+/// and encoding (`text_encoding` for originals without a byte order mark);
+/// those that then match the original are counted as unchanged.
+fn scan_changes(
+    orig_dir: &Path,
+    new_dir: &Path,
+    case_insensitive: bool,
+    algorithm: Algorithm,
+    text_extensions: &[String],
+    text_encoding: Option<&str>,
+    files: Option<&[String]>,
+) -> io::Result<ScanSummary> {
+    let text_encoding = text_encoding.map(TextEncoding::for_label).transpose()?;
+    let mut summary = match files {
+        Some(files) => pair_renames(scan_summary_subset(orig_dir, new_dir, files, algorithm)?),
+        None => scan_summary_by_content(orig_dir, new_dir, algorithm, case_insensitive)?,
+    };
+
+    let mut kept = Vec::with_capacity(summary.changes.len());
+    for mut change in std::mem::take(&mut summary.changes) {
+        if let FileChange::Diff { file, original_hash, final_hash, new_file, .. } = &mut change
+            && is_text_file(file, text_extensions)
+            && let Some(text) = text_mode_data(
+                &orig_dir.join(&*file),
+                &new_dir.join(new_file.as_deref().unwrap_or(file)),
+                text_encoding,
+            )?
+        {
+            let hash = algorithm.hash_bytes(&text.data);
+            if &hash == original_hash {
+                summary.unchanged.push(std::mem::take(file));
+                continue;
+            }
+            *final_hash = hash;
+        }
+        kept.push(change);
+    }
+    summary.changes = kept;
+    summary.unchanged.sort();
+    Ok(summary)
+}
+
+/// A modified file converted in text mode, with what it was converted to.
+struct TextModeData {
+    line_endings: Option<LineEnding>,
+    encoding: Option<String>,
+    data: Vec<u8>,
+}
+
+/// The modified file converted to the original's line endings and encoding
+/// (see `CreateOptions`), `fallback` being the encoding of originals without
+/// a byte order mark; `None` if the original is UTF-8 (or has no encoding to
+/// go by) and has no line breaks.
+fn text_mode_data(
+    orig_path: &Path,
+    new_path: &Path,
+    fallback: Option<TextEncoding>,
+) -> io::Result<Option<TextModeData>> {
+    let orig_data = fs::read(orig_path)?;
+    let new_data = fs::read(new_path)?;
+    let with_path = |path: &Path, e: io::Error| io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+
+    let Some(encoding) = TextEncoding::detect(&orig_data, fallback).filter(|e| !e.is_utf8()) else {
+        let Some(line_ending) = LineEnding::detect(&orig_data) else {
+            return Ok(None);
+        };
+        return Ok(Some(TextModeData {
+            line_endings: Some(line_ending),
+            encoding: None,
+            data: line_ending.apply(&new_data),
+        }));
+    };
+
+    let orig_text = encoding.decode(&orig_data).map_err(|e| with_path(orig_path, e))?;
+    let new_text = match TextEncoding::detect(&new_data, None) {
+        Some(own) => own.decode(&new_data),
+        None => match std::str::from_utf8(&new_data) {
+            Ok(text) if !text.contains('\0') => Ok(text.to_string()),
+            _ => encoding.decode(&new_data),
+        },
+    }
+    .map_err(|e| with_path(new_path, e))?;
+
+    let line_endings = LineEnding::detect(orig_text.as_bytes());
+    let new_text = match line_endings {
+        // Only line breaks change, so the text stays valid UTF-8
+        Some(line_ending) => String::from_utf8_lossy(&line_ending.apply(new_text.as_bytes())).into_owned(),
+        None => new_text,
+    };
+    Ok(Some(TextModeData {
+        line_endings,
+        encoding: Some(encoding.name().to_string()),
+        data: encoding.encode(&new_text).map_err(|e| with_path(new_path, e))?,
+    }))
+}
+
+/// Detect changes between two directories without creating a patch.
+/// Writes the list of changes as JSON to `changes_path` so it can be
+/// reviewed and later passed to `create_approved`.
+///
+/// `case_insensitive`, `algorithm`, `text_extensions` and `text_encoding`
+/// must match the options later used to create the patch.
+///
+/// Returns the scan summary: the changes written, the files found
+/// identical, and how many files were compared.
+pub fn detect_changes(
+    orig_dir: &Path,
+    new_dir: &Path,
+    changes_path: &Path,
+    case_insensitive: bool,
+    algorithm: Algorithm,
+    text_extensions: &[String],
+    text_encoding: Option<&str>,
+) -> io::Result<ScanSummary> {
+    let summary = scan_changes(orig_dir, new_dir, case_insensitive, algorithm, text_extensions, text_encoding, None)?;
+    save_changes(&summary.changes, changes_path)?;
+    Ok(summary)
+}
+
+/// Create a patch from two directories.
+/// Outputs a patch directory containing manifest.json, diffs/, and files/,
+/// and returns the manifest written.
+///
+/// Files matching a `.graftignore` in `orig_dir` or `new_dir` are left out of the patch.
+///
+/// `on_progress` is called once per change as it is written (diffed, copied
+/// or recorded in the manifest).
+pub fn create<F>(
+    orig_dir: &Path,
+    new_dir: &Path,
+    output_dir: &Path,
+    options: &CreateOptions,
+    on_progress: Option<F>,
+) -> io::Result<Manifest>
+where
+    F: FnMut(Progress),
+{
+    let changes = scan_changes(
+        orig_dir,
+        new_dir,
+        options.case_insensitive,
+        options.hash_algorithm,
+        options.text_extensions,
+        options.text_encoding,
+        options.files,
+    )?
+    .changes;
+    write_patch(changes, orig_dir, new_dir, output_dir, options, on_progress)
+}
+
+/// Create a patch containing only the changes listed in `changes_path`
+/// (as written by `detect_changes`, possibly with rejected changes removed).
+///
+/// The directories are rescanned and every approved change must still match
+/// exactly; if a file changed since review the patch is not created.
+/// Progress is reported as in `create`.
+pub fn create_approved<F>(
+    orig_dir: &Path,
+    new_dir: &Path,
+    output_dir: &Path,
+    changes_path: &Path,
+    options: &CreateOptions,
+    on_progress: Option<F>,
+) -> io::Result<Manifest>
+where
+    F: FnMut(Progress),
+{
+    let approved = load_changes(changes_path)?;
+    let current = scan_changes(
+        orig_dir,
+        new_dir,
+        options.case_insensitive,
+        options.hash_algorithm,
+        options.text_extensions,
+        options.text_encoding,
+        options.files,
+    )?
+    .changes;
+
+    if let Some(stale) = approved.iter().find(|c| !current.contains(c)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "approved change for {} no longer matches the directories",
+                stale.file()
+            ),
+        ));
+    }
+
+    write_patch(approved, orig_dir, new_dir, output_dir, options, on_progress)
+}
+
+/// Modification time of `file` in `new_dir`, in seconds since the Unix
+/// epoch, if `options` asks for it to be preserved.
+fn recorded_mtime(new_dir: &Path, file: &str, options: &CreateOptions) -> io::Result<Option<i64>> {
+    if !options.preserve_mtime {
+        return Ok(None);
+    }
+    let modified = fs::metadata(new_dir.join(file))?.modified()?;
+    Ok(modified.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs() as i64))
+}
+
+/// Create the directory `path` goes in, for files in subdirectories.
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+/// Materialize a list of changes into a patch directory.
+fn write_patch<F>(
+    changes: Vec<FileChange>,
+    orig_dir: &Path,
+    new_dir: &Path,
+    output_dir: &Path,
+    options: &CreateOptions,
+    mut on_progress: Option<F>,
+) -> io::Result<Manifest>
+where
+    F: FnMut(Progress),
+{
+    let version_file = match options.version_file {
+        Some(file) if changes.iter().any(|c| c.file() == file) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("version file {} is changed by the patch; pick a file it leaves alone", file),
+            ));
+        }
+        Some(file) => Some(VersionFile {
+            file: file.to_string(),
+            hash: options.hash_algorithm.hash_file(&orig_dir.join(file))?,
+            known_versions: Default::default(),
+        }),
+        None => None,
+    };
+
+    // Create output directory structure
+    fs::create_dir_all(output_dir)?;
+    let diffs_dir = output_dir.join(DIFFS_DIR);
+    let files_dir = output_dir.join(FILES_DIR);
+
+    // Only create subdirs if we need them
+    let is_upsert = |c: &FileChange| matches!(c, FileChange::Diff { file, .. } if options.upsert.contains(file));
+    let has_diffs = !options.manifest_only && changes.iter().any(|c| matches!(c, FileChange::Diff { .. }) && !is_upsert(c));
+    let has_new = !options.manifest_only && changes.iter().any(|c| matches!(c, FileChange::New { .. }) || is_upsert(c));
+
+    if has_diffs {
+        fs::create_dir_all(&diffs_dir)?;
+    }
+    if has_new {
+        fs::create_dir_all(&files_dir)?;
+    }
+
+    let mut manifest = Manifest::new(
+        options.version,
+        options.name.to_string(),
+        options.title.map(|s| s.to_string()),
+    );
+    manifest.description = options.description.map(|s| s.to_string());
+    manifest.author = options.author.map(|s| s.to_string());
+    manifest.target_game_version = options.target_game_version.map(|s| s.to_string());
+    manifest.version_file = version_file;
+    manifest.allow_restricted = options.allow_restricted;
+    manifest.match_by_content = options.match_by_content;
+    manifest.hash_algorithm = options.hash_algorithm;
+
+    let text_encoding = options.text_encoding.map(TextEncoding::for_label).transpose()?;
+    let total = changes.len();
+    for (index, change) in changes.into_iter().enumerate() {
+        if let Some(ref mut callback) = on_progress {
+            callback(Progress {
+                file: change.file(),
+                label: None,
+                index,
+                total,
+                action: match change {
+                    FileChange::Diff { ref file, .. } if options.upsert.contains(file) => ProgressAction::Copying,
+                    FileChange::Diff { .. } => ProgressAction::Diffing,
+                    FileChange::New { .. } => ProgressAction::Copying,
+                    FileChange::Old { .. }
+                    | FileChange::Rename { .. }
+                    | FileChange::Symlink { .. }
+                    | FileChange::MkDir { .. }
+                    | FileChange::RmDir { .. } => ProgressAction::Recording,
+                },
+            });
+        }
+
+        let entry = match change {
+            FileChange::Diff {
+                file,
+                original_hash,
+                final_hash,
+                mode,
+                new_file,
+            } => {
+                // Read files and create diff
+                let new_file = new_file.as_deref().unwrap_or(&file);
+                let text = if is_text_file(&file, options.text_extensions) {
+                    text_mode_data(&orig_dir.join(&file), &new_dir.join(new_file), text_encoding)?
+                } else {
+                    None
+                };
+                let (line_endings, encoding, new_data) = match text {
+                    Some(text) => (text.line_endings, text.encoding, text.data),
+                    None => (None, None, fs::read(new_dir.join(new_file))?),
+                };
+                let mtime = recorded_mtime(new_dir, new_file, options)?;
+                let label = options.labels.and_then(|labels| labels.get(&file)).cloned();
+                let compressed = options.compress_payloads;
+
+                if options.upsert.contains(&file) {
+                    // Stored whole, to be written whether or not the
+                    // original is there
+                    if !options.manifest_only {
+                        let dest_path = output_dir.join(added_name(&file, compressed));
+                        create_parent(&dest_path)?;
+                        fs::write(&dest_path, if compressed { compress(&new_data)? } else { new_data.clone() })?;
+                    }
+                    ManifestEntry::Add {
+                        file,
+                        final_hash,
+                        original_hash: Some(original_hash),
+                        size: Some(new_data.len() as u64),
+                        critical: false,
+                        label,
+                        mode,
+                        mtime,
+                        compressed,
+                    }
+                } else {
+                    let orig_data = fs::read(orig_dir.join(&file))?;
+                    let diff_data = create_diff(&orig_data, &new_data)?;
+
+                    // Write diff file
+                    let diff_data = if compressed { compress(&diff_data)? } else { diff_data };
+                    if !options.manifest_only {
+                        let diff_path = output_dir.join(diff_name(&file, compressed));
+                        create_parent(&diff_path)?;
+                        fs::write(&diff_path, &diff_data)?;
+                    }
+
+                    // Compute diff hash
+                    let diff_hash = options.hash_algorithm.hash_bytes(&diff_data);
+
+                    ManifestEntry::Patch {
+                        file,
+                        original_hash,
+                        diff_hash,
+                        final_hash,
+                        final_hashes: vec![],
+                        size: Some(new_data.len() as u64),
+                        critical: false,
+                        label,
+                        mode,
+                        mtime,
+                        compressed,
+                        line_endings,
+                        encoding,
+                    }
+                }
+            }
+            FileChange::New { file, final_hash, mode } => {
+                let mtime = recorded_mtime(new_dir, &file, options)?;
+                let label = options.labels.and_then(|labels| labels.get(&file)).cloned();
+                // Copy new file to files/
+                let compressed = options.compress_payloads;
+                let src_path = new_dir.join(&file);
+                let dest_path = output_dir.join(added_name(&file, compressed));
+                let size = if options.manifest_only {
+                    fs::metadata(&src_path)?.len()
+                } else if compressed {
+                    let data = fs::read(&src_path)?;
+                    create_parent(&dest_path)?;
+                    fs::write(&dest_path, compress(&data)?)?;
+                    data.len() as u64
+                } else {
+                    create_parent(&dest_path)?;
+                    fs::copy(&src_path, &dest_path)?
+                };
+
+                ManifestEntry::Add {
+                    file,
+                    final_hash,
+                    original_hash: None,
+                    size: Some(size),
+                    critical: false,
+                    label,
+                    mode,
+                    mtime,
+                    compressed,
+                }
+            }
+            FileChange::Old {
+                file,
+                original_hash,
+            } => {
+                // Nothing to write, just record in manifest
+                ManifestEntry::Delete { file, original_hash }
+            }
+            FileChange::Rename { from, to, hash } => {
+                // Content is already in the target, just record the move
+                ManifestEntry::Rename { from, to, hash }
+            }
+            FileChange::Symlink {
+                file,
+                original_target,
+                target,
+            } => {
+                // Links are recreated from their target, nothing to copy
+                ManifestEntry::Symlink {
+                    file,
+                    original_target,
+                    target,
+                }
+            }
+            FileChange::MkDir { dir } => ManifestEntry::MkDir { dir },
+            FileChange::RmDir { dir } => ManifestEntry::RmDir { dir },
+        };
+
+        manifest.entries.push(entry);
+    }
+
+    // Sort entries by filename for consistent output, keeping directory
+    // removals last (deepest first) so each is empty by the time it runs
+    manifest.entries.sort_by(|a, b| match (a, b) {
+        (ManifestEntry::RmDir { .. }, ManifestEntry::RmDir { .. }) => b.file().cmp(a.file()),
+        (ManifestEntry::RmDir { .. }, _) => Ordering::Greater,
+        (_, ManifestEntry::RmDir { .. }) => Ordering::Less,
+        _ => a.file().cmp(b.file()),
+    });
+
+    // Write manifest
+    let manifest_path = output_dir.join(MANIFEST_FILENAME);
+    manifest.save(&manifest_path)?;
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::diff::apply_diff;
+    use crate::utils::hash::hash_bytes;
+    use tempfile::tempdir;
+
+    const OPTIONS: CreateOptions<'static> = CreateOptions::new(1, "TestPatcher");
+
+    #[test]
+    fn creates_directory_structure() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        // Create a modified file (triggers diffs/ creation)
+        fs::write(orig_dir.path().join("modified.bin"), b"old").unwrap();
+        fs::write(new_dir.path().join("modified.bin"), b"new").unwrap();
+
+        // Create a new file (triggers files/ creation)
+        fs::write(new_dir.path().join("added.bin"), b"added").unwrap();
+
+        create(orig_dir.path(), new_dir.path(), output_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        assert!(output_dir.path().join("manifest.json").exists());
+        assert!(output_dir.path().join("diffs").exists());
+        assert!(output_dir.path().join("files").exists());
+    }
+
+    #[test]
+    fn upsert_creates_or_replaces_the_file() {
+        use crate::patch::{apply_entries, backup_entries, rollback, validate_entries, ByteProgress, PatchError, Verification, BACKUP_DIR};
+
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::write(orig_dir.path().join("strings.dat"), b"English").unwrap();
+        fs::write(new_dir.path().join("strings.dat"), b"Spanish").unwrap();
+
+        let upsert = vec!["strings.dat".to_string()];
+        let options = CreateOptions { upsert: &upsert, ..OPTIONS };
+        let manifest = create(orig_dir.path(), new_dir.path(), output_dir.path(), &options, None::<fn(Progress)>).unwrap();
+        assert!(matches!(
+            &manifest.entries[..],
+            [ManifestEntry::Add { original_hash: Some(hash), .. }] if *hash == hash_bytes(b"English")
+        ));
+        assert!(!output_dir.path().join(DIFFS_DIR).exists());
+
+        for original in [Some(&b"English"[..]), None, Some(&b"Other"[..])] {
+            let target = tempdir().unwrap();
+            let path = target.path().join("strings.dat");
+            if let Some(original) = original {
+                fs::write(&path, original).unwrap();
+            }
+            let entries = &manifest.entries;
+            let validated = validate_entries(entries, target.path(), Algorithm::Sha256, None::<fn(Progress)>);
+            if original == Some(b"Other") {
+                assert!(matches!(validated, Err(PatchError::ValidationFailed { .. })));
+                continue;
+            }
+            validated.unwrap();
+
+            let backup_dir = target.path().join(BACKUP_DIR);
+            backup_entries(entries, target.path(), &backup_dir, Algorithm::Sha256, None::<fn(Progress)>).unwrap();
+            apply_entries(
+                entries,
+                target.path(),
+                output_dir.path(),
+                &backup_dir,
+                Verification::Full,
+                Algorithm::Sha256,
+                None::<fn(Progress)>,
+                None::<fn(ByteProgress)>,
+            )
+            .unwrap();
+            assert_eq!(fs::read(&path).unwrap(), b"Spanish");
+
+            let applied: Vec<_> = entries.iter().collect();
+            rollback(&applied, target.path(), &backup_dir, None::<fn(Progress)>).unwrap();
+            assert_eq!(fs::read(&path).ok().as_deref(), original);
+        }
+    }
+
+    #[test]
+    fn manifest_only_writes_just_the_manifest() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let full_dir = tempdir().unwrap();
+        let manifest_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("modified.bin"), b"old").unwrap();
+        fs::write(new_dir.path().join("modified.bin"), b"new").unwrap();
+        fs::create_dir(new_dir.path().join("data")).unwrap();
+        fs::write(new_dir.path().join("data/added.bin"), b"added").unwrap();
+
+        let full = create(orig_dir.path(), new_dir.path(), full_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+        let options = CreateOptions { manifest_only: true, ..OPTIONS };
+        let manifest_only =
+            create(orig_dir.path(), new_dir.path(), manifest_dir.path(), &options, None::<fn(Progress)>).unwrap();
+
+        assert_eq!(manifest_only.entries, full.entries);
+        let written: Vec<_> = fs::read_dir(manifest_dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(written, vec!["manifest.json"]);
+    }
+
+    #[test]
+    fn records_version_file_hash() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        for dir in [orig_dir.path(), new_dir.path()] {
+            fs::write(dir.join("game.exe"), b"game 1.4").unwrap();
+        }
+        fs::write(orig_dir.path().join("strings.txt"), b"yes").unwrap();
+        fs::write(new_dir.path().join("strings.txt"), b"oui").unwrap();
+
+        let options = CreateOptions { version_file: Some("game.exe"), ..OPTIONS };
+        let manifest = create(orig_dir.path(), new_dir.path(), output_dir.path(), &options, None::<fn(Progress)>).unwrap();
+        let version_file = manifest.version_file.unwrap();
+        assert_eq!(version_file.file, "game.exe");
+        assert_eq!(version_file.hash, hash_bytes(b"game 1.4"));
+
+        // A file the patch changes can't identify the unpatched version
+        let options = CreateOptions { version_file: Some("strings.txt"), ..OPTIONS };
+        let err = create(orig_dir.path(), new_dir.path(), output_dir.path(), &options, None::<fn(Progress)>).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn creates_valid_diffs() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        let orig_content = b"original content here";
+        let new_content = b"modified content here";
+
+        fs::write(orig_dir.path().join("file.bin"), orig_content).unwrap();
+        fs::write(new_dir.path().join("file.bin"), new_content).unwrap();
+
+        create(orig_dir.path(), new_dir.path(), output_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        // Read the diff and apply it
+        let diff_data = fs::read(output_dir.path().join("diffs").join("file.bin.diff")).unwrap();
+        let result = apply_diff(orig_content, &diff_data).unwrap();
+
+        assert_eq!(result, new_content);
+    }
+
+    #[test]
+    fn copies_new_files() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        let content = b"new file content";
+        fs::write(new_dir.path().join("new.bin"), content).unwrap();
+
+        create(orig_dir.path(), new_dir.path(), output_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        let copied = fs::read(output_dir.path().join("files").join("new.bin")).unwrap();
+        assert_eq!(copied, content);
+    }
+
+    #[test]
+    fn manifest_contains_correct_entries() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        // Modified file
+        fs::write(orig_dir.path().join("modified.bin"), b"old").unwrap();
+        fs::write(new_dir.path().join("modified.bin"), b"new").unwrap();
+
+        // New file
+        fs::write(new_dir.path().join("added.bin"), b"added").unwrap();
+
+        // Deleted file
+        fs::write(orig_dir.path().join("deleted.bin"), b"deleted").unwrap();
+
+        // Unchanged file (should not appear in manifest)
+        fs::write(orig_dir.path().join("unchanged.bin"), b"same").unwrap();
+        fs::write(new_dir.path().join("unchanged.bin"), b"same").unwrap();
+
+        let created = create(orig_dir.path(), new_dir.path(), output_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        assert_eq!(created, manifest);
+
+        assert_eq!(manifest.entries.len(), 3);
+
+        // Check each entry type exists
+        assert!(manifest
+            .entries
+            .iter()
+            .any(|e| matches!(e, ManifestEntry::Patch { file, .. } if file == "modified.bin")));
+        assert!(manifest
+            .entries
+            .iter()
+            .any(|e| matches!(e, ManifestEntry::Add { file, .. } if file == "added.bin")));
+        assert!(manifest
+            .entries
+            .iter()
+            .any(|e| matches!(e, ManifestEntry::Delete { file, .. } if file == "deleted.bin")));
+    }
+
+    #[test]
+    fn manifest_has_correct_hashes() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        let orig_content = b"original";
+        let new_content = b"modified";
+
+        fs::write(orig_dir.path().join("file.bin"), orig_content).unwrap();
+        fs::write(new_dir.path().join("file.bin"), new_content).unwrap();
+
+        create(orig_dir.path(), new_dir.path(), output_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+
+        if let ManifestEntry::Patch {
+            original_hash,
+            diff_hash,
+            final_hash,
+            ..
+        } = &manifest.entries[0]
+        {
+            assert_eq!(original_hash, &hash_bytes(orig_content));
+            assert_eq!(final_hash, &hash_bytes(new_content));
+
+            // Verify diff_hash matches the actual diff file
+            let diff_data =
+                fs::read(output_dir.path().join("diffs").join("file.bin.diff")).unwrap();
+            assert_eq!(diff_hash, &hash_bytes(&diff_data));
+        } else {
+            panic!("Expected Patch entry");
+        }
+    }
+
+    #[test]
+    fn small_change_to_large_file_stores_small_delta() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        // A 1 MiB "executable" where only a short string table changes
+        let mut state = 1u32;
+        let original: Vec<u8> = (0..1024 * 1024)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect();
+        let mut translated = original.clone();
+        translated[500_000..500_032].copy_from_slice(b"Bonjour, voyageur! Bienvenue...!");
+        fs::write(orig_dir.path().join("game.exe"), &original).unwrap();
+        fs::write(new_dir.path().join("game.exe"), &translated).unwrap();
+
+        create(orig_dir.path(), new_dir.path(), output_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        assert!(matches!(&manifest.entries[0], ManifestEntry::Patch { .. }));
+        assert!(!output_dir.path().join("files/game.exe").exists());
+        let diff = fs::read(output_dir.path().join("diffs/game.exe.diff")).unwrap();
+        assert!(diff.len() < original.len() / 100, "diff is {} bytes", diff.len());
+        assert_eq!(apply_diff(&original, &diff).unwrap(), translated);
+    }
+
+    #[test]
+    fn text_mode_ignores_line_ending_changes() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        // Resaved on Windows: only the line endings changed
+        fs::write(orig_dir.path().join("menu.ini"), b"[menu]\nstart=Start\n").unwrap();
+        fs::write(new_dir.path().join("menu.ini"), b"[menu]\r\nstart=Start\r\n").unwrap();
+        // Translated on Windows
+        fs::write(orig_dir.path().join("strings.txt"), b"yes\nno\n").unwrap();
+        fs::write(new_dir.path().join("strings.txt"), b"oui\r\nnon\r\n").unwrap();
+        // Not a text extension, so diffed as is
+        fs::write(orig_dir.path().join("font.bin"), b"a\nb").unwrap();
+        fs::write(new_dir.path().join("font.bin"), b"a\r\nb").unwrap();
+
+        let extensions = vec!["txt".to_string(), "ini".to_string()];
+        let options = CreateOptions { text_extensions: &extensions, ..OPTIONS };
+        let manifest = create(orig_dir.path(), new_dir.path(), output_dir.path(), &options, None::<fn(Progress)>).unwrap();
+
+        let files: Vec<_> = manifest.entries.iter().map(ManifestEntry::file).collect();
+        assert_eq!(files, vec!["font.bin", "strings.txt"]);
+        let ManifestEntry::Patch { final_hash, line_endings, .. } = &manifest.entries[1] else {
+            panic!("Expected Patch entry");
+        };
+        assert_eq!(final_hash, &hash_bytes(b"oui\nnon\n"));
+        assert_eq!(*line_endings, Some(LineEnding::Lf));
+
+        let diff = fs::read(output_dir.path().join("diffs/strings.txt.diff")).unwrap();
+        assert_eq!(apply_diff(b"yes\nno\n", &diff).unwrap(), b"oui\nnon\n");
+    }
+
+    #[test]
+    fn text_mode_converts_to_original_encoding() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let utf16 = |text: &str| -> Vec<u8> {
+            b"\xff\xfe".iter().copied().chain(text.encode_utf16().flat_map(u16::to_le_bytes)).collect()
+        };
+
+        // Dialogue in UTF-16LE with a BOM, translated in UTF-8 on Windows
+        fs::write(orig_dir.path().join("dialogue.txt"), utf16("Hello\r\nBye\r\n")).unwrap();
+        fs::write(new_dir.path().join("dialogue.txt"), "Hola\nAdiós\n").unwrap();
+        // Shift-JIS without a BOM, going by --text-encoding
+        fs::write(orig_dir.path().join("menu.txt"), b"\x82\xcd\x82\xa2\n").unwrap();
+        fs::write(new_dir.path().join("menu.txt"), "いいえ\n").unwrap();
+        // UTF-8 (marked as such by its BOM) is left alone
+        fs::write(orig_dir.path().join("readme.txt"), "\u{feff}yes\n").unwrap();
+        fs::write(new_dir.path().join("readme.txt"), "sí\n").unwrap();
+
+        let extensions = vec!["txt".to_string()];
+        let options = CreateOptions { text_extensions: &extensions, text_encoding: Some("shift_jis"), ..OPTIONS };
+        let manifest = create(orig_dir.path(), new_dir.path(), output_dir.path(), &options, None::<fn(Progress)>).unwrap();
+
+        let converted: Vec<_> = manifest
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                ManifestEntry::Patch { file, final_hash, encoding, .. } => (file.as_str(), final_hash.clone(), encoding.clone()),
+                _ => panic!("Expected Patch entry"),
+            })
+            .collect();
+        assert_eq!(
+            converted,
+            vec![
+                ("dialogue.txt", hash_bytes(&utf16("Hola\r\nAdiós\r\n")), Some("UTF-16LE".to_string())),
+                ("menu.txt", hash_bytes(b"\x82\xa2\x82\xa2\x82\xa6\n"), Some("Shift_JIS".to_string())),
+                ("readme.txt", hash_bytes("sí\n".as_bytes()), None),
+            ]
+        );
+
+        // A character Shift-JIS can't hold stops the patch being created
+        fs::write(new_dir.path().join("menu.txt"), "\u{1f600}\n").unwrap();
+        let err = create(orig_dir.path(), new_dir.path(), output_dir.path(), &options, None::<fn(Progress)>).unwrap_err();
+        assert!(err.to_string().contains("can't be written in Shift_JIS"), "{}", err);
+    }
+
+    #[test]
+    fn moved_file_becomes_rename_without_payload() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::create_dir(orig_dir.path().join("audio")).unwrap();
+        fs::create_dir(new_dir.path().join("sound")).unwrap();
+        fs::write(orig_dir.path().join("audio/voice.bin"), b"voice lines").unwrap();
+        fs::write(new_dir.path().join("sound/voice.bin"), b"voice lines").unwrap();
+
+        let manifest = create(orig_dir.path(), new_dir.path(), output_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        assert!(manifest.entries.iter().any(|e| matches!(
+            e,
+            ManifestEntry::Rename { from, to, .. } if from == "audio/voice.bin" && to == "sound/voice.bin"
+        )));
+        assert!(!manifest.entries.iter().any(|e| matches!(e, ManifestEntry::Add { .. } | ManifestEntry::Delete { .. })));
+        assert!(!output_dir.path().join(FILES_DIR).exists());
+    }
+
+    #[test]
+    fn file_list_limits_patch_to_listed_files() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::write(orig_dir.path().join("voice_01.bin"), b"voice").unwrap();
+        fs::write(new_dir.path().join("voice_jp.bin"), b"voice").unwrap();
+        fs::write(orig_dir.path().join("save.dat"), b"before").unwrap();
+        fs::write(new_dir.path().join("save.dat"), b"after").unwrap();
+
+        let files = vec!["voice_01.bin".to_string(), "voice_jp.bin".to_string()];
+        let options = CreateOptions { files: Some(&files), ..OPTIONS };
+        let manifest = create(orig_dir.path(), new_dir.path(), output_dir.path(), &options, None::<fn(Progress)>).unwrap();
+
+        assert_eq!(manifest.entries.len(), 1);
+        assert!(matches!(
+            &manifest.entries[0],
+            ManifestEntry::Rename { from, to, .. } if from == "voice_01.bin" && to == "voice_jp.bin"
+        ));
+    }
+
+    #[test]
+    fn case_insensitive_diffs_case_renamed_file() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        fs::write(orig_dir.path().join("Data.bin"), b"original").unwrap();
+        fs::write(new_dir.path().join("data.bin"), b"translated").unwrap();
+
+        let options = CreateOptions { case_insensitive: true, ..OPTIONS };
+        let manifest = create(orig_dir.path(), new_dir.path(), output_dir.path(), &options, None::<fn(Progress)>).unwrap();
+
+        assert_eq!(manifest.entries.len(), 1);
+        assert!(matches!(&manifest.entries[0], ManifestEntry::Patch { file, .. } if file == "Data.bin"));
+        let diff = fs::read(output_dir.path().join("diffs/Data.bin.diff")).unwrap();
+        assert_eq!(apply_diff(b"original", &diff).unwrap(), b"translated");
+    }
+
+    #[test]
+    fn manifest_uses_selected_hash_algorithm() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("file.bin"), b"original").unwrap();
+        fs::write(new_dir.path().join("file.bin"), b"modified").unwrap();
+
+        let options = CreateOptions {
+            hash_algorithm: Algorithm::Blake3,
+            ..OPTIONS
+        };
+        create(orig_dir.path(), new_dir.path(), output_dir.path(), &options, None::<fn(Progress)>).unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        assert_eq!(manifest.hash_algorithm, Algorithm::Blake3);
+        let ManifestEntry::Patch { original_hash, final_hash, .. } = &manifest.entries[0] else {
+            panic!("Expected Patch entry");
+        };
+        assert_eq!(original_hash, &Algorithm::Blake3.hash_bytes(b"original"));
+        assert_eq!(final_hash, &Algorithm::Blake3.hash_bytes(b"modified"));
+    }
+
+    #[test]
+    fn reports_progress_per_change() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("a.bin"), b"original").unwrap();
+        fs::write(new_dir.path().join("a.bin"), b"modified").unwrap();
+        fs::write(new_dir.path().join("b.bin"), b"new").unwrap();
+        fs::write(orig_dir.path().join("c.bin"), b"old").unwrap();
+        fs::write(orig_dir.path().join("same.bin"), b"same").unwrap();
+        fs::write(new_dir.path().join("same.bin"), b"same").unwrap();
+
+        let mut events = Vec::new();
+        create(
+            orig_dir.path(),
+            new_dir.path(),
+            output_dir.path(),
+            &OPTIONS,
+            Some(|p: Progress| events.push((p.file.to_string(), p.index, p.total, p.action))),
+        )
+        .unwrap();
+
+        assert_eq!(
+            events,
+            vec![
+                ("a.bin".to_string(), 0, 3, ProgressAction::Diffing),
+                ("b.bin".to_string(), 1, 3, ProgressAction::Copying),
+                ("c.bin".to_string(), 2, 3, ProgressAction::Recording),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_directories_creates_empty_manifest() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        create(orig_dir.path(), new_dir.path(), output_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        assert!(manifest.entries.is_empty());
+    }
+
+    #[test]
+    fn skips_unnecessary_subdirs() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        // Only a deleted file - no diffs/ or files/ needed
+        fs::write(orig_dir.path().join("deleted.bin"), b"deleted").unwrap();
+
+        create(orig_dir.path(), new_dir.path(), output_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        assert!(output_dir.path().join("manifest.json").exists());
+        assert!(!output_dir.path().join("diffs").exists());
+        assert!(!output_dir.path().join("files").exists());
+    }
+
+    #[test]
+    fn detect_writes_changes_without_patch() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let changes_path = output_dir.path().join("changes.json");
+
+        fs::write(orig_dir.path().join("modified.bin"), b"old").unwrap();
+        fs::write(new_dir.path().join("modified.bin"), b"new").unwrap();
+        fs::write(new_dir.path().join("added.bin"), b"added").unwrap();
+        fs::write(orig_dir.path().join("same.bin"), b"same").unwrap();
+        fs::write(new_dir.path().join("same.bin"), b"same").unwrap();
+
+        let summary =
+            detect_changes(orig_dir.path(), new_dir.path(), &changes_path, false, Algorithm::Sha256, &[], None).unwrap();
+
+        assert_eq!(summary.changes.len(), 2);
+        assert_eq!(summary.unchanged, vec!["same.bin".to_string()]);
+        assert_eq!(summary.files_scanned, 3);
+        assert_eq!(summary.files_changed(), 2);
+        assert_eq!(load_changes(&changes_path).unwrap(), summary.changes);
+        assert!(!output_dir.path().join("manifest.json").exists());
+        assert!(!output_dir.path().join("diffs").exists());
+    }
+
+    #[test]
+    fn run_approved_only_includes_approved_changes() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let review_dir = tempdir().unwrap();
+        let changes_path = review_dir.path().join("changes.json");
+
+        fs::write(orig_dir.path().join("modified.bin"), b"old").unwrap();
+        fs::write(new_dir.path().join("modified.bin"), b"new").unwrap();
+        fs::write(new_dir.path().join("rejected.bin"), b"rejected").unwrap();
+
+        detect_changes(orig_dir.path(), new_dir.path(), &changes_path, false, Algorithm::Sha256, &[], None).unwrap();
+
+        // Reviewer rejects the new file
+        let mut changes = load_changes(&changes_path).unwrap();
+        changes.retain(|c| c.file() != "rejected.bin");
+        save_changes(&changes, &changes_path).unwrap();
+
+        create_approved(orig_dir.path(), new_dir.path(), output_dir.path(), &changes_path, &OPTIONS, None::<fn(Progress)>)
+            .unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        assert_eq!(manifest.entries.len(), 1);
+        assert_eq!(manifest.entries[0].file(), "modified.bin");
+        assert!(!output_dir.path().join("files").exists());
+    }
+
+    #[test]
+    fn run_approved_rejects_stale_changes() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        let review_dir = tempdir().unwrap();
+        let changes_path = review_dir.path().join("changes.json");
+
+        fs::write(orig_dir.path().join("modified.bin"), b"old").unwrap();
+        fs::write(new_dir.path().join("modified.bin"), b"new").unwrap();
+
+        detect_changes(orig_dir.path(), new_dir.path(), &changes_path, false, Algorithm::Sha256, &[], None).unwrap();
+
+        // File changes again after review
+        fs::write(new_dir.path().join("modified.bin"), b"newer").unwrap();
+
+        let result =
+            create_approved(orig_dir.path(), new_dir.path(), output_dir.path(), &changes_path, &OPTIONS, None::<fn(Progress)>);
+
+        assert!(result.is_err());
+        assert!(!output_dir.path().join("manifest.json").exists());
+    }
+
+    #[test]
+    fn skips_files_in_graftignore() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+
+        fs::write(new_dir.path().join(".graftignore"), "*.log\n").unwrap();
+        fs::write(new_dir.path().join("debug.log"), b"log").unwrap();
+        fs::write(new_dir.path().join("added.bin"), b"added").unwrap();
+
+        create(orig_dir.path(), new_dir.path(), output_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
+        assert_eq!(manifest.entries.len(), 1);
+        assert_eq!(manifest.entries[0].file(), "added.bin");
+    }
+}
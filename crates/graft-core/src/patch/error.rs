@@ -1,4 +1,5 @@
 use crate::path_restrictions::RestrictionViolation;
+use crate::utils::hash::short_hash;
 use std::fmt;
 
 /// Error type for patch operations.
@@ -24,6 +25,19 @@ pub enum PatchError {
     ManifestError { reason: String },
     /// Path restrictions violated (system dirs, executables, etc.)
     RestrictedPaths(Vec<RestrictionViolation>),
+    /// Not enough free disk space to back up and apply the patch (bytes)
+    InsufficientSpace { needed: u64, available: u64 },
+    /// The target's version file doesn't match the game version the patch
+    /// is for; `found` is the installed version, if the manifest knows it
+    WrongGameVersion { file: String, expected: Option<String>, found: Option<String> },
+    /// A directory the patch writes into can't be written
+    NotWritable { path: String, reason: String },
+    /// Another patcher holds the lock on the target (see `PatchLock`)
+    AlreadyInProgress { lock: String },
+    /// The manifest declares hook commands but running them wasn't allowed
+    HooksNotAllowed,
+    /// A hook command couldn't be started or exited unsuccessfully
+    HookFailed { command: String, reason: String },
 }
 
 impl fmt::Display for PatchError {
@@ -39,7 +53,7 @@ impl fmt::Display for PatchError {
                 write!(f, "file not found: '{}'", file)
             }
             PatchError::ValidationFailed { file, reason } => {
-                write!(f, "validation failed for '{}': {}", file, reason)
+                write!(f, "validation failed for '{}': {}", file, shorten_hashes(reason))
             }
             PatchError::BackupFailed { file, reason } => {
                 write!(f, "backup failed for '{}': {}", file, reason)
@@ -51,7 +65,9 @@ impl fmt::Display for PatchError {
                 write!(
                     f,
                     "verification failed for '{}': expected hash {}, got {}",
-                    file, expected, actual
+                    file,
+                    shorten_hashes(expected),
+                    shorten_hashes(actual)
                 )
             }
             PatchError::RollbackFailed { reason } => {
@@ -67,8 +83,123 @@ impl fmt::Display for PatchError {
                 }
                 Ok(())
             }
+            PatchError::InsufficientSpace { needed, available } => {
+                write!(
+                    f,
+                    "not enough disk space: {} needed, only {} available",
+                    format_bytes(*needed),
+                    format_bytes(*available)
+                )
+            }
+            PatchError::WrongGameVersion { file, expected, found } => {
+                match expected {
+                    Some(expected) => write!(f, "this patch is for version {} of the game", expected)?,
+                    None => write!(f, "this patch is for a different version of the game")?,
+                }
+                match found {
+                    Some(found) => write!(f, " but your install looks like {}", found),
+                    None => write!(f, " ('{}' is missing or doesn't match)", file),
+                }
+            }
+            PatchError::NotWritable { path, reason } => {
+                write!(
+                    f,
+                    "cannot write to '{}': {}; if the game is in a protected folder such as Program Files, \
+                     run the patcher as administrator",
+                    path, reason
+                )
+            }
+            PatchError::AlreadyInProgress { lock } => {
+                write!(
+                    f,
+                    "another patcher is already working on this folder; wait for it to finish \
+                     (if none is running, delete '{}')",
+                    lock
+                )
+            }
+            PatchError::HooksNotAllowed => {
+                write!(
+                    f,
+                    "this patch runs commands before or after applying; pass --allow-hooks to run them \
+                     or --no-hooks to apply it without them"
+                )
+            }
+            PatchError::HookFailed { command, reason } => {
+                write!(f, "hook command '{}' failed: {}", command, reason)
+            }
         }
     }
 }
 
+/// A byte count in the largest unit that keeps it at or above 1, e.g. "1.5 MB".
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// `text` with every full hex hash in it cut down to `short_hash`, leaving
+/// the rest (e.g. "one of", "failed to read file: ...") as it is.
+fn shorten_hashes(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut run = String::new();
+    for c in text.chars().chain(std::iter::once(' ')) {
+        if c.is_ascii_hexdigit() {
+            run.push(c);
+            continue;
+        }
+        if run.len() == 64 {
+            out.push_str(&short_hash(&run));
+        } else {
+            out.push_str(&run);
+        }
+        run.clear();
+        out.push(c);
+    }
+    out.pop();
+    out
+}
+
 impl std::error::Error for PatchError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::hash::hash_bytes;
+
+    #[test]
+    fn hash_errors_show_short_hashes() {
+        let expected = hash_bytes(b"expected");
+        let actual = hash_bytes(b"actual");
+        let err = PatchError::VerificationFailed {
+            file: "game.dat".to_string(),
+            expected: format!("one of {}, {}", expected, hash_bytes(b"other")),
+            actual: actual.clone(),
+        };
+        let message = err.to_string();
+        assert!(message.contains(&format!("one of {}, ", short_hash(&expected))));
+        assert!(message.ends_with(&format!("got {}", short_hash(&actual))));
+        assert!(!message.contains(&expected));
+
+        let err = PatchError::ValidationFailed {
+            file: "game.dat".to_string(),
+            reason: format!("hash mismatch: expected {}, got {}", expected, actual),
+        };
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "validation failed for 'game.dat': hash mismatch: expected {}, got {}",
+                short_hash(&expected),
+                short_hash(&actual)
+            )
+        );
+    }
+}
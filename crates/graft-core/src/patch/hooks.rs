@@ -0,0 +1,66 @@
+//! Commands a manifest asks to run around an apply (`pre_commands` and
+//! `post_commands`), e.g. to stop a game service first or clear a cache
+//! afterwards.
+//!
+//! Hooks run arbitrary programs, so callers only run them when the player
+//! has explicitly allowed it.
+
+use std::path::Path;
+use std::process::Command;
+
+use crate::patch::PatchError;
+
+/// Run `command` (a program followed by its arguments) in `dir` and wait
+/// for it, with the patcher's stdin, stdout and stderr.
+///
+/// Fails with `HookFailed` if the command is empty, can't be started or
+/// exits unsuccessfully.
+pub fn run_hook(command: &[String], dir: &Path) -> Result<(), PatchError> {
+    let failed = |reason: String| PatchError::HookFailed {
+        command: format_command(command),
+        reason,
+    };
+    let (program, args) = command.split_first().ok_or_else(|| failed("empty command".to_string()))?;
+    let status = Command::new(program)
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .map_err(|e| failed(e.to_string()))?;
+    if !status.success() {
+        return Err(failed(status.to_string()));
+    }
+    Ok(())
+}
+
+/// A command as one line for messages, e.g. `net stop GameSvc`.
+pub fn format_command(command: &[String]) -> String {
+    command.join(" ")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn command(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn runs_in_the_given_directory() {
+        let dir = tempdir().unwrap();
+        run_hook(&command(&["touch", "ran"]), dir.path()).unwrap();
+        assert!(dir.path().join("ran").exists());
+    }
+
+    #[test]
+    fn reports_failing_and_missing_commands() {
+        let dir = tempdir().unwrap();
+        assert!(matches!(
+            run_hook(&command(&["false"]), dir.path()),
+            Err(PatchError::HookFailed { command, .. }) if command == "false"
+        ));
+        assert!(run_hook(&command(&["graft-no-such-program"]), dir.path()).is_err());
+        assert!(run_hook(&[], dir.path()).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::patch::constants::LOCK_FILENAME;
+use crate::patch::PatchError;
+
+/// Lock on a target directory, held while a patch is applied so a second
+/// patcher (e.g. from a double-click) can't race it on the same files and
+/// backup.
+///
+/// The lock is an OS advisory lock (`File::try_lock`) on `LOCK_FILENAME` in
+/// the target. The OS releases it when the process exits, however it exits,
+/// so a patcher that crashed or was killed never leaves the target locked.
+///
+/// The file itself is left in place when the guard is dropped: removing it
+/// would let a patcher still waiting on the old file and one creating a new
+/// file both hold "the" lock, and Windows can't remove a file that is open.
+#[derive(Debug)]
+pub struct PatchLock {
+    _file: File,
+}
+
+impl PatchLock {
+    /// Lock `target_dir`, failing with `AlreadyInProgress` if another
+    /// patcher holds the lock.
+    pub fn acquire(target_dir: &Path) -> Result<Self, PatchError> {
+        let path = target_dir.join(LOCK_FILENAME);
+        let not_writable = |e: io::Error| PatchError::NotWritable {
+            path: target_dir.display().to_string(),
+            reason: e.to_string(),
+        };
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(not_writable)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(fs::TryLockError::WouldBlock) => {
+                return Err(PatchError::AlreadyInProgress {
+                    lock: path.display().to_string(),
+                });
+            }
+            Err(fs::TryLockError::Error(e)) => return Err(not_writable(e)),
+        }
+
+        // Record the process holding the lock, for anyone looking into a
+        // stuck lock; written through the locked handle, which Windows allows
+        file.set_len(0)
+            .and_then(|()| writeln!(file, "{}", std::process::id()))
+            .map_err(not_writable)?;
+        Ok(PatchLock { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn second_lock_is_refused_until_first_is_dropped() {
+        let target = tempdir().unwrap();
+        let lock = PatchLock::acquire(target.path()).unwrap();
+        assert!(matches!(
+            PatchLock::acquire(target.path()),
+            Err(PatchError::AlreadyInProgress { .. })
+        ));
+
+        drop(lock);
+        let lock = PatchLock::acquire(target.path()).unwrap();
+        assert!(matches!(
+            PatchLock::acquire(target.path()),
+            Err(PatchError::AlreadyInProgress { .. })
+        ));
+        drop(lock);
+    }
+
+    #[test]
+    fn leftover_lock_file_is_not_a_lock() {
+        // A patcher that crashed leaves the file but not the OS lock
+        let target = tempdir().unwrap();
+        fs::write(target.path().join(LOCK_FILENAME), "12345\n").unwrap();
+
+        let lock = PatchLock::acquire(target.path()).unwrap();
+        drop(lock);
+        assert_eq!(
+            fs::read_to_string(target.path().join(LOCK_FILENAME)).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+    }
+}
@@ -1,9 +1,17 @@
 pub mod apply;
 pub mod backup;
 mod constants;
+pub mod create;
 mod error;
+pub mod hooks;
+pub mod lock;
+pub mod payload;
+pub mod preview;
+pub mod resolve;
+pub mod space;
 pub mod validate;
 pub mod verify;
+pub mod writable;
 
 /// Action being performed on a file during progress.
 ///
@@ -22,10 +30,18 @@ pub enum ProgressAction {
     Patching,
     Adding,
     Deleting,
+    Renaming,
+    Linking,
+    Creating,
 
     // Rollback phase
     Restoring,
     Removing,
+
+    // Patch creation
+    Diffing,
+    Copying,
+    Recording,
 }
 
 /// Progress information passed to callbacks during batch operations.
@@ -33,6 +49,9 @@ pub enum ProgressAction {
 pub struct Progress<'a> {
     /// File being processed
     pub file: &'a str,
+    /// Label the patch author gave the entry, if any; show it instead of
+    /// `file` where players will see it
+    pub label: Option<&'a str>,
     /// Current index (0-based)
     pub index: usize,
     /// Total number of entries
@@ -41,10 +60,40 @@ pub struct Progress<'a> {
     pub action: ProgressAction,
 }
 
+/// Bytes written so far while applying, passed to byte progress callbacks.
+///
+/// `total` is the sum of the sizes the manifest records for the entries being
+/// applied (see `ManifestEntry::expected_size`), so files whose size wasn't
+/// recorded count toward neither figure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteProgress {
+    pub done: u64,
+    pub total: u64,
+}
+
 // Re-export public items
-pub use apply::{apply_entries, apply_entry};
-pub use backup::{backup_entries, rollback};
-pub use constants::{ASSETS_DIR, BACKUP_DIR, DIFFS_DIR, DIFF_EXTENSION, FILES_DIR, ICON_FILENAME, MANIFEST_FILENAME};
-pub use error::PatchError;
-pub use validate::{validate_backup, validate_entries, validate_patch_dir, validate_patched_entries, validate_path_restrictions};
-pub use verify::verify_entry;
+pub use apply::{
+    apply_entries, apply_entries_parallel, apply_entries_staged, apply_entries_with, apply_entry,
+    apply_entry_hashing, apply_entry_hashing_with, apply_entry_with, set_streaming_threshold,
+    streaming_threshold, Verification, DEFAULT_STREAMING_THRESHOLD,
+};
+pub use backup::{applied_entries, backup_entries, backup_entries_with, rollback, rollback_with};
+pub use constants::{
+    ASSETS_DIR, BACKUP_DIR, BACKUP_ENTRIES_FILENAME, BACKUP_HASHES_FILENAME, CHECKSUM_FILENAME,
+    COMPRESSED_EXTENSION, DIFFS_DIR, DIFF_EXTENSION, FILES_DIR, ICON_FILENAME, IGNORE_FILENAME,
+    LOCK_FILENAME, MANIFEST_FILENAME, SIGNATURE_FILENAME,
+};
+pub use create::{create, create_approved, detect_changes, CreateOptions};
+pub use error::{format_bytes, PatchError};
+pub use hooks::{format_command, run_hook};
+pub use lock::PatchLock;
+pub use preview::{preview_entry, DEFAULT_PREVIEW_LINES};
+pub use resolve::resolve_by_content;
+pub use space::{check_space, check_space_with};
+pub use validate::{
+    find_modified_entries, install_dir, remove_applied_entries, rollback_available, validate_backup,
+    validate_entries, validate_entries_forced, validate_entries_parallel, validate_game_version,
+    validate_patch_dir, validate_patch_files, validate_patched_entries, validate_path_restrictions,
+};
+pub use verify::{verify_entry, verify_entry_with, verify_entry_with_hash};
+pub use writable::check_writable;
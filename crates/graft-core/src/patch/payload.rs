@@ -0,0 +1,123 @@
+//! Locating and (de)compressing the diffs and added files a patch ships.
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::patch::constants::{COMPRESSED_EXTENSION, DIFFS_DIR, DIFF_EXTENSION, FILES_DIR};
+use crate::utils::manifest::ManifestEntry;
+
+/// Path of the diff for the patched file `file`, relative to the patch
+/// directory and `/`-separated (as in archives).
+pub fn diff_name(file: &str, compressed: bool) -> String {
+    with_compression(format!("{}/{}{}", DIFFS_DIR, file, DIFF_EXTENSION), compressed)
+}
+
+/// Path of the added file `file` in the patch, as for `diff_name`.
+pub fn added_name(file: &str, compressed: bool) -> String {
+    with_compression(format!("{}/{}", FILES_DIR, file), compressed)
+}
+
+fn with_compression(name: String, compressed: bool) -> String {
+    if compressed {
+        name + COMPRESSED_EXTENSION
+    } else {
+        name
+    }
+}
+
+/// Path of the diff or added file an entry ships in the patch, relative to
+/// the patch directory; `None` for entries that ship nothing.
+pub fn payload_name(entry: &ManifestEntry) -> Option<String> {
+    match entry {
+        ManifestEntry::Patch { file, compressed, .. } => Some(diff_name(file, *compressed)),
+        ManifestEntry::Add { file, compressed, .. } => Some(added_name(file, *compressed)),
+        ManifestEntry::Delete { .. }
+        | ManifestEntry::Rename { .. }
+        | ManifestEntry::Symlink { .. }
+        | ManifestEntry::MkDir { .. }
+        | ManifestEntry::RmDir { .. } => None,
+    }
+}
+
+/// Like `payload_name`, joined onto `patch_dir`.
+pub fn payload_path(patch_dir: &Path, entry: &ManifestEntry) -> Option<PathBuf> {
+    payload_name(entry).map(|name| patch_dir.join(name))
+}
+
+/// Gzip `data` for storing as a compressed payload.
+pub fn compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Undo `compress`.
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+/// Size of the data a compressed payload holds, read from the gzip trailer
+/// without decompressing.
+///
+/// The trailer stores the size modulo 4 GiB, so this is only exact for
+/// payloads smaller than that.
+pub fn decompressed_size(data: &[u8]) -> Option<u64> {
+    let trailer: [u8; 4] = data.get(data.len().checked_sub(4)?..)?.try_into().ok()?;
+    Some(u64::from(u32::from_le_bytes(trailer)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_payloads_get_gz_extension() {
+        let add = ManifestEntry::Add {
+            file: "text/fr.txt".to_string(),
+            final_hash: String::new(),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: true,
+        };
+        let patch = ManifestEntry::Patch {
+            file: "game.dat".to_string(),
+            original_hash: String::new(),
+            diff_hash: String::new(),
+            final_hash: String::new(),
+            final_hashes: vec![],
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+            line_endings: None,
+            encoding: None,
+        };
+
+        assert_eq!(payload_name(&add).as_deref(), Some("files/text/fr.txt.gz"));
+        assert_eq!(payload_name(&patch).as_deref(), Some("diffs/game.dat.diff"));
+        assert_eq!(payload_name(&ManifestEntry::MkDir { dir: "mods".to_string() }), None);
+    }
+
+    #[test]
+    fn compress_round_trips_and_records_size() {
+        let data = b"Bonjour le monde ".repeat(100);
+
+        let compressed = compress(&data).unwrap();
+
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress(&compressed).unwrap(), data);
+        assert_eq!(decompressed_size(&compressed), Some(data.len() as u64));
+    }
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use similar::TextDiff;
+
+use crate::patch::apply::streaming_threshold;
+use crate::patch::payload::{added_name, decompress, diff_name};
+use crate::patch::PatchError;
+use crate::utils::diff::apply_diff;
+use crate::utils::manifest::ManifestEntry;
+
+/// Default for the `max_lines` of `preview_entry`.
+pub const DEFAULT_PREVIEW_LINES: usize = 200;
+
+/// Lines of unchanged text shown around each change.
+const CONTEXT_LINES: usize = 3;
+
+/// A unified diff of what applying `entry` does to a text file in
+/// `target_dir`, for reviewing a patch before it's applied.
+///
+/// Only Patch and Add entries are previewed (an Add against an empty file,
+/// or the file an upsert replaces). `None` is returned for other entries,
+/// for files that aren't UTF-8 text on both sides (binary files), for files
+/// larger than the streaming threshold, which would have to be loaded whole,
+/// and for a Patch whose file isn't in the target yet (renamed by an earlier
+/// entry). Diffs longer than `max_lines` are cut short with a note saying
+/// how many lines were left out.
+pub fn preview_entry(
+    entry: &ManifestEntry,
+    target_dir: &Path,
+    patch_dir: &Path,
+    max_lines: usize,
+) -> Result<Option<String>, PatchError> {
+    let (file, old, new) = match entry {
+        ManifestEntry::Patch { file, compressed, .. } => {
+            let target_path = target_dir.join(file);
+            if !target_path.exists() || too_large(&target_path) {
+                return Ok(None);
+            }
+            let original = fs::read(&target_path).map_err(|e| unreadable(file, "file", e))?;
+            let diff = read_payload(&patch_dir.join(diff_name(file, *compressed)), *compressed)
+                .map_err(|e| unreadable(file, "diff file", e))?;
+            let patched = apply_diff(&original, &diff).map_err(|e| unreadable(file, "diff file", e))?;
+            (file, original, patched)
+        }
+        ManifestEntry::Add { file, compressed, .. } => {
+            let source_path = patch_dir.join(added_name(file, *compressed));
+            let target_path = target_dir.join(file);
+            if too_large(&source_path) || too_large(&target_path) {
+                return Ok(None);
+            }
+            let added = read_payload(&source_path, *compressed).map_err(|e| unreadable(file, "new file", e))?;
+            let existing = match fs::read(&target_path) {
+                Ok(data) => data,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(unreadable(file, "file", e)),
+            };
+            (file, existing, added)
+        }
+        ManifestEntry::Delete { .. }
+        | ManifestEntry::Rename { .. }
+        | ManifestEntry::Symlink { .. }
+        | ManifestEntry::MkDir { .. }
+        | ManifestEntry::RmDir { .. } => return Ok(None),
+    };
+
+    let (Some(old), Some(new)) = (as_text(&old), as_text(&new)) else {
+        return Ok(None);
+    };
+    if old == new {
+        return Ok(None);
+    }
+    let diff = TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .header(&format!("a/{}", file), &format!("b/{}", file))
+        .to_string();
+    Ok(Some(truncate_lines(diff, max_lines)))
+}
+
+/// `data` as text, or `None` if it isn't UTF-8 or holds NUL bytes (as
+/// binary files nearly always do).
+fn as_text(data: &[u8]) -> Option<&str> {
+    std::str::from_utf8(data).ok().filter(|text| !text.contains('\0'))
+}
+
+fn too_large(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.len() > streaming_threshold())
+}
+
+fn read_payload(path: &Path, compressed: bool) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    if compressed { decompress(&data) } else { Ok(data) }
+}
+
+fn unreadable(file: &str, what: &str, error: io::Error) -> PatchError {
+    PatchError::ValidationFailed {
+        file: file.to_string(),
+        reason: format!("failed to read {} for preview: {}", what, error),
+    }
+}
+
+/// The first `max_lines` lines of `text`, followed by a note of how many
+/// were left out if any were.
+fn truncate_lines(text: String, max_lines: usize) -> String {
+    let total = text.lines().count();
+    if total <= max_lines {
+        return text;
+    }
+    let mut out: String = text.split_inclusive('\n').take(max_lines).collect();
+    out.push_str(&format!("... {} more lines\n", total - max_lines));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch::{DIFFS_DIR, FILES_DIR};
+    use crate::utils::diff::create_diff;
+    use tempfile::tempdir;
+
+    fn patch_entry(file: &str) -> ManifestEntry {
+        ManifestEntry::Patch {
+            file: file.to_string(),
+            original_hash: "a".to_string(),
+            diff_hash: "b".to_string(),
+            final_hash: "c".to_string(),
+            final_hashes: vec![],
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+            line_endings: None,
+            encoding: None,
+        }
+    }
+
+    #[test]
+    fn previews_text_changes_as_unified_diff() {
+        let target = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let old = b"title=Hello\nquit=Goodbye\n";
+        let new = b"title=Hola\nquit=Goodbye\n";
+        fs::write(target.path().join("strings.txt"), old).unwrap();
+        fs::create_dir_all(patch_dir.path().join(DIFFS_DIR)).unwrap();
+        fs::write(patch_dir.path().join(diff_name("strings.txt", false)), create_diff(old, new).unwrap()).unwrap();
+
+        let preview = preview_entry(&patch_entry("strings.txt"), target.path(), patch_dir.path(), 100)
+            .unwrap()
+            .unwrap();
+        assert!(preview.starts_with("--- a/strings.txt\n+++ b/strings.txt\n"), "{}", preview);
+        assert!(preview.contains("-title=Hello\n+title=Hola\n"), "{}", preview);
+        assert!(preview.contains(" quit=Goodbye\n"), "{}", preview);
+    }
+
+    #[test]
+    fn skips_binary_files_and_caps_long_previews() {
+        let target = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        fs::create_dir_all(patch_dir.path().join(FILES_DIR)).unwrap();
+        let add = |file: &str, content: &[u8]| {
+            fs::write(patch_dir.path().join(added_name(file, false)), content).unwrap();
+            ManifestEntry::Add {
+                file: file.to_string(),
+                final_hash: "a".to_string(),
+                original_hash: None,
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: false,
+            }
+        };
+
+        let binary = add("voice.ogg", b"OggS\0\x02\xff");
+        assert_eq!(preview_entry(&binary, target.path(), patch_dir.path(), 100).unwrap(), None);
+
+        let lines: String = (0..50).map(|i| format!("line {}\n", i)).collect();
+        let long = add("credits.txt", lines.as_bytes());
+        let preview = preview_entry(&long, target.path(), patch_dir.path(), 10).unwrap().unwrap();
+        assert_eq!(preview.lines().count(), 11);
+        // 2 header lines, 1 hunk header and 50 added lines, 10 of them shown
+        assert!(preview.ends_with("... 43 more lines\n"), "{}", preview);
+    }
+}
@@ -0,0 +1,214 @@
+//! Locating patched files by content for manifests created with
+//! `match_by_content`.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::patch::PatchError;
+use crate::utils::dir_scan::list_files_recursive;
+use crate::utils::manifest::{Manifest, ManifestEntry};
+
+/// Find files the manifest expects to patch under a different name.
+///
+/// Does nothing unless `manifest.match_by_content` is set. Otherwise, for each
+/// Patch entry whose file is missing from the target, the target is searched
+/// for a file whose content matches `original_hash`. If one is found, a Rename
+/// entry moving it into place is inserted before the Patch entry, so the file
+/// is patched under the name the manifest expects.
+///
+/// Only files the manifest doesn't otherwise reference are considered, and
+/// each is used at most once. Patch entries with no match are left as they
+/// are (and fail validation as usual).
+///
+/// Returns the number of files located.
+pub fn resolve_by_content(manifest: &mut Manifest, target_dir: &Path) -> Result<usize, PatchError> {
+    if !manifest.match_by_content {
+        return Ok(0);
+    }
+
+    let missing = |e: &ManifestEntry| {
+        matches!(e, ManifestEntry::Patch { file, .. } if !target_dir.join(file).exists())
+    };
+    if !manifest.entries.iter().any(missing) {
+        return Ok(0);
+    }
+
+    let referenced: HashSet<&str> = manifest
+        .entries
+        .iter()
+        .flat_map(|e| match e {
+            ManifestEntry::Rename { from, to, .. } => vec![from.as_str(), to.as_str()],
+            other => vec![other.file()],
+        })
+        .collect();
+
+    // Hash each unreferenced target file once
+    let files = list_files_recursive(target_dir).map_err(|e| PatchError::ValidationFailed {
+        file: target_dir.display().to_string(),
+        reason: format!("failed to list target directory: {}", e),
+    })?;
+    let mut candidates: HashMap<String, String> = HashMap::new();
+    for file in files.into_iter().filter(|f| !referenced.contains(f.as_str())) {
+        let data = fs::read(target_dir.join(&file)).map_err(|e| PatchError::ValidationFailed {
+            file: file.clone(),
+            reason: format!("failed to read file: {}", e),
+        })?;
+        candidates.insert(file, manifest.hash_algorithm.hash_bytes(&data));
+    }
+
+    let mut located = 0;
+    let mut entries = Vec::with_capacity(manifest.entries.len());
+    for entry in manifest.entries.drain(..) {
+        if let ManifestEntry::Patch {
+            file,
+            original_hash,
+            ..
+        } = &entry
+            && !target_dir.join(file).exists()
+            && let Some(from) = take_match(&mut candidates, original_hash)
+        {
+            entries.push(ManifestEntry::Rename {
+                from,
+                to: file.clone(),
+                hash: original_hash.clone(),
+            });
+            located += 1;
+        }
+        entries.push(entry);
+    }
+    manifest.entries = entries;
+
+    Ok(located)
+}
+
+/// Remove and return the first (by name) candidate with the given hash.
+fn take_match(candidates: &mut HashMap<String, String>, hash: &str) -> Option<String> {
+    let file = candidates
+        .iter()
+        .filter(|(_, h)| *h == hash)
+        .map(|(f, _)| f)
+        .min()?
+        .clone();
+    candidates.remove(&file);
+    Some(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch::{
+        apply_entries, backup_entries, rollback, validate_entries, ByteProgress, Progress, Verification,
+        BACKUP_DIR, DIFFS_DIR, DIFF_EXTENSION,
+    };
+    use crate::utils::diff::create_diff;
+    use crate::utils::hash::{hash_bytes, Algorithm};
+    use tempfile::tempdir;
+
+    /// Manifest patching `expected.bin` from `original` to `modified`,
+    /// with the diff written to `patch_dir`.
+    fn patch_manifest(patch_dir: &Path, match_by_content: bool) -> Manifest {
+        let diff = create_diff(b"original", b"modified").unwrap();
+        fs::create_dir_all(patch_dir.join(DIFFS_DIR)).unwrap();
+        fs::write(
+            patch_dir.join(DIFFS_DIR).join(format!("expected.bin{}", DIFF_EXTENSION)),
+            &diff,
+        )
+        .unwrap();
+
+        Manifest {
+            match_by_content,
+            entries: vec![ManifestEntry::Patch {
+                file: "expected.bin".to_string(),
+                original_hash: hash_bytes(b"original"),
+                diff_hash: hash_bytes(&diff),
+                final_hash: hash_bytes(b"modified"),
+                final_hashes: vec![],
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: false,
+                line_endings: None,
+                encoding: None,
+            }],
+            ..Manifest::default()
+        }
+    }
+
+    #[test]
+    fn locates_renamed_file_by_hash() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("shuffled.bin"), b"original").unwrap();
+        fs::write(dir.path().join("other.bin"), b"other").unwrap();
+        let mut manifest = patch_manifest(&dir.path().join("patch"), true);
+
+        let located = resolve_by_content(&mut manifest, dir.path()).unwrap();
+
+        assert_eq!(located, 1);
+        assert_eq!(
+            manifest.entries[0],
+            ManifestEntry::Rename {
+                from: "shuffled.bin".to_string(),
+                to: "expected.bin".to_string(),
+                hash: hash_bytes(b"original"),
+            }
+        );
+        assert!(matches!(&manifest.entries[1], ManifestEntry::Patch { file, .. } if file == "expected.bin"));
+    }
+
+    #[test]
+    fn does_nothing_without_flag() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("shuffled.bin"), b"original").unwrap();
+        let mut manifest = patch_manifest(&dir.path().join("patch"), false);
+
+        assert_eq!(resolve_by_content(&mut manifest, dir.path()).unwrap(), 0);
+        assert_eq!(manifest.entries.len(), 1);
+    }
+
+    #[test]
+    fn leaves_present_files_alone() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("expected.bin"), b"original").unwrap();
+        fs::write(dir.path().join("copy.bin"), b"original").unwrap();
+        let mut manifest = patch_manifest(&dir.path().join("patch"), true);
+
+        assert_eq!(resolve_by_content(&mut manifest, dir.path()).unwrap(), 0);
+        assert_eq!(manifest.entries.len(), 1);
+    }
+
+    #[test]
+    fn resolved_patch_applies_and_rolls_back() {
+        let target = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        fs::write(target.path().join("shuffled.bin"), b"original").unwrap();
+        let mut manifest = patch_manifest(patch_dir.path(), true);
+        resolve_by_content(&mut manifest, target.path()).unwrap();
+        let backup_dir = target.path().join(BACKUP_DIR);
+
+        validate_entries(&manifest.entries, target.path(), Algorithm::Sha256, None::<fn(Progress)>).unwrap();
+        backup_entries(&manifest.entries, target.path(), &backup_dir, manifest.hash_algorithm, None::<fn(Progress)>).unwrap();
+        apply_entries(
+            &manifest.entries,
+            target.path(),
+            patch_dir.path(),
+            &backup_dir,
+            Verification::Full,
+            Algorithm::Sha256,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+        )
+        .unwrap();
+
+        assert_eq!(fs::read(target.path().join("expected.bin")).unwrap(), b"modified");
+        assert!(!target.path().join("shuffled.bin").exists());
+
+        let applied: Vec<_> = manifest.entries.iter().collect();
+        rollback(&applied, target.path(), &backup_dir, None::<fn(Progress)>).unwrap();
+
+        assert_eq!(fs::read(target.path().join("shuffled.bin")).unwrap(), b"original");
+        assert!(!target.path().join("expected.bin").exists());
+    }
+}
@@ -0,0 +1,201 @@
+use std::path::Path;
+
+use crate::patch::payload::{added_name, decompressed_size};
+use crate::patch::PatchError;
+use crate::utils::filesystem::{FileSystem, StdFs};
+use crate::utils::manifest::ManifestEntry;
+
+/// Check there is room to back up and apply `entries` before starting.
+///
+/// The bytes needed are estimated from what is on disk: an added file takes
+/// its size in the patch (once decompressed, if stored compressed), or the
+/// size the manifest records if it isn't in `patch_dir`, a patched file is
+/// assumed to stay the size it is now (apply writes it to a temp file before replacing the original), and
+/// every patched or deleted file, and every file an upsert replaces, is
+/// copied into the backup first. If
+/// `backup_dir` is outside `target_dir` the two are checked separately.
+///
+/// Returns `InsufficientSpace` if either volume is short. The check is
+/// skipped where free space can't be determined.
+pub fn check_space(
+    entries: &[ManifestEntry],
+    target_dir: &Path,
+    patch_dir: &Path,
+    backup_dir: &Path,
+) -> Result<(), PatchError> {
+    check_space_with(&StdFs, entries, target_dir, patch_dir, backup_dir)
+}
+
+/// Like `check_space`, using the given filesystem.
+pub fn check_space_with(
+    fs: &impl FileSystem,
+    entries: &[ManifestEntry],
+    target_dir: &Path,
+    patch_dir: &Path,
+    backup_dir: &Path,
+) -> Result<(), PatchError> {
+    // Missing files are left for validation to report
+    let size = |path: &Path| fs.file_size(path).unwrap_or(0);
+
+    let mut target_bytes = 0;
+    let mut backup_bytes = 0;
+    for entry in entries {
+        match entry {
+            ManifestEntry::Patch { file, .. } => {
+                let current = size(&target_dir.join(file));
+                target_bytes += current;
+                backup_bytes += current;
+            }
+            ManifestEntry::Add { file, compressed, original_hash, .. } => {
+                target_bytes += if !fs.exists(&patch_dir.join(added_name(file, *compressed))) {
+                    // Not extracted, e.g. applied straight from the archive
+                    entry.expected_size()
+                } else if *compressed {
+                    let data = fs.read(&patch_dir.join(added_name(file, true))).unwrap_or_default();
+                    decompressed_size(&data).unwrap_or(0)
+                } else {
+                    size(&patch_dir.join(added_name(file, false)))
+                };
+                // An upsert backs up the file it replaces
+                if original_hash.is_some() {
+                    backup_bytes += size(&target_dir.join(file));
+                }
+            }
+            ManifestEntry::Delete { file, .. } => {
+                backup_bytes += size(&target_dir.join(file));
+            }
+            ManifestEntry::Rename { .. }
+            | ManifestEntry::Symlink { .. }
+            | ManifestEntry::MkDir { .. }
+            | ManifestEntry::RmDir { .. } => {}
+        }
+    }
+
+    if backup_dir.starts_with(target_dir) {
+        ensure_available(fs, target_dir, target_bytes + backup_bytes)
+    } else {
+        ensure_available(fs, target_dir, target_bytes)?;
+        ensure_available(fs, backup_dir, backup_bytes)
+    }
+}
+
+/// Fail if fewer than `needed` bytes are free on the volume holding `dir`
+/// (or its nearest existing ancestor, as the backup may not exist yet).
+fn ensure_available(fs: &impl FileSystem, dir: &Path, needed: u64) -> Result<(), PatchError> {
+    if needed == 0 {
+        return Ok(());
+    }
+    let Some(existing) = dir.ancestors().find(|p| fs.exists(p)) else {
+        return Ok(());
+    };
+    match fs.available_space(existing) {
+        Ok(Some(available)) if available < needed => Err(PatchError::InsufficientSpace { needed, available }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch::BACKUP_DIR;
+    use crate::utils::filesystem::MemFs;
+
+    fn entries() -> Vec<ManifestEntry> {
+        vec![
+            ManifestEntry::Patch {
+                file: "game.dat".to_string(),
+                original_hash: String::new(),
+                diff_hash: String::new(),
+                final_hash: String::new(),
+                final_hashes: vec![],
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: false,
+                line_endings: None,
+                encoding: None,
+            },
+            ManifestEntry::Add {
+                file: "voice.pak".to_string(),
+                final_hash: String::new(),
+                original_hash: None,
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: false,
+            },
+            ManifestEntry::Delete {
+                file: "intro.bik".to_string(),
+                original_hash: String::new(),
+            },
+        ]
+    }
+
+    fn game_fs() -> MemFs {
+        let fs = MemFs::new();
+        fs.insert("/game/game.dat", vec![0; 100]);
+        fs.insert("/game/intro.bik", vec![0; 30]);
+        fs.insert("/patch/files/voice.pak", vec![0; 50]);
+        fs
+    }
+
+    #[test]
+    fn counts_new_files_and_backups() {
+        let fs = game_fs();
+        let check = || {
+            check_space_with(
+                &fs,
+                &entries(),
+                Path::new("/game"),
+                Path::new("/patch"),
+                &Path::new("/game").join(BACKUP_DIR),
+            )
+        };
+
+        // Patched file written and backed up, added file written, deleted
+        // file backed up
+        fs.set_available_space(Some(280));
+        assert!(check().is_ok());
+        fs.set_available_space(Some(279));
+        assert_eq!(
+            check(),
+            Err(PatchError::InsufficientSpace {
+                needed: 280,
+                available: 279
+            })
+        );
+
+        fs.set_available_space(None);
+        assert!(check().is_ok());
+    }
+
+    #[test]
+    fn separate_backup_dir_is_checked_on_its_own() {
+        let fs = game_fs();
+        fs.create_dir_all(Path::new("/scratch")).unwrap();
+        fs.set_available_space(Some(150));
+
+        // 150 to the target and 130 to the backup, each of which fits
+        let result = check_space_with(
+            &fs,
+            &entries(),
+            Path::new("/game"),
+            Path::new("/patch"),
+            Path::new("/scratch/backup"),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn shortage_is_reported_in_readable_units() {
+        let err = PatchError::InsufficientSpace {
+            needed: 3 * 1024 * 1024 / 2,
+            available: 512,
+        };
+        assert_eq!(err.to_string(), "not enough disk space: 1.5 MB needed, only 512 bytes available");
+    }
+}
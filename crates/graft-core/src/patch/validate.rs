@@ -1,12 +1,19 @@
-use crate::patch::constants::{DIFFS_DIR, DIFF_EXTENSION, FILES_DIR, MANIFEST_FILENAME};
+use crate::patch::backup::load_backup_hashes;
+use crate::patch::constants::{BACKUP_DIR, MANIFEST_FILENAME};
 use crate::patch::error::PatchError;
+use crate::patch::payload::{added_name, diff_name};
 use crate::patch::verify::verify_entry;
 use crate::patch::{Progress, ProgressAction};
 use crate::path_restrictions;
-use crate::utils::hash::hash_bytes;
+use crate::utils::filesystem::{FileSystem, StdFs};
+use crate::utils::hash::Algorithm;
 use crate::utils::manifest::{Manifest, ManifestEntry};
-use std::fs;
-use std::path::Path;
+use flate2::read::GzDecoder;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::fs::File;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
 
 /// Validate that a patch directory contains all required files.
 ///
@@ -31,21 +38,23 @@ pub fn validate_patch_dir(patch_dir: &Path) -> Result<Manifest, PatchError> {
     // Check all referenced files exist
     for entry in &manifest.entries {
         match entry {
-            ManifestEntry::Patch { file, .. } => {
-                let diff_path = patch_dir
-                    .join(DIFFS_DIR)
-                    .join(format!("{}{}", file, DIFF_EXTENSION));
+            ManifestEntry::Patch { file, compressed, .. } => {
+                let diff_path = patch_dir.join(diff_name(file, *compressed));
                 if !diff_path.exists() {
                     return Err(PatchError::DiffNotFound(file.clone()));
                 }
             }
-            ManifestEntry::Add { file, .. } => {
-                let file_path = patch_dir.join(FILES_DIR).join(file);
+            ManifestEntry::Add { file, compressed, .. } => {
+                let file_path = patch_dir.join(added_name(file, *compressed));
                 if !file_path.exists() {
                     return Err(PatchError::FileNotFound(file.clone()));
                 }
             }
-            ManifestEntry::Delete { .. } => {
+            ManifestEntry::Delete { .. }
+            | ManifestEntry::Rename { .. }
+            | ManifestEntry::Symlink { .. }
+            | ManifestEntry::MkDir { .. }
+            | ManifestEntry::RmDir { .. } => {
                 // Nothing to check - file should exist in target, not in patch
             }
         }
@@ -54,18 +63,100 @@ pub fn validate_patch_dir(patch_dir: &Path) -> Result<Manifest, PatchError> {
     Ok(manifest)
 }
 
+/// Validate that the diffs and added files in a patch directory are the ones
+/// the manifest was written for.
+///
+/// Checks that:
+/// - For Patch entries: the diff file's hash matches diff_hash
+/// - For Add entries: the added file's hash matches final_hash (after
+///   decompressing it, if it is stored compressed)
+///
+/// Call this with the validation of the target, before anything is backed
+/// up, so a corrupted download fails up front instead of partway through
+/// applying. Hashes are computed with `algorithm`, the manifest's
+/// `hash_algorithm`.
+pub fn validate_patch_files<F>(
+    entries: &[ManifestEntry],
+    patch_dir: &Path,
+    algorithm: Algorithm,
+    mut on_progress: Option<F>,
+) -> Result<(), PatchError>
+where
+    F: FnMut(Progress),
+{
+    let total = entries.len();
+    for (index, entry) in entries.iter().enumerate() {
+        let (path, expected, decompress) = match entry {
+            ManifestEntry::Patch { file, diff_hash, compressed, .. } => {
+                (patch_dir.join(diff_name(file, *compressed)), diff_hash, false)
+            }
+            ManifestEntry::Add { file, final_hash, compressed, .. } => {
+                (patch_dir.join(added_name(file, *compressed)), final_hash, *compressed)
+            }
+            ManifestEntry::Delete { .. }
+            | ManifestEntry::Rename { .. }
+            | ManifestEntry::Symlink { .. }
+            | ManifestEntry::MkDir { .. }
+            | ManifestEntry::RmDir { .. } => continue,
+        };
+        // Diffs are hashed as stored; added files by their decompressed content
+        if let Some(ref mut callback) = on_progress {
+            callback(Progress {
+                file: entry.file(),
+                label: entry.label(),
+                index,
+                total,
+                action: ProgressAction::Validating,
+            });
+        }
+
+        let actual = if decompress {
+            File::open(&path).and_then(|file| algorithm.hash_reader(GzDecoder::new(file)))
+        } else {
+            algorithm.hash_file(&path)
+        }
+        .map_err(|e| PatchError::ValidationFailed {
+            file: entry.file().to_string(),
+            reason: format!("failed to read patch file: {}", e),
+        })?;
+        if &actual != expected {
+            return Err(PatchError::ValidationFailed {
+                file: entry.file().to_string(),
+                reason: format!(
+                    "patch file is corrupted: expected hash {}, got {}",
+                    expected, actual
+                ),
+            });
+        }
+    }
+
+    Ok(())
+}
+
 /// Validate all manifest entries against a target directory before applying.
 ///
 /// Checks that:
 /// - For Patch entries: file exists and hash matches original_hash
 /// - For Add entries: file does NOT already exist
 /// - For Delete entries: if file exists, hash matches original_hash
+/// - For Rename entries: `from` exists with matching hash, `to` does NOT exist
+/// - For Symlink entries: the link points to `original_target`, or nothing
+///   exists at the path if there is none (already removed is fine), and a
+///   new target is relative and stays inside the target
+/// - For every entry: its paths stay inside the target and out of the
+///   backup directory (see `check_inside_target`), even for patches that
+///   allow restricted paths
+///
+/// A Patch entry for a file renamed by an earlier entry is checked at the
+/// file's current (pre-rename) location.
 ///
 /// This should be called before applying any changes to ensure the target
-/// directory is in the expected state.
+/// directory is in the expected state. Hashes are computed with `algorithm`,
+/// the manifest's `hash_algorithm`.
 pub fn validate_entries<F>(
     entries: &[ManifestEntry],
     target_dir: &Path,
+    algorithm: Algorithm,
     mut on_progress: Option<F>,
 ) -> Result<(), PatchError>
 where
@@ -73,41 +164,205 @@ where
 {
     let total = entries.len();
     for (index, entry) in entries.iter().enumerate() {
-        let action = match entry {
-            ManifestEntry::Patch { .. } => ProgressAction::Validating,
-            ManifestEntry::Add { .. } => ProgressAction::CheckingNotExists,
-            ManifestEntry::Delete { .. } => ProgressAction::Validating,
-        };
-
         if let Some(ref mut callback) = on_progress {
             callback(Progress {
                 file: entry.file(),
+                label: entry.label(),
                 index,
                 total,
-                action,
+                action: validate_action(entry),
             });
         }
-        match entry {
-            ManifestEntry::Patch {
-                file,
-                original_hash,
-                ..
-            } => {
-                let target_path = target_dir.join(file);
+        validate_entry(&entries[..index], entry, target_dir, algorithm, true)?;
+    }
+
+    Ok(())
+}
+
+/// Like `validate_entries`, but hashes entries on up to `workers` threads.
+///
+/// Progress is reported as entries start, so not necessarily in manifest
+/// order. If several entries fail, the error for the lowest-index one is
+/// returned, the same one `validate_entries` reports, regardless of which
+/// thread finished first.
+pub fn validate_entries_parallel<F>(
+    entries: &[ManifestEntry],
+    target_dir: &Path,
+    algorithm: Algorithm,
+    workers: usize,
+    on_progress: Option<F>,
+) -> Result<(), PatchError>
+where
+    F: FnMut(Progress) + Send,
+{
+    validate_entries_on(entries, target_dir, algorithm, workers, true, on_progress)
+}
+
+/// Like `validate_entries_parallel`, but without checking that patched
+/// files match their `original_hash`, for applying over an install that
+/// was already modified or is a slightly different build.
+///
+/// Every other check still applies; in particular, files to patch must
+/// exist. Nothing guarantees the diffs produce the right result from a
+/// different original, so every entry must be verified after applying.
+pub fn validate_entries_forced<F>(
+    entries: &[ManifestEntry],
+    target_dir: &Path,
+    algorithm: Algorithm,
+    workers: usize,
+    on_progress: Option<F>,
+) -> Result<(), PatchError>
+where
+    F: FnMut(Progress) + Send,
+{
+    validate_entries_on(entries, target_dir, algorithm, workers, false, on_progress)
+}
+
+/// Validate entries on up to `workers` threads, checking patched files'
+/// original hashes if `check_originals`.
+fn validate_entries_on<F>(
+    entries: &[ManifestEntry],
+    target_dir: &Path,
+    algorithm: Algorithm,
+    workers: usize,
+    check_originals: bool,
+    on_progress: Option<F>,
+) -> Result<(), PatchError>
+where
+    F: FnMut(Progress) + Send,
+{
+    let total = entries.len();
+    let on_progress = on_progress.map(Mutex::new);
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(workers.max(1))
+        .build()
+        .map_err(|e| PatchError::ValidationFailed {
+            file: String::new(),
+            reason: format!("failed to start validation threads: {}", e),
+        })?;
+
+    let first_error = pool.install(|| {
+        entries
+            .par_iter()
+            .enumerate()
+            .find_map_first(|(index, entry)| {
+                if let Some(callback) = &on_progress {
+                    let mut callback = callback.lock().unwrap();
+                    callback(Progress {
+                        file: entry.file(),
+                        label: entry.label(),
+                        index,
+                        total,
+                        action: validate_action(entry),
+                    });
+                }
+                validate_entry(&entries[..index], entry, target_dir, algorithm, check_originals).err()
+            })
+    });
+
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+fn validate_action(entry: &ManifestEntry) -> ProgressAction {
+    match entry {
+        ManifestEntry::Patch { .. } => ProgressAction::Validating,
+        ManifestEntry::Add { .. } | ManifestEntry::MkDir { .. } => ProgressAction::CheckingNotExists,
+        ManifestEntry::Delete { .. }
+        | ManifestEntry::Rename { .. }
+        | ManifestEntry::Symlink { .. }
+        | ManifestEntry::RmDir { .. } => ProgressAction::Validating,
+    }
+}
+
+/// Validate one entry; `earlier` are the entries before it in the manifest.
+/// Patched files are only compared to their `original_hash` if
+/// `check_originals`.
+fn validate_entry(
+    earlier: &[ManifestEntry],
+    entry: &ManifestEntry,
+    target_dir: &Path,
+    algorithm: Algorithm,
+    check_originals: bool,
+) -> Result<(), PatchError> {
+    check_inside_target(earlier, entry.file(), target_dir)?;
+    if let ManifestEntry::Rename { from, .. } = entry {
+        check_inside_target(earlier, from, target_dir)?;
+    }
+
+    match entry {
+        ManifestEntry::Patch {
+            file,
+            original_hash,
+            ..
+        } => {
+            let target_path = target_dir.join(renamed_from(earlier, file));
+
+            if !target_path.exists() {
+                return Err(PatchError::ValidationFailed {
+                    file: file.clone(),
+                    reason: "file not found in target".to_string(),
+                });
+            }
+            if !check_originals {
+                return Ok(());
+            }
+
+            let actual_hash = algorithm.hash_file(&target_path).map_err(|e| PatchError::ValidationFailed {
+                file: file.clone(),
+                reason: format!("failed to read file: {}", e),
+            })?;
+
+            if &actual_hash != original_hash {
+                return Err(PatchError::ValidationFailed {
+                    file: file.clone(),
+                    reason: format!(
+                        "hash mismatch: expected {}, got {}",
+                        original_hash, actual_hash
+                    ),
+                });
+            }
+        }
+        ManifestEntry::Add { file, original_hash, .. } => {
+            let target_path = target_dir.join(file);
 
-                if !target_path.exists() {
+            // An upsert replaces the file if it's there, but only the
+            // version it was made from
+            match original_hash {
+                _ if !target_path.exists() => {}
+                None => {
                     return Err(PatchError::ValidationFailed {
                         file: file.clone(),
-                        reason: "file not found in target".to_string(),
+                        reason: "file already exists in target".to_string(),
                     });
                 }
+                Some(_) if !check_originals => {}
+                Some(original_hash) => {
+                    let actual_hash = algorithm.hash_file(&target_path).map_err(|e| PatchError::ValidationFailed {
+                        file: file.clone(),
+                        reason: format!("failed to read file: {}", e),
+                    })?;
+                    if &actual_hash != original_hash {
+                        return Err(PatchError::ValidationFailed {
+                            file: file.clone(),
+                            reason: format!("hash mismatch: expected {}, got {}", original_hash, actual_hash),
+                        });
+                    }
+                }
+            }
+        }
+        ManifestEntry::Delete { file, original_hash } => {
+            let target_path = target_dir.join(file);
 
-                let data = fs::read(&target_path).map_err(|e| PatchError::ValidationFailed {
+            // Only validate hash if file exists - already gone is fine
+            if target_path.exists() {
+                let actual_hash = algorithm.hash_file(&target_path).map_err(|e| PatchError::ValidationFailed {
                     file: file.clone(),
                     reason: format!("failed to read file: {}", e),
                 })?;
 
-                let actual_hash = hash_bytes(&data);
                 if &actual_hash != original_hash {
                     return Err(PatchError::ValidationFailed {
                         file: file.clone(),
@@ -118,37 +373,93 @@ where
                     });
                 }
             }
-            ManifestEntry::Add { file, .. } => {
-                let target_path = target_dir.join(file);
+        }
+        ManifestEntry::Rename { from, to, hash } => {
+            let source_path = target_dir.join(from);
 
-                if target_path.exists() {
-                    return Err(PatchError::ValidationFailed {
-                        file: file.clone(),
-                        reason: "file already exists in target".to_string(),
-                    });
-                }
+            if !source_path.exists() {
+                return Err(PatchError::ValidationFailed {
+                    file: from.clone(),
+                    reason: "file not found in target".to_string(),
+                });
+            }
+            if target_dir.join(to).exists() {
+                return Err(PatchError::ValidationFailed {
+                    file: to.clone(),
+                    reason: "file already exists in target".to_string(),
+                });
             }
-            ManifestEntry::Delete { file, original_hash } => {
-                let target_path = target_dir.join(file);
 
-                // Only validate hash if file exists - already gone is fine
-                if target_path.exists() {
-                    let data = fs::read(&target_path).map_err(|e| PatchError::ValidationFailed {
-                        file: file.clone(),
-                        reason: format!("failed to read file: {}", e),
-                    })?;
+            let actual_hash = algorithm.hash_file(&source_path).map_err(|e| PatchError::ValidationFailed {
+                file: from.clone(),
+                reason: format!("failed to read file: {}", e),
+            })?;
 
-                    let actual_hash = hash_bytes(&data);
-                    if &actual_hash != original_hash {
-                        return Err(PatchError::ValidationFailed {
-                            file: file.clone(),
-                            reason: format!(
-                                "hash mismatch: expected {}, got {}",
-                                original_hash, actual_hash
-                            ),
-                        });
-                    }
-                }
+            if &actual_hash != hash {
+                return Err(PatchError::ValidationFailed {
+                    file: from.clone(),
+                    reason: format!("hash mismatch: expected {}, got {}", hash, actual_hash),
+                });
+            }
+        }
+        ManifestEntry::Symlink {
+            file,
+            original_target,
+            target,
+        } => {
+            if target.is_some() && !cfg!(unix) {
+                return Err(PatchError::ValidationFailed {
+                    file: file.clone(),
+                    reason: "symbolic links are not supported on this platform".to_string(),
+                });
+            }
+            // Checked whatever `allow_restricted` says: the patch's author
+            // sets that, and a link out of the target lets later writes
+            // land anywhere
+            if let Some(target) = target
+                && path_restrictions::check_link_target(file, target).is_err()
+            {
+                return Err(PatchError::ValidationFailed {
+                    file: file.clone(),
+                    reason: format!("symlink target '{}' is absolute or leaves the target directory", target),
+                });
+            }
+
+            let current = link_target(&target_dir.join(file)).map_err(|reason| PatchError::ValidationFailed {
+                file: file.clone(),
+                reason,
+            })?;
+            let removed = original_target.is_some() && target.is_none() && current.is_none();
+            if current != *original_target && !removed {
+                return Err(PatchError::ValidationFailed {
+                    file: file.clone(),
+                    reason: match (original_target, current) {
+                        (Some(expected), Some(actual)) => {
+                            format!("symlink points to '{}', expected '{}'", actual, expected)
+                        }
+                        (Some(_), None) => "symlink not found in target".to_string(),
+                        (None, _) => "symlink already exists in target".to_string(),
+                    },
+                });
+            }
+        }
+        ManifestEntry::MkDir { dir } => {
+            if target_dir.join(dir).exists() {
+                return Err(PatchError::ValidationFailed {
+                    file: dir.clone(),
+                    reason: "directory already exists in target".to_string(),
+                });
+            }
+        }
+        ManifestEntry::RmDir { dir } => {
+            // Already gone is fine, as for Delete; its contents are removed
+            // by the entries before it
+            let target_path = target_dir.join(dir);
+            if target_path.exists() && !StdFs.is_dir(&target_path) {
+                return Err(PatchError::ValidationFailed {
+                    file: dir.clone(),
+                    reason: "expected a directory, found a file".to_string(),
+                });
             }
         }
     }
@@ -156,38 +467,177 @@ where
     Ok(())
 }
 
+/// Where the symlink at `path` points, or `None` if nothing is there.
+///
+/// Fails with a reason if `path` exists but isn't a symlink, or can't be
+/// read.
+fn link_target(path: &Path) -> Result<Option<String>, String> {
+    match std::fs::symlink_metadata(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("failed to read file: {}", e)),
+        Ok(metadata) if !metadata.file_type().is_symlink() => {
+            Err("expected a symlink, found a regular file or directory".to_string())
+        }
+        Ok(_) => std::fs::read_link(path)
+            .map(|target| Some(target.to_string_lossy().into_owned()))
+            .map_err(|e| format!("failed to read symlink: {}", e)),
+    }
+}
+
+/// Check that an entry's `path` can't reach outside `target_dir`: it must be
+/// relative, without `..` components, and its parent must not lead out of
+/// the target through a symlinked directory, or through a symlink created
+/// by one of the `earlier` entries at all. It must not be in the backup
+/// directory either (see `in_backup_dir`).
+///
+/// The manifest comes from whoever made the patch, so this is what stops a
+/// malicious one from writing over `../../etc/passwd`.
+fn check_inside_target(earlier: &[ManifestEntry], path: &str, target_dir: &Path) -> Result<(), PatchError> {
+    let outside = |reason: &str| PatchError::ValidationFailed {
+        file: path.to_string(),
+        reason: reason.to_string(),
+    };
+    let relative = Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !relative {
+        return Err(outside("path is absolute or leaves the target directory"));
+    }
+    if in_backup_dir(path) {
+        return Err(outside(&format!("path is inside the backup directory {}", BACKUP_DIR)));
+    }
+    // Links made by earlier entries aren't on disk yet to be resolved below
+    let new_link = earlier.iter().find_map(|entry| match entry {
+        ManifestEntry::Symlink {
+            file, target: Some(_), ..
+        } if is_under(path, file) => Some(file),
+        _ => None,
+    });
+    if let Some(link) = new_link {
+        return Err(outside(&format!("path leads through the symlink {} the patch creates", link)));
+    }
+
+    // A missing target is reported by the other checks
+    let Ok(root) = target_dir.canonicalize() else {
+        return Ok(());
+    };
+    // Resolve the closest existing directory above the path; the path
+    // itself may be a symlink an entry replaces
+    let mut dir = target_dir.join(path);
+    dir.pop();
+    while !dir.exists() && dir.pop() {}
+    if let Ok(real) = dir.canonicalize()
+        && !real.starts_with(&root)
+    {
+        return Err(outside("path leads out of the target directory through a symlink"));
+    }
+    Ok(())
+}
+
+/// Whether the relative `path` is strictly inside `dir`, comparing whole
+/// components.
+fn is_under(path: &str, dir: &str) -> bool {
+    fn normal(path: &str) -> Vec<Component<'_>> {
+        Path::new(path).components().filter(|c| !matches!(c, Component::CurDir)).collect()
+    }
+    let (path, dir) = (normal(path), normal(dir));
+    path.len() > dir.len() && path.starts_with(&dir)
+}
+
+/// Whether the relative `path` is the target's backup directory or inside
+/// it, compared without case as on Windows. The patcher writes its backups
+/// there, so an entry for such a path would overwrite them (or be
+/// overwritten).
+pub(crate) fn in_backup_dir(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .find(|c| !matches!(c, Component::CurDir))
+        .is_some_and(|c| c.as_os_str().eq_ignore_ascii_case(BACKUP_DIR))
+}
+
+/// Where `file` is before `earlier` entries are applied: the source of the
+/// last Rename to `file`, or `file` itself.
+fn renamed_from<'a>(earlier: &'a [ManifestEntry], file: &'a str) -> &'a str {
+    earlier
+        .iter()
+        .rev()
+        .find_map(|e| match e {
+            ManifestEntry::Rename { from, to, .. } if to == file => Some(from.as_str()),
+            _ => None,
+        })
+        .unwrap_or(file)
+}
+
 /// Validate that backup directory contains expected files with correct hashes.
 ///
 /// This should be called before rolling back to ensure the backup is intact.
 ///
 /// Checks that:
-/// - For Patch entries: backup file MUST exist with hash matching original_hash
-/// - For Delete entries: if backup exists, hash MUST match original_hash (missing OK)
-/// - For Add entries: no backup expected
+/// - For Patch entries: backup file MUST exist with hash matching original_hash,
+///   or the hash recorded when it was taken (a forced apply backs up
+///   originals that differ from the manifest)
+/// - For Delete entries, and Add entries with an original_hash (upserts): if
+///   backup exists, hash MUST match original_hash (missing OK)
+/// - For other Add entries, and Rename, Symlink, MkDir and RmDir entries: no
+///   backup expected
+///
+/// Any file whose hash was recorded when the backup was taken must also
+/// still exist with that hash, or `VerificationFailed` is returned; backups
+/// from before hashes were recorded get only the checks above.
 pub fn validate_backup<F>(
     entries: &[ManifestEntry],
     backup_dir: &Path,
+    algorithm: Algorithm,
     mut on_progress: Option<F>,
 ) -> Result<(), PatchError>
 where
     F: FnMut(Progress),
 {
+    let recorded = load_backup_hashes(&StdFs, backup_dir).map_err(|e| PatchError::RollbackFailed {
+        reason: format!("failed to read backup hashes: {}", e),
+    })?;
+
     let total = entries.len();
     for (index, entry) in entries.iter().enumerate() {
         let action = match entry {
             ManifestEntry::Patch { .. } => ProgressAction::Validating,
-            ManifestEntry::Add { .. } => ProgressAction::Skipping,
-            ManifestEntry::Delete { .. } => ProgressAction::Validating,
+            ManifestEntry::Add { original_hash: None, .. }
+            | ManifestEntry::Rename { .. }
+            | ManifestEntry::Symlink { .. }
+            | ManifestEntry::MkDir { .. }
+            | ManifestEntry::RmDir { .. } => ProgressAction::Skipping,
+            ManifestEntry::Delete { .. } | ManifestEntry::Add { .. } => ProgressAction::Validating,
         };
 
         if let Some(ref mut callback) = on_progress {
             callback(Progress {
                 file: entry.file(),
+                label: entry.label(),
                 index,
                 total,
                 action,
             });
         }
+
+        let recorded_hash = recorded.get(entry.file());
+        if let Some(expected) = recorded_hash {
+            let backup_path = backup_dir.join(entry.file());
+            let actual = if backup_path.exists() {
+                algorithm.hash_file(&backup_path).map_err(|e| PatchError::RollbackFailed {
+                    reason: format!("failed to read backup '{}': {}", entry.file(), e),
+                })?
+            } else {
+                "(backup missing)".to_string()
+            };
+            if &actual != expected {
+                return Err(PatchError::VerificationFailed {
+                    file: entry.file().to_string(),
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+
         match entry {
             ManifestEntry::Patch {
                 file,
@@ -200,11 +650,11 @@ where
                         reason: format!("backup file not found: {}", file),
                     });
                 }
-                let data = fs::read(&backup_path).map_err(|e| PatchError::RollbackFailed {
+                let actual_hash = algorithm.hash_file(&backup_path).map_err(|e| PatchError::RollbackFailed {
                     reason: format!("failed to read backup '{}': {}", file, e),
                 })?;
-                let actual_hash = hash_bytes(&data);
-                if &actual_hash != original_hash {
+
+                if &actual_hash != original_hash && recorded_hash != Some(&actual_hash) {
                     return Err(PatchError::RollbackFailed {
                         reason: format!(
                             "backup hash mismatch for '{}': expected {}, got {}",
@@ -213,14 +663,19 @@ where
                     });
                 }
             }
-            ManifestEntry::Delete { file, original_hash } => {
+            ManifestEntry::Delete { file, original_hash }
+            | ManifestEntry::Add {
+                file,
+                original_hash: Some(original_hash),
+                ..
+            } => {
                 let backup_path = backup_dir.join(file);
                 if backup_path.exists() {
-                    let data = fs::read(&backup_path).map_err(|e| PatchError::RollbackFailed {
+                    let actual_hash = algorithm.hash_file(&backup_path).map_err(|e| PatchError::RollbackFailed {
                         reason: format!("failed to read backup '{}': {}", file, e),
                     })?;
-                    let actual_hash = hash_bytes(&data);
-                    if &actual_hash != original_hash {
+
+                    if &actual_hash != original_hash && recorded_hash != Some(&actual_hash) {
                         return Err(PatchError::RollbackFailed {
                             reason: format!(
                                 "backup hash mismatch for '{}': expected {}, got {}",
@@ -230,26 +685,58 @@ where
                     }
                 }
             }
-            ManifestEntry::Add { .. } => {
-                // No backup for added files
+            ManifestEntry::Add { original_hash: None, .. }
+            | ManifestEntry::Rename { .. }
+            | ManifestEntry::Symlink { .. }
+            | ManifestEntry::MkDir { .. }
+            | ManifestEntry::RmDir { .. } => {
+                // No backup for added or renamed files, symlinks (the
+                // manifest records where they pointed) or directories
             }
         }
     }
     Ok(())
 }
 
+/// Whether `manifest` can be rolled back on `target_dir` from the backup in
+/// `BACKUP_DIR`, without rolling anything back.
+///
+/// Returns false if there is no backup, or if `validate_backup` would reject
+/// it (a backup file is missing or damaged), so callers can disable a revert
+/// option up front instead of failing partway through a rollback. Fails only
+/// if the backup directory can't be inspected at all.
+pub fn rollback_available(target_dir: &Path, manifest: &Manifest) -> Result<bool, PatchError> {
+    let backup_dir = target_dir.join(BACKUP_DIR);
+    match std::fs::metadata(&backup_dir) {
+        Ok(metadata) if metadata.is_dir() => {}
+        Ok(_) => return Ok(false),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => {
+            return Err(PatchError::RollbackFailed {
+                reason: format!("failed to read backup directory '{}': {}", backup_dir.display(), e),
+            })
+        }
+    }
+    Ok(validate_backup(&manifest.entries, &backup_dir, manifest.hash_algorithm, None::<fn(Progress)>).is_ok())
+}
+
 /// Validate that all entries are in their expected post-patch state.
 ///
 /// This verifies:
 /// - Patch entries: file exists and matches final_hash
 /// - Add entries: file exists and matches final_hash
 /// - Delete entries: file does not exist
+/// - Rename entries: file exists at `to` with matching hash, not at `from`
+/// - Symlink entries: the link points to `target`, or is gone if there is none
+/// - MkDir entries: the directory exists
+/// - RmDir entries: the directory does not exist
 ///
 /// Use this before rollback to ensure patched files haven't been modified,
 /// or after apply to confirm patches were applied correctly.
 pub fn validate_patched_entries<F>(
     entries: &[ManifestEntry],
     target_dir: &Path,
+    algorithm: Algorithm,
     mut on_progress: Option<F>,
 ) -> Result<(), PatchError>
 where
@@ -260,17 +747,101 @@ where
         if let Some(ref mut callback) = on_progress {
             callback(Progress {
                 file: entry.file(),
+                label: entry.label(),
                 index,
                 total,
                 action: ProgressAction::Validating,
             });
         }
 
-        verify_entry(entry, target_dir)?;
+        verify_entry(entry, target_dir, algorithm)?;
     }
     Ok(())
 }
 
+/// Find entries whose target no longer matches the state the patch left it in.
+///
+/// Returns one error per modified entry, as `validate_patched_entries` would
+/// report it. Call this right before restoring so that files changed outside
+/// graft since the patch was applied aren't overwritten without notice.
+pub fn find_modified_entries(
+    entries: &[ManifestEntry],
+    target_dir: &Path,
+    algorithm: Algorithm,
+) -> Vec<PatchError> {
+    entries
+        .iter()
+        .filter_map(|entry| verify_entry(entry, target_dir, algorithm).err())
+        .collect()
+}
+
+/// Remove entries that are already in their post-patch state, returning them.
+///
+/// Used to resume an apply that was interrupted partway: entries the earlier
+/// run finished are dropped, so validation, backup and apply only see what is
+/// left to do. Only call this when that run's backup exists; on a fresh
+/// target, a file that already matches its final hash should fail validation
+/// rather than be silently skipped.
+pub fn remove_applied_entries(
+    entries: &mut Vec<ManifestEntry>,
+    target_dir: &Path,
+    algorithm: Algorithm,
+) -> Vec<ManifestEntry> {
+    let (applied, remaining) = entries
+        .drain(..)
+        .partition(|entry| verify_entry(entry, target_dir, algorithm).is_ok());
+    *entries = remaining;
+    applied
+}
+
+/// The directory a manifest's entries are relative to: `target_dir` joined
+/// with the manifest's `base_dir`, or `target_dir` itself without one.
+///
+/// Fails if `base_dir` is absolute or climbs out of the target with `..`,
+/// even for patches that allow restricted paths.
+pub fn install_dir(manifest: &Manifest, target_dir: &Path) -> Result<PathBuf, PatchError> {
+    let Some(base_dir) = &manifest.base_dir else {
+        return Ok(target_dir.to_path_buf());
+    };
+    let inside = Path::new(base_dir)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !inside {
+        return Err(PatchError::ManifestError {
+            reason: format!("base_dir must be a relative path inside the target: {}", base_dir),
+        });
+    }
+    Ok(target_dir.join(base_dir))
+}
+
+/// Check that the target is the game version the patch is for, when the
+/// manifest names a `version_file`: the file must hash to its recorded hash.
+///
+/// On a mismatch the error names the installed version if the hash is one
+/// of the manifest's `known_versions`. Manifests without a version file
+/// always pass.
+pub fn validate_game_version(manifest: &Manifest, target_dir: &Path) -> Result<(), PatchError> {
+    let Some(version_file) = &manifest.version_file else {
+        return Ok(());
+    };
+    let actual = manifest.hash_algorithm.hash_file(&target_dir.join(&version_file.file)).ok();
+    if actual.as_ref() == Some(&version_file.hash) {
+        return Ok(());
+    }
+    let found = actual.and_then(|actual| {
+        version_file
+            .known_versions
+            .iter()
+            .find(|(_, hash)| **hash == actual)
+            .map(|(version, _)| version.clone())
+    });
+    Err(PatchError::WrongGameVersion {
+        file: version_file.file.clone(),
+        expected: manifest.target_game_version.clone(),
+        found,
+    })
+}
+
 /// Validate that a manifest's paths don't violate security restrictions.
 ///
 /// When `manifest.allow_restricted` is false (the default), this checks:
@@ -290,15 +861,58 @@ pub fn validate_path_restrictions(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::patch::{backup_entries, BACKUP_DIR, BACKUP_HASHES_FILENAME};
+    use crate::utils::hash::hash_bytes;
+    use crate::utils::manifest::with_manifest_hash;
     use std::fs;
     use tempfile::tempdir;
 
+    #[test]
+    fn install_dir_joins_base_dir() {
+        let target = Path::new("/games/example");
+        let mut manifest = Manifest::default();
+        assert_eq!(install_dir(&manifest, target).unwrap(), target);
+
+        manifest.base_dir = Some("data/localization".to_string());
+        assert_eq!(install_dir(&manifest, target).unwrap(), target.join("data/localization"));
+
+        for base_dir in ["../elsewhere", "data/../../elsewhere", "/etc"] {
+            manifest.base_dir = Some(base_dir.to_string());
+            assert!(matches!(install_dir(&manifest, target), Err(PatchError::ManifestError { .. })));
+        }
+    }
+
+    #[test]
+    fn game_version_is_checked_against_version_file() {
+        let target = tempdir().unwrap();
+        fs::write(target.path().join("game.exe"), b"game 1.5").unwrap();
+
+        let mut manifest = Manifest::default();
+        assert!(validate_game_version(&manifest, target.path()).is_ok());
+
+        manifest.target_game_version = Some("1.4".to_string());
+        manifest.version_file = Some(crate::utils::manifest::VersionFile {
+            file: "game.exe".to_string(),
+            hash: hash_bytes(b"game 1.4"),
+            known_versions: [("1.5".to_string(), hash_bytes(b"game 1.5"))].into(),
+        });
+        let err = validate_game_version(&manifest, target.path()).unwrap_err();
+        assert_eq!(err.to_string(), "this patch is for version 1.4 of the game but your install looks like 1.5");
+
+        fs::write(target.path().join("game.exe"), b"game 1.6").unwrap();
+        let err = validate_game_version(&manifest, target.path()).unwrap_err();
+        assert!(matches!(err, PatchError::WrongGameVersion { found: None, .. }));
+
+        fs::write(target.path().join("game.exe"), b"game 1.4").unwrap();
+        assert!(validate_game_version(&manifest, target.path()).is_ok());
+    }
+
     #[test]
     fn validates_empty_patch() {
         let dir = tempdir().unwrap();
         fs::write(
             dir.path().join("manifest.json"),
-            r#"{"version": 1, "name": "TestPatcher", "entries": []}"#,
+            with_manifest_hash(r#"{"version": 1, "name": "TestPatcher", "entries": []}"#),
         )
         .unwrap();
 
@@ -318,9 +932,9 @@ mod tests {
         let dir = tempdir().unwrap();
         fs::write(
             dir.path().join("manifest.json"),
-            r#"{"version": 1, "name": "TestPatcher", "entries": [
+            with_manifest_hash(r#"{"version": 1, "name": "TestPatcher", "entries": [
                 {"operation": "patch", "file": "test.bin", "original_hash": "a", "diff_hash": "b", "final_hash": "c"}
-            ]}"#,
+            ]}"#),
         )
         .unwrap();
 
@@ -333,9 +947,9 @@ mod tests {
         let dir = tempdir().unwrap();
         fs::write(
             dir.path().join("manifest.json"),
-            r#"{"version": 1, "name": "TestPatcher", "entries": [
+            with_manifest_hash(r#"{"version": 1, "name": "TestPatcher", "entries": [
                 {"operation": "add", "file": "new.bin", "final_hash": "a"}
-            ]}"#,
+            ]}"#),
         )
         .unwrap();
 
@@ -350,11 +964,11 @@ mod tests {
         // Create manifest
         fs::write(
             dir.path().join("manifest.json"),
-            r#"{"version": 1, "name": "TestPatcher", "entries": [
+            with_manifest_hash(r#"{"version": 1, "name": "TestPatcher", "entries": [
                 {"operation": "patch", "file": "modified.bin", "original_hash": "a", "diff_hash": "b", "final_hash": "c"},
                 {"operation": "add", "file": "new.bin", "final_hash": "d"},
                 {"operation": "delete", "file": "old.bin", "original_hash": "e"}
-            ]}"#,
+            ]}"#),
         )
         .unwrap();
 
@@ -372,4 +986,348 @@ mod tests {
         let manifest = result.unwrap();
         assert_eq!(manifest.entries.len(), 3);
     }
+
+    #[test]
+    fn patch_files_must_match_manifest_hashes() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("diffs")).unwrap();
+        fs::write(dir.path().join("diffs/modified.bin.diff"), b"diff data").unwrap();
+        fs::create_dir(dir.path().join("files")).unwrap();
+        fs::write(dir.path().join("files/new.bin"), b"new file data").unwrap();
+
+        let mut entries = vec![
+            ManifestEntry::Patch {
+                file: "modified.bin".to_string(),
+                original_hash: "a".to_string(),
+                diff_hash: hash_bytes(b"diff data"),
+                final_hash: "c".to_string(),
+                final_hashes: vec![],
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: false,
+                line_endings: None,
+                encoding: None,
+            },
+            ManifestEntry::Add {
+                file: "new.bin".to_string(),
+                final_hash: hash_bytes(b"new file data"),
+                original_hash: None,
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: false,
+            },
+            ManifestEntry::Delete {
+                file: "old.bin".to_string(),
+                original_hash: "e".to_string(),
+            },
+        ];
+        validate_patch_files(&entries, dir.path(), Algorithm::Sha256, None::<fn(Progress)>).unwrap();
+
+        fs::write(dir.path().join("files/new.bin"), b"truncated").unwrap();
+        let result = validate_patch_files(&entries, dir.path(), Algorithm::Sha256, None::<fn(Progress)>);
+        assert!(matches!(result, Err(PatchError::ValidationFailed { file, .. }) if file == "new.bin"));
+
+        entries.remove(1);
+        fs::write(dir.path().join("diffs/modified.bin.diff"), b"corrupted").unwrap();
+        let result = validate_patch_files(&entries, dir.path(), Algorithm::Sha256, None::<fn(Progress)>);
+        assert!(matches!(result, Err(PatchError::ValidationFailed { file, .. }) if file == "modified.bin"));
+    }
+
+    #[test]
+    fn find_modified_entries_reports_changed_files() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("patched.bin"), b"patched").unwrap();
+        fs::write(dir.path().join("changed.bin"), b"edited by hand").unwrap();
+
+        let entries = vec![
+            ManifestEntry::Add {
+                file: "patched.bin".to_string(),
+                final_hash: hash_bytes(b"patched"),
+                original_hash: None,
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: false,
+            },
+            ManifestEntry::Add {
+                file: "changed.bin".to_string(),
+                final_hash: hash_bytes(b"patched"),
+                original_hash: None,
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: false,
+            },
+        ];
+
+        let modified = find_modified_entries(&entries, dir.path(), Algorithm::Sha256);
+
+        assert_eq!(modified.len(), 1);
+        assert!(matches!(&modified[0], PatchError::VerificationFailed { file, .. } if file == "changed.bin"));
+    }
+
+    #[test]
+    fn remove_applied_entries_keeps_unfinished_work() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("done.bin"), b"patched").unwrap();
+        fs::write(dir.path().join("todo.bin"), b"original").unwrap();
+
+        let patch = |file: &str| ManifestEntry::Patch {
+            file: file.to_string(),
+            original_hash: hash_bytes(b"original"),
+            diff_hash: String::new(),
+            final_hash: hash_bytes(b"patched"),
+            final_hashes: vec![],
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+            line_endings: None,
+            encoding: None,
+        };
+        let mut entries = vec![patch("done.bin"), patch("todo.bin")];
+
+        let applied = remove_applied_entries(&mut entries, dir.path(), Algorithm::Sha256);
+
+        assert_eq!(applied.iter().map(|e| e.file()).collect::<Vec<_>>(), ["done.bin"]);
+        assert_eq!(entries.iter().map(|e| e.file()).collect::<Vec<_>>(), ["todo.bin"]);
+    }
+
+    /// Many Patch entries; every one from `bad_from` on has the wrong hash.
+    fn many_entries(dir: &Path, count: usize, bad_from: usize) -> Vec<ManifestEntry> {
+        (0..count)
+            .map(|i| {
+                let file = format!("text_{:03}.txt", i);
+                let content = format!("line {}", i);
+                let on_disk = if i >= bad_from { "edited" } else { content.as_str() };
+                fs::write(dir.join(&file), on_disk).unwrap();
+                ManifestEntry::Patch {
+                    file,
+                    original_hash: hash_bytes(content.as_bytes()),
+                    diff_hash: String::new(),
+                    final_hash: String::new(),
+                    final_hashes: vec![],
+                    size: None,
+                    critical: false,
+                    label: None,
+                    mode: None,
+                    mtime: None,
+                    compressed: false,
+                    line_endings: None,
+                    encoding: None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parallel_validation_accepts_valid_target() {
+        let dir = tempdir().unwrap();
+        let entries = many_entries(dir.path(), 100, usize::MAX);
+
+        let mut reports = 0;
+        validate_entries_parallel(&entries, dir.path(), Algorithm::Sha256, 4, Some(|_: Progress| reports += 1)).unwrap();
+        assert_eq!(reports, 100);
+    }
+
+    #[test]
+    fn parallel_validation_reports_lowest_index_failure() {
+        let dir = tempdir().unwrap();
+        let entries = many_entries(dir.path(), 100, 37);
+
+        let sequential = validate_entries(&entries, dir.path(), Algorithm::Sha256, None::<fn(Progress)>).unwrap_err();
+        // Repeat so a lucky thread schedule can't hide a nondeterministic pick
+        for _ in 0..10 {
+            let parallel =
+                validate_entries_parallel(&entries, dir.path(), Algorithm::Sha256, 8, None::<fn(Progress)>).unwrap_err();
+            assert!(matches!(&parallel, PatchError::ValidationFailed { file, .. } if file == "text_037.txt"));
+            assert_eq!(parallel.to_string(), sequential.to_string());
+        }
+    }
+
+    #[test]
+    fn backup_validation_checks_recorded_hashes() {
+        let target = tempdir().unwrap();
+        let backup_dir = target.path().join(BACKUP_DIR);
+        fs::write(target.path().join("old.txt"), b"to delete").unwrap();
+        let entries = vec![ManifestEntry::Delete {
+            file: "old.txt".to_string(),
+            original_hash: hash_bytes(b"to delete"),
+        }];
+        let validate = || validate_backup(&entries, &backup_dir, Algorithm::Sha256, None::<fn(Progress)>);
+
+        backup_entries(&entries, target.path(), &backup_dir, Algorithm::Sha256, None::<fn(Progress)>).unwrap();
+        assert!(validate().is_ok());
+
+        // A deleted file's backup can legitimately be absent, but not once
+        // the backup recorded taking it
+        fs::write(backup_dir.join("old.txt"), b"to del").unwrap();
+        assert!(matches!(validate(), Err(PatchError::VerificationFailed { file, .. }) if file == "old.txt"));
+        fs::remove_file(backup_dir.join("old.txt")).unwrap();
+        assert!(matches!(validate(), Err(PatchError::VerificationFailed { file, .. }) if file == "old.txt"));
+
+        // Without the index, as in backups from older versions
+        fs::remove_file(backup_dir.join(BACKUP_HASHES_FILENAME)).unwrap();
+        assert!(validate().is_ok());
+    }
+
+    #[test]
+    fn rollback_is_available_only_with_a_valid_backup() {
+        let target = tempdir().unwrap();
+        fs::write(target.path().join("game.bin"), b"original").unwrap();
+        let manifest = Manifest {
+            entries: vec![ManifestEntry::Delete {
+                file: "game.bin".to_string(),
+                original_hash: hash_bytes(b"original"),
+            }],
+            ..Manifest::default()
+        };
+        assert!(!rollback_available(target.path(), &manifest).unwrap());
+
+        let backup_dir = target.path().join(BACKUP_DIR);
+        backup_entries(&manifest.entries, target.path(), &backup_dir, Algorithm::Sha256, None::<fn(Progress)>).unwrap();
+        assert!(rollback_available(target.path(), &manifest).unwrap());
+
+        fs::write(backup_dir.join("game.bin"), b"damaged").unwrap();
+        assert!(!rollback_available(target.path(), &manifest).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_entries_check_current_target() {
+        let dir = tempdir().unwrap();
+        std::os::unix::fs::symlink("engine.cfg", dir.path().join("current.cfg")).unwrap();
+        fs::write(dir.path().join("game.dat"), b"data").unwrap();
+        let symlink = |file: &str, original_target: Option<&str>, target: Option<&str>| ManifestEntry::Symlink {
+            file: file.to_string(),
+            original_target: original_target.map(str::to_string),
+            target: target.map(str::to_string),
+        };
+        let validate = |entry| validate_entries(&[entry], dir.path(), Algorithm::Sha256, None::<fn(Progress)>);
+
+        assert!(validate(symlink("current.cfg", Some("engine.cfg"), Some("custom.cfg"))).is_ok());
+        assert!(validate(symlink("new.cfg", None, Some("engine.cfg"))).is_ok());
+        // Already removed
+        assert!(validate(symlink("old.cfg", Some("engine.cfg"), None)).is_ok());
+
+        let err = validate(symlink("current.cfg", Some("other.cfg"), Some("custom.cfg"))).unwrap_err();
+        assert!(err.to_string().contains("symlink points to 'engine.cfg', expected 'other.cfg'"));
+        let err = validate(symlink("current.cfg", None, Some("custom.cfg"))).unwrap_err();
+        assert!(err.to_string().contains("symlink already exists"));
+        let err = validate(symlink("game.dat", Some("engine.cfg"), None)).unwrap_err();
+        assert!(err.to_string().contains("expected a symlink"));
+    }
+
+    #[test]
+    fn entries_outside_target_are_rejected() {
+        let dir = tempdir().unwrap();
+        let add = |file: &str| ManifestEntry::Add {
+            file: file.to_string(),
+            final_hash: "a".to_string(),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+        };
+        let validate = |entry| validate_entries(&[entry], dir.path(), Algorithm::Sha256, None::<fn(Progress)>);
+
+        assert!(validate(add("data/new.txt")).is_ok());
+        assert!(validate(add("./new.txt")).is_ok());
+        for file in ["../../etc/passwd", "data/../../outside.txt", "/etc/passwd", ".."] {
+            let err = validate(add(file)).unwrap_err();
+            assert!(matches!(err, PatchError::ValidationFailed { .. }), "{}", file);
+        }
+
+        let rename = ManifestEntry::Rename {
+            from: "../secret.txt".to_string(),
+            to: "stolen.txt".to_string(),
+            hash: "a".to_string(),
+        };
+        assert!(matches!(validate(rename), Err(PatchError::ValidationFailed { .. })));
+
+        #[cfg(unix)]
+        {
+            let outside = tempdir().unwrap();
+            std::os::unix::fs::symlink(outside.path(), dir.path().join("link")).unwrap();
+            let err = validate(add("link/payload.txt")).unwrap_err();
+            assert!(err.to_string().contains("through a symlink"), "{}", err);
+            assert!(validate(add("link/deeper/payload.txt")).is_err());
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn entries_through_new_symlinks_are_rejected() {
+        let dir = tempdir().unwrap();
+        let outside = tempdir().unwrap();
+        let link = |target: &str| ManifestEntry::Symlink {
+            file: "game".to_string(),
+            original_target: None,
+            target: Some(target.to_string()),
+        };
+        let add = ManifestEntry::Add {
+            file: "game/evil.txt".to_string(),
+            final_hash: "a".to_string(),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+        };
+        let validate = |entries: &[ManifestEntry]| {
+            validate_entries(entries, dir.path(), Algorithm::Sha256, None::<fn(Progress)>)
+        };
+
+        for target in [outside.path().to_str().unwrap(), "..", "data/../../outside"] {
+            let err = validate(&[link(target), add.clone()]).unwrap_err();
+            assert!(err.to_string().contains("leaves the target directory"), "{}: {}", target, err);
+        }
+
+        // Even a link inside the target isn't written through, as it isn't
+        // on disk yet to check where it really leads
+        assert!(validate(&[link("data")]).is_ok());
+        let err = validate(&[link("data"), add]).unwrap_err();
+        assert!(err.to_string().contains("symlink game the patch creates"), "{}", err);
+    }
+
+    #[test]
+    fn entries_in_backup_dir_are_rejected() {
+        let dir = tempdir().unwrap();
+        let delete = |file: &str| ManifestEntry::Delete {
+            file: file.to_string(),
+            original_hash: "a".to_string(),
+        };
+        let validate = |entry| validate_entries(&[entry], dir.path(), Algorithm::Sha256, None::<fn(Progress)>);
+
+        for file in [".patch-backup", ".patch-backup/game.exe", "./.Patch-Backup/game.exe"] {
+            let err = validate(delete(file)).unwrap_err();
+            assert!(err.to_string().contains("backup directory"), "{}", err);
+        }
+        assert!(validate(delete("data/.patch-backup/game.exe")).is_ok());
+        assert!(validate(delete(".patch-backup-old/game.exe")).is_ok());
+
+        let rename = ManifestEntry::Rename {
+            from: ".patch-backup/hashes.json".to_string(),
+            to: "hashes.json".to_string(),
+            hash: "a".to_string(),
+        };
+        assert!(matches!(validate(rename), Err(PatchError::ValidationFailed { .. })));
+    }
 }
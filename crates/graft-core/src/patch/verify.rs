@@ -1,50 +1,169 @@
-use std::fs;
 use std::path::Path;
 
 use crate::patch::PatchError;
-use crate::utils::hash::hash_bytes;
+use crate::utils::filesystem::{FileSystem, StdFs};
+use crate::utils::hash::Algorithm;
 use crate::utils::manifest::ManifestEntry;
 
 /// Verify a single manifest entry after it has been applied.
 ///
-/// - Patch: verifies file matches final_hash
+/// - Patch: verifies file matches final_hash, or any of final_hashes
 /// - Add: verifies file matches final_hash
 /// - Delete: verifies file no longer exists
-pub fn verify_entry(entry: &ManifestEntry, target_dir: &Path) -> Result<(), PatchError> {
-    match entry {
+/// - Rename: verifies file moved (matches hash at `to`, gone from `from`)
+/// - Symlink: verifies the link points to `target` (or is gone if none)
+/// - MkDir: verifies the directory exists
+/// - RmDir: verifies the directory no longer exists
+///
+/// Hashes are computed with `algorithm`, the manifest's `hash_algorithm`.
+pub fn verify_entry(entry: &ManifestEntry, target_dir: &Path, algorithm: Algorithm) -> Result<(), PatchError> {
+    verify_entry_with(&StdFs, entry, target_dir, algorithm)
+}
+
+/// Like `verify_entry`, for a Patch or Add entry whose file was hashed as it
+/// was written (see `apply_entry_hashing`), so it isn't read back from disk.
+///
+/// Any other entry has no written file to compare and fails verification;
+/// use `verify_entry` for those.
+pub fn verify_entry_with_hash(entry: &ManifestEntry, actual_hash: &str) -> Result<(), PatchError> {
+    let (file, final_hash, final_hashes) = match entry {
         ManifestEntry::Patch {
-            file, final_hash, ..
+            file,
+            final_hash,
+            final_hashes,
+            ..
+        } => (file, final_hash, final_hashes.as_slice()),
+        ManifestEntry::Add { file, final_hash, .. } => (file, final_hash, &[][..]),
+        _ => {
+            return Err(PatchError::VerificationFailed {
+                file: entry.file().to_string(),
+                expected: "a patched or added file".to_string(),
+                actual: format!("{} entry", entry.operation()),
+            });
         }
-        | ManifestEntry::Add { file, final_hash } => {
-            let target_path = target_dir.join(file);
+    };
+
+    if actual_hash != final_hash && !final_hashes.iter().any(|h| h == actual_hash) {
+        return Err(PatchError::VerificationFailed {
+            file: file.clone(),
+            expected: describe_expected(final_hash, final_hashes),
+            actual: actual_hash.to_string(),
+        });
+    }
+    Ok(())
+}
 
-            let data = fs::read(&target_path).map_err(|e| PatchError::VerificationFailed {
+/// The hash a Patch entry's file should have, or the list of them when
+/// several results are accepted.
+fn describe_expected(final_hash: &str, final_hashes: &[String]) -> String {
+    if final_hashes.is_empty() {
+        return final_hash.to_string();
+    }
+    let all: Vec<&str> = std::iter::once(final_hash).chain(final_hashes.iter().map(String::as_str)).collect();
+    format!("one of {}", all.join(", "))
+}
+
+/// Like `verify_entry`, using the given filesystem.
+pub fn verify_entry_with(
+    fs: &impl FileSystem,
+    entry: &ManifestEntry,
+    target_dir: &Path,
+    algorithm: Algorithm,
+) -> Result<(), PatchError> {
+    match entry {
+        ManifestEntry::Patch {
+            file,
+            final_hash,
+            final_hashes,
+            ..
+        } => {
+            let actual_hash = fs.hash(&target_dir.join(file), algorithm).map_err(|e| PatchError::VerificationFailed {
+                file: file.clone(),
+                expected: describe_expected(final_hash, final_hashes),
+                actual: format!("failed to read file: {}", e),
+            })?;
+            verify_entry_with_hash(entry, &actual_hash)?;
+        }
+        ManifestEntry::Add {
+            file, final_hash, ..
+        } => {
+            let actual_hash = fs.hash(&target_dir.join(file), algorithm).map_err(|e| PatchError::VerificationFailed {
                 file: file.clone(),
                 expected: final_hash.clone(),
                 actual: format!("failed to read file: {}", e),
             })?;
+            verify_entry_with_hash(entry, &actual_hash)?;
+        }
+        ManifestEntry::Delete { file, .. } => {
+            let target_path = target_dir.join(file);
 
-            let actual_hash = hash_bytes(&data);
-
-            if &actual_hash != final_hash {
+            if fs.exists(&target_path) {
                 return Err(PatchError::VerificationFailed {
                     file: file.clone(),
-                    expected: final_hash.clone(),
-                    actual: actual_hash,
+                    expected: "file deleted".to_string(),
+                    actual: "file still exists".to_string(),
                 });
             }
         }
-        ManifestEntry::Delete { file, .. } => {
-            let target_path = target_dir.join(file);
+        ManifestEntry::Rename { from, to, hash } => {
+            let actual_hash = fs.hash(&target_dir.join(to), algorithm).map_err(|e| PatchError::VerificationFailed {
+                file: to.clone(),
+                expected: hash.clone(),
+                actual: format!("failed to read file: {}", e),
+            })?;
 
-            if target_path.exists() {
+            if &actual_hash != hash {
                 return Err(PatchError::VerificationFailed {
-                    file: file.clone(),
-                    expected: "file deleted".to_string(),
+                    file: to.clone(),
+                    expected: hash.clone(),
+                    actual: actual_hash,
+                });
+            }
+
+            if fs.exists(&target_dir.join(from)) {
+                return Err(PatchError::VerificationFailed {
+                    file: from.clone(),
+                    expected: "file moved".to_string(),
                     actual: "file still exists".to_string(),
                 });
             }
         }
+        ManifestEntry::Symlink { file, target, .. } => {
+            let actual = fs
+                .read_link(&target_dir.join(file))
+                .ok()
+                .map(|t| t.to_string_lossy().into_owned());
+
+            if actual != *target {
+                let describe = |t: &Option<String>| match t {
+                    Some(t) => format!("symlink to '{}'", t),
+                    None => "no symlink".to_string(),
+                };
+                return Err(PatchError::VerificationFailed {
+                    file: file.clone(),
+                    expected: describe(target),
+                    actual: describe(&actual),
+                });
+            }
+        }
+        ManifestEntry::MkDir { dir } => {
+            if !fs.is_dir(&target_dir.join(dir)) {
+                return Err(PatchError::VerificationFailed {
+                    file: dir.clone(),
+                    expected: "directory created".to_string(),
+                    actual: "no directory".to_string(),
+                });
+            }
+        }
+        ManifestEntry::RmDir { dir } => {
+            if fs.exists(&target_dir.join(dir)) {
+                return Err(PatchError::VerificationFailed {
+                    file: dir.clone(),
+                    expected: "directory removed".to_string(),
+                    actual: "directory still exists".to_string(),
+                });
+            }
+        }
     }
 
     Ok(())
@@ -53,6 +172,8 @@ pub fn verify_entry(entry: &ManifestEntry, target_dir: &Path) -> Result<(), Patc
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::hash::hash_bytes;
+    use std::fs;
     use tempfile::tempdir;
 
     #[test]
@@ -67,9 +188,18 @@ mod tests {
             original_hash: "x".to_string(),
             diff_hash: "y".to_string(),
             final_hash: hash_bytes(content),
+            final_hashes: vec![],
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+            line_endings: None,
+            encoding: None,
         };
 
-        let result = verify_entry(&entry, target_dir.path());
+        let result = verify_entry(&entry, target_dir.path(), Algorithm::Sha256);
         assert!(result.is_ok());
     }
 
@@ -84,15 +214,84 @@ mod tests {
             original_hash: "x".to_string(),
             diff_hash: "y".to_string(),
             final_hash: "expected_hash".to_string(),
+            final_hashes: vec![],
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+            line_endings: None,
+            encoding: None,
         };
 
-        let result = verify_entry(&entry, target_dir.path());
+        let result = verify_entry(&entry, target_dir.path(), Algorithm::Sha256);
         assert!(matches!(
             result,
             Err(PatchError::VerificationFailed { .. })
         ));
     }
 
+    #[test]
+    fn verify_patch_accepts_any_final_hash() {
+        let target_dir = tempdir().unwrap();
+        fs::write(target_dir.path().join("file.bin"), b"patched (EU)").unwrap();
+
+        let mut entry = ManifestEntry::Patch {
+            file: "file.bin".to_string(),
+            original_hash: "x".to_string(),
+            diff_hash: "y".to_string(),
+            final_hash: hash_bytes(b"patched (US)"),
+            final_hashes: vec![hash_bytes(b"patched (JP)"), hash_bytes(b"patched (EU)")],
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+            line_endings: None,
+            encoding: None,
+        };
+        assert!(verify_entry(&entry, target_dir.path(), Algorithm::Sha256).is_ok());
+
+        if let ManifestEntry::Patch { final_hashes, .. } = &mut entry {
+            final_hashes.pop();
+        }
+        let Err(PatchError::VerificationFailed { expected, .. }) =
+            verify_entry(&entry, target_dir.path(), Algorithm::Sha256)
+        else {
+            panic!("expected a verification failure");
+        };
+        assert!(expected.starts_with("one of "));
+        assert!(expected.contains(&hash_bytes(b"patched (JP)")));
+    }
+
+    #[test]
+    fn verify_with_hash_compares_without_reading() {
+        let entry = ManifestEntry::Add {
+            file: "missing.bin".to_string(),
+            final_hash: hash_bytes(b"new"),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+        };
+        assert!(verify_entry_with_hash(&entry, &hash_bytes(b"new")).is_ok());
+        assert!(matches!(
+            verify_entry_with_hash(&entry, &hash_bytes(b"other")),
+            Err(PatchError::VerificationFailed { .. })
+        ));
+
+        let delete = ManifestEntry::Delete {
+            file: "old.bin".to_string(),
+            original_hash: "x".to_string(),
+        };
+        assert!(verify_entry_with_hash(&delete, "x").is_err());
+    }
+
     #[test]
     fn verify_add_correct_hash() {
         let target_dir = tempdir().unwrap();
@@ -103,9 +302,16 @@ mod tests {
         let entry = ManifestEntry::Add {
             file: "new.bin".to_string(),
             final_hash: hash_bytes(content),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
         };
 
-        let result = verify_entry(&entry, target_dir.path());
+        let result = verify_entry(&entry, target_dir.path(), Algorithm::Sha256);
         assert!(result.is_ok());
     }
 
@@ -118,9 +324,16 @@ mod tests {
         let entry = ManifestEntry::Add {
             file: "new.bin".to_string(),
             final_hash: "expected_hash".to_string(),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
         };
 
-        let result = verify_entry(&entry, target_dir.path());
+        let result = verify_entry(&entry, target_dir.path(), Algorithm::Sha256);
         assert!(matches!(
             result,
             Err(PatchError::VerificationFailed { .. })
@@ -137,7 +350,7 @@ mod tests {
         };
 
         // File doesn't exist - should pass
-        let result = verify_entry(&entry, target_dir.path());
+        let result = verify_entry(&entry, target_dir.path(), Algorithm::Sha256);
         assert!(result.is_ok());
     }
 
@@ -152,7 +365,7 @@ mod tests {
             original_hash: "x".to_string(),
         };
 
-        let result = verify_entry(&entry, target_dir.path());
+        let result = verify_entry(&entry, target_dir.path(), Algorithm::Sha256);
         assert!(matches!(
             result,
             Err(PatchError::VerificationFailed { .. })
@@ -168,9 +381,18 @@ mod tests {
             original_hash: "x".to_string(),
             diff_hash: "y".to_string(),
             final_hash: "z".to_string(),
+            final_hashes: vec![],
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+            line_endings: None,
+            encoding: None,
         };
 
-        let result = verify_entry(&entry, target_dir.path());
+        let result = verify_entry(&entry, target_dir.path(), Algorithm::Sha256);
         assert!(matches!(
             result,
             Err(PatchError::VerificationFailed { .. })
@@ -0,0 +1,99 @@
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use crate::patch::PatchError;
+use crate::utils::manifest::ManifestEntry;
+use crate::utils::temp_file::temp_path;
+
+/// Check that the files `entries` touch, and the backup, can be written
+/// before anything is backed up.
+///
+/// A temp file is created and removed in every directory apply or backup
+/// would write into; directories that don't exist yet are checked through
+/// the nearest one that does, since that is where they'll be created.
+///
+/// Returns `NotWritable` for the first directory that refuses, e.g. a game
+/// installed where only an administrator can write.
+pub fn check_writable(entries: &[ManifestEntry], target_dir: &Path, backup_dir: &Path) -> Result<(), PatchError> {
+    let mut dirs = BTreeSet::new();
+    dirs.insert(existing_ancestor(target_dir));
+    dirs.insert(existing_ancestor(backup_dir));
+    for entry in entries {
+        let mut files = vec![entry.file()];
+        if let ManifestEntry::Rename { from, .. } = entry {
+            files.push(from);
+        }
+        for file in files {
+            if let Some(parent) = target_dir.join(file).parent() {
+                dirs.insert(existing_ancestor(parent));
+            }
+        }
+    }
+
+    for dir in dirs {
+        let probe = temp_path(&dir);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&probe)
+            .and_then(|_| fs::remove_file(&probe))
+            .map_err(|e| PatchError::NotWritable {
+                path: dir.display().to_string(),
+                reason: e.to_string(),
+            })?;
+    }
+    Ok(())
+}
+
+/// `path` if it exists, otherwise its closest existing ancestor.
+fn existing_ancestor(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find(|p| p.exists())
+        .unwrap_or(path)
+        .to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn add(file: &str) -> ManifestEntry {
+        ManifestEntry::Add {
+            file: file.to_string(),
+            final_hash: String::new(),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+        }
+    }
+
+    #[test]
+    fn probes_leave_nothing_behind() {
+        let target = tempdir().unwrap();
+        fs::create_dir(target.path().join("data")).unwrap();
+        let entries = [add("data/new/strings.bin"), add("readme.txt")];
+
+        check_writable(&entries, target.path(), &target.path().join(".patch-backup")).unwrap();
+
+        let mut left: Vec<_> = fs::read_dir(target.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        left.extend(fs::read_dir(target.path().join("data")).unwrap().map(|e| e.unwrap().file_name()));
+        assert_eq!(left, vec!["data"]);
+    }
+
+    #[test]
+    fn unwritable_directory_is_reported() {
+        let target = tempdir().unwrap();
+        // A file where apply needs a directory can't be written into
+        fs::write(target.path().join("data"), b"not a directory").unwrap();
+
+        let err = check_writable(&[add("data/strings.bin")], target.path(), &target.path().join(".patch-backup"))
+            .unwrap_err();
+        assert!(matches!(err, PatchError::NotWritable { path, .. } if path.ends_with("data")));
+    }
+}
@@ -1,12 +1,13 @@
 //! Path restriction checks to prevent patching sensitive locations.
 //!
 //! When `allow_restricted` is false in the manifest, these checks prevent:
-//! - Path traversal attacks (../)
+//! - Path traversal attacks (../), including symlinks pointing out of the
+//!   target directory
 //! - Patching system directories
 //! - Patching executable files
 //! - Patching inside .app bundles (macOS)
 
-use crate::utils::manifest::Manifest;
+use crate::utils::manifest::{Manifest, ManifestEntry};
 use std::path::Path;
 
 /// A violation of path restrictions.
@@ -55,6 +56,21 @@ pub fn check_manifest(
         if let Err(v) = check_path(file, target_dir) {
             violations.push(v);
         }
+        // A rename also touches its source
+        if let ManifestEntry::Rename { from, .. } = entry
+            && let Err(v) = check_path(from, target_dir)
+        {
+            violations.push(v);
+        }
+        // A symlink must not give later writes a way out of the target
+        if let ManifestEntry::Symlink {
+            target: Some(target),
+            ..
+        } = entry
+            && let Err(v) = check_link_target(file, target)
+        {
+            violations.push(v);
+        }
     }
 
     if violations.is_empty() {
@@ -93,6 +109,27 @@ fn check_path_traversal(file: &str) -> Result<(), RestrictionViolation> {
     Ok(())
 }
 
+/// Check that a symlink at `file` pointing to `target` stays inside the
+/// target directory: `target` is relative and its `..` components don't
+/// climb above the root.
+pub(crate) fn check_link_target(file: &str, target: &str) -> Result<(), RestrictionViolation> {
+    let violation = || RestrictionViolation::PathTraversal {
+        path: format!("{} -> {}", file, target),
+    };
+
+    let mut depth = Path::new(file).components().count().saturating_sub(1);
+    for component in Path::new(target).components() {
+        match component {
+            std::path::Component::Normal(_) => depth += 1,
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => depth = depth.checked_sub(1).ok_or_else(violation)?,
+            std::path::Component::RootDir | std::path::Component::Prefix(_) => return Err(violation()),
+        }
+    }
+
+    Ok(())
+}
+
 /// Blocked file extensions by platform.
 #[cfg(target_os = "windows")]
 const BLOCKED_EXTENSIONS_WINDOWS: &[&str] = &[
@@ -279,6 +316,7 @@ fn is_protected_path(_path: &Path) -> Option<&'static str> {
 mod tests {
     use super::*;
     use crate::utils::manifest::ManifestEntry;
+    use crate::utils::hash::Algorithm;
 
     #[test]
     fn path_traversal_is_blocked() {
@@ -294,6 +332,15 @@ mod tests {
         assert!(check_path_traversal("config.json").is_ok());
     }
 
+    #[test]
+    fn symlinks_out_of_target_are_blocked() {
+        assert!(check_link_target("lib/libgame.so", "libgame.so.1").is_ok());
+        assert!(check_link_target("data/engine.cfg", "../engine.cfg").is_ok());
+        assert!(check_link_target("engine.cfg", "../engine.cfg").is_err());
+        assert!(check_link_target("data/engine.cfg", "../../etc/passwd").is_err());
+        assert!(check_link_target("libgame.so", "/usr/lib/libgame.so").is_err());
+    }
+
     #[test]
     fn blocked_extensions_cross_platform() {
         assert!(check_blocked_extension("script.sh").is_err());
@@ -320,7 +367,26 @@ mod tests {
                 original_hash: "a".to_string(),
                 diff_hash: "b".to_string(),
                 final_hash: "c".to_string(),
+                final_hashes: vec![],
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: false,
+                line_endings: None,
+                encoding: None,
             }],
+            match_by_content: false,
+            hash_algorithm: Algorithm::default(),
+            unhashed: false,
+            description: None,
+            author: None,
+            target_game_version: None,
+            version_file: None,
+            base_dir: None,
+            pre_commands: vec![],
+            post_commands: vec![],
         };
 
         let result = check_manifest(&manifest, Path::new("/tmp"));
@@ -339,7 +405,26 @@ mod tests {
                 original_hash: "a".to_string(),
                 diff_hash: "b".to_string(),
                 final_hash: "c".to_string(),
+                final_hashes: vec![],
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: false,
+                line_endings: None,
+                encoding: None,
             }],
+            match_by_content: false,
+            hash_algorithm: Algorithm::default(),
+            unhashed: false,
+            description: None,
+            author: None,
+            target_game_version: None,
+            version_file: None,
+            base_dir: None,
+            pre_commands: vec![],
+            post_commands: vec![],
         };
 
         let result = check_manifest(&manifest, Path::new("/tmp"));
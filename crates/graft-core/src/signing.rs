@@ -0,0 +1,279 @@
+//! Ed25519 signatures over patch archives.
+//!
+//! A signed archive carries `SIGNATURE_FILENAME` next to the manifest. It
+//! holds the signer's public key and a signature over the SHA-256 digest of
+//! every other file in the archive (paths and contents), so changing,
+//! adding or removing any file breaks the signature. The digest is taken
+//! over the files, not the compressed bytes, so repacking an archive with a
+//! different codec keeps it valid.
+//!
+//! Anyone able to rewrite the archive can also re-sign it with a key of
+//! their own, so the key in the signature file proves nothing by itself.
+//! Verification takes the expected public key from outside the archive
+//! (see `verify_archive`); without one, the signer's key is only reported
+//! for the user to compare with the one the patch author publishes.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use ed25519_dalek::pkcs8::DecodePrivateKey;
+use ed25519_dalek::{Signature, Signer};
+use serde::{Deserialize, Serialize};
+
+use crate::archive::{
+    contents_digest as digest, create_archive_from_entries, hash_archive_files, hash_contents, read_archive_bytes,
+    ArchiveCompression,
+};
+use crate::patch::SIGNATURE_FILENAME;
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+/// Contents of the signature file, as JSON.
+#[derive(Debug, Serialize, Deserialize)]
+struct SignatureFile {
+    /// Signer's public key, as hex
+    public_key: String,
+    /// Signature over the archive digest, as hex
+    signature: String,
+}
+
+/// Errors from signing or verifying an archive.
+#[derive(Debug)]
+pub enum SignatureError {
+    /// The private key file couldn't be read or isn't an Ed25519 key.
+    InvalidKey(String),
+    /// The signature file is unreadable or malformed.
+    Malformed(String),
+    /// The signature doesn't match the archive contents.
+    Mismatch,
+    /// A signature was expected but the archive isn't signed.
+    Unsigned,
+    /// The archive is signed, but not with the expected key. Holds the
+    /// signer's public key, as hex.
+    UntrustedKey(String),
+    /// The archive couldn't be read or written.
+    Archive(io::Error),
+}
+
+impl std::fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureError::InvalidKey(msg) => write!(f, "Invalid signing key: {}", msg),
+            SignatureError::Malformed(msg) => write!(f, "Malformed signature: {}", msg),
+            SignatureError::Mismatch => {
+                write!(f, "Signature does not match the patch contents; it may have been tampered with")
+            }
+            SignatureError::Unsigned => write!(f, "Patch is not signed, but a signature was expected"),
+            SignatureError::UntrustedKey(key) => {
+                write!(f, "Patch is signed by {}, not by the expected key", key)
+            }
+            SignatureError::Archive(e) => write!(f, "Failed to read archive: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SignatureError::Archive(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Load an Ed25519 private key from a PKCS#8 PEM file, as written by
+/// `openssl genpkey -algorithm ed25519`.
+pub fn load_signing_key(path: &Path) -> Result<SigningKey, SignatureError> {
+    let pem = fs::read_to_string(path)
+        .map_err(|e| SignatureError::InvalidKey(format!("{}: {}", path.display(), e)))?;
+    SigningKey::from_pkcs8_pem(&pem)
+        .map_err(|e| SignatureError::InvalidKey(format!("{}: {}", path.display(), e)))
+}
+
+/// The public key for `key`, as hex, the way verification reports it.
+pub fn public_key_hex(key: &SigningKey) -> String {
+    to_hex(key.verifying_key().as_bytes())
+}
+
+/// Parse a public key given as hex, as printed by `graft build --sign-key`.
+pub fn parse_public_key(hex: &str) -> Result<VerifyingKey, SignatureError> {
+    from_hex::<32>(hex.trim())
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| SignatureError::InvalidKey(format!("not an Ed25519 public key: {}", hex)))
+}
+
+/// Sign an archive, returning a copy with the signature file added (or
+/// replaced), compressed with `compression`.
+pub fn sign_archive(
+    data: &[u8],
+    key: &SigningKey,
+    compression: ArchiveCompression,
+) -> Result<Vec<u8>, SignatureError> {
+    let mut contents = read_archive_bytes(data).map_err(SignatureError::Archive)?;
+    contents.remove(SIGNATURE_FILENAME);
+
+    let signature = key.sign(&digest(&hash_contents(&contents)));
+    let file = SignatureFile {
+        public_key: public_key_hex(key),
+        signature: to_hex(&signature.to_bytes()),
+    };
+    let json = serde_json::to_vec_pretty(&file)
+        .map_err(|e| SignatureError::Archive(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+    contents.insert(SIGNATURE_FILENAME.to_string(), json);
+
+    create_archive_from_entries(&contents, compression).map_err(SignatureError::Archive)
+}
+
+/// Check an archive's signature.
+///
+/// With `expected`, the archive must be signed by that key: an unsigned
+/// archive fails with `Unsigned` and one signed by any other key with
+/// `UntrustedKey`. Without it, whatever key the archive was signed with is
+/// accepted and returned for the caller to show.
+///
+/// Returns the signer's public key as hex, or `None` if the archive isn't
+/// signed. The files are hashed as they're decompressed, so memory use
+/// doesn't grow with the size of the archive.
+pub fn verify_archive(data: &[u8], expected: Option<&VerifyingKey>) -> Result<Option<String>, SignatureError> {
+    let (mut hashes, kept) = hash_archive_files(data, &[SIGNATURE_FILENAME]).map_err(SignatureError::Archive)?;
+    hashes.remove(SIGNATURE_FILENAME);
+    let Some(json) = kept.get(SIGNATURE_FILENAME) else {
+        return match expected {
+            Some(_) => Err(SignatureError::Unsigned),
+            None => Ok(None),
+        };
+    };
+
+    let file: SignatureFile =
+        serde_json::from_slice(json).map_err(|e| SignatureError::Malformed(e.to_string()))?;
+    let public_key = from_hex::<32>(&file.public_key)
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| SignatureError::Malformed("invalid public key".to_string()))?;
+    if let Some(expected) = expected
+        && *expected != public_key
+    {
+        return Err(SignatureError::UntrustedKey(file.public_key));
+    }
+    let signature = from_hex::<64>(&file.signature)
+        .map(|bytes| Signature::from_bytes(&bytes))
+        .ok_or_else(|| SignatureError::Malformed("invalid signature".to_string()))?;
+
+    public_key
+        .verify_strict(&digest(&hashes), &signature)
+        .map_err(|_| SignatureError::Mismatch)?;
+
+    Ok(Some(file.public_key))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{create_archive_bytes, Codec};
+    use ed25519_dalek::pkcs8::EncodePrivateKey;
+    use ed25519_dalek::pkcs8::spki::der::pem::LineEnding;
+    use tempfile::tempdir;
+
+    fn sample_archive() -> Vec<u8> {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("manifest.json"), r#"{"version": 1, "name": "Test", "entries": []}"#).unwrap();
+        fs::create_dir(dir.path().join("files")).unwrap();
+        fs::write(dir.path().join("files/strings.txt"), "Hola").unwrap();
+        create_archive_bytes(dir.path()).unwrap()
+    }
+
+    #[test]
+    fn signed_archive_verifies() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let signed = sign_archive(&sample_archive(), &key, ArchiveCompression::default()).unwrap();
+
+        assert_eq!(verify_archive(&signed, None).unwrap(), Some(public_key_hex(&key)));
+    }
+
+    #[test]
+    fn unsigned_archive_has_no_signer() {
+        assert_eq!(verify_archive(&sample_archive(), None).unwrap(), None);
+    }
+
+    #[test]
+    fn expected_key_must_have_signed_the_archive() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let expected = parse_public_key(&public_key_hex(&key)).unwrap();
+        let signed = sign_archive(&sample_archive(), &key, ArchiveCompression::default()).unwrap();
+        assert_eq!(verify_archive(&signed, Some(&expected)).unwrap(), Some(public_key_hex(&key)));
+
+        // Re-signed by someone else, the archive verifies against its own key only
+        let other = SigningKey::from_bytes(&[8; 32]);
+        let resigned = sign_archive(&signed, &other, ArchiveCompression::default()).unwrap();
+        assert!(verify_archive(&resigned, None).unwrap().is_some());
+        assert!(matches!(
+            verify_archive(&resigned, Some(&expected)),
+            Err(SignatureError::UntrustedKey(k)) if k == public_key_hex(&other)
+        ));
+
+        // Stripping the signature doesn't get past an expected key either
+        assert!(matches!(
+            verify_archive(&sample_archive(), Some(&expected)),
+            Err(SignatureError::Unsigned)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_public_keys() {
+        assert!(matches!(parse_public_key("not hex"), Err(SignatureError::InvalidKey(_))));
+        assert!(matches!(parse_public_key("abcd"), Err(SignatureError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn tampered_archive_fails_verification() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let signed = sign_archive(&sample_archive(), &key, ArchiveCompression::default()).unwrap();
+
+        let mut contents = read_archive_bytes(&signed).unwrap();
+        contents.insert("files/strings.txt".to_string(), b"Hacked".to_vec());
+        let tampered = create_archive_from_entries(&contents, ArchiveCompression::default()).unwrap();
+
+        assert!(matches!(verify_archive(&tampered, Some(&key.verifying_key())), Err(SignatureError::Mismatch)));
+    }
+
+    #[test]
+    fn signature_survives_recompression() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let signed = sign_archive(&sample_archive(), &key, ArchiveCompression::default()).unwrap();
+
+        let zstd = ArchiveCompression::new(Codec::Zstd, None).unwrap();
+        let repacked = create_archive_from_entries(&read_archive_bytes(&signed).unwrap(), zstd).unwrap();
+
+        assert!(verify_archive(&repacked, None).unwrap().is_some());
+    }
+
+    #[test]
+    fn loads_pem_keys() {
+        let dir = tempdir().unwrap();
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let path = dir.path().join("signing.pem");
+        fs::write(&path, key.to_pkcs8_pem(LineEnding::LF).unwrap().as_bytes()).unwrap();
+        fs::write(dir.path().join("garbage.pem"), "not a key").unwrap();
+
+        assert_eq!(load_signing_key(&path).unwrap().to_bytes(), key.to_bytes());
+        assert!(matches!(
+            load_signing_key(&dir.path().join("garbage.pem")),
+            Err(SignatureError::InvalidKey(_))
+        ));
+    }
+}
@@ -1,17 +1,142 @@
-use std::io;
+//! Binary deltas between two versions of a file.
+//!
+//! Deltas are bsdiff output compressed with zlib, behind `COMPRESSED_MAGIC`.
+//! Most of a bsdiff delta is zeros where the files agree, so compression is
+//! what makes a small change to a large file produce a small diff. Diffs
+//! written before compression was added are plain bsdiff output; they can
+//! never start with the magic (it would be an impossibly large first block
+//! length) and are still applied as-is.
+//!
+//! `apply_diff` holds the original, the delta and the result in memory at
+//! once, which is fastest but infeasible for multi-gigabyte archives.
+//! `apply_diff_streaming` reads the original through `Seek` and writes the
+//! result as it goes, holding only a window of each, at the cost of a seek
+//! per bsdiff block. Creating a diff still needs both files in memory.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+/// Prefix marking a zlib-compressed bsdiff delta.
+const COMPRESSED_MAGIC: &[u8; 8] = b"GRAFTBZ1";
 
 pub fn create_diff(old: &[u8], new: &[u8]) -> io::Result<Vec<u8>> {
     let mut diff = Vec::new();
     bsdiff::diff(old, new, &mut diff)?;
-    Ok(diff)
+
+    let mut encoder = ZlibEncoder::new(COMPRESSED_MAGIC.to_vec(), Compression::default());
+    encoder.write_all(&diff)?;
+    encoder.finish()
 }
 
 pub fn apply_diff(orig: &[u8], diff: &[u8]) -> io::Result<Vec<u8>> {
     let mut output = Vec::new();
-    bsdiff::patch(orig, &mut diff.as_ref(), &mut output)?;
+    match diff.strip_prefix(COMPRESSED_MAGIC) {
+        Some(compressed) => bsdiff::patch(orig, &mut ZlibDecoder::new(compressed), &mut output)?,
+        None => bsdiff::patch(orig, &mut &diff[..], &mut output)?,
+    }
     Ok(output)
 }
 
+/// Bytes of the original and the delta held at once by `apply_diff_streaming`.
+const WINDOW_SIZE: usize = 1024 * 1024;
+
+/// Like `apply_diff`, reading the original from `orig` and the delta from
+/// `diff` and writing the result to `out`, a window at a time, so memory use
+/// doesn't grow with the files. Returns the number of bytes written.
+pub fn apply_diff_streaming(orig: &mut (impl Read + Seek), diff: impl Read, out: &mut impl Write) -> io::Result<u64> {
+    apply_diff_windowed(orig, diff, out, WINDOW_SIZE)
+}
+
+/// `apply_diff_streaming` with windows of `window` bytes.
+fn apply_diff_windowed(
+    orig: &mut (impl Read + Seek),
+    diff: impl Read,
+    out: &mut impl Write,
+    window: usize,
+) -> io::Result<u64> {
+    let mut diff = io::BufReader::new(diff);
+    let mut prefix = Vec::with_capacity(COMPRESSED_MAGIC.len());
+    (&mut diff).take(COMPRESSED_MAGIC.len() as u64).read_to_end(&mut prefix)?;
+    let mut diff: Box<dyn Read + '_> = if prefix == COMPRESSED_MAGIC {
+        Box::new(ZlibDecoder::new(diff))
+    } else {
+        Box::new(io::Cursor::new(prefix).chain(diff))
+    };
+
+    let mut new_window = vec![0; window];
+    let mut old_window = vec![0; window];
+    let mut old_pos: u64 = 0;
+    let mut written: u64 = 0;
+    let mut control = [0; 24];
+
+    while read_control(&mut diff, &mut control)? {
+        let mix_len = u64::from_le_bytes(control[0..8].try_into().unwrap());
+        let copy_len = u64::from_le_bytes(control[8..16].try_into().unwrap());
+        let seek = offset(control[16..24].try_into().unwrap());
+
+        // Bytes the delta adds to the original, a window at a time
+        orig.seek(SeekFrom::Start(old_pos))?;
+        let mut left = mix_len;
+        while left > 0 {
+            let len = left.min(window as u64) as usize;
+            diff.read_exact(&mut new_window[..len])?;
+            orig.read_exact(&mut old_window[..len])?;
+            for (new, old) in new_window[..len].iter_mut().zip(&old_window[..len]) {
+                *new = new.wrapping_add(*old);
+            }
+            out.write_all(&new_window[..len])?;
+            left -= len as u64;
+        }
+
+        // Bytes copied straight from the delta
+        if io::copy(&mut (&mut diff).take(copy_len), out)? != copy_len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        written = written
+            .checked_add(mix_len)
+            .and_then(|n| n.checked_add(copy_len))
+            .ok_or(io::ErrorKind::InvalidData)?;
+        old_pos = i64::try_from(old_pos)
+            .ok()
+            .and_then(|pos| pos.checked_add(i64::try_from(mix_len).ok()?))
+            .and_then(|pos| pos.checked_add(seek))
+            .and_then(|pos| u64::try_from(pos).ok())
+            .ok_or(io::ErrorKind::InvalidData)?;
+    }
+
+    Ok(written)
+}
+
+/// Read the next bsdiff control block into `control`, or return false if the
+/// delta ends cleanly before it.
+fn read_control(diff: &mut impl Read, control: &mut [u8; 24]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < control.len() {
+        match diff.read(&mut control[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// Decode bsdiff's sign-magnitude little-endian offset.
+fn offset(bytes: [u8; 8]) -> i64 {
+    let value = i64::from_le_bytes(bytes);
+    if value & (1 << 63) == 0 {
+        value
+    } else {
+        -(value & !(1 << 63))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -56,4 +181,58 @@ mod tests {
 
         assert_eq!(result, modified);
     }
+
+    #[test]
+    fn small_change_gives_small_diff() {
+        let old: Vec<u8> = (0..256 * 1024u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+        let mut new = old.clone();
+        new[100_000..100_016].copy_from_slice(b"translated text!");
+
+        let diff = create_diff(&old, &new).unwrap();
+
+        assert!(diff.len() < 1024, "diff is {} bytes", diff.len());
+        assert_eq!(apply_diff(&old, &diff).unwrap(), new);
+    }
+
+    #[test]
+    fn applies_uncompressed_diffs() {
+        let old = b"original file content";
+        let new = b"modified file content here";
+        let mut raw = Vec::new();
+        bsdiff::diff(old, new, &mut raw).unwrap();
+
+        assert_eq!(apply_diff(old, &raw).unwrap(), new);
+    }
+
+    #[test]
+    fn streaming_matches_in_memory() {
+        let window = 256;
+        let old: Vec<u8> = (0..16 * 1024u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+        let mut new = old.clone();
+        new[window - 8..window + 8].copy_from_slice(b"translated text!");
+        new.drain(10..20);
+        new.extend_from_slice(b"appended");
+
+        let mut raw = Vec::new();
+        bsdiff::diff(&old, &new, &mut raw).unwrap();
+
+        for diff in [create_diff(&old, &new).unwrap(), raw] {
+            let mut out = Vec::new();
+            let written = apply_diff_windowed(&mut io::Cursor::new(&old), &diff[..], &mut out, window).unwrap();
+
+            assert_eq!(written, new.len() as u64);
+            assert_eq!(out, new);
+        }
+    }
+
+    #[test]
+    fn streaming_rejects_truncated_diff() {
+        let diff = create_diff(b"original file content", b"modified file content here").unwrap();
+        let mut raw = Vec::new();
+        ZlibDecoder::new(&diff[COMPRESSED_MAGIC.len()..]).read_to_end(&mut raw).unwrap();
+
+        let result = apply_diff_streaming(&mut io::Cursor::new(b"original file content"), &raw[..30], &mut Vec::new());
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
 }
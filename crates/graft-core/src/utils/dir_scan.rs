@@ -1,31 +1,68 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::io;
 use std::path::Path;
 
-use crate::utils::hash::hash_bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::patch::{BACKUP_DIR, LOCK_FILENAME};
+use crate::utils::hash::Algorithm;
+use crate::utils::ignore::IgnoreList;
 
 /// Represents a detected difference between two directories.
 /// This is an intermediate type - does not include diff_hash since
 /// the diff hasn't been created yet.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "lowercase")]
 pub enum FileChange {
     /// File exists in both directories but content differs
     Diff {
         file: String,
         original_hash: String,
         final_hash: String,
+        /// Unix permission bits of the new file
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<u32>,
+        /// Name of the file in the new directory, when it differs from
+        /// `file` (its name in the original) only in case
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        new_file: Option<String>,
     },
     /// File only exists in new directory
     New {
         file: String,
         final_hash: String,
+        /// Unix permission bits of the new file
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<u32>,
     },
     /// File only exists in original directory
     Old {
         file: String,
         original_hash: String,
     },
+    /// File only exists in original directory with the same content as a
+    /// file only in new directory
+    Rename {
+        from: String,
+        to: String,
+        hash: String,
+    },
+    /// Symlink added, retargeted or removed. Link targets are recorded as
+    /// written, not resolved.
+    Symlink {
+        file: String,
+        /// Where the link points in the original directory, if it's there
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        original_target: Option<String>,
+        /// Where the link points in the new directory, if it's there
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<String>,
+    },
+    /// Directory only exists in new directory and has no files in it
+    MkDir { dir: String },
+    /// Directory only exists in original directory
+    RmDir { dir: String },
 }
 
 impl FileChange {
@@ -34,12 +71,28 @@ impl FileChange {
             FileChange::Diff { file, .. } => file,
             FileChange::New { file, .. } => file,
             FileChange::Old { file, .. } => file,
+            FileChange::Rename { to, .. } => to,
+            FileChange::Symlink { file, .. } => file,
+            FileChange::MkDir { dir } | FileChange::RmDir { dir } => dir,
         }
     }
 }
 
+/// Order changes by path, except that directory removals come last,
+/// deepest first, so each directory is emptied before it is removed.
+fn sort_changes(changes: &mut [FileChange]) {
+    changes.sort_by(|a, b| match (a, b) {
+        (FileChange::RmDir { .. }, FileChange::RmDir { .. }) => b.file().cmp(a.file()),
+        (FileChange::RmDir { .. }, _) => std::cmp::Ordering::Greater,
+        (_, FileChange::RmDir { .. }) => std::cmp::Ordering::Less,
+        _ => a.file().cmp(b.file()),
+    });
+}
+
 /// List all file names (not paths) in a directory.
-/// Only returns regular files, not subdirectories.
+/// Only returns regular files, not subdirectories or symlinks, and never
+/// anything named like the backup directory (`.patch-backup`) or the
+/// patcher's lock file (`.patch-lock`).
 pub fn list_files(dir: &Path) -> io::Result<Vec<String>> {
     let mut files = Vec::new();
 
@@ -47,10 +100,11 @@ pub fn list_files(dir: &Path) -> io::Result<Vec<String>> {
         let entry = entry?;
         let file_type = entry.file_type()?;
 
-        if file_type.is_file() {
-            if let Some(name) = entry.file_name().to_str() {
-                files.push(name.to_string());
-            }
+        if file_type.is_file()
+            && let Some(name) = entry.file_name().to_str()
+            && !is_patcher_state(name)
+        {
+            files.push(name.to_string());
         }
     }
 
@@ -58,69 +112,478 @@ pub fn list_files(dir: &Path) -> io::Result<Vec<String>> {
     Ok(files)
 }
 
-/// Compare two directories and categorize files into changes.
+/// List all files in a directory and its subdirectories, as paths relative
+/// to `dir` with `/` separators, sorted.
+///
+/// The backup directory (`.patch-backup`, in any case) and lock file
+/// (`.patch-lock`) are skipped, so a directory that has already been patched
+/// can be scanned without picking up what the patcher left in it; so is a
+/// file or symlink named like the backup directory at the top.
+/// Symlinks are not followed or listed; see `list_symlinks_recursive`.
+pub fn list_files_recursive(dir: &Path) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    collect_files(dir, "", &mut files, &mut BTreeMap::new(), &mut Vec::new())?;
+    files.sort();
+    Ok(files)
+}
+
+/// List all symlinks in a directory and its subdirectories, keyed by path
+/// relative to `dir` (as for `list_files_recursive`), with the path each
+/// one points to.
+pub fn list_symlinks_recursive(dir: &Path) -> io::Result<BTreeMap<String, String>> {
+    let mut links = BTreeMap::new();
+    collect_files(dir, "", &mut Vec::new(), &mut links, &mut Vec::new())?;
+    Ok(links)
+}
+
+fn collect_files(
+    dir: &Path,
+    prefix: &str,
+    files: &mut Vec<String>,
+    links: &mut BTreeMap<String, String>,
+    dirs: &mut Vec<String>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if prefix.is_empty() && is_patcher_state(&name) {
+            continue;
+        }
+
+        if file_type.is_file() {
+            files.push(format!("{}{}", prefix, name));
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(entry.path())?;
+            links.insert(format!("{}{}", prefix, name), target.to_string_lossy().into_owned());
+        } else if file_type.is_dir() {
+            dirs.push(format!("{}{}", prefix, name));
+            collect_files(&entry.path(), &format!("{}{}/", prefix, name), files, links, dirs)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `name` is the backup directory's or lock file's, compared
+/// without case as on Windows.
+fn is_patcher_state(name: &str) -> bool {
+    name.eq_ignore_ascii_case(BACKUP_DIR) || name.eq_ignore_ascii_case(LOCK_FILENAME)
+}
+
+/// Compare two directory trees and categorize files into changes.
 /// Returns entries for: patch (modified), add (new), delete (removed).
-/// Unchanged files (same hash) are skipped.
-pub fn categorize_files(orig_dir: &Path, new_dir: &Path) -> io::Result<Vec<FileChange>> {
-    let orig_files: HashSet<String> = list_files(orig_dir)?.into_iter().collect();
-    let new_files: HashSet<String> = list_files(new_dir)?.into_iter().collect();
+/// Files in subdirectories are named by their relative path (`data/text/en.txt`).
+/// Unchanged files (same hash) are skipped. Files are hashed with `algorithm`,
+/// streaming their contents so large archives don't have to fit in memory.
+///
+/// Symlinks are compared by where they point and reported as `Symlink`
+/// changes. A path that is a symlink in one directory and a regular file in
+/// the other is an error.
+///
+/// Directories are compared too: one only in the new directory with no
+/// files anywhere under it is reported as `MkDir` (others are created by
+/// the files added to them), and one only in the original as `RmDir`.
+///
+/// Files matched by a `.graftignore` in either directory are left out of
+/// both sides, so volatile files (saves, configs, logs) never show up as
+/// changes.
+///
+/// With `case_insensitive`, paths that differ only in case are the same
+/// path, as on Windows: `Data.bin` renamed to `data.bin` is no change at
+/// all, and a modified one is a `Diff` under its original name (with the
+/// new name in `new_file`). Two paths in one directory that differ only in
+/// case are then an error, since a case-insensitive target can't hold both.
+pub fn categorize_files(
+    orig_dir: &Path,
+    new_dir: &Path,
+    algorithm: Algorithm,
+    case_insensitive: bool,
+) -> io::Result<Vec<FileChange>> {
+    Ok(scan_summary(orig_dir, new_dir, algorithm, case_insensitive)?.changes)
+}
 
-    let mut changes = Vec::new();
+/// The outcome of comparing two directories: the changes, and the files
+/// that were found identical, for reports such as "120 files scanned, 15
+/// changed, 105 identical".
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanSummary {
+    /// Changes, as returned by `categorize_files`
+    pub changes: Vec<FileChange>,
+    /// Files in both directories with the same content, sorted (by their
+    /// name in the original directory)
+    pub unchanged: Vec<String>,
+    /// Regular files compared: every path in either directory, counted
+    /// once, leaving out symlinks and ignored files
+    pub files_scanned: usize,
+}
 
-    // Files in both directories - check if modified
-    for file in orig_files.intersection(&new_files) {
-        let orig_path = orig_dir.join(file);
-        let new_path = new_dir.join(file);
+impl ScanSummary {
+    /// Files scanned that were added, removed, renamed or modified.
+    pub fn files_changed(&self) -> usize {
+        self.files_scanned - self.unchanged.len()
+    }
+}
+
+/// Like `categorize_files`, but also lists the files that are unchanged
+/// and counts every file compared.
+pub fn scan_summary(
+    orig_dir: &Path,
+    new_dir: &Path,
+    algorithm: Algorithm,
+    case_insensitive: bool,
+) -> io::Result<ScanSummary> {
+    let mut ignore = IgnoreList::load(orig_dir)?;
+    ignore.extend(IgnoreList::load(new_dir)?);
+    let scan = |dir: &Path| -> io::Result<Scan> {
+        let mut files = Vec::new();
+        let mut links = BTreeMap::new();
+        let mut dirs = Vec::new();
+        collect_files(dir, "", &mut files, &mut links, &mut dirs)?;
+        // A directory holding an ignored file can't be created or removed
+        // by the patch, so it's left out along with the file
+        let ignored: Vec<String> = files
+            .iter()
+            .chain(links.keys())
+            .filter(|f| ignore.is_ignored(f))
+            .cloned()
+            .collect();
+        dirs.retain(|d| {
+            let prefix = format!("{}/", d);
+            !ignore.is_ignored(d) && !ignore.is_ignored(&prefix) && !ignored.iter().any(|f| f.starts_with(&prefix))
+        });
+        links.retain(|f, _| !ignore.is_ignored(f));
+        files.retain(|f| !ignore.is_ignored(f));
+        Scan::new(files, links, dirs, case_insensitive)
+    };
+    let orig = scan(orig_dir)?;
+    let new = scan(new_dir)?;
+    compare_scans(orig_dir, new_dir, &orig, &new, algorithm)
+}
 
-        let orig_data = fs::read(&orig_path)?;
-        let new_data = fs::read(&new_path)?;
+/// Like `categorize_files`, but only the files at the relative paths in
+/// `files` are examined, so a patch for a few known files doesn't scan a
+/// whole game install, and can't pick up unrelated local changes. Each path
+/// becomes a `Diff`, `New` or `Old` change (or a `Symlink` change, for a
+/// symlink), or nothing if it is unchanged.
+///
+/// Paths are compared exactly as given, and `.graftignore` files don't
+/// apply, since the files were named on purpose. Directories aren't
+/// compared, so there are no `MkDir` or `RmDir` changes. A path that is in
+/// neither directory, is a directory, leaves the directory or is in the
+/// backup directory is an error.
+pub fn categorize_files_subset(
+    orig_dir: &Path,
+    new_dir: &Path,
+    files: &[String],
+    algorithm: Algorithm,
+) -> io::Result<Vec<FileChange>> {
+    Ok(scan_summary_subset(orig_dir, new_dir, files, algorithm)?.changes)
+}
 
-        let orig_hash = hash_bytes(&orig_data);
-        let new_hash = hash_bytes(&new_data);
+/// Like `categorize_files_subset`, but also lists the files that are
+/// unchanged and counts every file compared, as `scan_summary` does.
+pub fn scan_summary_subset(
+    orig_dir: &Path,
+    new_dir: &Path,
+    files: &[String],
+    algorithm: Algorithm,
+) -> io::Result<ScanSummary> {
+    let files: BTreeSet<&str> = files.iter().map(String::as_str).collect();
+    let orig = scan_listed(orig_dir, &files)?;
+    let new = scan_listed(new_dir, &files)?;
+    if let Some(missing) = files.iter().find(|file| {
+        ![&orig, &new]
+            .iter()
+            .any(|scan| scan.files.contains_key(**file) || scan.links.contains_key(**file))
+    }) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("listed file '{}' is in neither directory", missing),
+        ));
+    }
+    compare_scans(orig_dir, new_dir, &orig, &new, algorithm)
+}
+
+/// The regular files and symlinks among `files` in `dir`.
+fn scan_listed(dir: &Path, files: &BTreeSet<&str>) -> io::Result<Scan> {
+    let mut found = Vec::new();
+    let mut links = BTreeMap::new();
+    for &file in files {
+        let inside = Path::new(file)
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)));
+        if file.is_empty() || !inside || file.split('/').next().is_some_and(is_patcher_state) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("listed file '{}' must be a relative path inside the directory", file),
+            ));
+        }
+        let path = dir.join(file);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_file() => found.push(file.to_string()),
+            Ok(metadata) if metadata.is_symlink() => {
+                links.insert(file.to_string(), fs::read_link(&path)?.to_string_lossy().into_owned());
+            }
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("listed file '{}' is a directory; list the files in it instead", file),
+                ));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Scan::new(found, links, Vec::new(), false)
+}
+
+/// Compare the scans of two directories, hashing files with `algorithm`.
+fn compare_scans(
+    orig_dir: &Path,
+    new_dir: &Path,
+    orig: &Scan,
+    new: &Scan,
+    algorithm: Algorithm,
+) -> io::Result<ScanSummary> {
+    let mut changes = Vec::new();
+    let mut unchanged = Vec::new();
+    let files_scanned =
+        orig.files.len() + new.files.keys().filter(|key| !orig.files.contains_key(*key)).count();
+
+    // Symlinks in either directory - check if added, retargeted or removed
+    let link_keys: BTreeSet<&String> = orig.links.keys().chain(new.links.keys()).collect();
+    for key in link_keys {
+        if orig.files.contains_key(key) || new.files.contains_key(key) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("'{}' is a symlink in one directory and a regular file in the other", key),
+            ));
+        }
+        let original = orig.links.get(key);
+        let current = new.links.get(key);
+        let original_target = original.map(|(_, target)| target);
+        let target = current.map(|(_, target)| target);
+        if original_target != target {
+            let (file, _) = current.or(original).expect("key comes from one of the maps");
+            changes.push(FileChange::Symlink {
+                file: file.clone(),
+                original_target: original_target.cloned(),
+                target: target.cloned(),
+            });
+        }
+    }
 
-        if orig_hash != new_hash {
-            changes.push(FileChange::Diff {
+    for (key, file) in &orig.files {
+        let orig_path = orig_dir.join(file);
+        let orig_hash = algorithm.hash_file(&orig_path)?;
+
+        match new.files.get(key) {
+            // Files in both directories - check if modified
+            Some(new_file) => {
+                let new_path = new_dir.join(new_file);
+                let new_hash = algorithm.hash_file(&new_path)?;
+
+                if orig_hash != new_hash {
+                    changes.push(FileChange::Diff {
+                        file: file.clone(),
+                        original_hash: orig_hash,
+                        final_hash: new_hash,
+                        mode: file_mode(&new_path)?,
+                        new_file: (new_file != file).then(|| new_file.clone()),
+                    });
+                } else {
+                    unchanged.push(file.clone());
+                }
+            }
+            // Files only in original directory
+            None => changes.push(FileChange::Old {
                 file: file.clone(),
                 original_hash: orig_hash,
-                final_hash: new_hash,
-            });
+            }),
         }
-        // Unchanged files are skipped
     }
 
     // Files only in new directory
-    for file in new_files.difference(&orig_files) {
+    for (key, file) in &new.files {
+        if orig.files.contains_key(key) {
+            continue;
+        }
         let new_path = new_dir.join(file);
-        let new_data = fs::read(&new_path)?;
-        let new_hash = hash_bytes(&new_data);
+        let new_hash = algorithm.hash_file(&new_path)?;
 
         changes.push(FileChange::New {
             file: file.clone(),
             final_hash: new_hash,
+            mode: file_mode(&new_path)?,
         });
     }
 
-    // Files only in original directory
-    for file in orig_files.difference(&new_files) {
-        let orig_path = orig_dir.join(file);
-        let orig_data = fs::read(&orig_path)?;
-        let orig_hash = hash_bytes(&orig_data);
+    // Directories only in new directory that no added file or link will create
+    for (key, dir) in &new.dirs {
+        if orig.dirs.contains_key(key) {
+            continue;
+        }
+        let prefix = format!("{}/", key);
+        if !new.files.keys().chain(new.links.keys()).any(|f| f.starts_with(&prefix)) {
+            changes.push(FileChange::MkDir { dir: dir.clone() });
+        }
+    }
 
-        changes.push(FileChange::Old {
-            file: file.clone(),
-            original_hash: orig_hash,
-        });
+    // Directories only in original directory
+    for (key, dir) in &orig.dirs {
+        if !new.dirs.contains_key(key) {
+            changes.push(FileChange::RmDir { dir: dir.clone() });
+        }
+    }
+
+    // Sort by path for consistent ordering
+    sort_changes(&mut changes);
+    unchanged.sort();
+
+    Ok(ScanSummary {
+        changes,
+        unchanged,
+        files_scanned,
+    })
+}
+
+/// The files, symlinks (with their targets) and directories found in one
+/// directory, keyed by path, or by lowercased path when comparing without
+/// case.
+struct Scan {
+    files: BTreeMap<String, String>,
+    links: BTreeMap<String, (String, String)>,
+    dirs: BTreeMap<String, String>,
+}
+
+impl Scan {
+    fn new(
+        files: Vec<String>,
+        links: BTreeMap<String, String>,
+        dirs: Vec<String>,
+        case_insensitive: bool,
+    ) -> io::Result<Scan> {
+        let mut seen = BTreeMap::new();
+        let mut key = |path: &str| -> io::Result<String> {
+            let key = if case_insensitive { path.to_lowercase() } else { path.to_string() };
+            if let Some(other) = seen.insert(key.clone(), path.to_string()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("'{}' and '{}' differ only in case", other, path),
+                ));
+            }
+            Ok(key)
+        };
+
+        let mut scan = Scan {
+            files: BTreeMap::new(),
+            links: BTreeMap::new(),
+            dirs: BTreeMap::new(),
+        };
+        for file in files {
+            scan.files.insert(key(&file)?, file);
+        }
+        for (file, target) in links {
+            scan.links.insert(key(&file)?, (file, target));
+        }
+        for dir in dirs {
+            scan.dirs.insert(key(&dir)?, dir);
+        }
+        Ok(scan)
+    }
+}
+
+/// Unix permission bits of a file, or `None` on platforms without them.
+#[cfg(unix)]
+fn file_mode(path: &Path) -> io::Result<Option<u32>> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(Some(fs::metadata(path)?.permissions().mode() & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn file_mode(_path: &Path) -> io::Result<Option<u32>> {
+    Ok(None)
+}
+
+/// Like `categorize_files`, but files are also matched by content hash
+/// regardless of name: a removed file and an added file with identical
+/// content are reported as a single `Rename` instead of `Old` + `New`.
+pub fn categorize_files_by_content(
+    orig_dir: &Path,
+    new_dir: &Path,
+    algorithm: Algorithm,
+    case_insensitive: bool,
+) -> io::Result<Vec<FileChange>> {
+    Ok(scan_summary_by_content(orig_dir, new_dir, algorithm, case_insensitive)?.changes)
+}
+
+/// Like `scan_summary`, but with renames paired up as in
+/// `categorize_files_by_content`.
+pub fn scan_summary_by_content(
+    orig_dir: &Path,
+    new_dir: &Path,
+    algorithm: Algorithm,
+    case_insensitive: bool,
+) -> io::Result<ScanSummary> {
+    Ok(pair_renames(scan_summary(orig_dir, new_dir, algorithm, case_insensitive)?))
+}
+
+/// `summary` with each removed file that has the same content as an added
+/// one reported as a `Rename` of it.
+pub(crate) fn pair_renames(summary: ScanSummary) -> ScanSummary {
+    let mut changes = Vec::new();
+    let mut added = Vec::new();
+    for change in summary.changes {
+        match change {
+            FileChange::New { .. } => added.push(change),
+            other => changes.push(other),
+        }
+    }
+
+    for change in &mut changes {
+        if let FileChange::Old { file, original_hash } = change
+            && let Some(pos) = added.iter().position(
+                |a| matches!(a, FileChange::New { final_hash, .. } if final_hash == original_hash),
+            )
+            && let FileChange::New { file: to, final_hash: hash, .. } = added.remove(pos)
+        {
+            *change = FileChange::Rename {
+                from: std::mem::take(file),
+                to,
+                hash,
+            };
+        }
     }
 
-    // Sort by filename for consistent ordering
-    changes.sort_by(|a, b| a.file().cmp(b.file()));
+    changes.extend(added);
+    sort_changes(&mut changes);
 
-    Ok(changes)
+    ScanSummary { changes, ..summary }
+}
+
+/// Write a list of detected changes as JSON, e.g. for review before
+/// the patch is materialized.
+pub fn save_changes(changes: &[FileChange], path: &Path) -> io::Result<()> {
+    let content = serde_json::to_string_pretty(changes)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(path, content)
+}
+
+/// Read a list of changes previously written by `save_changes`.
+pub fn load_changes(path: &Path) -> io::Result<Vec<FileChange>> {
+    let content = fs::read_to_string(path)?;
+    serde_json::from_str(&content)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::patch::IGNORE_FILENAME;
+    use crate::utils::hash::hash_bytes;
     use std::fs::File;
     use tempfile::tempdir;
 
@@ -161,6 +624,43 @@ mod tests {
         assert!(files.is_empty());
     }
 
+    #[test]
+    fn list_files_recursive_returns_relative_paths() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("data/text/en")).unwrap();
+        fs::create_dir_all(dir.path().join(BACKUP_DIR)).unwrap();
+        File::create(dir.path().join("game.exe")).unwrap();
+        File::create(dir.path().join("data/text/en/strings.txt")).unwrap();
+        File::create(dir.path().join("data/fonts.pak")).unwrap();
+        File::create(dir.path().join(BACKUP_DIR).join("game.exe")).unwrap();
+        File::create(dir.path().join(LOCK_FILENAME)).unwrap();
+
+        let files = list_files_recursive(dir.path()).unwrap();
+
+        assert_eq!(files, vec!["data/fonts.pak", "data/text/en/strings.txt", "game.exe"]);
+    }
+
+    #[test]
+    fn scans_skip_backup_dir() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        fs::write(orig_dir.path().join("game.dat"), b"same").unwrap();
+        fs::write(new_dir.path().join("game.dat"), b"same").unwrap();
+        // Left over from patching the directories in place
+        fs::create_dir_all(orig_dir.path().join(BACKUP_DIR)).unwrap();
+        fs::write(orig_dir.path().join(BACKUP_DIR).join("game.dat"), b"old").unwrap();
+        fs::create_dir_all(new_dir.path().join(".Patch-Backup/data")).unwrap();
+        fs::write(new_dir.path().join(".Patch-Backup/data/strings.txt"), b"old").unwrap();
+
+        let changes = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap();
+        assert!(changes.is_empty(), "{:?}", changes);
+
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("game.dat")).unwrap();
+        File::create(dir.path().join(BACKUP_DIR)).unwrap();
+        assert_eq!(list_files(dir.path()).unwrap(), vec!["game.dat"]);
+    }
+
     #[test]
     fn list_files_nonexistent_directory() {
         let result = list_files(Path::new("/nonexistent/directory"));
@@ -176,12 +676,12 @@ mod tests {
         fs::write(orig_dir.path().join("file.bin"), b"original").unwrap();
         fs::write(new_dir.path().join("file.bin"), b"modified").unwrap();
 
-        let changes = categorize_files(orig_dir.path(), new_dir.path()).unwrap();
+        let changes = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap();
 
         assert_eq!(changes.len(), 1);
         assert!(matches!(
             &changes[0],
-            FileChange::Diff { file, original_hash, final_hash }
+            FileChange::Diff { file, original_hash, final_hash, .. }
             if file == "file.bin" && original_hash != final_hash
         ));
     }
@@ -193,7 +693,7 @@ mod tests {
 
         fs::write(new_dir.path().join("new_file.bin"), b"new content").unwrap();
 
-        let changes = categorize_files(orig_dir.path(), new_dir.path()).unwrap();
+        let changes = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap();
 
         assert_eq!(changes.len(), 1);
         assert!(matches!(
@@ -209,7 +709,7 @@ mod tests {
 
         fs::write(orig_dir.path().join("old_file.bin"), b"old content").unwrap();
 
-        let changes = categorize_files(orig_dir.path(), new_dir.path()).unwrap();
+        let changes = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap();
 
         assert_eq!(changes.len(), 1);
         assert!(matches!(
@@ -226,7 +726,7 @@ mod tests {
         fs::write(orig_dir.path().join("same.bin"), b"same content").unwrap();
         fs::write(new_dir.path().join("same.bin"), b"same content").unwrap();
 
-        let changes = categorize_files(orig_dir.path(), new_dir.path()).unwrap();
+        let changes = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap();
 
         assert!(changes.is_empty());
     }
@@ -250,7 +750,7 @@ mod tests {
         // Old (only in orig)
         fs::write(orig_dir.path().join("old.bin"), b"old").unwrap();
 
-        let changes = categorize_files(orig_dir.path(), new_dir.path()).unwrap();
+        let changes = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap();
 
         assert_eq!(changes.len(), 3);
 
@@ -259,35 +759,273 @@ mod tests {
         assert!(changes.iter().any(|c| matches!(c, FileChange::Diff { file, .. } if file == "modified.bin")));
     }
 
+    #[test]
+    fn categorize_includes_subdirectories() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        fs::create_dir_all(orig_dir.path().join("data/text/en")).unwrap();
+        fs::create_dir_all(new_dir.path().join("data/text/en")).unwrap();
+        fs::create_dir_all(new_dir.path().join("data/text/fr")).unwrap();
+
+        fs::write(orig_dir.path().join("data/text/en/strings.txt"), b"old").unwrap();
+        fs::write(new_dir.path().join("data/text/en/strings.txt"), b"new").unwrap();
+        fs::write(new_dir.path().join("data/text/fr/strings.txt"), b"nouveau").unwrap();
+
+        let changes = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap();
+
+        let files: Vec<_> = changes.iter().map(|c| c.file()).collect();
+        assert_eq!(files, vec!["data/text/en/strings.txt", "data/text/fr/strings.txt"]);
+        assert!(matches!(&changes[0], FileChange::Diff { .. }));
+        assert!(matches!(&changes[1], FileChange::New { .. }));
+    }
+
+    #[test]
+    fn categorize_honors_graftignore_in_original_directory() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        fs::write(orig_dir.path().join(IGNORE_FILENAME), "*.sav\nconfig.ini\n").unwrap();
+
+        fs::write(orig_dir.path().join("slot1.sav"), b"old save").unwrap();
+        fs::write(new_dir.path().join("slot1.sav"), b"new save").unwrap();
+        fs::write(orig_dir.path().join("config.ini"), b"volume=5").unwrap();
+        fs::write(new_dir.path().join("slot2.sav"), b"another save").unwrap();
+        fs::write(orig_dir.path().join("game.dat"), b"old").unwrap();
+        fs::write(new_dir.path().join("game.dat"), b"new").unwrap();
+
+        let changes = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap();
+
+        let files: Vec<_> = changes.iter().map(|c| c.file()).collect();
+        assert_eq!(files, vec!["game.dat"]);
+    }
+
     #[test]
     fn categorize_empty_directories() {
         let orig_dir = tempdir().unwrap();
         let new_dir = tempdir().unwrap();
 
-        let changes = categorize_files(orig_dir.path(), new_dir.path()).unwrap();
+        let changes = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap();
 
         assert!(changes.is_empty());
     }
 
+    #[test]
+    fn categorize_reports_created_and_removed_directories() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        fs::create_dir_all(new_dir.path().join("mods/maps")).unwrap();
+        fs::create_dir_all(new_dir.path().join("data/text")).unwrap();
+        fs::write(new_dir.path().join("data/text/fr.txt"), b"bonjour").unwrap();
+        fs::create_dir_all(orig_dir.path().join("cache/shaders")).unwrap();
+        fs::write(orig_dir.path().join("cache/shaders/a.bin"), b"shader").unwrap();
+
+        let changes = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap();
+
+        // Only directories no added file creates get an entry, and removals
+        // come after the files in them, deepest first
+        let files: Vec<_> = changes.iter().map(|c| c.file()).collect();
+        assert_eq!(
+            files,
+            vec!["cache/shaders/a.bin", "data/text/fr.txt", "mods", "mods/maps", "cache/shaders", "cache"]
+        );
+        assert!(matches!(&changes[2], FileChange::MkDir { .. }));
+        assert!(matches!(&changes[3], FileChange::MkDir { .. }));
+        assert!(matches!(&changes[4], FileChange::RmDir { .. }));
+        assert!(matches!(&changes[5], FileChange::RmDir { .. }));
+    }
+
     #[test]
     fn categorize_nonexistent_directory_errors() {
         let new_dir = tempdir().unwrap();
 
-        let result = categorize_files(Path::new("/nonexistent"), new_dir.path());
+        let result = categorize_files(Path::new("/nonexistent"), new_dir.path(), Algorithm::Sha256, false);
 
         assert!(result.is_err());
     }
 
+    #[test]
+    fn categorize_by_content_pairs_renamed_files() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("voice_01.bin"), b"voice").unwrap();
+        fs::write(new_dir.path().join("a7f3.bin"), b"voice").unwrap();
+        fs::write(new_dir.path().join("extra.bin"), b"extra").unwrap();
+        fs::write(orig_dir.path().join("removed.bin"), b"removed").unwrap();
+
+        let changes = categorize_files_by_content(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap();
+
+        assert_eq!(changes.len(), 3);
+        assert!(changes.iter().any(|c| matches!(
+            c,
+            FileChange::Rename { from, to, hash }
+            if from == "voice_01.bin" && to == "a7f3.bin" && *hash == hash_bytes(b"voice")
+        )));
+        assert!(changes.iter().any(|c| matches!(c, FileChange::New { file, .. } if file == "extra.bin")));
+        assert!(changes.iter().any(|c| matches!(c, FileChange::Old { file, .. } if file == "removed.bin")));
+    }
+
+    #[test]
+    fn categorize_subset_only_examines_listed_files() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        fs::create_dir(orig_dir.path().join("data")).unwrap();
+        fs::create_dir(new_dir.path().join("data")).unwrap();
+
+        fs::write(orig_dir.path().join("data/strings.txt"), b"hello").unwrap();
+        fs::write(new_dir.path().join("data/strings.txt"), b"hola").unwrap();
+        fs::write(new_dir.path().join("data/menu.txt"), b"menu").unwrap();
+        fs::write(orig_dir.path().join("old.bin"), b"old").unwrap();
+        fs::write(orig_dir.path().join("same.bin"), b"same").unwrap();
+        fs::write(new_dir.path().join("same.bin"), b"same").unwrap();
+        // Not listed, so left out
+        fs::write(orig_dir.path().join("save.dat"), b"before").unwrap();
+        fs::write(new_dir.path().join("save.dat"), b"after").unwrap();
+        fs::write(new_dir.path().join("local.cfg"), b"local").unwrap();
+
+        let files: Vec<String> = ["data/strings.txt", "data/menu.txt", "old.bin", "same.bin"]
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        let summary = scan_summary_subset(orig_dir.path(), new_dir.path(), &files, Algorithm::Sha256).unwrap();
+
+        assert_eq!(summary.changes.len(), 3);
+        assert!(summary.changes.iter().any(|c| matches!(c, FileChange::Diff { file, .. } if file == "data/strings.txt")));
+        assert!(summary.changes.iter().any(|c| matches!(c, FileChange::New { file, .. } if file == "data/menu.txt")));
+        assert!(summary.changes.iter().any(|c| matches!(c, FileChange::Old { file, .. } if file == "old.bin")));
+        assert_eq!(summary.unchanged, vec!["same.bin".to_string()]);
+    }
+
+    #[test]
+    fn categorize_subset_rejects_bad_paths() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        fs::create_dir(orig_dir.path().join("data")).unwrap();
+        fs::write(orig_dir.path().join("a.bin"), b"a").unwrap();
+
+        let subset = |file: &str| {
+            categorize_files_subset(orig_dir.path(), new_dir.path(), &[file.to_string()], Algorithm::Sha256)
+                .unwrap_err()
+                .kind()
+        };
+        assert_eq!(subset("missing.bin"), io::ErrorKind::NotFound);
+        assert_eq!(subset("data"), io::ErrorKind::InvalidInput);
+        assert_eq!(subset("../a.bin"), io::ErrorKind::InvalidInput);
+        assert_eq!(subset(".patch-backup/a.bin"), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn categorize_without_content_matching_keeps_old_and_new() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("voice_01.bin"), b"voice").unwrap();
+        fs::write(new_dir.path().join("a7f3.bin"), b"voice").unwrap();
+
+        let changes = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap();
+
+        assert_eq!(changes.len(), 2);
+        assert!(!changes.iter().any(|c| matches!(c, FileChange::Rename { .. })));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn categorize_reports_symlink_changes() {
+        use std::os::unix::fs::symlink;
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        symlink("libgame.so.1", orig_dir.path().join("libgame.so")).unwrap();
+        symlink("libgame.so.2", new_dir.path().join("libgame.so")).unwrap();
+        symlink("engine.cfg", orig_dir.path().join("unchanged.cfg")).unwrap();
+        symlink("engine.cfg", new_dir.path().join("unchanged.cfg")).unwrap();
+        symlink("engine.cfg", orig_dir.path().join("removed.cfg")).unwrap();
+        fs::create_dir(new_dir.path().join("data")).unwrap();
+        symlink("../engine.cfg", new_dir.path().join("data/added.cfg")).unwrap();
+
+        let changes = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap();
+
+        let link = |file: &str, original_target: Option<&str>, target: Option<&str>| FileChange::Symlink {
+            file: file.to_string(),
+            original_target: original_target.map(str::to_string),
+            target: target.map(str::to_string),
+        };
+        assert_eq!(
+            changes,
+            vec![
+                link("data/added.cfg", None, Some("../engine.cfg")),
+                link("libgame.so", Some("libgame.so.1"), Some("libgame.so.2")),
+                link("removed.cfg", Some("engine.cfg"), None),
+            ]
+        );
+        assert_eq!(list_files_recursive(new_dir.path()).unwrap(), Vec::<String>::new());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn categorize_rejects_symlink_replacing_file() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        fs::write(orig_dir.path().join("engine.cfg"), b"cfg").unwrap();
+        std::os::unix::fs::symlink("other.cfg", new_dir.path().join("engine.cfg")).unwrap();
+
+        let err = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn case_insensitive_scan_ignores_case_only_renames() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        fs::create_dir(orig_dir.path().join("Data")).unwrap();
+        fs::create_dir(new_dir.path().join("data")).unwrap();
+        fs::write(orig_dir.path().join("Data/Movie.bik"), b"movie").unwrap();
+        fs::write(new_dir.path().join("data/movie.bik"), b"movie").unwrap();
+        fs::write(orig_dir.path().join("Strings.txt"), b"hello").unwrap();
+        fs::write(new_dir.path().join("strings.txt"), b"hola").unwrap();
+
+        let changes = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, true).unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(
+            &changes[0],
+            FileChange::Diff { file, new_file: Some(new_file), .. } if file == "Strings.txt" && new_file == "strings.txt"
+        ));
+
+        // Compared by exact name, the same directories give deletes and adds
+        let changes = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, false).unwrap();
+        assert!(changes.iter().any(|c| matches!(c, FileChange::Old { file, .. } if file == "Data/Movie.bik")));
+        assert!(changes.iter().any(|c| matches!(c, FileChange::New { file, .. } if file == "data/movie.bik")));
+    }
+
+    #[test]
+    fn case_insensitive_scan_rejects_case_collisions() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        fs::write(new_dir.path().join("data.bin"), b"one").unwrap();
+        fs::write(new_dir.path().join("DATA.bin"), b"two").unwrap();
+        if fs::read_dir(new_dir.path()).unwrap().count() < 2 {
+            // The filesystem is case-insensitive itself, so can't hold both
+            return;
+        }
+
+        let err = categorize_files(orig_dir.path(), new_dir.path(), Algorithm::Sha256, true).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("differ only in case"));
+    }
+
     #[test]
     fn file_helper_returns_filename() {
         let diff = FileChange::Diff {
             file: "a.bin".to_string(),
             original_hash: "x".to_string(),
             final_hash: "z".to_string(),
+            mode: None,
+            new_file: None,
         };
         let new = FileChange::New {
             file: "b.bin".to_string(),
             final_hash: "x".to_string(),
+            mode: None,
         };
         let old = FileChange::Old {
             file: "c.bin".to_string(),
@@ -298,4 +1036,58 @@ mod tests {
         assert_eq!(new.file(), "b.bin");
         assert_eq!(old.file(), "c.bin");
     }
+
+    #[test]
+    fn summary_lists_unchanged_files() {
+        let orig = tempdir().unwrap();
+        let new = tempdir().unwrap();
+        fs::write(orig.path().join("same.bin"), b"same").unwrap();
+        fs::write(new.path().join("same.bin"), b"same").unwrap();
+        fs::write(orig.path().join("modified.bin"), b"old").unwrap();
+        fs::write(new.path().join("modified.bin"), b"new").unwrap();
+        fs::write(orig.path().join("moved.bin"), b"moved").unwrap();
+        fs::write(new.path().join("renamed.bin"), b"moved").unwrap();
+
+        let summary = scan_summary(orig.path(), new.path(), Algorithm::Sha256, false).unwrap();
+        assert_eq!(summary.changes.len(), 3);
+        assert_eq!(summary.unchanged, vec!["same.bin".to_string()]);
+        assert_eq!(summary.files_scanned, 4);
+        assert_eq!(summary.files_changed(), 3);
+
+        let by_content = scan_summary_by_content(orig.path(), new.path(), Algorithm::Sha256, false).unwrap();
+        assert_eq!(by_content.changes.len(), 2);
+        assert_eq!(by_content.unchanged, summary.unchanged);
+        assert_eq!(by_content.files_scanned, 4);
+    }
+
+    #[test]
+    fn changes_roundtrip_through_json() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("changes.json");
+
+        let changes = vec![
+            FileChange::Diff {
+                file: "a.bin".to_string(),
+                original_hash: "aaa".to_string(),
+                final_hash: "bbb".to_string(),
+                mode: None,
+                new_file: None,
+            },
+            FileChange::New {
+                file: "b.bin".to_string(),
+                final_hash: "ccc".to_string(),
+                mode: None,
+            },
+            FileChange::Old {
+                file: "c.bin".to_string(),
+                original_hash: "ddd".to_string(),
+            },
+        ];
+
+        save_changes(&changes, &path).unwrap();
+        let json = fs::read_to_string(&path).unwrap();
+        assert!(json.contains(r#""change": "diff""#));
+
+        assert_eq!(load_changes(&path).unwrap(), changes);
+    }
 }
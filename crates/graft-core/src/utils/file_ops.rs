@@ -1,37 +1,74 @@
-use std::fs;
 use std::io;
 use std::path::Path;
 
-/// Copy a file to a backup directory, preserving the filename.
-/// Creates the backup directory if it doesn't exist.
-pub fn backup_file(file: &Path, backup_dir: &Path) -> io::Result<()> {
-    let filename = file
-        .file_name()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no filename"))?;
+use crate::utils::filesystem::{FileSystem, StdFs};
+use crate::utils::hash::Algorithm;
+
+/// Copy `file`, a path relative to `target_dir`, into `backup_dir` under the
+/// same relative path.
+///
+/// The backup mirrors the target's layout, so `data/en/strings.bin` and
+/// `data/fr/strings.bin` get separate backups. Intermediate directories are
+/// created as needed.
+///
+/// The backup keeps the original's modification time, so `restore_file` can
+/// put it back. Returns the hash (with `algorithm`) of the file that was
+/// copied, so the backup can be checked before it is restored.
+pub fn backup_file(target_dir: &Path, file: &str, backup_dir: &Path, algorithm: Algorithm) -> io::Result<String> {
+    backup_file_with(&StdFs, target_dir, file, backup_dir, algorithm)
+}
 
-    fs::create_dir_all(backup_dir)?;
+/// Like `backup_file`, using the given filesystem.
+pub fn backup_file_with(
+    fs: &impl FileSystem,
+    target_dir: &Path,
+    file: &str,
+    backup_dir: &Path,
+    algorithm: Algorithm,
+) -> io::Result<String> {
+    let source = target_dir.join(file);
+    let backup_path = backup_dir.join(file);
+    if let Some(parent) = backup_path.parent() {
+        fs.create_dir_all(parent)?;
+    }
+    let hash = fs.hash(&source, algorithm)?;
+    fs.copy(&source, &backup_path)?;
+    copy_modified(fs, &source, &backup_path);
+    Ok(hash)
+}
 
-    let backup_path = backup_dir.join(filename);
-    fs::copy(file, &backup_path)?;
+/// Restore `file`, a path relative to `target_dir`, from its backup in
+/// `backup_dir`, overwriting the current file and restoring its modification
+/// time. Intermediate directories in the target are recreated if they've
+/// gone.
+pub fn restore_file(target_dir: &Path, file: &str, backup_dir: &Path) -> io::Result<()> {
+    restore_file_with(&StdFs, target_dir, file, backup_dir)
+}
 
+/// Like `restore_file`, using the given filesystem.
+pub fn restore_file_with(fs: &impl FileSystem, target_dir: &Path, file: &str, backup_dir: &Path) -> io::Result<()> {
+    let target_path = target_dir.join(file);
+    if let Some(parent) = target_path.parent() {
+        fs.create_dir_all(parent)?;
+    }
+    let backup_path = backup_dir.join(file);
+    fs.copy(&backup_path, &target_path)?;
+    copy_modified(fs, &backup_path, &target_path);
     Ok(())
 }
 
-/// Restore a file from a backup directory, overwriting the original.
-pub fn restore_file(file: &Path, backup_dir: &Path) -> io::Result<()> {
-    let filename = file
-        .file_name()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no filename"))?;
-
-    let backup_path = backup_dir.join(filename);
-    fs::copy(&backup_path, file)?;
-
-    Ok(())
+/// Give `to` the modification time of `from`. Best effort: the contents are
+/// what matter, so a filesystem that can't keep times doesn't fail the copy.
+fn copy_modified(fs: &impl FileSystem, from: &Path, to: &Path) {
+    if let Ok(time) = fs.modified(from) {
+        let _ = fs.set_modified(to, time);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
     use tempfile::tempdir;
 
     #[test]
@@ -42,7 +79,8 @@ mod tests {
         let file_path = source_dir.path().join("test.bin");
         fs::write(&file_path, b"original content").unwrap();
 
-        backup_file(&file_path, backup_dir.path()).unwrap();
+        let hash = backup_file(source_dir.path(), "test.bin", backup_dir.path(), Algorithm::Sha256).unwrap();
+        assert_eq!(hash, crate::utils::hash::hash_bytes(b"original content"));
 
         let backup_path = backup_dir.path().join("test.bin");
         assert!(backup_path.exists());
@@ -59,7 +97,7 @@ mod tests {
         fs::write(&file_path, b"content").unwrap();
 
         assert!(!backup_dir.exists());
-        backup_file(&file_path, &backup_dir).unwrap();
+        backup_file(source_dir.path(), "test.bin", &backup_dir, Algorithm::Sha256).unwrap();
         assert!(backup_dir.exists());
         assert!(backup_dir.join("test.bin").exists());
     }
@@ -67,9 +105,9 @@ mod tests {
     #[test]
     fn backup_missing_file_errors() {
         let backup_dir = tempdir().unwrap();
-        let missing = Path::new("/nonexistent/file.bin");
+        let missing = Path::new("/nonexistent");
 
-        let result = backup_file(missing, backup_dir.path());
+        let result = backup_file(missing, "file.bin", backup_dir.path(), Algorithm::Sha256);
 
         assert!(result.is_err());
     }
@@ -85,7 +123,7 @@ mod tests {
         let backup_path = backup_dir.path().join("test.bin");
         fs::write(&backup_path, b"original content").unwrap();
 
-        restore_file(&file_path, backup_dir.path()).unwrap();
+        restore_file(target_dir.path(), "test.bin", backup_dir.path()).unwrap();
 
         assert_eq!(fs::read(&file_path).unwrap(), b"original content");
     }
@@ -100,20 +138,58 @@ mod tests {
         fs::write(&backup_path, b"backup content").unwrap();
 
         assert!(!file_path.exists());
-        restore_file(&file_path, backup_dir.path()).unwrap();
+        restore_file(target_dir.path(), "test.bin", backup_dir.path()).unwrap();
         assert!(file_path.exists());
         assert_eq!(fs::read(&file_path).unwrap(), b"backup content");
     }
 
     #[test]
-    fn restore_missing_backup_errors() {
+    fn backup_and_restore_keep_modification_time() {
         let target_dir = tempdir().unwrap();
         let backup_dir = tempdir().unwrap();
-
         let file_path = target_dir.path().join("test.bin");
+        fs::write(&file_path, b"original content").unwrap();
+        let original = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_500_000_000);
+        StdFs.set_modified(&file_path, original).unwrap();
 
-        let result = restore_file(&file_path, backup_dir.path());
+        backup_file(target_dir.path(), "test.bin", backup_dir.path(), Algorithm::Sha256).unwrap();
+        fs::write(&file_path, b"patched content").unwrap();
+        restore_file(target_dir.path(), "test.bin", backup_dir.path()).unwrap();
+
+        assert_eq!(fs::metadata(&file_path).unwrap().modified().unwrap(), original);
+    }
+
+    #[test]
+    fn restore_missing_backup_errors() {
+        let target_dir = tempdir().unwrap();
+        let backup_dir = tempdir().unwrap();
+
+        let result = restore_file(target_dir.path(), "test.bin", backup_dir.path());
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn same_named_files_in_different_directories_keep_separate_backups() {
+        let target_dir = tempdir().unwrap();
+        let backup_dir = tempdir().unwrap();
+        for lang in ["en", "fr"] {
+            fs::create_dir_all(target_dir.path().join(lang)).unwrap();
+            fs::write(target_dir.path().join(lang).join("strings.bin"), lang).unwrap();
+        }
+
+        backup_file(target_dir.path(), "en/strings.bin", backup_dir.path(), Algorithm::Sha256).unwrap();
+        backup_file(target_dir.path(), "fr/strings.bin", backup_dir.path(), Algorithm::Sha256).unwrap();
+        assert_eq!(fs::read(backup_dir.path().join("en/strings.bin")).unwrap(), b"en");
+        assert_eq!(fs::read(backup_dir.path().join("fr/strings.bin")).unwrap(), b"fr");
+
+        // Overwrite both, and remove one directory entirely
+        fs::write(target_dir.path().join("en/strings.bin"), b"patched").unwrap();
+        fs::remove_dir_all(target_dir.path().join("fr")).unwrap();
+
+        restore_file(target_dir.path(), "en/strings.bin", backup_dir.path()).unwrap();
+        restore_file(target_dir.path(), "fr/strings.bin", backup_dir.path()).unwrap();
+        assert_eq!(fs::read(target_dir.path().join("en/strings.bin")).unwrap(), b"en");
+        assert_eq!(fs::read(target_dir.path().join("fr/strings.bin")).unwrap(), b"fr");
+    }
 }
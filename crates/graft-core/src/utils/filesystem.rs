@@ -0,0 +1,590 @@
+//! Filesystem abstraction used by the apply, backup, verify and rollback steps.
+//!
+//! `StdFs` is the real filesystem. With the `test-util` feature, `MemFs`
+//! provides an in-memory implementation that can also simulate I/O failures.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::utils::diff::apply_diff_streaming;
+use crate::utils::hash::Algorithm;
+use crate::utils::temp_file;
+
+/// The filesystem operations patching needs.
+pub trait FileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+    /// Replace a file's contents so a crash leaves the old or the new
+    /// contents, never a mix. The default writes in place; `StdFs` writes a
+    /// temp file and renames it into place.
+    fn write_atomic(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.write(path, data)
+    }
+    /// Like `write_atomic`, calling `on_written` with the number of bytes
+    /// written so far as the data goes out. The default writes everything at
+    /// once and reports once; `StdFs` writes in chunks, reporting after each.
+    fn write_atomic_reporting(&self, path: &Path, data: &[u8], on_written: &mut dyn FnMut(u64)) -> io::Result<()> {
+        self.write_atomic(path, data)?;
+        on_written(data.len() as u64);
+        Ok(())
+    }
+    /// Replace the file at `to` with a copy of `from`, as
+    /// `write_atomic_reporting` does. The default copies in place and
+    /// reports once; `StdFs` streams the copy into a temp file and renames it
+    /// into place.
+    fn copy_atomic_reporting(&self, from: &Path, to: &Path, on_written: &mut dyn FnMut(u64)) -> io::Result<()> {
+        self.copy(from, to)?;
+        on_written(self.file_size(to)?);
+        Ok(())
+    }
+    /// Rebuild the file at `path` by applying the bsdiff delta read from
+    /// `diff` to its current contents, replacing it as `write_atomic_reporting`
+    /// does. The default holds the whole file in memory; `StdFs` streams it
+    /// through `apply_diff_streaming`, so memory use stays bounded.
+    fn patch_streaming(&self, path: &Path, diff: &mut dyn Read, on_written: &mut dyn FnMut(u64)) -> io::Result<()> {
+        let mut patched = Vec::new();
+        apply_diff_streaming(&mut io::Cursor::new(self.read(path)?), diff, &mut patched)?;
+        self.write_atomic_reporting(path, &patched, on_written)
+    }
+    /// Open a file for reading. The default reads the whole file up front;
+    /// `StdFs` reads it as it is consumed.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(io::Cursor::new(self.read(path)?)))
+    }
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    /// Remove an empty directory.
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+    fn exists(&self, path: &Path) -> bool;
+    /// Whether `path` is a directory (not following symlinks).
+    fn is_dir(&self, path: &Path) -> bool;
+    /// Where the symlink at `path` points. Fails if `path` isn't a symlink.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+    /// Create a symlink at `link` pointing to `target`. Fails with
+    /// `Unsupported` on platforms graft doesn't create symlinks on.
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()>;
+    /// Set Unix permission bits. Does nothing on platforms without them.
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()>;
+    /// When the file at `path` was last modified.
+    fn modified(&self, path: &Path) -> io::Result<SystemTime>;
+    /// Set the file's modification time.
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()>;
+
+    /// Hash a file's contents. The default reads the whole file; `StdFs`
+    /// streams it instead.
+    fn hash(&self, path: &Path, algorithm: Algorithm) -> io::Result<String> {
+        Ok(algorithm.hash_bytes(&self.read(path)?))
+    }
+
+    /// Size of a file in bytes. The default reads the whole file; `StdFs`
+    /// asks for its metadata instead.
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        Ok(self.read(path)?.len() as u64)
+    }
+
+    /// Bytes free for writing on the volume holding `path`, which must
+    /// exist, or `None` if that can't be determined on this platform.
+    fn available_space(&self, _path: &Path) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+/// The real filesystem, backed by `std::fs`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdFs;
+
+impl FileSystem for StdFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        fs::write(path, data)
+    }
+
+    fn write_atomic(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        temp_file::write_atomic(path, data)
+    }
+
+    fn write_atomic_reporting(&self, path: &Path, data: &[u8], on_written: &mut dyn FnMut(u64)) -> io::Result<()> {
+        temp_file::write_atomic_reporting(path, data, on_written)
+    }
+
+    fn copy_atomic_reporting(&self, from: &Path, to: &Path, on_written: &mut dyn FnMut(u64)) -> io::Result<()> {
+        temp_file::copy_atomic_reporting(from, to, on_written)
+    }
+
+    fn patch_streaming(&self, path: &Path, diff: &mut dyn Read, on_written: &mut dyn FnMut(u64)) -> io::Result<()> {
+        temp_file::write_atomic_with(path, on_written, |mut out| {
+            let mut orig = io::BufReader::new(fs::File::open(path)?);
+            apply_diff_streaming(&mut orig, diff, &mut out).map(|_| ())
+        })
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(io::BufReader::new(fs::File::open(path)?)))
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::copy(from, to).map(|_| ())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        fs::symlink_metadata(path).is_ok_and(|m| m.is_dir())
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    #[cfg(unix)]
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    #[cfg(not(unix))]
+    fn symlink(&self, _target: &Path, _link: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "symbolic links are not supported on this platform",
+        ))
+    }
+
+    #[cfg(unix)]
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+
+    #[cfg(not(unix))]
+    fn set_mode(&self, _path: &Path, _mode: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        fs::metadata(path)?.modified()
+    }
+
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()> {
+        filetime::set_file_mtime(path, filetime::FileTime::from_system_time(time))
+    }
+
+    fn hash(&self, path: &Path, algorithm: Algorithm) -> io::Result<String> {
+        algorithm.hash_file(path)
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        fs::metadata(path).map(|m| m.len())
+    }
+
+    #[cfg(unix)]
+    fn available_space(&self, path: &Path) -> io::Result<Option<u64>> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let c_path =
+            CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: `c_path` is a valid NUL-terminated string and `stat` is a
+        // plain C struct statvfs fills in
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+    }
+
+    #[cfg(windows)]
+    fn available_space(&self, path: &Path) -> io::Result<Option<u64>> {
+        use std::os::windows::ffi::OsStrExt;
+        use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let mut available = 0u64;
+        // SAFETY: `wide` is NUL-terminated and the totals we don't need may
+        // be null
+        let ok = unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut()) };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(available))
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+pub use mem::MemFs;
+
+#[cfg(any(test, feature = "test-util"))]
+mod mem {
+    use super::FileSystem;
+    use std::cell::{Cell, RefCell};
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::time::SystemTime;
+
+    /// In-memory filesystem for tests.
+    ///
+    /// Directories are implicit: a path exists if it is a file, was created
+    /// with `create_dir_all`, or is the parent of a file. Parent directories
+    /// are not required to exist before writing.
+    ///
+    /// Use `fail_writes` / `fail_reads` to make operations on a path return
+    /// a given error (e.g. `StorageFull`, `PermissionDenied`), and
+    /// `set_available_space` to report how much room is left.
+    #[derive(Debug, Default)]
+    pub struct MemFs {
+        files: RefCell<BTreeMap<PathBuf, Vec<u8>>>,
+        links: RefCell<BTreeMap<PathBuf, PathBuf>>,
+        dirs: RefCell<BTreeSet<PathBuf>>,
+        modes: RefCell<HashMap<PathBuf, u32>>,
+        mtimes: RefCell<HashMap<PathBuf, SystemTime>>,
+        read_faults: RefCell<HashMap<PathBuf, io::ErrorKind>>,
+        write_faults: RefCell<HashMap<PathBuf, io::ErrorKind>>,
+        available: Cell<Option<u64>>,
+    }
+
+    impl MemFs {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Add or replace a file.
+        pub fn insert(&self, path: impl AsRef<Path>, data: impl Into<Vec<u8>>) {
+            self.files
+                .borrow_mut()
+                .insert(path.as_ref().to_path_buf(), data.into());
+        }
+
+        /// Get the contents of a file, if it exists.
+        pub fn get(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+            self.files.borrow().get(path.as_ref()).cloned()
+        }
+
+        /// The permission bits last set on a file with `set_mode`, if any.
+        pub fn mode(&self, path: impl AsRef<Path>) -> Option<u32> {
+            self.modes.borrow().get(path.as_ref()).copied()
+        }
+
+        /// The modification time last set on a file with `set_modified`, if
+        /// any.
+        pub fn mtime(&self, path: impl AsRef<Path>) -> Option<SystemTime> {
+            self.mtimes.borrow().get(path.as_ref()).copied()
+        }
+
+        /// Make reads of `path` fail with `kind`.
+        pub fn fail_reads(&self, path: impl AsRef<Path>, kind: io::ErrorKind) {
+            self.read_faults
+                .borrow_mut()
+                .insert(path.as_ref().to_path_buf(), kind);
+        }
+
+        /// Make writes, copies to, and removals of `path` fail with `kind`.
+        pub fn fail_writes(&self, path: impl AsRef<Path>, kind: io::ErrorKind) {
+            self.write_faults
+                .borrow_mut()
+                .insert(path.as_ref().to_path_buf(), kind);
+        }
+
+        /// Report `bytes` free on every path (`None`, the default, for
+        /// unknown). Writes aren't limited by it.
+        pub fn set_available_space(&self, bytes: Option<u64>) {
+            self.available.set(bytes);
+        }
+
+        /// Clear all simulated failures.
+        pub fn clear_faults(&self) {
+            self.read_faults.borrow_mut().clear();
+            self.write_faults.borrow_mut().clear();
+        }
+
+        fn check(faults: &RefCell<HashMap<PathBuf, io::ErrorKind>>, path: &Path) -> io::Result<()> {
+            match faults.borrow().get(path) {
+                Some(kind) => Err(io::Error::new(*kind, format!("simulated failure: {}", path.display()))),
+                None => Ok(()),
+            }
+        }
+
+        fn not_found(path: &Path) -> io::Error {
+            io::Error::new(io::ErrorKind::NotFound, format!("no such file: {}", path.display()))
+        }
+    }
+
+    impl FileSystem for MemFs {
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            Self::check(&self.read_faults, path)?;
+            self.get(path).ok_or_else(|| Self::not_found(path))
+        }
+
+        fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+            Self::check(&self.write_faults, path)?;
+            self.insert(path, data);
+            Ok(())
+        }
+
+        fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+            let data = self.read(from)?;
+            self.write(to, &data)
+        }
+
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            Self::check(&self.write_faults, path)?;
+            if self.links.borrow_mut().remove(path).is_some() {
+                return Ok(());
+            }
+            self.files
+                .borrow_mut()
+                .remove(path)
+                .map(|_| ())
+                .ok_or_else(|| Self::not_found(path))
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            Self::check(&self.write_faults, from)?;
+            Self::check(&self.write_faults, to)?;
+            let data = self
+                .files
+                .borrow_mut()
+                .remove(from)
+                .ok_or_else(|| Self::not_found(from))?;
+            self.insert(to, data);
+            Ok(())
+        }
+
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            Self::check(&self.write_faults, path)?;
+            self.dirs.borrow_mut().insert(path.to_path_buf());
+            Ok(())
+        }
+
+        fn remove_dir(&self, path: &Path) -> io::Result<()> {
+            Self::check(&self.write_faults, path)?;
+            if !self.is_dir(path) {
+                return Err(Self::not_found(path));
+            }
+            let beneath = |p: &PathBuf| p != path && p.starts_with(path);
+            if self.files.borrow().keys().any(beneath)
+                || self.links.borrow().keys().any(beneath)
+                || self.dirs.borrow().iter().any(beneath)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::DirectoryNotEmpty,
+                    format!("directory not empty: {}", path.display()),
+                ));
+            }
+            self.dirs.borrow_mut().remove(path);
+            Ok(())
+        }
+
+        fn exists(&self, path: &Path) -> bool {
+            self.files.borrow().keys().any(|f| f.starts_with(path))
+                || self.links.borrow().contains_key(path)
+                || self.dirs.borrow().iter().any(|d| d.starts_with(path))
+        }
+
+        fn is_dir(&self, path: &Path) -> bool {
+            self.exists(path) && !self.files.borrow().contains_key(path) && !self.links.borrow().contains_key(path)
+        }
+
+        fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+            Self::check(&self.read_faults, path)?;
+            self.links.borrow().get(path).cloned().ok_or_else(|| Self::not_found(path))
+        }
+
+        fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+            Self::check(&self.write_faults, link)?;
+            if self.exists(link) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("already exists: {}", link.display()),
+                ));
+            }
+            self.links.borrow_mut().insert(link.to_path_buf(), target.to_path_buf());
+            Ok(())
+        }
+
+        fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+            Self::check(&self.write_faults, path)?;
+            if !self.files.borrow().contains_key(path) {
+                return Err(Self::not_found(path));
+            }
+            self.modes.borrow_mut().insert(path.to_path_buf(), mode);
+            Ok(())
+        }
+
+        /// Files never given a time with `set_modified` report the epoch.
+        fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+            Self::check(&self.read_faults, path)?;
+            if !self.files.borrow().contains_key(path) {
+                return Err(Self::not_found(path));
+            }
+            Ok(self.mtime(path).unwrap_or(SystemTime::UNIX_EPOCH))
+        }
+
+        fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()> {
+            Self::check(&self.write_faults, path)?;
+            if !self.files.borrow().contains_key(path) {
+                return Err(Self::not_found(path));
+            }
+            self.mtimes.borrow_mut().insert(path.to_path_buf(), time);
+            Ok(())
+        }
+
+        fn available_space(&self, path: &Path) -> io::Result<Option<u64>> {
+            if !self.exists(path) {
+                return Err(Self::not_found(path));
+            }
+            Ok(self.available.get())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mem_fs_reads_and_writes() {
+        let fs = MemFs::new();
+        fs.write(Path::new("/game/a.bin"), b"data").unwrap();
+
+        assert_eq!(fs.read(Path::new("/game/a.bin")).unwrap(), b"data");
+        assert!(fs.exists(Path::new("/game/a.bin")));
+        assert!(fs.exists(Path::new("/game")));
+        assert!(!fs.exists(Path::new("/game/b.bin")));
+    }
+
+    #[test]
+    fn mem_fs_copy_and_remove() {
+        let fs = MemFs::new();
+        fs.insert("/a.bin", "data");
+
+        fs.copy(Path::new("/a.bin"), Path::new("/b.bin")).unwrap();
+        fs.remove_file(Path::new("/a.bin")).unwrap();
+
+        assert_eq!(fs.get("/b.bin").unwrap(), b"data");
+        assert!(!fs.exists(Path::new("/a.bin")));
+        assert_eq!(
+            fs.remove_file(Path::new("/a.bin")).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn mem_fs_rename() {
+        let fs = MemFs::new();
+        fs.insert("/a.bin", "data");
+
+        fs.rename(Path::new("/a.bin"), Path::new("/b.bin")).unwrap();
+
+        assert_eq!(fs.get("/b.bin").unwrap(), b"data");
+        assert!(!fs.exists(Path::new("/a.bin")));
+        assert_eq!(
+            fs.rename(Path::new("/a.bin"), Path::new("/c.bin")).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn mem_fs_directories() {
+        let fs = MemFs::new();
+        fs.create_dir_all(Path::new("/game/mods")).unwrap();
+        fs.insert("/game/data/a.bin", "data");
+
+        assert!(fs.is_dir(Path::new("/game/mods")));
+        assert!(fs.is_dir(Path::new("/game/data")));
+        assert!(!fs.is_dir(Path::new("/game/data/a.bin")));
+        assert_eq!(
+            fs.remove_dir(Path::new("/game/data")).unwrap_err().kind(),
+            io::ErrorKind::DirectoryNotEmpty
+        );
+
+        fs.remove_dir(Path::new("/game/mods")).unwrap();
+        assert!(!fs.exists(Path::new("/game/mods")));
+    }
+
+    #[test]
+    fn mem_fs_symlinks() {
+        let fs = MemFs::new();
+        fs.symlink(Path::new("libgame.so.1"), Path::new("/game/libgame.so")).unwrap();
+
+        assert_eq!(fs.read_link(Path::new("/game/libgame.so")).unwrap(), Path::new("libgame.so.1"));
+        assert!(fs.exists(Path::new("/game/libgame.so")));
+        assert!(fs.symlink(Path::new("other"), Path::new("/game/libgame.so")).is_err());
+
+        fs.remove_file(Path::new("/game/libgame.so")).unwrap();
+        assert!(!fs.exists(Path::new("/game/libgame.so")));
+    }
+
+    #[test]
+    fn std_fs_reports_sizes_and_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.bin");
+        fs::write(&path, b"data").unwrap();
+
+        assert_eq!(StdFs.file_size(&path).unwrap(), 4);
+        #[cfg(any(unix, windows))]
+        {
+            assert!(StdFs.available_space(dir.path()).unwrap().is_some());
+            assert!(StdFs.available_space(&dir.path().join("missing")).is_err());
+        }
+    }
+
+    #[test]
+    fn std_and_mem_fs_patch_streaming() {
+        let diff = crate::utils::diff::create_diff(b"original content", b"translated content").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive.pak");
+        fs::write(&path, b"original content").unwrap();
+
+        StdFs.patch_streaming(&path, &mut &diff[..], &mut |_| {}).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"translated content");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let mem = MemFs::new();
+        mem.insert("/archive.pak", "original content");
+        mem.patch_streaming(Path::new("/archive.pak"), &mut &diff[..], &mut |_| {}).unwrap();
+        assert_eq!(mem.get("/archive.pak").unwrap(), b"translated content");
+    }
+
+    #[test]
+    fn mem_fs_simulates_failures() {
+        let fs = MemFs::new();
+        fs.insert("/a.bin", "data");
+        fs.fail_writes("/b.bin", io::ErrorKind::StorageFull);
+        fs.fail_reads("/a.bin", io::ErrorKind::PermissionDenied);
+
+        let err = fs.write(Path::new("/b.bin"), b"x").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+        let err = fs.read(Path::new("/a.bin")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        fs.clear_faults();
+        assert!(fs.write(Path::new("/b.bin"), b"x").is_ok());
+        assert!(fs.read(Path::new("/a.bin")).is_ok());
+    }
+}
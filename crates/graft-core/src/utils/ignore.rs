@@ -0,0 +1,205 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::patch::IGNORE_FILENAME;
+use crate::utils::manifest::ManifestEntry;
+
+/// Patterns loaded from a `.graftignore` file.
+///
+/// One pattern per line. Blank lines and lines starting with `#` are skipped.
+/// `*` matches any run of characters and `?` matches a single character.
+/// A pattern ending in `/` matches everything under that directory. As in
+/// `.gitignore`, a pattern with no `/` also matches a file of that name in
+/// any subdirectory.
+///
+/// The `.graftignore` file itself is always ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IgnoreList {
+    patterns: Vec<String>,
+}
+
+impl IgnoreList {
+    /// Parse patterns from the contents of a `.graftignore` file.
+    pub fn parse(content: &str) -> Self {
+        let patterns = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.to_string())
+            .collect();
+        IgnoreList { patterns }
+    }
+
+    /// Load `.graftignore` from a directory.
+    /// Returns an empty list if the directory has no `.graftignore`.
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        match fs::read_to_string(dir.join(IGNORE_FILENAME)) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Add the patterns from another list.
+    pub fn extend(&mut self, other: IgnoreList) {
+        self.patterns.extend(other.patterns);
+    }
+
+    /// Check whether a file (relative path) matches any pattern.
+    pub fn is_ignored(&self, file: &str) -> bool {
+        let name = file.rsplit('/').next().unwrap_or(file);
+        file == IGNORE_FILENAME
+            || self.patterns.iter().any(|pattern| match pattern.strip_suffix('/') {
+                Some(dir) => file
+                    .strip_prefix(dir)
+                    .is_some_and(|rest| rest.starts_with('/')),
+                None if !pattern.contains('/') => glob_match(pattern, name) || glob_match(pattern, file),
+                None => glob_match(pattern, file),
+            })
+    }
+
+    /// Remove ignored entries from `entries`, returning the removed ones
+    /// in their original order.
+    pub fn filter_entries(&self, entries: &mut Vec<ManifestEntry>) -> Vec<ManifestEntry> {
+        let (skipped, kept) = entries.drain(..).partition(|e| self.is_ignored(e.file()));
+        *entries = kept;
+        skipped
+    }
+}
+
+/// Match `text` against a pattern where `*` matches any run of characters
+/// and `?` matches exactly one.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen, and the text position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // Let the last `*` swallow one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn parse_skips_comments_and_blank_lines() {
+        let list = IgnoreList::parse("# my mods\n\nmod.pak\n  saves/  \n");
+        assert_eq!(list.patterns, vec!["mod.pak", "saves/"]);
+    }
+
+    #[test]
+    fn matches_exact_names() {
+        let list = IgnoreList::parse("mod.pak");
+        assert!(list.is_ignored("mod.pak"));
+        assert!(!list.is_ignored("mod.pak.bak"));
+        assert!(!list.is_ignored("other.pak"));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        let list = IgnoreList::parse("*.sav\nslot?.dat");
+        assert!(list.is_ignored("game.sav"));
+        assert!(list.is_ignored(".sav"));
+        assert!(list.is_ignored("slot1.dat"));
+        assert!(!list.is_ignored("slot10.dat"));
+        assert!(!list.is_ignored("game.sav.txt"));
+    }
+
+    #[test]
+    fn matches_directories() {
+        let list = IgnoreList::parse("mods/");
+        assert!(list.is_ignored("mods/extra.pak"));
+        assert!(list.is_ignored("mods/nested/extra.pak"));
+        assert!(!list.is_ignored("mods"));
+        assert!(!list.is_ignored("modsextra.pak"));
+    }
+
+    #[test]
+    fn patterns_without_slash_match_in_subdirectories() {
+        let list = IgnoreList::parse("config.ini\ndata/*.log");
+        assert!(list.is_ignored("config.ini"));
+        assert!(list.is_ignored("settings/config.ini"));
+        assert!(list.is_ignored("data/debug.log"));
+        assert!(!list.is_ignored("other/debug.log"));
+    }
+
+    #[test]
+    fn extend_combines_patterns() {
+        let mut list = IgnoreList::parse("*.sav");
+        list.extend(IgnoreList::parse("*.log"));
+        assert!(list.is_ignored("slot1.sav"));
+        assert!(list.is_ignored("debug.log"));
+    }
+
+    #[test]
+    fn always_ignores_itself() {
+        let list = IgnoreList::default();
+        assert!(list.is_ignored(IGNORE_FILENAME));
+        assert!(!list.is_ignored("data.bin"));
+    }
+
+    #[test]
+    fn load_missing_file_is_empty() {
+        let dir = tempdir().unwrap();
+        assert_eq!(IgnoreList::load(dir.path()).unwrap(), IgnoreList::default());
+    }
+
+    #[test]
+    fn load_reads_file() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join(IGNORE_FILENAME), "*.sav\n").unwrap();
+
+        let list = IgnoreList::load(dir.path()).unwrap();
+        assert!(list.is_ignored("slot1.sav"));
+    }
+
+    #[test]
+    fn filter_entries_removes_ignored() {
+        let mut entries = vec![
+            ManifestEntry::Delete {
+                file: "slot1.sav".to_string(),
+                original_hash: "a".to_string(),
+            },
+            ManifestEntry::Add {
+                file: "data.bin".to_string(),
+                final_hash: "b".to_string(),
+                original_hash: None,
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: false,
+            },
+        ];
+
+        let skipped = IgnoreList::parse("*.sav").filter_entries(&mut entries);
+
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].file(), "slot1.sav");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].file(), "data.bin");
+    }
+}
@@ -0,0 +1,89 @@
+//! Line-ending normalization for text files in patches.
+//!
+//! A translator editing a script on Windows can turn every LF in it into
+//! CRLF, so the whole file looks changed. When creating a patch in text
+//! mode, the modified file is converted to the original's line endings
+//! before it is hashed and diffed, so only real edits end up in the diff.
+
+use serde::{Deserialize, Serialize};
+
+/// Line-ending convention of a text file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    /// `\n`, as on Unix
+    Lf,
+    /// `\r\n`, as on Windows
+    Crlf,
+}
+
+impl LineEnding {
+    /// The convention `data` uses, judged by its first line break, or
+    /// `None` if it has none.
+    pub fn detect(data: &[u8]) -> Option<LineEnding> {
+        let pos = data.iter().position(|&b| b == b'\n')?;
+        if pos > 0 && data[pos - 1] == b'\r' {
+            Some(LineEnding::Crlf)
+        } else {
+            Some(LineEnding::Lf)
+        }
+    }
+
+    /// `data` with every line break (`\n` or `\r\n`) written in this
+    /// convention. A lone `\r` is left alone.
+    pub fn apply(self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        for (i, &b) in data.iter().enumerate() {
+            match b {
+                b'\r' if data.get(i + 1) == Some(&b'\n') => {}
+                b'\n' => match self {
+                    LineEnding::Lf => out.push(b'\n'),
+                    LineEnding::Crlf => out.extend_from_slice(b"\r\n"),
+                },
+                _ => out.push(b),
+            }
+        }
+        out
+    }
+}
+
+/// Whether `file` (a relative path) has one of `extensions`, compared
+/// without case and with or without a leading dot (`txt` or `.txt`).
+pub fn is_text_file(file: &str, extensions: &[String]) -> bool {
+    let name = file.rsplit('/').next().unwrap_or(file);
+    let Some((_, extension)) = name.rsplit_once('.') else {
+        return false;
+    };
+    extensions
+        .iter()
+        .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_first_line_break() {
+        assert_eq!(LineEnding::detect(b"a\r\nb\nc"), Some(LineEnding::Crlf));
+        assert_eq!(LineEnding::detect(b"a\nb\r\nc"), Some(LineEnding::Lf));
+        assert_eq!(LineEnding::detect(b"\nb"), Some(LineEnding::Lf));
+        assert_eq!(LineEnding::detect(b"one line"), None);
+    }
+
+    #[test]
+    fn converts_mixed_line_breaks() {
+        let mixed = b"a\r\nb\nc\rd\r\n";
+        assert_eq!(LineEnding::Lf.apply(mixed), b"a\nb\nc\rd\n");
+        assert_eq!(LineEnding::Crlf.apply(mixed), b"a\r\nb\r\nc\rd\r\n");
+    }
+
+    #[test]
+    fn matches_extensions_case_insensitively() {
+        let extensions = vec!["txt".to_string(), ".JSON".to_string()];
+        assert!(is_text_file("data/strings.TXT", &extensions));
+        assert!(is_text_file("config.json", &extensions));
+        assert!(!is_text_file("data.txt/movie.bik", &extensions));
+        assert!(!is_text_file("txt", &extensions));
+    }
+}
@@ -1,8 +1,12 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::io;
 use std::path::Path;
 
+use crate::utils::hash::Algorithm;
+use crate::utils::line_endings::LineEnding;
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "operation", rename_all = "lowercase")]
 pub enum ManifestEntry {
@@ -11,15 +15,104 @@ pub enum ManifestEntry {
         original_hash: String,
         diff_hash: String,
         final_hash: String,
+        /// Other acceptable hashes for the patched file, for regional
+        /// variants that patch to slightly different but equally valid
+        /// results; verification passes if the file matches any of them
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        final_hashes: Vec<String>,
+        /// Size in bytes of the file once written, used to report byte
+        /// progress; `None` in manifests from before it was recorded
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        size: Option<u64>,
+        /// Always verified after apply, even when verification is sampled
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        critical: bool,
+        /// Name for the entry shown to players while it is applied (e.g.
+        /// "Spanish dialogue"), instead of the file name
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        /// Unix permission bits to set after writing (ignored on Windows)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<u32>,
+        /// Modification time to set after writing, in seconds since the
+        /// Unix epoch; only recorded when the patch was created with
+        /// `--preserve-mtime`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mtime: Option<i64>,
+        /// The payload is stored gzip-compressed, with `.gz` appended to
+        /// its name; `final_hash` is still of the decompressed file
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        compressed: bool,
+        /// Line endings the modified file was converted to (the original's)
+        /// before diffing, for patches created in text mode; the diff and
+        /// `final_hash` are of the converted file
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        line_endings: Option<LineEnding>,
+        /// Character encoding the modified file was converted to (the
+        /// original's, e.g. `UTF-16LE` or `Shift_JIS`) before diffing, for
+        /// patches created in text mode; the diff and `final_hash` are of the
+        /// converted file
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        encoding: Option<String>,
     },
     Add {
         file: String,
         final_hash: String,
+        /// Hash of a file that may already be at `file`, for files some
+        /// installs are missing ("upsert"): a missing file is created, one
+        /// with this hash is backed up and replaced, and any other is
+        /// refused. `None` requires the file to be absent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        original_hash: Option<String>,
+        /// Size in bytes of the file once written, used to report byte
+        /// progress; `None` in manifests from before it was recorded
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        size: Option<u64>,
+        /// Always verified after apply, even when verification is sampled
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        critical: bool,
+        /// Name for the entry shown to players while it is applied (e.g.
+        /// "Spanish dialogue"), instead of the file name
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        /// Unix permission bits to set after writing (ignored on Windows)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<u32>,
+        /// Modification time to set after writing, in seconds since the
+        /// Unix epoch; only recorded when the patch was created with
+        /// `--preserve-mtime`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mtime: Option<i64>,
+        /// The payload is stored gzip-compressed, with `.gz` appended to
+        /// its name; `final_hash` is still of the decompressed file
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        compressed: bool,
     },
     Delete {
         file: String,
         original_hash: String,
     },
+    /// Move a file with unchanged content from `from` to `to`
+    Rename {
+        from: String,
+        to: String,
+        hash: String,
+    },
+    /// Create, retarget or remove the symlink at `file`
+    Symlink {
+        file: String,
+        /// Where the link points before patching; `None` if it doesn't exist
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        original_target: Option<String>,
+        /// Where the link points after patching; `None` removes it
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<String>,
+    },
+    /// Create the (empty) directory `dir`
+    MkDir { dir: String },
+    /// Remove the directory `dir`, which must be empty once the entries
+    /// before it have been applied
+    RmDir { dir: String },
 }
 
 impl ManifestEntry {
@@ -28,6 +121,62 @@ impl ManifestEntry {
             ManifestEntry::Patch { file, .. } => file,
             ManifestEntry::Add { file, .. } => file,
             ManifestEntry::Delete { file, .. } => file,
+            ManifestEntry::Rename { to, .. } => to,
+            ManifestEntry::Symlink { file, .. } => file,
+            ManifestEntry::MkDir { dir } | ManifestEntry::RmDir { dir } => dir,
+        }
+    }
+
+    /// The operation name, as written in the manifest's `operation` field.
+    pub fn operation(&self) -> &'static str {
+        match self {
+            ManifestEntry::Patch { .. } => "patch",
+            ManifestEntry::Add { .. } => "add",
+            ManifestEntry::Delete { .. } => "delete",
+            ManifestEntry::Rename { .. } => "rename",
+            ManifestEntry::Symlink { .. } => "symlink",
+            ManifestEntry::MkDir { .. } => "mkdir",
+            ManifestEntry::RmDir { .. } => "rmdir",
+        }
+    }
+
+    /// Bytes the entry writes to the target, as recorded in the manifest.
+    ///
+    /// Zero for entries that write no file content, and for patched or
+    /// added files whose size wasn't recorded.
+    pub fn expected_size(&self) -> u64 {
+        match self {
+            ManifestEntry::Patch { size, .. } | ManifestEntry::Add { size, .. } => size.unwrap_or(0),
+            ManifestEntry::Delete { .. }
+            | ManifestEntry::Rename { .. }
+            | ManifestEntry::Symlink { .. }
+            | ManifestEntry::MkDir { .. }
+            | ManifestEntry::RmDir { .. } => 0,
+        }
+    }
+
+    /// The patch author's label for the entry, if any.
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            ManifestEntry::Patch { label, .. } | ManifestEntry::Add { label, .. } => label.as_deref(),
+            ManifestEntry::Delete { .. }
+            | ManifestEntry::Rename { .. }
+            | ManifestEntry::Symlink { .. }
+            | ManifestEntry::MkDir { .. }
+            | ManifestEntry::RmDir { .. } => None,
+        }
+    }
+
+    /// Whether the entry is marked as critical by the patch author.
+    pub fn is_critical(&self) -> bool {
+        match self {
+            ManifestEntry::Patch { critical, .. } => *critical,
+            ManifestEntry::Add { critical, .. } => *critical,
+            ManifestEntry::Delete { .. }
+            | ManifestEntry::Rename { .. }
+            | ManifestEntry::Symlink { .. }
+            | ManifestEntry::MkDir { .. }
+            | ManifestEntry::RmDir { .. } => false,
         }
     }
 }
@@ -39,11 +188,51 @@ pub struct Manifest {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
+    /// What the patch does, shown to the user before applying
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Who made the patch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Version of the game the patch applies to (e.g., "1.4")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_game_version: Option<String>,
+    /// Game file whose hash identifies the installed version, checked
+    /// before applying (see `patch::validate_game_version`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_file: Option<VersionFile>,
+    /// Subdirectory of the target that entry paths are relative to (e.g.
+    /// "localization"), so players can pick the game's root folder; without
+    /// one, entries are relative to the target itself (see
+    /// `patch::install_dir`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_dir: Option<String>,
+    /// Commands to run in the target before anything is changed, each a
+    /// program followed by its arguments (e.g. `["net", "stop", "GameSvc"]`).
+    /// Only run when the player allows it (see `patch::run_hook`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_commands: Vec<Vec<String>>,
+    /// Commands to run in the target once every entry is applied, as for
+    /// `pre_commands`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_commands: Vec<Vec<String>>,
     /// If true, allows patching restricted paths (system dirs, executables).
     /// Default is false for security.
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     pub allow_restricted: bool,
+    /// If true, a patched file missing from the target is looked up by its
+    /// `original_hash` under any name (see `patch::resolve_by_content`).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub match_by_content: bool,
+    /// Algorithm used for every hash in `entries`. Manifests without the
+    /// field use SHA-256.
+    #[serde(default, skip_serializing_if = "Algorithm::is_default")]
+    pub hash_algorithm: Algorithm,
     pub entries: Vec<ManifestEntry>,
+    /// Set by `parse` when the file records no `manifest_hash`, so edits to
+    /// it can't be detected. Never saved.
+    #[serde(skip)]
+    pub unhashed: bool,
 }
 
 impl Manifest {
@@ -52,22 +241,134 @@ impl Manifest {
             version,
             name,
             title,
+            description: None,
+            author: None,
+            target_game_version: None,
+            version_file: None,
+            base_dir: None,
+            pre_commands: Vec::new(),
+            post_commands: Vec::new(),
             allow_restricted: false,
+            match_by_content: false,
+            hash_algorithm: Algorithm::default(),
             entries: Vec::new(),
+            unhashed: false,
         }
     }
 
     pub fn load(path: &Path) -> io::Result<Manifest> {
         let content = fs::read_to_string(path)?;
-        serde_json::from_str(&content)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        Self::parse(&content)
     }
 
+    /// Parse a manifest from its JSON contents.
+    ///
+    /// Fails if the manifest records a `manifest_hash` that doesn't match
+    /// its contents, i.e. it was edited after it was saved. A manifest
+    /// without one (saved by an older version, or hand-written) loads with
+    /// `unhashed` set, for callers to warn about (see `MISSING_HASH_WARNING`).
+    pub fn parse(content: &str) -> io::Result<Manifest> {
+        let mut manifest: Manifest = serde_json::from_str(content)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let recorded: RecordedHash = serde_json::from_str(content)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let Some(expected) = &recorded.manifest_hash else {
+            manifest.unhashed = true;
+            return Ok(manifest);
+        };
+        let actual = manifest.content_hash()?;
+        if &actual != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("manifest hash mismatch: expected {}, got {}", expected, actual),
+            ));
+        }
+        Ok(manifest)
+    }
+
+    /// Load the manifest at `path` whatever `manifest_hash` it records, and
+    /// save it again with its current `content_hash`, e.g. after editing it
+    /// by hand.
+    pub fn rehash(path: &Path) -> io::Result<Manifest> {
+        let content = fs::read_to_string(path)?;
+        let manifest: Manifest = serde_json::from_str(&content)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        manifest.save(path)?;
+        Ok(manifest)
+    }
+
+    /// Save the manifest as JSON, with its `content_hash` recorded as
+    /// `manifest_hash`.
     pub fn save(&self, path: &Path) -> io::Result<()> {
-        let content = serde_json::to_string_pretty(self)
+        let saved = SavedManifest {
+            manifest: self,
+            manifest_hash: self.content_hash()?,
+        };
+        let content = serde_json::to_string_pretty(&saved)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
         fs::write(path, content)
     }
+
+    /// Whether the manifest declares any `pre_commands` or `post_commands`.
+    pub fn has_hooks(&self) -> bool {
+        !self.pre_commands.is_empty() || !self.post_commands.is_empty()
+    }
+
+    /// Hash of the whole manifest (every field but `manifest_hash` itself),
+    /// with the manifest's `hash_algorithm`.
+    ///
+    /// The manifest is hashed in its canonical form: compact JSON, with
+    /// fields in declaration order, entries in manifest order and optional
+    /// fields left out when unset (as `save` writes them). The hash
+    /// therefore doesn't depend on how the file was formatted.
+    pub fn content_hash(&self) -> io::Result<String> {
+        let canonical = serde_json::to_vec(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(self.hash_algorithm.hash_bytes(&canonical))
+    }
+}
+
+/// A game file that tells releases apart, such as the main executable.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionFile {
+    /// Path relative to the game directory
+    pub file: String,
+    /// Hash of the file in the version the patch is for
+    pub hash: String,
+    /// Hash of the file in other releases, by version (e.g. "1.5"), so a
+    /// mismatch can say which version is installed
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub known_versions: BTreeMap<String, String>,
+}
+
+/// A manifest as `save` writes it. The hash is kept out of `Manifest` itself
+/// so it can't go stale as entries are changed in memory.
+#[derive(Serialize)]
+struct SavedManifest<'a> {
+    #[serde(flatten)]
+    manifest: &'a Manifest,
+    manifest_hash: String,
+}
+
+/// The `manifest_hash` field from a saved manifest, if any. Read on its own
+/// so a manifest without one can be reported as such.
+#[derive(Deserialize)]
+struct RecordedHash {
+    #[serde(default)]
+    manifest_hash: Option<String>,
+}
+
+/// Warning for a manifest loaded with `unhashed` set.
+pub const MISSING_HASH_WARNING: &str = "manifest has no manifest_hash, so edits to it can't be detected; \
+     run `graft patch rehash <patch-dir>` to record one";
+
+/// `json` with the `manifest_hash` that `Manifest::save` would record.
+#[cfg(any(test, feature = "test-util"))]
+pub fn with_manifest_hash(json: &str) -> String {
+    let manifest: Manifest = serde_json::from_str(json).unwrap();
+    let mut value: serde_json::Value = serde_json::from_str(json).unwrap();
+    value["manifest_hash"] = manifest.content_hash().unwrap().into();
+    value.to_string()
 }
 
 impl Default for Manifest {
@@ -77,15 +378,28 @@ impl Default for Manifest {
 }
 
 /// Patch metadata extracted from manifest
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct PatchInfo {
     pub version: u32,
     pub name: String,
     pub title: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub target_game_version: Option<String>,
     pub entry_count: usize,
     pub patches: usize,
     pub additions: usize,
     pub deletions: usize,
+    pub renames: usize,
+    pub symlinks: usize,
+    /// Directories created or removed
+    pub directories: usize,
+    /// Bytes of patched and added files once written, as recorded in the
+    /// manifest; entries from manifests without sizes count as zero
+    pub total_bytes: u64,
+    /// Public key (hex) of a verified signer; `None` for unsigned patches.
+    /// Filled in by whoever checked the signature, not by the manifest.
+    pub signed_by: Option<String>,
 }
 
 impl PatchInfo {
@@ -93,21 +407,37 @@ impl PatchInfo {
         let mut patches = 0;
         let mut additions = 0;
         let mut deletions = 0;
+        let mut renames = 0;
+        let mut symlinks = 0;
+        let mut directories = 0;
+        let mut total_bytes = 0;
         for entry in &manifest.entries {
+            total_bytes += entry.expected_size();
             match entry {
                 ManifestEntry::Patch { .. } => patches += 1,
                 ManifestEntry::Add { .. } => additions += 1,
                 ManifestEntry::Delete { .. } => deletions += 1,
+                ManifestEntry::Rename { .. } => renames += 1,
+                ManifestEntry::Symlink { .. } => symlinks += 1,
+                ManifestEntry::MkDir { .. } | ManifestEntry::RmDir { .. } => directories += 1,
             }
         }
         PatchInfo {
             version: manifest.version,
             name: manifest.name.clone(),
             title: manifest.title.clone(),
+            description: manifest.description.clone(),
+            author: manifest.author.clone(),
+            target_game_version: manifest.target_game_version.clone(),
             entry_count: manifest.entries.len(),
             patches,
             additions,
             deletions,
+            renames,
+            symlinks,
+            directories,
+            total_bytes,
+            signed_by: None,
         }
     }
 
@@ -117,10 +447,18 @@ impl PatchInfo {
             version: 1,
             name: "DemoPatcher".to_string(),
             title: Some("Graft Patcher (Demo)".to_string()),
+            description: Some("Translates all menus and dialogue".to_string()),
+            author: Some("Graft".to_string()),
+            target_game_version: Some("1.0".to_string()),
             entry_count: 42,
             patches: 35,
             additions: 5,
             deletions: 2,
+            renames: 0,
+            symlinks: 0,
+            directories: 0,
+            total_bytes: 340 * 1024 * 1024,
+            signed_by: None,
         }
     }
 }
@@ -143,16 +481,42 @@ mod tests {
                     original_hash: "abc123".to_string(),
                     diff_hash: "def456".to_string(),
                     final_hash: "ghi789".to_string(),
+                    final_hashes: vec![],
+                    size: None,
+                    critical: false,
+                    label: None,
+                    mode: None,
+                    mtime: None,
+                    compressed: false,
+                    line_endings: None,
+                    encoding: None,
                 },
                 ManifestEntry::Add {
                     file: "new_asset.bin".to_string(),
                     final_hash: "jkl012".to_string(),
+                    original_hash: None,
+                    size: None,
+                    critical: false,
+                    label: None,
+                    mode: None,
+                    mtime: None,
+                    compressed: false,
                 },
                 ManifestEntry::Delete {
                     file: "old_asset.bin".to_string(),
                     original_hash: "mno345".to_string(),
                 },
             ],
+            match_by_content: false,
+            hash_algorithm: Algorithm::default(),
+            unhashed: false,
+            description: None,
+            author: None,
+            target_game_version: None,
+            version_file: None,
+            base_dir: None,
+            pre_commands: vec![],
+            post_commands: vec![],
         };
 
         let temp_file = NamedTempFile::new().unwrap();
@@ -179,7 +543,7 @@ mod tests {
         }"#;
 
         let temp_file = NamedTempFile::new().unwrap();
-        fs::write(temp_file.path(), json).unwrap();
+        fs::write(temp_file.path(), with_manifest_hash(json)).unwrap();
 
         let manifest = Manifest::load(temp_file.path()).unwrap();
         assert_eq!(manifest.version, 1);
@@ -214,7 +578,24 @@ mod tests {
             entries: vec![ManifestEntry::Add {
                 file: "test.bin".to_string(),
                 final_hash: "hash123".to_string(),
+                original_hash: None,
+                size: None,
+                critical: false,
+                label: None,
+                mode: None,
+                mtime: None,
+                compressed: false,
             }],
+            match_by_content: false,
+            hash_algorithm: Algorithm::default(),
+            unhashed: false,
+            description: None,
+            author: None,
+            target_game_version: None,
+            version_file: None,
+            base_dir: None,
+            pre_commands: vec![],
+            post_commands: vec![],
         };
 
         let temp_file = NamedTempFile::new().unwrap();
@@ -235,10 +616,26 @@ mod tests {
             original_hash: "x".to_string(),
             diff_hash: "y".to_string(),
             final_hash: "z".to_string(),
+            final_hashes: vec![],
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
+            line_endings: None,
+            encoding: None,
         };
         let add = ManifestEntry::Add {
             file: "b.bin".to_string(),
             final_hash: "x".to_string(),
+            original_hash: None,
+            size: None,
+            critical: false,
+            label: None,
+            mode: None,
+            mtime: None,
+            compressed: false,
         };
         let delete = ManifestEntry::Delete {
             file: "c.bin".to_string(),
@@ -254,10 +651,140 @@ mod tests {
     fn title_is_deserialized() {
         let json = r#"{"version": 1, "name": "TestPatcher", "title": "My Custom Title", "entries": []}"#;
         let temp_file = NamedTempFile::new().unwrap();
-        fs::write(temp_file.path(), json).unwrap();
+        fs::write(temp_file.path(), with_manifest_hash(json)).unwrap();
 
         let manifest = Manifest::load(temp_file.path()).unwrap();
         assert_eq!(manifest.name, "TestPatcher");
         assert_eq!(manifest.title, Some("My Custom Title".to_string()));
     }
+
+    #[test]
+    fn hash_algorithm_defaults_to_sha256() {
+        let json = r#"{"version": 1, "name": "TestPatcher", "entries": []}"#;
+        let manifest = Manifest::parse(&with_manifest_hash(json)).unwrap();
+        assert_eq!(manifest.hash_algorithm, Algorithm::Sha256);
+
+        // The default is left out, so older patchers can still read the manifest
+        let saved = serde_json::to_string(&manifest).unwrap();
+        assert!(!saved.contains("hash_algorithm"));
+    }
+
+    #[test]
+    fn hash_algorithm_is_round_tripped() {
+        let json = r#"{"version": 1, "name": "TestPatcher", "hash_algorithm": "blake3", "entries": []}"#;
+        let manifest = Manifest::parse(&with_manifest_hash(json)).unwrap();
+        assert_eq!(manifest.hash_algorithm, Algorithm::Blake3);
+
+        let saved = serde_json::to_string(&manifest).unwrap();
+        assert_eq!(Manifest::parse(&with_manifest_hash(&saved)).unwrap().hash_algorithm, Algorithm::Blake3);
+    }
+
+    #[test]
+    fn metadata_is_surfaced_in_patch_info() {
+        let json = r#"{
+            "version": 2,
+            "name": "SpanishPatch",
+            "title": "Spanish Translation",
+            "description": "Translates all menus and dialogue",
+            "author": "Fan Translations",
+            "target_game_version": "1.4",
+            "entries": []
+        }"#;
+        let manifest = Manifest::parse(&with_manifest_hash(json)).unwrap();
+        let info = PatchInfo::from_manifest(&manifest);
+
+        assert_eq!(info.description.as_deref(), Some("Translates all menus and dialogue"));
+        assert_eq!(info.author.as_deref(), Some("Fan Translations"));
+        assert_eq!(info.target_game_version.as_deref(), Some("1.4"));
+    }
+
+    #[test]
+    fn patch_info_sums_written_bytes() {
+        let json = r#"{
+            "version": 1,
+            "name": "TestPatcher",
+            "entries": [
+                {"operation": "patch", "file": "a.bin", "original_hash": "1", "diff_hash": "2", "final_hash": "3", "size": 1000},
+                {"operation": "add", "file": "b.bin", "final_hash": "4", "size": 24},
+                {"operation": "add", "file": "c.bin", "final_hash": "5"},
+                {"operation": "delete", "file": "d.bin", "original_hash": "6"}
+            ]
+        }"#;
+        let info = PatchInfo::from_manifest(&Manifest::parse(&with_manifest_hash(json)).unwrap());
+        assert_eq!(info.total_bytes, 1024);
+    }
+
+    #[test]
+    fn missing_metadata_is_not_serialized() {
+        let manifest = Manifest::default();
+        assert_eq!(manifest.description, None);
+
+        let saved = serde_json::to_string(&manifest).unwrap();
+        assert!(!saved.contains("description"));
+        assert!(!saved.contains("author"));
+        assert!(!saved.contains("target_game_version"));
+    }
+
+    #[test]
+    fn edited_entries_fail_the_manifest_hash() {
+        let mut manifest = Manifest::default();
+        manifest.entries.push(ManifestEntry::Delete {
+            file: "intro.bik".to_string(),
+            original_hash: "abc123".to_string(),
+        });
+        let temp_file = NamedTempFile::new().unwrap();
+        manifest.save(temp_file.path()).unwrap();
+        let saved = fs::read_to_string(temp_file.path()).unwrap();
+        assert!(saved.contains(&manifest.content_hash().unwrap()));
+
+        // Reformatting doesn't change the canonical entries
+        let value: serde_json::Value = serde_json::from_str(&saved).unwrap();
+        assert_eq!(Manifest::parse(&value.to_string()).unwrap(), manifest);
+
+        let tampered = saved.replace("abc123", "def456");
+        let err = Manifest::parse(&tampered).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("manifest hash mismatch"));
+    }
+
+    #[test]
+    fn every_field_is_covered_by_the_manifest_hash() {
+        let temp_file = NamedTempFile::new().unwrap();
+        Manifest::default().save(temp_file.path()).unwrap();
+        let saved: serde_json::Value = serde_json::from_str(&fs::read_to_string(temp_file.path()).unwrap()).unwrap();
+
+        let edits: [(&str, serde_json::Value); 5] = [
+            ("allow_restricted", true.into()),
+            ("base_dir", "..".into()),
+            ("hash_algorithm", "blake3".into()),
+            ("post_commands", serde_json::json!([["sh", "-c", "true"]])),
+            ("version_file", serde_json::json!({"file": "game.exe", "hash": "abc"})),
+        ];
+        for (field, value) in edits {
+            let mut edited = saved.clone();
+            edited[field] = value;
+            let err = Manifest::parse(&edited.to_string()).unwrap_err();
+            assert!(err.to_string().contains("manifest hash mismatch"), "{}: {}", field, err);
+        }
+
+        // Without a hash there's nothing to check, which the caller is told
+        let mut unhashed = saved.clone();
+        unhashed.as_object_mut().unwrap().remove("manifest_hash");
+        let manifest = Manifest::parse(&unhashed.to_string()).unwrap();
+        assert!(manifest.unhashed);
+        assert!(!Manifest::parse(&saved.to_string()).unwrap().unhashed);
+    }
+
+    #[test]
+    fn rehash_records_the_hash_of_an_edited_manifest() {
+        let temp_file = NamedTempFile::new().unwrap();
+        Manifest::default().save(temp_file.path()).unwrap();
+        let edited = fs::read_to_string(temp_file.path()).unwrap().replace("\"Patcher\"", "\"Renamed\"");
+        fs::write(temp_file.path(), edited).unwrap();
+        assert!(Manifest::load(temp_file.path()).is_err());
+
+        let rehashed = Manifest::rehash(temp_file.path()).unwrap();
+        assert_eq!(rehashed.name, "Renamed");
+        assert_eq!(Manifest::load(temp_file.path()).unwrap(), rehashed);
+    }
 }
@@ -1,5 +1,10 @@
 pub mod diff;
 pub mod dir_scan;
 pub mod file_ops;
+pub mod filesystem;
 pub mod hash;
+pub mod ignore;
+pub mod line_endings;
 pub mod manifest;
+pub mod temp_file;
+pub mod text_encoding;
@@ -0,0 +1,316 @@
+//! Temporary file naming for writes into the target directory.
+//!
+//! Temp files are created next to the file they replace, so several workers
+//! (or several graft processes) may be creating them in the same directory at
+//! once. Names combine the process id, a per-process counter and a random
+//! value, so they never collide, and all share `TEMP_PREFIX` so files left
+//! behind by a crashed run can be found and removed.
+
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Filename prefix shared by all temp files graft creates.
+pub const TEMP_PREFIX: &str = ".graft-tmp-";
+
+/// Temp files created so far by this process.
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Bytes written between progress reports in `write_atomic_reporting`.
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// A path in `dir` for a new temp file, unique across threads and processes.
+///
+/// The file is not created; open it with `create_new` so a collision (which
+/// would take another process reusing our pid and drawing the same random
+/// value) fails rather than clobbering someone else's file.
+pub fn temp_path(dir: &Path) -> PathBuf {
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let random = RandomState::new().build_hasher().finish();
+    dir.join(format!(
+        "{}{}-{}-{:016x}",
+        TEMP_PREFIX,
+        process::id(),
+        count,
+        random
+    ))
+}
+
+/// Replace the contents of `path` with `data`, so a crash part way through
+/// leaves either the old file or the new one, never a half-written mix.
+///
+/// The data is written and synced to a temp file next to `path`, which is
+/// then renamed over it. If the rename fails because the temp file ended up
+/// on another device, the data is copied into `path` and synced instead. The
+/// temp file is removed if anything fails.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    write_atomic_reporting(path, data, &mut |_| {})
+}
+
+/// Like `write_atomic`, writing the data in chunks and calling `on_written`
+/// with the number of bytes written so far after each one.
+pub fn write_atomic_reporting(path: &Path, data: &[u8], on_written: &mut dyn FnMut(u64)) -> io::Result<()> {
+    replace_with(path, |temp| write_synced(temp, data, on_written))
+}
+
+/// Like `write_atomic_reporting`, with the contents produced by `write`
+/// rather than held in memory. `write` gets a buffered writer into the temp
+/// file, which calls `on_written` each time another chunk has gone out.
+///
+/// `path` itself is only replaced once `write` returns, so `write` may still
+/// read the old file.
+pub fn write_atomic_with(
+    path: &Path,
+    on_written: &mut dyn FnMut(u64),
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    replace_with(path, |temp| {
+        let file = OpenOptions::new().write(true).create_new(true).open(temp)?;
+        let mut writer = ReportingWriter { inner: io::BufWriter::new(file), written: 0, reported: 0, on_written };
+        write(&mut writer)?;
+        writer.inner.flush()?;
+        (writer.on_written)(writer.written);
+        writer.inner.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()
+    })
+}
+
+/// Replace the contents of `path` with a copy of the file at `source`, as
+/// `write_atomic_reporting` does, streaming it rather than reading it into
+/// memory.
+pub fn copy_atomic_reporting(source: &Path, path: &Path, on_written: &mut dyn FnMut(u64)) -> io::Result<()> {
+    write_atomic_with(path, on_written, |out| io::copy(&mut File::open(source)?, out).map(|_| ()))
+}
+
+/// Fill a temp file next to `path` with `fill`, then move it over `path`.
+fn replace_with(path: &Path, fill: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let temp = temp_path(dir);
+
+    let result = fill(&temp).and_then(|()| match fs::rename(&temp, path) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(&temp, path)?;
+            File::open(path)?.sync_all()?;
+            fs::remove_file(&temp)
+        }
+        other => other,
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Write `data` to a new file at `path` and flush it to disk.
+fn write_synced(path: &Path, data: &[u8], on_written: &mut dyn FnMut(u64)) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    let mut written = 0;
+    for chunk in data.chunks(CHUNK_SIZE) {
+        file.write_all(chunk)?;
+        written += chunk.len() as u64;
+        on_written(written);
+    }
+    file.sync_all()
+}
+
+/// Writer that counts the bytes passing through and reports every
+/// `CHUNK_SIZE`.
+struct ReportingWriter<'a, W> {
+    inner: W,
+    written: u64,
+    reported: u64,
+    on_written: &'a mut dyn FnMut(u64),
+}
+
+impl<W: Write> Write for ReportingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        if self.written - self.reported >= CHUNK_SIZE as u64 {
+            self.reported = self.written;
+            (self.on_written)(self.written);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Whether `name` is the name of a graft temp file.
+pub fn is_temp_file(name: &str) -> bool {
+    name.starts_with(TEMP_PREFIX)
+}
+
+/// Remove temp files left in `dir` by an earlier run that didn't finish.
+///
+/// Temp files created by this process are left alone. Returns the number of
+/// files removed.
+pub fn remove_stray_temp_files(dir: &Path) -> io::Result<usize> {
+    let own_prefix = format!("{}{}-", TEMP_PREFIX, process::id());
+    let mut removed = 0;
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if !is_temp_file(name) || name.starts_with(&own_prefix) || !entry.file_type()?.is_file() {
+            continue;
+        }
+        fs::remove_file(entry.path())?;
+        removed += 1;
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::thread;
+    use tempfile::tempdir;
+
+    #[test]
+    fn concurrent_temp_files_never_collide() {
+        let dir = tempdir().unwrap();
+
+        // Each worker writes files the way apply does: into a fresh temp
+        // file, then renamed into place
+        let paths: Vec<PathBuf> = thread::scope(|s| {
+            let workers: Vec<_> = (0..8)
+                .map(|worker| {
+                    let dir = dir.path();
+                    s.spawn(move || {
+                        let mut paths = Vec::new();
+                        for i in 0..50 {
+                            let path = temp_path(dir);
+                            let mut file =
+                                OpenOptions::new().write(true).create_new(true).open(&path).unwrap();
+                            file.write_all(b"data").unwrap();
+                            drop(file);
+                            fs::rename(&path, dir.join(format!("file-{}-{}.bin", worker, i))).unwrap();
+                            paths.push(path);
+                        }
+                        paths
+                    })
+                })
+                .collect();
+            workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+        });
+
+        let unique: HashSet<_> = paths.iter().collect();
+        assert_eq!(unique.len(), 400);
+        let names: Vec<String> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names.len(), 400);
+        assert!(!names.iter().any(|n| is_temp_file(n)));
+    }
+
+    #[test]
+    fn removes_stray_temp_files_from_other_runs() {
+        let dir = tempdir().unwrap();
+        let stray = dir.path().join(format!("{}4294967295-0-00000000deadbeef", TEMP_PREFIX));
+        fs::write(&stray, b"half written").unwrap();
+        let own = temp_path(dir.path());
+        fs::write(&own, b"in progress").unwrap();
+        fs::write(dir.path().join("game.dat"), b"data").unwrap();
+
+        assert_eq!(remove_stray_temp_files(dir.path()).unwrap(), 1);
+
+        assert!(!stray.exists());
+        assert!(own.exists());
+        assert!(dir.path().join("game.dat").exists());
+    }
+
+    #[test]
+    fn write_atomic_replaces_contents() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("game.dat");
+        fs::write(&path, b"old contents").unwrap();
+
+        write_atomic(&path, b"new").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        let names: Vec<_> = fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(names.len(), 1);
+    }
+
+    #[test]
+    fn write_atomic_reporting_reports_each_chunk() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("movie.bik");
+        let data = vec![7u8; CHUNK_SIZE * 2 + 1];
+
+        let mut reports = Vec::new();
+        write_atomic_reporting(&path, &data, &mut |written| reports.push(written)).unwrap();
+
+        let chunk = CHUNK_SIZE as u64;
+        assert_eq!(reports, vec![chunk, chunk * 2, chunk * 2 + 1]);
+        assert_eq!(fs::read(&path).unwrap(), data);
+    }
+
+    #[test]
+    fn write_atomic_with_can_read_the_old_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("strings.bin");
+        fs::write(&path, b"old").unwrap();
+
+        let mut reports = Vec::new();
+        write_atomic_with(&path, &mut |written| reports.push(written), |out| {
+            let old = fs::read(&path)?;
+            out.write_all(&old)?;
+            out.write_all(b" and new")
+        })
+        .unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"old and new");
+        assert_eq!(reports, vec![11]);
+    }
+
+    #[test]
+    fn copy_atomic_replaces_contents() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("source.bin");
+        let path = dir.path().join("game.dat");
+        fs::write(&source, b"new contents").unwrap();
+        fs::write(&path, b"old").unwrap();
+
+        let mut reports = Vec::new();
+        copy_atomic_reporting(&source, &path, &mut |written| reports.push(written)).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new contents");
+        assert_eq!(reports, vec![12]);
+        let names: Vec<_> = fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(names.len(), 2);
+    }
+
+    #[test]
+    fn failed_write_atomic_leaves_no_temp_file() {
+        let dir = tempdir().unwrap();
+        // A directory can't be replaced by a file, so the rename fails
+        let path = dir.path().join("data");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("inner.bin"), b"x").unwrap();
+
+        assert!(write_atomic(&path, b"new").is_err());
+
+        assert!(path.join("inner.bin").exists());
+        let stray = fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|e| is_temp_file(&e.as_ref().unwrap().file_name().to_string_lossy()))
+            .count();
+        assert_eq!(stray, 0);
+    }
+}
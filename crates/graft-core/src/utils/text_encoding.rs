@@ -0,0 +1,127 @@
+//! Character encodings of text files in patches.
+//!
+//! Older games often keep their dialogue in UTF-16 or Shift-JIS, while
+//! translators edit in UTF-8. When creating a patch in text mode, a modified
+//! file is converted to the original's encoding before it is hashed and
+//! diffed, so the patched file is one the game can read.
+
+use std::io;
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+/// Encoding of a text file, and whether it starts with a byte order mark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextEncoding {
+    encoding: &'static Encoding,
+    bom: bool,
+}
+
+impl TextEncoding {
+    /// The encoding named by `label` (e.g. `utf-16le`, `shift_jis`), as
+    /// recognized by web browsers, without a byte order mark.
+    pub fn for_label(label: &str) -> io::Result<TextEncoding> {
+        Encoding::for_label(label.trim().as_bytes())
+            .map(|encoding| TextEncoding { encoding, bom: false })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("unknown text encoding '{}'", label)))
+    }
+
+    /// The encoding of `data` given by its byte order mark (UTF-8 or
+    /// UTF-16), or `fallback` if it has none.
+    pub fn detect(data: &[u8], fallback: Option<TextEncoding>) -> Option<TextEncoding> {
+        match Encoding::for_bom(data) {
+            Some((encoding, _)) => Some(TextEncoding { encoding, bom: true }),
+            None => fallback,
+        }
+    }
+
+    /// The encoding's name, as recorded in manifests (e.g. `UTF-16LE`,
+    /// `Shift_JIS`).
+    pub fn name(self) -> &'static str {
+        self.encoding.name()
+    }
+
+    /// Whether this is UTF-8, with or without a byte order mark.
+    pub fn is_utf8(self) -> bool {
+        self.encoding == UTF_8
+    }
+
+    /// `data` decoded from this encoding, without its byte order mark.
+    /// Fails with `InvalidData` if it isn't valid in this encoding.
+    pub fn decode(self, data: &[u8]) -> io::Result<String> {
+        let data = match Encoding::for_bom(data) {
+            Some((_, bom_len)) if self.bom => &data[bom_len..],
+            _ => data,
+        };
+        self.encoding
+            .decode_without_bom_handling_and_without_replacement(data)
+            .map(|text| text.into_owned())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("text is not valid {}", self.name())))
+    }
+
+    /// `text` encoded in this encoding, starting with a byte order mark if
+    /// this one has one. Fails with `InvalidData` naming the first character
+    /// the encoding can't represent.
+    pub fn encode(self, text: &str) -> io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(text.len() + 3);
+        if self.bom {
+            out.extend_from_slice(match self.encoding {
+                e if e == UTF_16LE => b"\xff\xfe",
+                e if e == UTF_16BE => b"\xfe\xff",
+                _ => b"\xef\xbb\xbf",
+            });
+        }
+        // encoding_rs only encodes to encodings a web page may be sent in,
+        // which leaves out UTF-16
+        if self.encoding == UTF_16LE {
+            out.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        } else if self.encoding == UTF_16BE {
+            out.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+        } else {
+            let (encoded, _, had_errors) = self.encoding.encode(text);
+            if had_errors {
+                let unmappable = text
+                    .chars()
+                    .find(|c| self.encoding.encode(c.encode_utf8(&mut [0; 4])).2)
+                    .unwrap_or(char::REPLACEMENT_CHARACTER);
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("'{}' can't be written in {}", unmappable, self.name()),
+                ));
+            }
+            out.extend_from_slice(&encoded);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_bom_or_falls_back() {
+        let utf16 = TextEncoding::detect(b"\xff\xfeh\0i\0", None).unwrap();
+        assert_eq!(utf16.name(), "UTF-16LE");
+        assert_eq!(utf16.decode(b"\xff\xfeh\0i\0").unwrap(), "hi");
+
+        let sjis = TextEncoding::for_label("shift_jis").unwrap();
+        assert_eq!(TextEncoding::detect(b"plain", Some(sjis)), Some(sjis));
+        assert_eq!(TextEncoding::detect(b"plain", None), None);
+        assert!(TextEncoding::for_label("klingon").is_err());
+    }
+
+    #[test]
+    fn encodes_utf16_and_legacy_encodings() {
+        let utf16 = TextEncoding::detect(b"\xff\xfe", None).unwrap();
+        assert_eq!(utf16.encode("hé").unwrap(), b"\xff\xfeh\0\xe9\0");
+
+        let sjis = TextEncoding::for_label("shift_jis").unwrap();
+        let encoded = sjis.encode("こんにちは").unwrap();
+        assert_eq!(encoded, b"\x82\xb1\x82\xf1\x82\xc9\x82\xbf\x82\xcd");
+        assert_eq!(sjis.decode(&encoded).unwrap(), "こんにちは");
+        assert!(sjis.decode(b"\x82").is_err());
+
+        let err = sjis.encode("caf\u{e9} \u{1f600}").unwrap_err();
+        assert!(err.to_string().contains("'é' can't be written in Shift_JIS"), "{}", err);
+    }
+}
@@ -0,0 +1,283 @@
+//! End-to-end round trips: create a patch from two directories, apply it to a
+//! copy of the original, verify it, roll it back, and check the target is
+//! byte-identical to where it started.
+//!
+//! Patches are made with `patch::create`, the same path `graft patch create`
+//! takes.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use graft_core::patch::payload::diff_name;
+use graft_core::patch::{
+    apply_entries, apply_entries_parallel, backup_entries, create, rollback, validate_entries,
+    validate_patched_entries, ByteProgress, CreateOptions, Progress, Verification, BACKUP_DIR,
+    MANIFEST_FILENAME,
+};
+use graft_core::utils::hash::Algorithm;
+use graft_core::utils::manifest::{Manifest, ManifestEntry};
+use tempfile::tempdir;
+
+/// Every file under `dir` (recursively, skipping the backup directory) with
+/// its contents, keyed by path relative to `dir`. Symlinks are recorded as
+/// `-> target` rather than followed.
+fn snapshot(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+    fn walk(root: &Path, dir: &Path, files: &mut BTreeMap<String, Vec<u8>>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.file_name().unwrap() == BACKUP_DIR {
+                continue;
+            }
+            let relative = path.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/");
+            if path.is_symlink() {
+                let target = fs::read_link(&path).unwrap();
+                files.insert(relative, format!("-> {}", target.display()).into_bytes());
+            } else if path.is_dir() {
+                walk(root, &path, files);
+            } else {
+                files.insert(relative, fs::read(&path).unwrap());
+            }
+        }
+    }
+
+    let mut files = BTreeMap::new();
+    walk(dir, dir, &mut files);
+    files
+}
+
+/// Recursively copy a directory into `to`, copying symlinks as symlinks.
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        let dest = to.join(path.file_name().unwrap());
+        if path.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(fs::read_link(&path).unwrap(), dest).unwrap();
+        } else if path.is_dir() {
+            copy_dir(&path, &dest);
+        } else {
+            fs::copy(&path, dest).unwrap();
+        }
+    }
+}
+
+/// Deterministic pseudo-random bytes, so diffs have something to chew on.
+fn noise(seed: u32, len: usize) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(2_654_435_761).max(1);
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// An original and a modified game directory exercising every operation:
+/// patched, added, deleted and renamed files, in the top level and in nested
+/// directories, plus unchanged files and created and removed empty
+/// directories. On Unix there are also added,
+/// retargeted, removed and unchanged symlinks.
+fn game_dirs(root: &Path) -> (PathBuf, PathBuf) {
+    let orig = root.join("orig");
+    let new = root.join("new");
+    for dir in [&orig, &new] {
+        fs::create_dir_all(dir.join("data/text/en")).unwrap();
+        fs::create_dir_all(dir.join("saves")).unwrap();
+    }
+
+    // Patched: large binary with a few changed regions, and a text file
+    let data = noise(1, 256 * 1024);
+    let mut patched = data.clone();
+    patched[1000..1100].copy_from_slice(&noise(2, 100));
+    patched.extend_from_slice(&noise(3, 4096));
+    fs::write(orig.join("game.dat"), &data).unwrap();
+    fs::write(new.join("game.dat"), &patched).unwrap();
+    fs::write(orig.join("data/text/en/strings.txt"), "Hello\nGoodbye\n").unwrap();
+    fs::write(new.join("data/text/en/strings.txt"), "Hello!\nGoodbye!\n").unwrap();
+
+    // Added (one into a directory the target doesn't have), deleted and renamed
+    fs::write(new.join("fonts.pak"), noise(4, 8192)).unwrap();
+    fs::create_dir_all(new.join("data/text/fr")).unwrap();
+    fs::write(new.join("data/text/fr/strings.txt"), "Bonjour\nAu revoir\n").unwrap();
+    fs::write(orig.join("intro.bik"), noise(5, 2048)).unwrap();
+    fs::write(orig.join("voice_en.pak"), noise(6, 4096)).unwrap();
+    fs::write(new.join("voice_fr.pak"), noise(6, 4096)).unwrap();
+
+    // Unchanged, including an empty file
+    fs::write(orig.join("engine.cfg"), "fullscreen=1\n").unwrap();
+    fs::write(new.join("engine.cfg"), "fullscreen=1\n").unwrap();
+    fs::write(orig.join("empty.log"), b"").unwrap();
+    fs::write(new.join("empty.log"), b"").unwrap();
+
+    // Empty directory created, and directories removed once emptied
+    fs::create_dir_all(new.join("mods")).unwrap();
+    fs::create_dir_all(orig.join("cache/shaders")).unwrap();
+    fs::create_dir_all(orig.join("movies")).unwrap();
+    fs::write(orig.join("movies/credits.bik"), noise(8, 1024)).unwrap();
+
+    fs::write(orig.join("saves/slot1.sav"), noise(7, 512)).unwrap();
+    fs::write(new.join("saves/slot1.sav"), noise(7, 512)).unwrap();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::symlink;
+        symlink("engine.cfg", orig.join("current.cfg")).unwrap();
+        symlink("empty.log", new.join("current.cfg")).unwrap();
+        symlink("slot1.sav", new.join("saves/latest.sav")).unwrap();
+        symlink("engine.cfg", orig.join("legacy.cfg")).unwrap();
+        symlink("../engine.cfg", orig.join("data/engine.cfg")).unwrap();
+        symlink("../engine.cfg", new.join("data/engine.cfg")).unwrap();
+    }
+
+    (orig, new)
+}
+
+/// Patch settings for most round trips.
+const OPTIONS: CreateOptions<'static> = CreateOptions::new(1, "RoundTrip");
+
+/// Create a patch directory for the changes between `orig` and `new` and
+/// return its manifest, loaded back from disk.
+fn create_patch(orig: &Path, new: &Path, patch_dir: &Path, options: &CreateOptions) -> Manifest {
+    create(orig, new, patch_dir, options, None::<fn(Progress)>).unwrap();
+    Manifest::load(&patch_dir.join(MANIFEST_FILENAME)).unwrap()
+}
+
+/// Run the full cycle on a fresh target with a patch made with `options`,
+/// applying with `jobs` threads.
+fn round_trip(options: &CreateOptions, jobs: usize) {
+    let root = tempdir().unwrap();
+    let (orig, new) = game_dirs(root.path());
+    let patch_dir = root.path().join("patch");
+    let target = root.path().join("target");
+    copy_dir(&orig, &target);
+    let backup_dir = target.join(BACKUP_DIR);
+
+    let manifest = create_patch(&orig, &new, &patch_dir, options);
+    let operations: Vec<_> = manifest.entries.iter().map(|e| e.operation()).collect();
+    for op in ["patch", "add", "delete", "rename", "mkdir", "rmdir"] {
+        assert!(operations.contains(&op), "patch has no {} entry", op);
+    }
+    #[cfg(unix)]
+    assert_eq!(operations.iter().filter(|&&op| op == "symlink").count(), 3);
+    assert_eq!(manifest.hash_algorithm, options.hash_algorithm);
+    for entry in &manifest.entries {
+        if let ManifestEntry::Patch { size, compressed, .. } | ManifestEntry::Add { size, compressed, .. } = entry {
+            assert!(size.is_some(), "{} has no size", entry.file());
+            assert_eq!(*compressed, options.compress_payloads, "{}", entry.file());
+        }
+    }
+
+    let before = snapshot(&target);
+
+    validate_entries(&manifest.entries, &target, manifest.hash_algorithm, None::<fn(Progress)>).unwrap();
+    backup_entries(&manifest.entries, &target, &backup_dir, manifest.hash_algorithm, None::<fn(Progress)>).unwrap();
+    if jobs > 1 {
+        apply_entries_parallel(
+            &manifest.entries,
+            &target,
+            &patch_dir,
+            &backup_dir,
+            Verification::Full,
+            manifest.hash_algorithm,
+            jobs,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+        )
+        .unwrap();
+    } else {
+        apply_entries(
+            &manifest.entries,
+            &target,
+            &patch_dir,
+            &backup_dir,
+            Verification::Full,
+            manifest.hash_algorithm,
+            None::<fn(Progress)>,
+            None::<fn(ByteProgress)>,
+        )
+        .unwrap();
+    }
+
+    // Patched target matches the modified directory
+    validate_patched_entries(&manifest.entries, &target, manifest.hash_algorithm, None::<fn(Progress)>).unwrap();
+    assert_eq!(snapshot(&target), snapshot(&new));
+    assert!(target.join("mods").is_dir());
+    assert!(!target.join("cache").exists());
+    assert!(!target.join("movies").exists());
+
+    let applied: Vec<_> = manifest.entries.iter().collect();
+    rollback(&applied, &target, &backup_dir, None::<fn(Progress)>).unwrap();
+
+    assert_eq!(snapshot(&target), before);
+    assert!(!target.join("mods").exists());
+    assert!(target.join("cache/shaders").is_dir());
+    assert!(target.join("movies").is_dir());
+}
+
+#[test]
+fn apply_and_rollback_restores_original_tree() {
+    round_trip(&OPTIONS, 1);
+}
+
+#[test]
+fn parallel_apply_and_rollback_restores_original_tree() {
+    round_trip(&OPTIONS, 4);
+}
+
+#[test]
+fn compressed_blake3_patch_round_trips() {
+    let text_extensions = ["txt".to_string()];
+    round_trip(
+        &CreateOptions {
+            hash_algorithm: Algorithm::Blake3,
+            compress_payloads: true,
+            text_extensions: &text_extensions,
+            ..OPTIONS
+        },
+        1,
+    );
+}
+
+#[test]
+fn failed_apply_leaves_original_tree() {
+    let root = tempdir().unwrap();
+    let (orig, new) = game_dirs(root.path());
+    let patch_dir = root.path().join("patch");
+    let target = root.path().join("target");
+    copy_dir(&orig, &target);
+    let backup_dir = target.join(BACKUP_DIR);
+
+    let manifest = create_patch(&orig, &new, &patch_dir, &OPTIONS);
+    // Corrupt the diff for the last patched file so the failure comes after
+    // other entries have been applied
+    let last_patch = manifest
+        .entries
+        .iter()
+        .rev()
+        .find(|e| e.operation() == "patch")
+        .unwrap()
+        .file();
+    fs::write(patch_dir.join(diff_name(last_patch, false)), b"corrupted").unwrap();
+
+    let before = snapshot(&target);
+
+    validate_entries(&manifest.entries, &target, manifest.hash_algorithm, None::<fn(Progress)>).unwrap();
+    backup_entries(&manifest.entries, &target, &backup_dir, manifest.hash_algorithm, None::<fn(Progress)>).unwrap();
+    let result = apply_entries(
+        &manifest.entries,
+        &target,
+        &patch_dir,
+        &backup_dir,
+        Verification::Full,
+        manifest.hash_algorithm,
+        None::<fn(Progress)>,
+        None::<fn(ByteProgress)>,
+    );
+
+    assert!(result.is_err());
+    assert_eq!(snapshot(&target), before);
+}
@@ -1,7 +1,16 @@
 use crate::runner::{PatchRunner, ProgressAction, ProgressEvent, RollbackEvent};
 use crate::validator::PatchValidator;
-use std::io::{self, Write};
+use graft_core::exit_code::ExitCode;
+use graft_core::signing::VerifyingKey;
+use graft_core::utils::hash::hash_bytes;
+use std::io::{self, IsTerminal, Write};
 use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait for an answer at a confirmation prompt
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(60);
 
 fn format_action(action: ProgressAction) -> &'static str {
     match action {
@@ -12,136 +21,383 @@ fn format_action(action: ProgressAction) -> &'static str {
         ProgressAction::Patching => "Patching",
         ProgressAction::Adding => "Adding",
         ProgressAction::Deleting => "Deleting",
+        ProgressAction::Renaming => "Renaming",
+        ProgressAction::Linking => "Linking",
+        ProgressAction::Creating => "Creating",
         ProgressAction::Restoring => "Restoring",
         ProgressAction::Removing => "Removing",
+        ProgressAction::Diffing => "Diffing",
+        ProgressAction::Copying => "Copying",
+        ProgressAction::Recording => "Recording",
+    }
+}
+
+/// A reply to `confirm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    /// Nobody answered: stdin isn't a terminal, or the prompt timed out
+    Unanswered,
+}
+
+/// Ask a yes/no question on stdin, defaulting to "no". The prompt is
+/// written to `out`.
+///
+/// Returns `Unanswered` without prompting when stdin isn't a terminal (e.g.
+/// the patcher was double-clicked), and when no answer arrives within
+/// `PROMPT_TIMEOUT`, so an unattended launch never hangs.
+fn confirm(out: &mut dyn Write, question: &str) -> io::Result<Answer> {
+    if !io::stdin().is_terminal() {
+        writeln!(out, "\n{} [y/N] no (stdin is not a terminal)", question)?;
+        return Ok(Answer::Unanswered);
     }
+
+    write!(
+        out,
+        "\n{} [y/N] (no answer in {}s means no) ",
+        question,
+        PROMPT_TIMEOUT.as_secs()
+    )?;
+    out.flush()?;
+
+    match read_answer(|input| io::stdin().read_line(input), PROMPT_TIMEOUT) {
+        Some(answer) if answer.trim().eq_ignore_ascii_case("y") => Ok(Answer::Yes),
+        Some(_) => Ok(Answer::No),
+        None => {
+            writeln!(out, "\nNo answer after {}s.", PROMPT_TIMEOUT.as_secs())?;
+            Ok(Answer::Unanswered)
+        }
+    }
+}
+
+/// Read one line on a background thread via `read_line`, giving up after `timeout`.
+///
+/// Returns None on timeout, end of input, or a read error. A timed-out
+/// reader thread is left blocked; it ends with the process.
+fn read_answer<F>(read_line: F, timeout: Duration) -> Option<String>
+where
+    F: FnOnce(&mut String) -> io::Result<usize> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = String::new();
+        if let Ok(n) = read_line(&mut input)
+            && n > 0
+        {
+            let _ = tx.send(input);
+        }
+    });
+    rx.recv_timeout(timeout).ok()
+}
+
+/// Print the embedded archive's hash and patch info.
+///
+/// The hash matches the `.archive.sha256` sidecar written by `graft build`,
+/// so a patcher embedding a stale archive can be spotted.
+pub fn run_self_check(
+    patch_data: &[u8],
+    expected_key: Option<&VerifyingKey>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let info = PatchValidator::validate(patch_data, expected_key)?;
+
+    println!("Patch: {} v{}", info.name, info.version);
+    println!("Operations: {}", info.entry_count);
+    println!("Archive size: {} bytes", patch_data.len());
+    println!("Archive SHA-256: {}", hash_bytes(patch_data));
+    println!("Signed by: {}", info.signed_by.as_deref().unwrap_or("(unsigned)"));
+
+    Ok(())
+}
+
+/// How `run_headless` reports apply progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProgressFormat {
+    /// Lines for people to read
+    Human,
+    /// One JSON object per event on stdout; everything else goes to stderr
+    Json,
 }
 
 /// Run in headless (CLI) mode with embedded patch data
+///
+/// With `log`, the apply is also appended to that file (see
+/// `PatchRunner::with_log`). With `no_extract`, files are read from
+/// `patch_data` as they're applied instead of extracting it first (see
+/// `PatchRunner::new_streaming`). With `preview`, a diff of every text file
+/// the patch changes is shown before the confirmation prompt. With
+/// `expected_key`, the patch must be signed by that key. A patch that
+/// declares hook commands is refused unless `no_hooks` says to apply it
+/// without them.
+#[allow(clippy::too_many_arguments)]
 pub fn run_headless(
     patch_data: &[u8],
+    expected_key: Option<&VerifyingKey>,
     target_path: &Path,
     skip_confirm: bool,
+    backup_dir: Option<&Path>,
+    progress_format: ProgressFormat,
+    jobs: usize,
+    delete_backup: bool,
+    log: Option<&Path>,
+    temp_dir: Option<&Path>,
+    no_extract: bool,
+    preview: bool,
+    no_hooks: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Graft Patcher - Headless Mode");
-    println!("==============================");
+    // With JSON progress, stdout carries only the events
+    let json = progress_format == ProgressFormat::Json;
+    let mut out: Box<dyn Write> = if json {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    };
+
+    writeln!(out, "Graft Patcher - Headless Mode")?;
+    writeln!(out, "==============================")?;
 
     // Validate patch and get info
-    print!("Validating patch data... ");
-    io::stdout().flush()?;
+    write!(out, "Validating patch data... ")?;
+    out.flush()?;
 
-    let info = PatchValidator::validate(patch_data)?;
-    println!("done");
+    let info = match PatchValidator::validate(patch_data, expected_key) {
+        Ok(info) => info,
+        Err(e) => {
+            writeln!(out, "failed")?;
+            eprintln!("\nError: {}", e);
+            ExitCode::Validation.exit();
+        }
+    };
+    writeln!(out, "done")?;
 
     // Show patch info
-    println!("\nPatch Information:");
-    println!("  Version: {}", info.version);
-    println!("  Operations: {}", info.entry_count);
-    println!("    - {} patches", info.patches);
-    println!("    - {} additions", info.additions);
-    println!("    - {} deletions", info.deletions);
-    println!("\nTarget: {}", target_path.display());
+    writeln!(out, "\nPatch Information:")?;
+    writeln!(out, "  Name: {}", info.title.as_deref().unwrap_or(&info.name))?;
+    if let Some(description) = &info.description {
+        writeln!(out, "  Description: {}", description)?;
+    }
+    if let Some(author) = &info.author {
+        writeln!(out, "  Author: {}", author)?;
+    }
+    if let Some(game_version) = &info.target_game_version {
+        writeln!(out, "  Game version: {}", game_version)?;
+    }
+    writeln!(out, "  Version: {}", info.version)?;
+    match &info.signed_by {
+        Some(key) => writeln!(out, "  Signed by: {}", key)?,
+        None => writeln!(out, "  Signature: none (unsigned patch)")?,
+    }
+    writeln!(out, "  Operations: {}", info.entry_count)?;
+    writeln!(out, "    - {} patches", info.patches)?;
+    writeln!(out, "    - {} additions", info.additions)?;
+    writeln!(out, "    - {} deletions", info.deletions)?;
+    if info.renames > 0 {
+        writeln!(out, "    - {} renames", info.renames)?;
+    }
+    if info.symlinks > 0 {
+        writeln!(out, "    - {} symlinks", info.symlinks)?;
+    }
+    if info.total_bytes > 0 {
+        writeln!(out, "  Writes: {}", graft_core::patch::format_bytes(info.total_bytes))?;
+    }
+    writeln!(out, "\nTarget: {}", target_path.display())?;
 
     // Create runner for validation checks
-    let runner = PatchRunner::new(patch_data)?;
+    let runner = if no_extract {
+        PatchRunner::new_streaming(patch_data, temp_dir)?
+    } else {
+        PatchRunner::new_with_temp_dir(patch_data, temp_dir)?
+    };
+    let mut runner = runner.with_jobs(jobs);
+    if let Some(log) = log {
+        runner = runner.with_log(log);
+    }
+    if no_hooks {
+        runner = runner.without_hooks();
+    } else if runner.has_hooks() {
+        eprintln!("\nError: This patch runs commands before or after applying, which the patcher doesn't do.");
+        eprintln!("To apply it without them, run again with --no-hooks.");
+        ExitCode::Validation.exit();
+    }
+
+    // An earlier run that was interrupted partway is finished rather than refused
+    let resuming = runner.can_resume(target_path, backup_dir);
 
     // Check if already patched (backup exists)
-    if PatchRunner::has_backup(target_path) {
+    if PatchRunner::has_backup(target_path, backup_dir) && !resuming {
         eprintln!("\nError: This folder appears to already be patched.");
-        eprintln!("A backup directory (.patch-backup) was found.");
+        eprintln!(
+            "A backup directory ({}) was found.",
+            PatchRunner::backup_dir(target_path, backup_dir).display()
+        );
         eprintln!();
         eprintln!("To rollback the patch, run:");
-        eprintln!("  {} headless rollback {}", std::env::args().next().unwrap_or_default(), target_path.display());
-        std::process::exit(1);
+        eprintln!("  {}", rollback_command(target_path, backup_dir, false));
+        ExitCode::Validation.exit();
     }
 
     // Pre-validate target folder
-    print!("\nValidating target folder... ");
-    io::stdout().flush()?;
+    write!(out, "\nValidating target folder... ")?;
+    out.flush()?;
 
-    if let Err(e) = runner.validate_target(target_path) {
-        println!("failed");
+    if !resuming
+        && let Err(e) = runner.validate_target(target_path)
+    {
+        if runner.is_up_to_date(target_path) {
+            writeln!(out, "done")?;
+            writeln!(out, "\nTarget folder is already up to date. Nothing to do.")?;
+            return Ok(());
+        }
+        writeln!(out, "failed")?;
         eprintln!("\nError: Target folder cannot be patched.");
         eprintln!("{}", e);
-        std::process::exit(1);
+        ExitCode::from(&e).exit();
+    }
+    writeln!(out, "done")?;
+    if resuming {
+        writeln!(out, "\nAn earlier patch attempt was interrupted; the remaining changes will be applied.")?;
+    }
+
+    if preview {
+        show_preview(&mut out, &runner, target_path)?;
     }
-    println!("done");
 
     // Confirm unless -y flag
     if !skip_confirm {
-        print!("\nApply patch? [y/N] ");
-        io::stdout().flush()?;
-
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        if !input.trim().eq_ignore_ascii_case("y") {
-            println!("Aborted.");
-            return Ok(());
+        match confirm(&mut out, "Apply patch?")? {
+            Answer::Yes => {}
+            Answer::No => {
+                writeln!(out, "Aborted. Use -y to apply without confirmation.")?;
+                return Ok(());
+            }
+            Answer::Unanswered => {
+                writeln!(out, "Aborted. Use -y to apply without confirmation.")?;
+                ExitCode::Cancelled.exit();
+            }
         }
     }
 
     // Apply patch
-    println!("\nApplying patch...");
-
-    let result = runner.apply(target_path, |event| match event {
-        ProgressEvent::PhaseStarted { phase } => {
-            println!("\n{}...", phase);
-        }
-        ProgressEvent::Operation {
-            file,
-            index,
-            total,
-            action,
-        } => {
-            println!("  [{}/{}] {}: {}", index + 1, total, format_action(action), file);
-        }
-        ProgressEvent::Done { files_patched } => {
-            println!("\n{} files processed.", files_patched);
+    writeln!(out, "\nApplying patch...")?;
+
+    let result = runner.apply(target_path, backup_dir, delete_backup, |event| {
+        if json {
+            println!("{}", event.to_json());
+            return;
         }
-        ProgressEvent::Error { .. } => {
-            // Error details will be printed by the result handler below
+        match event {
+            ProgressEvent::PhaseStarted { phase } => {
+                println!("\n{}...", phase);
+            }
+            ProgressEvent::Operation {
+                file,
+                label,
+                index,
+                total,
+                action,
+            } => {
+                let name = label.as_deref().unwrap_or(&file);
+                println!("  [{}/{}] {}: {}", index + 1, total, format_action(action), name);
+            }
+            ProgressEvent::BytesProcessed { .. } => {
+                // Too frequent for line-per-event output; --progress-format
+                // json passes them on
+            }
+            ProgressEvent::Warning { message } => {
+                eprintln!("Warning: {}", message);
+            }
+            ProgressEvent::Done { files_patched } => {
+                println!("\n{} files processed.", files_patched);
+            }
+            ProgressEvent::Error { .. } => {
+                // Error details will be printed by the result handler below
+            }
         }
     });
 
     match result {
         Ok(()) => {
-            println!("\nPatch applied successfully!");
-            println!();
-            println!("To rollback later, run:");
-            println!("  {} headless rollback {}", std::env::args().next().unwrap_or_default(), target_path.display());
+            writeln!(out, "\nPatch applied successfully!")?;
+            writeln!(out)?;
+            if PatchRunner::has_backup(target_path, backup_dir) {
+                writeln!(out, "To rollback later, run:")?;
+                writeln!(out, "  {}", rollback_command(target_path, backup_dir, false))?;
+            } else {
+                writeln!(out, "The backup was deleted; the patch can no longer be rolled back.")?;
+            }
             Ok(())
         }
         Err(e) => {
             eprintln!("\nError: {}", e);
-            std::process::exit(1);
+            if let Some(log) = log {
+                eprintln!("A log of this attempt was written to {}", log.display());
+            }
+            ExitCode::from(&e).exit();
+        }
+    }
+}
+
+/// Print the diff of each text file applying the patch would change.
+fn show_preview(out: &mut dyn Write, runner: &PatchRunner, target_path: &Path) -> io::Result<()> {
+    writeln!(out, "\nPreview of text changes:")?;
+    match runner.preview(target_path, graft_core::patch::DEFAULT_PREVIEW_LINES) {
+        Ok(previews) if previews.is_empty() => writeln!(out, "  (no text files are changed)"),
+        Ok(previews) => {
+            for (_, diff) in previews {
+                writeln!(out)?;
+                write!(out, "{}", diff)?;
+            }
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("\nError: Could not preview the patch.");
+            eprintln!("{}", e);
+            ExitCode::from(&e).exit();
         }
     }
 }
 
+/// The command line that rolls back `target_path`, for hints.
+fn rollback_command(target_path: &Path, backup_dir: Option<&Path>, force: bool) -> String {
+    let mut command = format!("{} headless rollback", std::env::args().next().unwrap_or_default());
+    if force {
+        command.push_str(" --force");
+    }
+    if let Some(dir) = backup_dir {
+        command.push_str(&format!(" --backup-dir {}", dir.display()));
+    }
+    format!("{} {}", command, target_path.display())
+}
+
 /// Run rollback in headless (CLI) mode
 pub fn run_rollback(
     patch_data: &[u8],
     target_path: &Path,
     force: bool,
+    backup_dir: Option<&Path>,
+    temp_dir: Option<&Path>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     println!("Graft Patcher - Headless Rollback");
     println!("==================================");
     println!("\nTarget: {}", target_path.display());
 
     // Create runner
-    let runner = PatchRunner::new(patch_data)?;
+    let runner = PatchRunner::new_with_temp_dir(patch_data, temp_dir)?;
 
     // Check if backup exists
-    if !PatchRunner::has_backup(target_path) {
+    if !PatchRunner::has_backup(target_path, backup_dir) {
         eprintln!("\nError: No backup directory found.");
-        eprintln!("Cannot rollback without .patch-backup directory.");
-        std::process::exit(1);
+        eprintln!(
+            "Cannot rollback without the backup directory ({}).",
+            PatchRunner::backup_dir(target_path, backup_dir).display()
+        );
+        ExitCode::Validation.exit();
     }
 
     println!("\nRolling back...");
 
     let mut error_occurred = false;
-    let result = runner.rollback(target_path, force, |event| match event {
+    let result = runner.rollback(target_path, backup_dir, force, |event| match event {
         RollbackEvent::ValidatingTarget => {
             print!("Validating target files... ");
             let _ = io::stdout().flush();
@@ -157,9 +413,16 @@ pub fn run_rollback(
             eprintln!("{}", reason);
             eprintln!();
             eprintln!("To force rollback anyway, run:");
-            eprintln!("  {} headless rollback --force {}", std::env::args().next().unwrap_or_default(), target_path.display());
+            eprintln!("  {}", rollback_command(target_path, backup_dir, true));
             error_occurred = true;
         }
+        RollbackEvent::OverwritingModified { files } => {
+            eprintln!("\n\nWARNING: {} files were changed since patching and will be overwritten:", files.len());
+            for file in &files {
+                eprintln!("  {}", file);
+            }
+            eprintln!();
+        }
         RollbackEvent::Rolling { file, index, total, action } => {
             if index == 0 {
                 println!("done\n");
@@ -176,7 +439,7 @@ pub fn run_rollback(
     });
 
     if error_occurred {
-        std::process::exit(1);
+        result.as_ref().err().map_or(ExitCode::Failure, ExitCode::from).exit();
     }
 
     match result {
@@ -184,13 +447,8 @@ pub fn run_rollback(
             println!("\nRollback complete!");
 
             // Ask about deleting backup
-            print!("\nDelete backup directory? [y/N] ");
-            io::stdout().flush()?;
-
-            let mut input = String::new();
-            io::stdin().read_line(&mut input)?;
-            if input.trim().eq_ignore_ascii_case("y") {
-                if let Err(e) = PatchRunner::delete_backup(target_path) {
+            if confirm(&mut io::stdout(), "Delete backup directory?")? == Answer::Yes {
+                if let Err(e) = PatchRunner::delete_backup(target_path, backup_dir) {
                     eprintln!("Warning: Failed to delete backup: {}", e);
                 } else {
                     println!("Backup deleted.");
@@ -203,7 +461,35 @@ pub fn run_rollback(
         }
         Err(e) => {
             eprintln!("\nError: {}", e);
-            std::process::exit(1);
+            ExitCode::from(&e).exit();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, Cursor};
+
+    #[test]
+    fn read_answer_returns_line() {
+        let mut reader = Cursor::new(b"y\n".to_vec());
+        let answer = read_answer(move |input| reader.read_line(input), Duration::from_secs(5));
+        assert_eq!(answer.as_deref(), Some("y\n"));
+    }
+
+    #[test]
+    fn read_answer_none_on_end_of_input() {
+        let answer = read_answer(|input| io::empty().read_line(input), Duration::from_secs(5));
+        assert_eq!(answer, None);
+    }
+
+    #[test]
+    fn read_answer_times_out() {
+        let stalled = |_: &mut String| {
+            thread::sleep(Duration::from_secs(3600));
+            Ok(0)
+        };
+        assert_eq!(read_answer(stalled, Duration::from_millis(50)), None);
+    }
+}
@@ -1,7 +1,8 @@
-use crate::runner::{PatchRunner, Phase, ProgressAction, ProgressEvent, RollbackEvent};
+use crate::runner::{self, format_estimate, PatchRunner, Phase, ProgressAction, ProgressEvent, RollbackEvent};
 use crate::validator::{PatchInfo, PatchValidationError, PatchValidator};
 use eframe::egui;
-use std::path::PathBuf;
+use graft_core::signing::VerifyingKey;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::thread;
 
@@ -14,8 +15,14 @@ fn format_action(action: ProgressAction) -> &'static str {
         ProgressAction::Patching => "Patching",
         ProgressAction::Adding => "Adding",
         ProgressAction::Deleting => "Deleting",
+        ProgressAction::Renaming => "Renaming",
+        ProgressAction::Linking => "Linking",
+        ProgressAction::Creating => "Creating",
         ProgressAction::Restoring => "Restoring",
         ProgressAction::Removing => "Removing",
+        ProgressAction::Diffing => "Diffing",
+        ProgressAction::Copying => "Copying",
+        ProgressAction::Recording => "Recording",
     }
 }
 
@@ -28,8 +35,13 @@ pub enum AppState {
     ValidatingFolder { path: PathBuf },
     /// Folder validation failed - cannot patch this folder
     FolderInvalid { path: PathBuf, reason: String },
-    /// Folder already has backup - appears to be patched already
-    AlreadyPatched { path: PathBuf, modified: bool },
+    /// Folder already has backup - appears to be patched already;
+    /// `can_rollback` is false if the backup is missing files or damaged
+    AlreadyPatched { path: PathBuf, modified: bool, can_rollback: bool },
+    /// An earlier apply was interrupted partway and can be finished
+    Interrupted { path: PathBuf },
+    /// Folder has no backup but every file is already in its patched state
+    UpToDate { path: PathBuf },
     /// User has selected a folder, ready to apply
     FolderSelected { path: PathBuf },
     /// Patch is being applied
@@ -39,6 +51,11 @@ pub enum AppState {
         current_phase: Option<Phase>,
         completed_phases: usize,
         phase_total: usize,
+        /// Set once byte progress arrives; the bar then follows bytes
+        /// written rather than entries applied
+        by_bytes: bool,
+        /// Time left and write rate, once byte progress allows estimating them
+        estimate: Option<String>,
         log: Vec<String>,
     },
     /// Patch applied successfully
@@ -73,7 +90,11 @@ pub enum ValidationResult {
     /// Folder cannot be patched
     Invalid { reason: String },
     /// Folder appears to already be patched
-    AlreadyPatched { modified: bool },
+    AlreadyPatched { modified: bool, can_rollback: bool },
+    /// An earlier apply was interrupted and can be resumed
+    Interrupted,
+    /// Folder is already up to date - nothing to do
+    UpToDate,
 }
 
 /// Application mode
@@ -123,9 +144,10 @@ impl GraftApp {
     /// Create a new app with patch data
     ///
     /// Validates the patch to get PatchInfo for display, then stores
-    /// the raw data for the worker thread to use when applying.
-    pub fn new(patch_data: Vec<u8>) -> Result<Self, PatchValidationError> {
-        let patch_info = PatchValidator::validate(&patch_data)?;
+    /// the raw data for the worker thread to use when applying. With
+    /// `expected_key`, the patch must be signed by that key.
+    pub fn new(patch_data: Vec<u8>, expected_key: Option<&VerifyingKey>) -> Result<Self, PatchValidationError> {
+        let patch_info = PatchValidator::validate(&patch_data, expected_key)?;
         let title = patch_info
             .title
             .clone()
@@ -183,10 +205,15 @@ impl GraftApp {
             };
 
             // Check if backup exists (already patched scenario)
-            if PatchRunner::has_backup(&path) {
+            if PatchRunner::has_backup(&path, None) {
+                if runner.can_resume(&path, None) {
+                    let _ = tx.send(ValidationResult::Interrupted);
+                    return;
+                }
                 // Check if files are in patched state
                 let modified = !runner.is_patched(&path);
-                let _ = tx.send(ValidationResult::AlreadyPatched { modified });
+                let can_rollback = runner.can_rollback(&path);
+                let _ = tx.send(ValidationResult::AlreadyPatched { modified, can_rollback });
                 return;
             }
 
@@ -195,6 +222,9 @@ impl GraftApp {
                 Ok(()) => {
                     let _ = tx.send(ValidationResult::Valid);
                 }
+                Err(_) if runner.is_up_to_date(&path) => {
+                    let _ = tx.send(ValidationResult::UpToDate);
+                }
                 Err(e) => {
                     let _ = tx.send(ValidationResult::Invalid {
                         reason: e.to_string(),
@@ -214,6 +244,8 @@ impl GraftApp {
                     current_phase: Some(Phase::Applying),
                     completed_phases: 0,
                     phase_total: self.patch_info.entry_count,
+                    by_bytes: false,
+                    estimate: None,
                     log: vec!["[Demo] Starting patch application...".to_string()],
                 };
                 return;
@@ -234,6 +266,8 @@ impl GraftApp {
             current_phase: None,
             completed_phases: 0,
             phase_total: total,
+            by_bytes: false,
+            estimate: None,
             log: Vec::new(),
         };
 
@@ -252,7 +286,7 @@ impl GraftApp {
                 }
             };
 
-            let _ = runner.apply(&target_path, |event| {
+            let _ = runner.with_log(runner::default_log_path()).apply(&target_path, None, false, |event| {
                 let _ = tx.send(event);
             });
         });
@@ -298,6 +332,7 @@ impl GraftApp {
                 }
                 ProgressEvent::Operation {
                     file,
+                    label,
                     index,
                     total,
                     action,
@@ -307,16 +342,45 @@ impl GraftApp {
                         progress,
                         completed_phases,
                         phase_total,
+                        by_bytes,
                         ..
                     } = &mut self.state
                     {
-                        log.push(format!("  [{}/{}] {}: {}", index + 1, total, format_action(action), file));
+                        let name = label.as_deref().unwrap_or(&file);
+                        log.push(format!("  [{}/{}] {}: {}", index + 1, total, format_action(action), name));
                         *phase_total = total;
-                        // Progress: completed phases + current phase progress
-                        let phase_progress = (index + 1) as f32 / total.max(1) as f32;
+                        if !*by_bytes {
+                            // Progress: completed phases + current phase progress
+                            let phase_progress = (index + 1) as f32 / total.max(1) as f32;
+                            *progress = (*completed_phases as f32 + phase_progress) / 3.0;
+                        }
+                    }
+                }
+                ProgressEvent::BytesProcessed {
+                    done,
+                    total,
+                    throughput_bytes_per_sec,
+                    eta_secs,
+                } => {
+                    if let AppState::Applying {
+                        progress,
+                        completed_phases,
+                        by_bytes,
+                        estimate,
+                        ..
+                    } = &mut self.state
+                    {
+                        *by_bytes = true;
+                        *estimate = format_estimate(throughput_bytes_per_sec, eta_secs);
+                        let phase_progress = done as f32 / total.max(1) as f32;
                         *progress = (*completed_phases as f32 + phase_progress) / 3.0;
                     }
                 }
+                ProgressEvent::Warning { message } => {
+                    if let AppState::Applying { log, .. } = &mut self.state {
+                        log.push(format!("Warning: {}", message));
+                    }
+                }
                 ProgressEvent::Done { files_patched } => {
                     if let AppState::Applying { path, log, .. } = &self.state {
                         self.state = AppState::Success {
@@ -328,11 +392,15 @@ impl GraftApp {
                     should_clear_rx = true;
                 }
                 ProgressEvent::Error { message, details } => {
-                    let log = if let AppState::Applying { log, .. } = &self.state {
+                    let mut log = if let AppState::Applying { log, .. } = &self.state {
                         log.clone()
                     } else {
                         Vec::new()
                     };
+                    let log_path = runner::default_log_path();
+                    if log_path.exists() {
+                        log.push(format!("A log of this attempt was written to {}", log_path.display()));
+                    }
                     self.state = AppState::Error {
                         message,
                         details,
@@ -370,8 +438,14 @@ impl GraftApp {
                     ValidationResult::Invalid { reason } => {
                         self.state = AppState::FolderInvalid { path, reason };
                     }
-                    ValidationResult::AlreadyPatched { modified } => {
-                        self.state = AppState::AlreadyPatched { path, modified };
+                    ValidationResult::AlreadyPatched { modified, can_rollback } => {
+                        self.state = AppState::AlreadyPatched { path, modified, can_rollback };
+                    }
+                    ValidationResult::Interrupted => {
+                        self.state = AppState::Interrupted { path };
+                    }
+                    ValidationResult::UpToDate => {
+                        self.state = AppState::UpToDate { path };
                     }
                 }
             }
@@ -407,6 +481,12 @@ impl GraftApp {
                     }
                     should_clear_rx = true;
                 }
+                RollbackEvent::OverwritingModified { files } => {
+                    if let AppState::RollingBack { log, .. } = &mut self.state {
+                        log.push(format!("Warning: overwriting {} files changed since patching", files.len()));
+                        log.extend(files.into_iter().map(|f| format!("  {}", f)));
+                    }
+                }
                 RollbackEvent::Rolling { file, index, total, action } => {
                     if let AppState::RollingBack { log, .. } = &mut self.state {
                         log.push(format!("  [{}/{}] {}: {}", index + 1, total, format_action(action), file));
@@ -477,7 +557,7 @@ impl GraftApp {
                 }
             };
 
-            let _ = runner.rollback(&path, force, |event| {
+            let _ = runner.rollback(&path, None, force, |event| {
                 let _ = tx.send(event);
             });
         });
@@ -510,10 +590,28 @@ impl GraftApp {
         ui.heading("Patch Ready to Apply");
         ui.add_space(16.0);
 
+        if let Some(description) = &self.patch_info.description {
+            ui.label(description);
+            ui.add_space(8.0);
+        }
+
         ui.group(|ui| {
+            if let Some(author) = &self.patch_info.author {
+                ui.label(format!("Author: {}", author));
+            }
+            if let Some(game_version) = &self.patch_info.target_game_version {
+                ui.label(format!("For game version: {}", game_version));
+            }
+            match &self.patch_info.signed_by {
+                Some(key) => ui.label(format!("Signed by key: {}", key)),
+                None => ui.label("Not signed"),
+            };
             ui.label(format!("Graft version: {}", env!("CARGO_PKG_VERSION")));
             ui.label(format!("Patch version: {}", self.patch_info.version));
             ui.label(format!("Total operations: {}", self.patch_info.entry_count));
+            if self.patch_info.total_bytes > 0 {
+                ui.label(format!("Data to write: {}", graft_core::patch::format_bytes(self.patch_info.total_bytes)));
+            }
             ui.add_space(8.0);
             ui.horizontal(|ui| {
                 ui.label(format!("{} patches", self.patch_info.patches));
@@ -521,6 +619,14 @@ impl GraftApp {
                 ui.label(format!("{} additions", self.patch_info.additions));
                 ui.separator();
                 ui.label(format!("{} deletions", self.patch_info.deletions));
+                if self.patch_info.renames > 0 {
+                    ui.separator();
+                    ui.label(format!("{} renames", self.patch_info.renames));
+                }
+                if self.patch_info.symlinks > 0 {
+                    ui.separator();
+                    ui.label(format!("{} symlinks", self.patch_info.symlinks));
+                }
             });
         });
 
@@ -593,11 +699,15 @@ impl GraftApp {
         log: Vec<String>,
         progress: f32,
         current_phase: Option<Phase>,
+        estimate: Option<String>,
     ) {
         ui.heading("Applying Patch...");
         ui.add_space(16.0);
 
         ui.add(egui::ProgressBar::new(progress).show_percentage());
+        if let Some(estimate) = estimate {
+            ui.label(egui::RichText::new(estimate).color(egui::Color32::GRAY));
+        }
         ui.add_space(8.0);
 
         if let Some(phase) = current_phase {
@@ -611,8 +721,8 @@ impl GraftApp {
         if matches!(self.mode, Mode::Demo) {
             ui.add_space(16.0);
             ui.horizontal(|ui| {
-                if ui.button("Simulate Progress").clicked() {
-                    if let AppState::Applying {
+                if ui.button("Simulate Progress").clicked()
+                    && let AppState::Applying {
                         path,
                         phase_total,
                         completed_phases,
@@ -620,34 +730,35 @@ impl GraftApp {
                         current_phase,
                         ..
                     } = &self.state
-                    {
-                        let mut new_log = log.clone();
-                        let batch_size = 10; // Simulate ~10 ops per click
-                        let new_completed = (completed_phases + batch_size).min(*phase_total);
-                        // Log each simulated operation in the batch
-                        for i in (*completed_phases + 1)..=new_completed {
-                            new_log.push(format!(
-                                "  [{}/{}] Patching: file_{}.bin",
-                                i, phase_total, i
-                            ));
-                        }
-                        let new_progress = new_completed as f32 / *phase_total as f32;
-                        if new_completed >= *phase_total {
-                            self.state = AppState::Success {
-                                path: path.clone(),
-                                files_patched: *phase_total,
-                                log: new_log,
-                            };
-                        } else {
-                            self.state = AppState::Applying {
-                                path: path.clone(),
-                                progress: new_progress,
-                                current_phase: *current_phase,
-                                completed_phases: new_completed,
-                                phase_total: *phase_total,
-                                log: new_log,
-                            };
-                        }
+                {
+                    let mut new_log = log.clone();
+                    let batch_size = 10; // Simulate ~10 ops per click
+                    let new_completed = (completed_phases + batch_size).min(*phase_total);
+                    // Log each simulated operation in the batch
+                    for i in (*completed_phases + 1)..=new_completed {
+                        new_log.push(format!(
+                            "  [{}/{}] Patching: file_{}.bin",
+                            i, phase_total, i
+                        ));
+                    }
+                    let new_progress = new_completed as f32 / *phase_total as f32;
+                    if new_completed >= *phase_total {
+                        self.state = AppState::Success {
+                            path: path.clone(),
+                            files_patched: *phase_total,
+                            log: new_log,
+                        };
+                    } else {
+                        self.state = AppState::Applying {
+                            path: path.clone(),
+                            progress: new_progress,
+                            current_phase: *current_phase,
+                            completed_phases: new_completed,
+                            phase_total: *phase_total,
+                            by_bytes: false,
+                            estimate: None,
+                            log: new_log,
+                        };
                     }
                 }
                 if ui.button("Simulate Error").clicked() {
@@ -796,7 +907,7 @@ impl GraftApp {
         });
     }
 
-    fn render_validating_folder(&self, ui: &mut egui::Ui, path: &PathBuf) {
+    fn render_validating_folder(&self, ui: &mut egui::Ui, path: &Path) {
         ui.heading("Validating Folder...");
         ui.add_space(16.0);
 
@@ -852,7 +963,7 @@ impl GraftApp {
         });
     }
 
-    fn render_already_patched(&mut self, ui: &mut egui::Ui, path: PathBuf, modified: bool) {
+    fn render_already_patched(&mut self, ui: &mut egui::Ui, path: PathBuf, modified: bool, can_rollback: bool) {
         ui.vertical_centered(|ui| {
             ui.add_space(8.0);
 
@@ -890,7 +1001,11 @@ impl GraftApp {
                 if ui.button("Re-apply Patch").clicked() {
                     self.start_apply(path.clone());
                 }
-                if ui.button("Rollback Anyway").clicked() {
+                if ui
+                    .add_enabled(can_rollback, egui::Button::new("Rollback Anyway"))
+                    .on_disabled_hover_text("The backup is missing files or damaged")
+                    .clicked()
+                {
                     self.start_rollback(path.clone(), true);
                 }
             });
@@ -898,14 +1013,105 @@ impl GraftApp {
             ui.label("This folder appears to already be patched.");
             ui.add_space(16.0);
             ui.horizontal(|ui| {
-                if ui.button("Rollback").clicked() {
+                if ui
+                    .add_enabled(can_rollback, egui::Button::new("Rollback"))
+                    .on_disabled_hover_text("The backup is missing files or damaged")
+                    .clicked()
+                {
                     self.start_rollback(path.clone(), false);
                 }
             });
         }
+        if !can_rollback {
+            ui.add_space(8.0);
+            ui.label(
+                egui::RichText::new("The backup for this folder is missing files or damaged, so it can't be rolled back.")
+                    .small()
+                    .color(egui::Color32::GRAY),
+            );
+        }
     }
 
-    fn render_rolling_back(&self, ui: &mut egui::Ui, path: &PathBuf, log: &[String]) {
+    fn render_interrupted(&mut self, ui: &mut egui::Ui, path: PathBuf) {
+        ui.vertical_centered(|ui| {
+            ui.add_space(8.0);
+
+            // Blue info circle
+            let (rect, _) = ui.allocate_exact_size(egui::vec2(60.0, 60.0), egui::Sense::hover());
+            ui.painter()
+                .circle_filled(rect.center(), 30.0, egui::Color32::from_rgb(59, 130, 246));
+            ui.painter().text(
+                rect.center(),
+                egui::Align2::CENTER_CENTER,
+                "i",
+                egui::FontId::proportional(36.0),
+                egui::Color32::WHITE,
+            );
+
+            ui.add_space(8.0);
+            ui.heading("Patching Was Interrupted");
+        });
+
+        ui.add_space(8.0);
+        ui.group(|ui| {
+            ui.label("Target folder:");
+            ui.label(egui::RichText::new(path.display().to_string()).monospace().small());
+        });
+
+        ui.add_space(8.0);
+        ui.label("An earlier attempt to patch this folder stopped partway. Resume to finish it, or roll back to undo what was applied.");
+        ui.add_space(16.0);
+        ui.horizontal(|ui| {
+            if ui.button("Resume Patch").clicked() {
+                self.start_apply(path.clone());
+            }
+            // Partly patched files are expected here, so skip the patched-state check
+            if ui.button("Rollback").clicked() {
+                self.start_rollback(path.clone(), true);
+            }
+        });
+    }
+
+    fn render_up_to_date(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, path: PathBuf) {
+        ui.vertical_centered(|ui| {
+            ui.add_space(8.0);
+
+            // Green circle with white checkmark
+            let (rect, _) = ui.allocate_exact_size(egui::vec2(60.0, 60.0), egui::Sense::hover());
+            ui.painter()
+                .circle_filled(rect.center(), 30.0, egui::Color32::from_rgb(34, 197, 94));
+            ui.painter().text(
+                rect.center(),
+                egui::Align2::CENTER_CENTER,
+                "\u{2713}",
+                egui::FontId::proportional(36.0),
+                egui::Color32::WHITE,
+            );
+
+            ui.add_space(8.0);
+            ui.heading("Your Game Is Already Up to Date");
+        });
+
+        ui.add_space(8.0);
+        ui.group(|ui| {
+            ui.label("Target folder:");
+            ui.label(egui::RichText::new(path.display().to_string()).monospace().small());
+        });
+
+        ui.add_space(8.0);
+        ui.label("All files already match the patched version. There is nothing to do.");
+        ui.add_space(16.0);
+        ui.horizontal(|ui| {
+            if ui.button("Choose Different Folder...").clicked() {
+                self.select_folder();
+            }
+            if ui.button("Quit").clicked() {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+        });
+    }
+
+    fn render_rolling_back(&self, ui: &mut egui::Ui, path: &Path, log: &[String]) {
         ui.heading("Rolling Back...");
         ui.add_space(16.0);
 
@@ -1002,7 +1208,7 @@ impl GraftApp {
 
         ui.horizontal(|ui| {
             if ui.button("Delete Backup").clicked() {
-                if let Err(e) = PatchRunner::delete_backup(&path) {
+                if let Err(e) = PatchRunner::delete_backup(&path, None) {
                     self.state = AppState::Error {
                         message: "Failed to delete backup".to_string(),
                         details: Some(e.to_string()),
@@ -1048,16 +1254,19 @@ impl eframe::App for GraftApp {
                 AppState::FolderInvalid { path, reason } => {
                     self.render_folder_invalid(ui, path, reason)
                 }
-                AppState::AlreadyPatched { path, modified } => {
-                    self.render_already_patched(ui, path, modified)
+                AppState::AlreadyPatched { path, modified, can_rollback } => {
+                    self.render_already_patched(ui, path, modified, can_rollback)
                 }
+                AppState::Interrupted { path } => self.render_interrupted(ui, path),
+                AppState::UpToDate { path } => self.render_up_to_date(ctx, ui, path),
                 AppState::FolderSelected { path } => self.render_folder_selected(ui, path),
                 AppState::Applying {
                     log,
                     progress,
                     current_phase,
+                    estimate,
                     ..
-                } => self.render_applying(ui, log, progress, current_phase),
+                } => self.render_applying(ui, log, progress, current_phase, estimate),
                 AppState::Success {
                     path,
                     files_patched,
@@ -1084,7 +1293,7 @@ impl eframe::App for GraftApp {
 }
 
 /// Run the GUI application
-pub fn run(patch_data: Option<&[u8]>) -> eframe::Result<()> {
+pub fn run(patch_data: Option<&[u8]>, expected_key: Option<&VerifyingKey>) -> eframe::Result<()> {
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([400.0, 380.0])
@@ -1093,10 +1302,22 @@ pub fn run(patch_data: Option<&[u8]>) -> eframe::Result<()> {
     };
 
     let app: GraftApp = if let Some(data) = patch_data {
-        match GraftApp::new(data.to_vec()) {
+        match GraftApp::new(data.to_vec(), expected_key) {
             Ok(app) => app,
             Err(e) => {
                 eprintln!("Failed to load embedded patch: {}", e);
+                if let PatchValidationError::SignatureInvalid(_) = e {
+                    // Tell the user why nothing opened rather than failing silently
+                    rfd::MessageDialog::new()
+                        .set_level(rfd::MessageLevel::Error)
+                        .set_title("Patch signature check failed")
+                        .set_description(format!(
+                            "{}\n\nThis patcher may have been modified after it was built. \
+                             Do not run it; download it again from the patch author.",
+                            e
+                        ))
+                        .show();
+                }
                 return Err(eframe::Error::AppCreation(Box::new(std::io::Error::new(
                     std::io::ErrorKind::InvalidData,
                     e.to_string(),
@@ -31,21 +31,39 @@
 //! - **Demo mode** (automatic): if no patch data is found, runs with mock data
 //! - **Headless apply**: `graft-gui headless apply <path>` - CLI-only for scripting
 //! - **Headless rollback**: `graft-gui headless rollback <path>` - undo a patch
+//! - **Self-check**: `graft-gui --self-check` - print the embedded archive's SHA-256
+//!
+//! Statically linked (musl) builds have no GUI mode: they can't load the
+//! system's X11, Wayland or GL libraries, so they only run headless.
 
 mod cli;
+#[cfg(not(target_env = "musl"))]
 mod gui;
 mod runner;
 mod self_read;
 mod validator;
 
 use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use cli::ProgressFormat;
+use graft_core::exit_code::ExitCode;
+use graft_core::signing::VerifyingKey;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
 #[command(name = "graft-gui")]
 #[command(about = "GUI/CLI patcher application")]
 #[command(version)]
 struct Args {
+    /// Print the embedded patch archive's SHA-256 and patch info, then exit
+    #[arg(long)]
+    self_check: bool,
+
+    /// Refuse to run unless the patch is signed with this Ed25519 public key (hex,
+    /// as printed by `graft build --sign-key`); defaults to the key the patcher
+    /// was built with, if any
+    #[arg(long, global = true, value_name = "HEX")]
+    expect_key: Option<String>,
+
     #[command(subcommand)]
     command: Option<Command>,
 }
@@ -69,6 +87,57 @@ enum HeadlessAction {
         /// Skip confirmation prompt
         #[arg(short = 'y', long)]
         yes: bool,
+
+        /// Keep backups in this directory instead of .patch-backup inside the target
+        #[arg(long, value_name = "DIR")]
+        backup_dir: Option<PathBuf>,
+
+        /// How to report progress: human-readable lines, or one JSON object
+        /// per event on stdout (other output moves to stderr)
+        #[arg(long, value_enum, default_value_t = ProgressFormat::Human)]
+        progress_format: ProgressFormat,
+
+        /// Number of entries to validate and apply in parallel
+        #[arg(short = 'j', long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        jobs: u64,
+
+        /// Keep the backup after a successful apply, so the patch can be rolled back (default)
+        #[arg(long, overrides_with = "delete_backup")]
+        keep_backup: bool,
+
+        /// Delete the backup once the patch is applied and verified, to reclaim disk space
+        #[arg(long, overrides_with = "keep_backup")]
+        delete_backup: bool,
+
+        /// Patch files larger than this many MiB as a stream, to bound memory use
+        #[arg(long, value_name = "MIB", default_value_t = 256)]
+        stream_above: u64,
+
+        /// Append a log of the apply (each progress event and any error, as JSON lines) to this file;
+        /// defaults to $GRAFT_PATCH_LOG if set
+        #[arg(long, value_name = "PATH")]
+        log: Option<PathBuf>,
+
+        /// Extract the patch under this directory instead of the system temp directory
+        #[arg(long, value_name = "DIR")]
+        temp_dir: Option<PathBuf>,
+
+        /// Read each file from the embedded patch as it's applied instead of extracting the
+        /// whole patch first, so the temp directory only needs room for one file at a time
+        /// (entries are then applied one at a time)
+        #[arg(long)]
+        no_extract: bool,
+
+        /// Before asking to apply, show a diff of each text file the patch changes
+        /// (binary files are skipped, and long diffs cut short)
+        #[arg(long)]
+        preview: bool,
+
+        /// Apply a patch that declares commands to run before or after applying
+        /// without running them (the patcher never runs them, and refuses such
+        /// a patch otherwise)
+        #[arg(long)]
+        no_hooks: bool,
     },
 
     /// Rollback a previously applied patch
@@ -79,18 +148,75 @@ enum HeadlessAction {
         /// Force rollback even if files have been modified
         #[arg(short, long)]
         force: bool,
+
+        /// Directory the backups were written to, if not .patch-backup inside the target
+        #[arg(long, value_name = "DIR")]
+        backup_dir: Option<PathBuf>,
+
+        /// Extract the patch under this directory instead of the system temp directory
+        #[arg(long, value_name = "DIR")]
+        temp_dir: Option<PathBuf>,
     },
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
+    let expected_key = match validator::expected_key(args.expect_key.as_deref()) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::Validation.exit();
+        }
+    };
+    let expected_key = expected_key.as_ref();
+
+    if args.self_check {
+        return run_self_check(expected_key);
+    }
+
     match args.command {
         Some(Command::Headless { action }) => match action {
-            HeadlessAction::Apply { path, yes } => run_headless(&path, yes),
-            HeadlessAction::Rollback { path, force } => run_rollback(&path, force),
+            HeadlessAction::Apply {
+                path,
+                yes,
+                backup_dir,
+                progress_format,
+                jobs,
+                keep_backup: _,
+                delete_backup,
+                stream_above,
+                log,
+                temp_dir,
+                no_extract,
+                preview,
+                no_hooks,
+            } => {
+                graft_core::patch::set_streaming_threshold(stream_above.saturating_mul(1024 * 1024));
+                let log = log.or_else(runner::log_path_from_env);
+                run_headless(
+                    expected_key,
+                    &path,
+                    yes,
+                    backup_dir.as_deref(),
+                    progress_format,
+                    jobs as usize,
+                    delete_backup,
+                    log.as_deref(),
+                    temp_dir.as_deref(),
+                    no_extract,
+                    preview,
+                    no_hooks,
+                )
+            }
+            HeadlessAction::Rollback {
+                path,
+                force,
+                backup_dir,
+                temp_dir,
+            } => run_rollback(&path, force, backup_dir.as_deref(), temp_dir.as_deref()),
         },
-        None => run_gui(),
+        None => run_gui(expected_key),
     }
 }
 
@@ -128,36 +254,92 @@ fn get_patch_data() -> Result<Vec<u8>, Box<dyn std::error::Error>> {
 /// Run the GUI application
 ///
 /// If no patch data is embedded/appended, automatically runs in demo mode.
-fn run_gui() -> Result<(), Box<dyn std::error::Error>> {
+#[cfg(not(target_env = "musl"))]
+fn run_gui(expected_key: Option<&VerifyingKey>) -> Result<(), Box<dyn std::error::Error>> {
     match get_patch_data() {
-        Ok(data) => gui::run(Some(&data)).map_err(|e| e.into()),
+        Ok(data) => gui::run(Some(&data), expected_key).map_err(|e| e.into()),
         Err(_) => {
             // No patch data - run in demo mode
-            gui::run(None).map_err(|e| e.into())
+            gui::run(None, expected_key).map_err(|e| e.into())
+        }
+    }
+}
+
+/// Explain how to run a statically linked patcher, which has no GUI
+#[cfg(target_env = "musl")]
+fn run_gui(_expected_key: Option<&VerifyingKey>) -> Result<(), Box<dyn std::error::Error>> {
+    let exe = std::env::args().next().unwrap_or_else(|| "patcher".to_string());
+    eprintln!("This patcher runs from a terminal only. To apply the patch, run:");
+    eprintln!("  {} headless apply <game-folder>", exe);
+    eprintln!("Use the patcher without -musl in its name for a graphical one.");
+    ExitCode::Validation.exit();
+}
+
+/// Report the embedded archive hash (for comparing against the build sidecar)
+fn run_self_check(expected_key: Option<&VerifyingKey>) -> Result<(), Box<dyn std::error::Error>> {
+    match get_patch_data() {
+        Ok(data) => cli::run_self_check(&data, expected_key),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("Self-check requires patch data.");
+            ExitCode::Validation.exit();
         }
     }
 }
 
 /// Run in headless (CLI) mode
-fn run_headless(target_path: &PathBuf, skip_confirm: bool) -> Result<(), Box<dyn std::error::Error>> {
+#[allow(clippy::too_many_arguments)]
+fn run_headless(
+    expected_key: Option<&VerifyingKey>,
+    target_path: &Path,
+    skip_confirm: bool,
+    backup_dir: Option<&Path>,
+    progress_format: ProgressFormat,
+    jobs: usize,
+    delete_backup: bool,
+    log: Option<&Path>,
+    temp_dir: Option<&Path>,
+    no_extract: bool,
+    preview: bool,
+    no_hooks: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
     match get_patch_data() {
-        Ok(data) => cli::run_headless(&data, target_path, skip_confirm),
+        Ok(data) => cli::run_headless(
+            &data,
+            expected_key,
+            target_path,
+            skip_confirm,
+            backup_dir,
+            progress_format,
+            jobs,
+            delete_backup,
+            log,
+            temp_dir,
+            no_extract,
+            preview,
+            no_hooks,
+        ),
         Err(e) => {
             eprintln!("Error: {}", e);
             eprintln!("Headless mode requires patch data.");
-            std::process::exit(1);
+            ExitCode::Validation.exit();
         }
     }
 }
 
 /// Run rollback in headless (CLI) mode
-fn run_rollback(target_path: &PathBuf, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+fn run_rollback(
+    target_path: &Path,
+    force: bool,
+    backup_dir: Option<&Path>,
+    temp_dir: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
     match get_patch_data() {
-        Ok(data) => cli::run_rollback(&data, target_path, force),
+        Ok(data) => cli::run_rollback(&data, target_path, force, backup_dir, temp_dir),
         Err(e) => {
             eprintln!("Error: {}", e);
             eprintln!("Rollback mode requires patch data.");
-            std::process::exit(1);
+            ExitCode::Validation.exit();
         }
     }
 }
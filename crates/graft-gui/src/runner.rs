@@ -1,11 +1,36 @@
-use flate2::read::GzDecoder;
-use graft_core::patch::{self, PatchError, Progress, BACKUP_DIR};
-use graft_core::utils::manifest::Manifest;
-use std::cell::RefCell;
+use graft_core::archive::{extract_archive, read_archive_file, with_archive_fetcher};
+use graft_core::patch::payload::payload_name;
+use graft_core::patch::{self, ByteProgress, PatchError, Progress, Verification, BACKUP_DIR, IGNORE_FILENAME};
+use graft_core::utils::ignore::IgnoreList;
+use graft_core::utils::manifest::{Manifest, ManifestEntry, MISSING_HASH_WARNING};
+use graft_core::utils::temp_file::remove_stray_temp_files;
+use serde_json::json;
+use std::collections::{HashSet, VecDeque};
+use std::env;
 use std::fmt;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use tar::Archive;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tempfile::TempDir;
+
+/// File name of the apply log in the default location (see `default_log_path`)
+pub const LOG_FILENAME: &str = "graft-patch.log";
+
+/// Environment variable naming where to write the apply log
+pub const LOG_ENV_VAR: &str = "GRAFT_PATCH_LOG";
+
+/// The log path set with `LOG_ENV_VAR`, if any.
+pub fn log_path_from_env() -> Option<PathBuf> {
+    env::var_os(LOG_ENV_VAR).filter(|p| !p.is_empty()).map(PathBuf::from)
+}
+
+/// Where the patcher logs applies when nothing else is asked for: the path
+/// in `LOG_ENV_VAR`, or `LOG_FILENAME` in the temp directory.
+pub fn default_log_path() -> PathBuf {
+    log_path_from_env().unwrap_or_else(|| env::temp_dir().join(LOG_FILENAME))
+}
 
 /// Processing phases for orchestration
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -33,19 +58,171 @@ pub enum ProgressEvent {
     /// Progress on a specific file operation (mapped from core Progress)
     Operation {
         file: String,
+        /// The entry's label from the manifest, to show instead of `file`
+        label: Option<String>,
         index: usize,
         total: usize,
         action: ProgressAction,
     },
+    /// Bytes written so far while applying, out of the total the manifest
+    /// records; sent during large writes too, so only for manifests that
+    /// record file sizes
+    ///
+    /// The write rate and time left are averaged over the last few seconds
+    /// (see `RateEstimator`), and are `None` until there's enough to go by.
+    BytesProcessed {
+        done: u64,
+        total: u64,
+        throughput_bytes_per_sec: Option<u64>,
+        eta_secs: Option<u64>,
+    },
+    /// Something the user should know about that doesn't stop the patch,
+    /// e.g. entries left alone because `.graftignore` matches them
+    Warning { message: String },
     /// Patch completed successfully
     Done { files_patched: usize },
     /// An error occurred
     Error { message: String, details: Option<String> },
 }
 
+impl ProgressEvent {
+    /// The event as a single-line JSON object, e.g.
+    /// `{"event":"done","files_patched":3}`. Phases and actions are
+    /// snake_case names (`backing_up`, `patching`); operations only have a
+    /// `label` if the entry has one.
+    pub fn to_json(&self) -> String {
+        let value = match self {
+            ProgressEvent::PhaseStarted { phase } => json!({
+                "event": "phase_started",
+                "phase": phase_name(*phase),
+            }),
+            ProgressEvent::Operation {
+                file,
+                label,
+                index,
+                total,
+                action,
+            } => {
+                let mut value = json!({
+                    "event": "operation",
+                    "file": file,
+                    "index": index,
+                    "total": total,
+                    "action": action_name(*action),
+                });
+                if let Some(label) = label {
+                    value["label"] = json!(label);
+                }
+                value
+            }
+            ProgressEvent::BytesProcessed {
+                done,
+                total,
+                throughput_bytes_per_sec,
+                eta_secs,
+            } => json!({
+                "event": "bytes_processed",
+                "done": done,
+                "total": total,
+                "throughput_bytes_per_sec": throughput_bytes_per_sec,
+                "eta_secs": eta_secs,
+            }),
+            ProgressEvent::Warning { message } => json!({
+                "event": "warning",
+                "message": message,
+            }),
+            ProgressEvent::Done { files_patched } => json!({
+                "event": "done",
+                "files_patched": files_patched,
+            }),
+            ProgressEvent::Error { message, details } => json!({
+                "event": "error",
+                "message": message,
+                "details": details,
+            }),
+        };
+        value.to_string()
+    }
+}
+
+fn phase_name(phase: Phase) -> &'static str {
+    match phase {
+        Phase::Validating => "validating",
+        Phase::BackingUp => "backing_up",
+        Phase::Applying => "applying",
+    }
+}
+
+fn action_name(action: ProgressAction) -> &'static str {
+    match action {
+        ProgressAction::Validating => "validating",
+        ProgressAction::CheckingNotExists => "checking_not_exists",
+        ProgressAction::BackingUp => "backing_up",
+        ProgressAction::Skipping => "skipping",
+        ProgressAction::Patching => "patching",
+        ProgressAction::Adding => "adding",
+        ProgressAction::Deleting => "deleting",
+        ProgressAction::Renaming => "renaming",
+        ProgressAction::Linking => "linking",
+        ProgressAction::Creating => "creating",
+        ProgressAction::Restoring => "restoring",
+        ProgressAction::Removing => "removing",
+        ProgressAction::Diffing => "diffing",
+        ProgressAction::Copying => "copying",
+        ProgressAction::Recording => "recording",
+    }
+}
+
 // Re-export ProgressAction for consumers
 pub use graft_core::patch::ProgressAction;
 
+/// Moving average of the write rate over the last `WINDOW`, for estimating
+/// how long an apply has left.
+#[derive(Debug, Default)]
+pub struct RateEstimator {
+    /// (when, bytes done) for the updates within the window, oldest first
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl RateEstimator {
+    /// How far back updates count toward the rate
+    pub const WINDOW: Duration = Duration::from_secs(5);
+    /// Shortest span of updates the rate is estimated from; before that the
+    /// estimate would mostly be noise
+    pub const MIN_SPAN: Duration = Duration::from_secs(1);
+
+    /// Record that `done` of `total` bytes were written at `now`, returning
+    /// the rate in bytes per second and the seconds left at that rate, or
+    /// `None` for each until they can be estimated.
+    pub fn update(&mut self, now: Instant, done: u64, total: u64) -> (Option<u64>, Option<u64>) {
+        self.samples.push_back((now, done));
+        // Keep one sample older than the window, so the rate spans all of it
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) >= Self::WINDOW {
+            self.samples.pop_front();
+        }
+
+        let (start, start_done) = self.samples[0];
+        let span = now.duration_since(start);
+        if span < Self::MIN_SPAN {
+            return (None, None);
+        }
+        let rate = (done.saturating_sub(start_done) as f64 / span.as_secs_f64()) as u64;
+        let eta = (rate > 0).then(|| total.saturating_sub(done).div_ceil(rate));
+        (Some(rate), eta)
+    }
+}
+
+/// An estimate as players see it, e.g. "~45s remaining, 120.0 MB/s";
+/// `None` if there's no rate yet.
+pub fn format_estimate(throughput_bytes_per_sec: Option<u64>, eta_secs: Option<u64>) -> Option<String> {
+    let rate = format!("{}/s", patch::format_bytes(throughput_bytes_per_sec?));
+    Some(match eta_secs {
+        Some(secs) if secs >= 60 => format!("~{}m {}s remaining, {}", secs / 60, secs % 60, rate),
+        Some(secs) => format!("~{}s remaining, {}", secs, rate),
+        None => rate,
+    })
+}
+
 /// Progress event emitted during rollback
 #[derive(Debug, Clone)]
 pub enum RollbackEvent {
@@ -55,6 +232,8 @@ pub enum RollbackEvent {
     TargetModified { reason: String },
     /// Validating backup files
     ValidatingBackup,
+    /// Forced rollback is about to overwrite files changed since patching
+    OverwritingModified { files: Vec<String> },
     /// Rolling back a specific file
     Rolling {
         file: String,
@@ -68,85 +247,446 @@ pub enum RollbackEvent {
     Error { message: String },
 }
 
+/// Log of one apply, one JSON object per line, for debugging failures on
+/// players' machines.
+///
+/// Writing is best effort: a log that can't be written never fails the
+/// apply.
+struct PatchLog {
+    file: File,
+}
+
+impl PatchLog {
+    /// Append to the log at `path`, starting with a line naming the patch
+    /// and target; `None` if the file can't be opened.
+    fn open(path: &Path, manifest: &Manifest, target: &Path) -> Option<PatchLog> {
+        let file = File::options().create(true).append(true).open(path).ok()?;
+        let mut log = PatchLog { file };
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        log.line(&json!({
+            "event": "started",
+            "patch": manifest.name,
+            "version": manifest.version,
+            "target": target.display().to_string(),
+            "time": time,
+        })
+        .to_string());
+        Some(log)
+    }
+
+    /// Record an event. Byte counts are left out: they arrive many times a
+    /// second during large writes.
+    fn event(&mut self, event: &ProgressEvent) {
+        if !matches!(event, ProgressEvent::BytesProcessed { .. }) {
+            self.line(&event.to_json());
+        }
+    }
+
+    /// Record how the apply ended.
+    fn finish(&mut self, result: &Result<(), PatchError>) {
+        let value = match result {
+            Ok(()) => json!({ "event": "finished", "ok": true }),
+            Err(e) => json!({
+                "event": "finished",
+                "ok": false,
+                "error": e.to_string(),
+                "debug": format!("{:?}", e),
+            }),
+        };
+        self.line(&value.to_string());
+    }
+
+    fn line(&mut self, line: &str) {
+        let _ = writeln!(self.file, "{}", line);
+    }
+}
+
 /// Core patch runner that handles extraction and application
-pub struct PatchRunner {
+///
+/// `'a` is the lifetime of the archive a runner from `new_streaming`
+/// borrows; runners that extract the patch don't borrow anything.
+pub struct PatchRunner<'a> {
     patch_dir: PathBuf,
+    /// Temp directory the patch was extracted to by `new` or
+    /// `new_with_temp_dir`, removed when the
+    /// runner is dropped; `None` when the caller chose the directory
+    _extracted: Option<TempDir>,
     manifest: Manifest,
+    /// The patch archive, kept by `new_streaming` to read each entry's
+    /// files from while applying; `None` when it was extracted up front
+    archive: Option<&'a [u8]>,
+    jobs: usize,
+    /// Where `apply` appends its log, if anywhere
+    log_path: Option<PathBuf>,
+    /// Apply a patch that declares hook commands without running them,
+    /// instead of refusing it (see `without_hooks`)
+    skip_hooks: bool,
 }
 
-impl PatchRunner {
+impl<'a> PatchRunner<'a> {
     /// Create a new runner from compressed patch data
+    ///
+    /// The patch is extracted to a temp directory that is removed when the
+    /// runner is dropped. Applying and rolling back borrow the runner, so
+    /// the files stay in place until both are done.
     pub fn new(data: &[u8]) -> Result<Self, PatchRunnerError> {
-        // Create temp directory for extracted patch
-        let temp_dir = tempfile::tempdir()
-            .map_err(|e| PatchRunnerError::ExtractionFailed(format!("Failed to create temp directory: {}", e)))?;
-
-        // Decompress and extract
-        let decoder = GzDecoder::new(data);
-        let mut archive = Archive::new(decoder);
-        archive
-            .unpack(temp_dir.path())
+        Self::new_with_temp_dir(data, None)
+    }
+
+    /// Like `new`, but with the temp directory created inside `temp_base`
+    /// (e.g. a folder next to the game) instead of the system temp
+    /// directory, which can be too small or not writable on locked-down
+    /// systems. `None` uses the system temp directory, as `new` does.
+    pub fn new_with_temp_dir(data: &[u8], temp_base: Option<&Path>) -> Result<Self, PatchRunnerError> {
+        let temp_dir = create_temp_dir(temp_base)?;
+        let mut runner = Self::new_in(data, temp_dir.path())?;
+        runner._extracted = Some(temp_dir);
+        Ok(runner)
+    }
+
+    /// Like `new_with_temp_dir`, but without extracting the patch: only the
+    /// manifest is read up front, and `apply` copies each diff or added
+    /// file out of the archive just before applying its entry, removing it
+    /// again afterwards. The temp directory then holds about one file of the
+    /// patch at a time instead of all of it, for patches too large to
+    /// extract next to the game.
+    ///
+    /// Patch files are checked against their hashes as they are read rather
+    /// than all before anything is backed up (the archive's checksum has
+    /// already been verified by then), and entries are applied one at a
+    /// time whatever `with_jobs` says; validation still uses the threads.
+    ///
+    /// The runner borrows `data` rather than copying it, so the archive is
+    /// held in memory once.
+    pub fn new_streaming(data: &'a [u8], temp_base: Option<&Path>) -> Result<Self, PatchRunnerError> {
+        let manifest = read_archive_file(data, patch::MANIFEST_FILENAME)
+            .map_err(|e| PatchRunnerError::ExtractionFailed(format!("Failed to read patch archive: {}", e)))?
+            .ok_or_else(|| PatchRunnerError::ManifestLoadFailed("Failed to load manifest: not in the patch archive".to_string()))?;
+        let manifest = Manifest::parse(&String::from_utf8_lossy(&manifest))
+            .map_err(|e| PatchRunnerError::ManifestLoadFailed(format!("Failed to load manifest: {}", e)))?;
+
+        let temp_dir = create_temp_dir(temp_base)?;
+        Ok(PatchRunner {
+            patch_dir: temp_dir.path().to_path_buf(),
+            _extracted: Some(temp_dir),
+            manifest,
+            archive: Some(data),
+            jobs: 1,
+            log_path: None,
+            skip_hooks: false,
+        })
+    }
+
+    /// Create a new runner from compressed patch data, extracting it to
+    /// `dir` (created if missing).
+    ///
+    /// The caller owns `dir` and removes it when done; the runner never
+    /// deletes it.
+    pub fn new_in(data: &[u8], dir: &Path) -> Result<Self, PatchRunnerError> {
+        fs::create_dir_all(dir)
+            .map_err(|e| PatchRunnerError::ExtractionFailed(format!("Failed to create extraction directory: {}", e)))?;
+
+        // Decompress and extract, as a tar stream or zip archive
+        extract_archive(data, dir)
             .map_err(|e| PatchRunnerError::ExtractionFailed(format!("Failed to extract patch archive: {}", e)))?;
 
         // Load manifest
-        let manifest_path = temp_dir.path().join(patch::MANIFEST_FILENAME);
+        let manifest_path = dir.join(patch::MANIFEST_FILENAME);
         let manifest = Manifest::load(&manifest_path)
             .map_err(|e| PatchRunnerError::ManifestLoadFailed(format!("Failed to load manifest: {}", e)))?;
 
-        // Keep temp_dir alive by converting to path
-        let patch_dir = temp_dir.keep();
-
         Ok(PatchRunner {
-            patch_dir,
+            patch_dir: dir.to_path_buf(),
+            _extracted: None,
             manifest,
+            archive: None,
+            jobs: 1,
+            log_path: None,
+            skip_hooks: false,
         })
     }
 
+    /// Validate and apply on up to `jobs` threads instead of one.
+    ///
+    /// Worth it for patches with many small files. Entries for the same file
+    /// still run in order on one thread, and a failure on any thread rolls
+    /// back everything applied, as in sequential mode. Operation events then
+    /// arrive in completion order rather than by index.
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Append a log of every apply to `path`: the patch and target, each
+    /// progress event as JSON (as `ProgressEvent::to_json` writes it), and
+    /// the error if the apply fails.
+    pub fn with_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.log_path = Some(path.into());
+        self
+    }
+
+    /// Apply a patch that declares `pre_commands` or `post_commands`
+    /// without running them.
+    ///
+    /// Patchers never run hook commands, so by default `apply` refuses such
+    /// a patch with `HooksNotAllowed` rather than quietly leaving them out.
+    pub fn without_hooks(mut self) -> Self {
+        self.skip_hooks = true;
+        self
+    }
+
+    /// Whether the patch declares hook commands, which `apply` refuses
+    /// unless the runner was made `without_hooks`.
+    pub fn has_hooks(&self) -> bool {
+        self.manifest.has_hooks()
+    }
+
     /// Apply patch to target directory with progress callback
     ///
-    /// The callback is invoked for each progress event. Returns Ok(()) on success,
+    /// The callback is invoked for each progress event, from worker threads
+    /// if `with_jobs` asked for more than one. Returns Ok(()) on success,
     /// or the first error encountered.
     ///
     /// This uses the full patch workflow including:
     /// - Validation before making any changes
     /// - Backup of files that will be modified/deleted (to .patch-backup)
     /// - Atomic rollback on failure
-    pub fn apply<F>(&self, target: &Path, on_progress: F) -> Result<(), PatchError>
+    ///
+    /// If a backup already exists, entries an earlier, interrupted run finished
+    /// are skipped, so applying again completes the patch (see `can_resume`).
+    ///
+    /// Backups go to `backup_dir` if given (e.g. on another volume when the
+    /// game folder is short on space), mirroring the target's layout. With
+    /// `delete_backup`, the backup is removed once every entry is applied and
+    /// verified, so the patch can no longer be rolled back; if removing it
+    /// fails it is left in place and the apply still succeeds.
+    ///
+    /// With `with_log`, the apply is also recorded in the log.
+    pub fn apply<F>(
+        &self,
+        target: &Path,
+        backup_dir: Option<&Path>,
+        delete_backup: bool,
+        mut on_progress: F,
+    ) -> Result<(), PatchError>
+    where
+        F: FnMut(ProgressEvent) + Send,
+    {
+        let mut log = self
+            .log_path
+            .as_deref()
+            .and_then(|path| PatchLog::open(path, &self.manifest, target));
+        let result = self.apply_unlogged(target, backup_dir, delete_backup, |event| {
+            if let Some(log) = &mut log {
+                log.event(&event);
+            }
+            on_progress(event);
+        });
+        if let Some(log) = &mut log {
+            log.finish(&result);
+        }
+        result
+    }
+
+    fn apply_unlogged<F>(
+        &self,
+        target: &Path,
+        backup_dir: Option<&Path>,
+        delete_backup: bool,
+        on_progress: F,
+    ) -> Result<(), PatchError>
     where
-        F: FnMut(ProgressEvent),
+        F: FnMut(ProgressEvent) + Send,
     {
-        let backup_dir = target.join(patch::BACKUP_DIR);
+        let backup_dir = Self::backup_dir(target, backup_dir);
 
-        // Use RefCell to allow multiple closures to borrow on_progress
-        let on_progress = RefCell::new(on_progress);
+        // Mutex so the closures below can share on_progress, across worker
+        // threads when applying in parallel
+        let on_progress = Mutex::new(on_progress);
 
         // Helper to convert core Progress to ProgressEvent::Operation
         let send_operation = |p: Progress| {
-            (on_progress.borrow_mut())(ProgressEvent::Operation {
+            (on_progress.lock().unwrap())(ProgressEvent::Operation {
                 file: p.file.to_owned(),
+                label: p.label.map(str::to_owned),
                 index: p.index,
                 total: p.total,
                 action: p.action,
             });
         };
+        let estimator = Mutex::new(RateEstimator::default());
+        let send_bytes = |b: ByteProgress| {
+            if b.total > 0 {
+                let (throughput_bytes_per_sec, eta_secs) =
+                    estimator.lock().unwrap().update(Instant::now(), b.done, b.total);
+                (on_progress.lock().unwrap())(ProgressEvent::BytesProcessed {
+                    done: b.done,
+                    total: b.total,
+                    throughput_bytes_per_sec,
+                    eta_secs,
+                });
+            }
+        };
 
         // Validation phase
-        (on_progress.borrow_mut())(ProgressEvent::PhaseStarted {
+        (on_progress.lock().unwrap())(ProgressEvent::PhaseStarted {
             phase: Phase::Validating,
         });
 
+        if self.manifest.unhashed {
+            (on_progress.lock().unwrap())(ProgressEvent::Warning {
+                message: MISSING_HASH_WARNING.to_string(),
+            });
+        }
+
+        if self.manifest.has_hooks() {
+            let commands: Vec<String> = self.manifest.pre_commands.iter()
+                .chain(&self.manifest.post_commands)
+                .map(|command| patch::format_command(command))
+                .collect();
+            if !self.skip_hooks {
+                (on_progress.lock().unwrap())(ProgressEvent::Error {
+                    message: "This patch runs commands, which the patcher doesn't do".to_string(),
+                    details: Some(commands.join("\n")),
+                });
+                return Err(PatchError::HooksNotAllowed);
+            }
+            (on_progress.lock().unwrap())(ProgressEvent::Warning {
+                message: format!("Not running the patch's commands: {}", commands.join(", ")),
+            });
+        }
+
+        // Keep a second patcher (e.g. from a double-click) off the target
+        // until this one is done
+        let _lock = match patch::PatchLock::acquire(target) {
+            Ok(lock) => lock,
+            Err(e) => {
+                (on_progress.lock().unwrap())(ProgressEvent::Error {
+                    message: "Another patcher is running".to_string(),
+                    details: Some(e.to_string()),
+                });
+                return Err(e);
+            }
+        };
+
+        // Entries are relative to the patch's base_dir inside the target, if
+        // it names one; the backup stays where it was put above
+        let install_dir = match patch::install_dir(&self.manifest, target) {
+            Ok(dir) => dir,
+            Err(e) => {
+                (on_progress.lock().unwrap())(ProgressEvent::Error {
+                    message: "Validation failed".to_string(),
+                    details: Some(e.to_string()),
+                });
+                return Err(e);
+            }
+        };
+        let target = install_dir.as_path();
+
+        // Leave files the player has asked us not to touch (mods, saves) alone
+        let resolved = self.unignored(target).and_then(|(mut manifest, skipped)| {
+            patch::resolve_by_content(&mut manifest, target)?;
+            Ok((manifest, skipped))
+        });
+        let mut manifest = match resolved {
+            Ok((manifest, skipped)) => {
+                if !skipped.is_empty() {
+                    let files: Vec<&str> = skipped.iter().map(ManifestEntry::file).collect();
+                    (on_progress.lock().unwrap())(ProgressEvent::Warning {
+                        message: format!("Leaving {} files matched by {} alone: {}", files.len(), IGNORE_FILENAME, files.join(", ")),
+                    });
+                }
+                manifest
+            }
+            Err(e) => {
+                (on_progress.lock().unwrap())(ProgressEvent::Error {
+                    message: "Validation failed".to_string(),
+                    details: Some(e.to_string()),
+                });
+                return Err(e);
+            }
+        };
+
         // Check path restrictions first (unless allow_restricted is set in manifest)
-        if let Err(e) = patch::validate_path_restrictions(&self.manifest, target) {
-            (on_progress.borrow_mut())(ProgressEvent::Error {
+        if let Err(e) = patch::validate_path_restrictions(&manifest, target) {
+            (on_progress.lock().unwrap())(ProgressEvent::Error {
                 message: "Path restrictions violated".to_string(),
                 details: Some(e.to_string()),
             });
             return Err(e);
         }
 
-        if let Err(e) = patch::validate_entries(&self.manifest.entries, target, Some(&send_operation))
+        if let Err(e) = patch::validate_game_version(&manifest, target) {
+            (on_progress.lock().unwrap())(ProgressEvent::Error {
+                message: "Wrong game version".to_string(),
+                details: Some(e.to_string()),
+            });
+            return Err(e);
+        }
+
+        if backup_dir.exists() {
+            patch::remove_applied_entries(&mut manifest.entries, target, manifest.hash_algorithm);
+        }
+
+        // A corrupted download fails here, before anything is backed up
+        // (unless the files are read from the archive as they're applied)
+        if self.archive.is_none()
+            && let Err(e) = patch::validate_patch_files(
+                &manifest.entries,
+                &self.patch_dir,
+                manifest.hash_algorithm,
+                None::<fn(Progress)>,
+            )
         {
-            (on_progress.borrow_mut())(ProgressEvent::Error {
+            (on_progress.lock().unwrap())(ProgressEvent::Error {
+                message: "Patch files are corrupted".to_string(),
+                details: Some(e.to_string()),
+            });
+            return Err(e);
+        }
+
+        let validated = if self.jobs > 1 {
+            patch::validate_entries_parallel(
+                &manifest.entries,
+                target,
+                manifest.hash_algorithm,
+                self.jobs,
+                Some(&send_operation),
+            )
+        } else {
+            patch::validate_entries(&manifest.entries, target, manifest.hash_algorithm, Some(&send_operation))
+        };
+        if let Err(e) = validated {
+            (on_progress.lock().unwrap())(ProgressEvent::Error {
+                message: "Validation failed".to_string(),
+                details: Some(e.to_string()),
+            });
+            return Err(e);
+        }
+
+        if let Err(e) = patch::check_space(&manifest.entries, target, &self.patch_dir, &backup_dir) {
+            (on_progress.lock().unwrap())(ProgressEvent::Error {
+                message: "Not enough disk space".to_string(),
+                details: Some(e.to_string()),
+            });
+            return Err(e);
+        }
+
+        if let Err(e) = patch::check_writable(&manifest.entries, target, &backup_dir) {
+            (on_progress.lock().unwrap())(ProgressEvent::Error {
+                message: "Cannot write to the game folder".to_string(),
+                details: Some(e.to_string()),
+            });
+            return Err(e);
+        }
+
+        // Clear out temp files left behind by an earlier run that crashed mid-write
+        if let Err(e) = remove_stray_temp_files(target) {
+            let e = PatchError::ValidationFailed {
+                file: target.display().to_string(),
+                reason: format!("failed to remove stray temp files: {}", e),
+            };
+            (on_progress.lock().unwrap())(ProgressEvent::Error {
                 message: "Validation failed".to_string(),
                 details: Some(e.to_string()),
             });
@@ -154,13 +694,13 @@ impl PatchRunner {
         }
 
         // Backup phase
-        (on_progress.borrow_mut())(ProgressEvent::PhaseStarted {
+        (on_progress.lock().unwrap())(ProgressEvent::PhaseStarted {
             phase: Phase::BackingUp,
         });
         if let Err(e) =
-            patch::backup_entries(&self.manifest.entries, target, &backup_dir, Some(&send_operation))
+            patch::backup_entries(&manifest.entries, target, &backup_dir, manifest.hash_algorithm, Some(&send_operation))
         {
-            (on_progress.borrow_mut())(ProgressEvent::Error {
+            (on_progress.lock().unwrap())(ProgressEvent::Error {
                 message: "Backup failed".to_string(),
                 details: Some(e.to_string()),
             });
@@ -168,52 +708,281 @@ impl PatchRunner {
         }
 
         // Apply phase
-        (on_progress.borrow_mut())(ProgressEvent::PhaseStarted {
+        (on_progress.lock().unwrap())(ProgressEvent::PhaseStarted {
             phase: Phase::Applying,
         });
-        if let Err(e) = patch::apply_entries(
-            &self.manifest.entries,
-            target,
-            &self.patch_dir,
-            &backup_dir,
-            Some(&send_operation),
-        ) {
-            (on_progress.borrow_mut())(ProgressEvent::Error {
+        let applied = if let Some(data) = &self.archive {
+            self.apply_streamed(data, &manifest, target, &backup_dir, &send_operation, &send_bytes)
+        } else if self.jobs > 1 {
+            patch::apply_entries_parallel(
+                &manifest.entries,
+                target,
+                &self.patch_dir,
+                &backup_dir,
+                Verification::Full,
+                manifest.hash_algorithm,
+                self.jobs,
+                Some(&send_operation),
+                Some(&send_bytes),
+            )
+        } else {
+            patch::apply_entries(
+                &manifest.entries,
+                target,
+                &self.patch_dir,
+                &backup_dir,
+                Verification::Full,
+                manifest.hash_algorithm,
+                Some(&send_operation),
+                Some(&send_bytes),
+            )
+        };
+        if let Err(e) = applied {
+            (on_progress.lock().unwrap())(ProgressEvent::Error {
                 message: "Apply failed".to_string(),
                 details: Some(e.to_string()),
             });
             return Err(e);
         }
 
-        (on_progress.borrow_mut())(ProgressEvent::Done {
-            files_patched: self.manifest.entries.len(),
+        if delete_backup {
+            let _ = remove_dir_all_robust(&backup_dir);
+        }
+
+        (on_progress.lock().unwrap())(ProgressEvent::Done {
+            files_patched: manifest.entries.len(),
         });
 
         Ok(())
     }
 
+    /// Apply `manifest`'s entries one at a time, copying each one's diff or
+    /// added file from the archive `data` into the patch directory just
+    /// before it's applied and removing it once it is.
+    fn apply_streamed<F, G>(
+        &self,
+        data: &[u8],
+        manifest: &Manifest,
+        target: &Path,
+        backup_dir: &Path,
+        on_progress: F,
+        on_bytes: G,
+    ) -> Result<(), PatchError>
+    where
+        F: FnMut(Progress),
+        G: FnMut(ByteProgress),
+    {
+        let needed: HashSet<String> = manifest.entries.iter().filter_map(payload_name).collect();
+        let applied = with_archive_fetcher(data, &self.patch_dir, &needed, |fetch| {
+            let mut previous: Option<PathBuf> = None;
+            let applied = patch::apply_entries_staged(
+                &manifest.entries,
+                target,
+                &self.patch_dir,
+                backup_dir,
+                Verification::Full,
+                manifest.hash_algorithm,
+                |entry| {
+                    if let Some(path) = previous.take() {
+                        let _ = fs::remove_file(path);
+                    }
+                    let Some(name) = payload_name(entry) else {
+                        return Ok(());
+                    };
+                    previous = Some(fetch(&name).map_err(|e| PatchError::ApplyFailed {
+                        file: entry.file().to_string(),
+                        reason: format!("failed to read {} from the patch archive: {}", name, e),
+                    })?);
+                    patch::validate_patch_files(
+                        std::slice::from_ref(entry),
+                        &self.patch_dir,
+                        manifest.hash_algorithm,
+                        None::<fn(Progress)>,
+                    )
+                },
+                Some(on_progress),
+                Some(on_bytes),
+            );
+            if let Some(path) = previous {
+                let _ = fs::remove_file(path);
+            }
+            applied
+        });
+        applied.unwrap_or_else(|e| {
+            Err(PatchError::ValidationFailed {
+                file: patch::MANIFEST_FILENAME.to_string(),
+                reason: format!("failed to read the patch archive: {}", e),
+            })
+        })
+    }
+
     /// Validate that target folder can be patched (pre-apply check)
     ///
     /// Returns Ok(()) if all files are in expected pre-patch state,
     /// or an error describing the first problem found.
     ///
-    /// Also checks path restrictions (unless allow_restricted is set in manifest).
+    /// Also checks path restrictions (unless allow_restricted is set in
+    /// manifest) and the game version (if the manifest names a version file).
     pub fn validate_target(&self, target: &Path) -> Result<(), PatchError> {
+        let target = &patch::install_dir(&self.manifest, target)?;
+        let manifest = self.resolve(target)?;
         // Check path restrictions first
-        patch::validate_path_restrictions(&self.manifest, target)?;
-        patch::validate_entries(&self.manifest.entries, target, None::<fn(Progress)>)
+        patch::validate_path_restrictions(&manifest, target)?;
+        patch::validate_game_version(&manifest, target)?;
+        patch::validate_entries(&manifest.entries, target, manifest.hash_algorithm, None::<fn(Progress)>)
+    }
+
+    /// Unified diffs of the text files applying the patch would change in
+    /// `target`, as (file, diff) pairs in manifest order, each cut to
+    /// `max_lines` lines (see `patch::preview_entry`). Binary files are left
+    /// out. Call after `validate_target`.
+    ///
+    /// A runner from `new_streaming` reads each file from the archive for
+    /// its preview and removes it again.
+    pub fn preview(&self, target: &Path, max_lines: usize) -> Result<Vec<(String, String)>, PatchError> {
+        let target = &patch::install_dir(&self.manifest, target)?;
+        let manifest = self.resolve(target)?;
+        let mut previews = Vec::new();
+        let mut preview_all = |fetch: &mut dyn FnMut(&str) -> io::Result<Option<PathBuf>>| {
+            for entry in &manifest.entries {
+                let fetched = payload_name(entry).map(|name| fetch(&name)).transpose().map_err(|e| {
+                    PatchError::ValidationFailed {
+                        file: entry.file().to_string(),
+                        reason: format!("failed to read the patch archive: {}", e),
+                    }
+                })?;
+                let preview = patch::preview_entry(entry, target, &self.patch_dir, max_lines);
+                if let Some(path) = fetched.flatten() {
+                    let _ = fs::remove_file(path);
+                }
+                if let Some(diff) = preview? {
+                    previews.push((entry.file().to_string(), diff));
+                }
+            }
+            Ok(())
+        };
+
+        match &self.archive {
+            Some(data) => {
+                let needed: HashSet<String> = manifest.entries.iter().filter_map(payload_name).collect();
+                with_archive_fetcher(data, &self.patch_dir, &needed, |fetch| {
+                    preview_all(&mut |name| fetch(name).map(Some))
+                })
+                .unwrap_or_else(|e| {
+                    Err(PatchError::ValidationFailed {
+                        file: patch::MANIFEST_FILENAME.to_string(),
+                        reason: format!("failed to read the patch archive: {}", e),
+                    })
+                })?;
+            }
+            None => preview_all(&mut |_| Ok(None))?,
+        }
+        Ok(previews)
+    }
+
+    /// The manifest to apply to `target`, with renamed files located by
+    /// content if the patch was created with `match_by_content`.
+    fn resolve(&self, target: &Path) -> Result<Manifest, PatchError> {
+        let (mut manifest, _) = self.unignored(target)?;
+        patch::resolve_by_content(&mut manifest, target)?;
+        Ok(manifest)
+    }
+
+    /// The manifest without the entries matched by `.graftignore` in
+    /// `target` (the directory entries are relative to), which are never
+    /// applied or rolled back, and those entries.
+    fn unignored(&self, target: &Path) -> Result<(Manifest, Vec<ManifestEntry>), PatchError> {
+        let ignore = IgnoreList::load(target).map_err(|e| PatchError::ManifestError {
+            reason: format!("failed to read {}: {}", IGNORE_FILENAME, e),
+        })?;
+        let mut manifest = self.manifest.clone();
+        let skipped = ignore.filter_entries(&mut manifest.entries);
+        Ok((manifest, skipped))
     }
 
     /// Check if target appears to be in patched state
     ///
     /// Returns true if all files match their expected post-patch hashes.
     pub fn is_patched(&self, target: &Path) -> bool {
-        patch::validate_patched_entries(&self.manifest.entries, target, None::<fn(Progress)>).is_ok()
+        let Ok(target) = patch::install_dir(&self.manifest, target) else {
+            return false;
+        };
+        let Ok((manifest, _)) = self.unignored(&target) else {
+            return false;
+        };
+        patch::validate_patched_entries(&manifest.entries, &target, manifest.hash_algorithm, None::<fn(Progress)>).is_ok()
+    }
+
+    /// Check if target is already up to date with this patch
+    ///
+    /// Returns true if the target passes path restrictions and every entry is
+    /// already in its post-patch state (patched/added files match `final_hash`,
+    /// deleted files are gone). Unlike `is_patched`, this is meant for targets
+    /// with no backup, e.g. a fresh install of the already-patched version.
+    pub fn is_up_to_date(&self, target: &Path) -> bool {
+        let Ok(install_dir) = patch::install_dir(&self.manifest, target) else {
+            return false;
+        };
+        !self.manifest.entries.is_empty()
+            && patch::validate_path_restrictions(&self.manifest, &install_dir).is_ok()
+            && self.is_patched(target)
+    }
+
+    /// Check if an interrupted apply can be resumed on target
+    ///
+    /// Returns true if a backup exists, the target isn't fully patched yet,
+    /// and every entry not yet applied is still in its pre-patch state, so
+    /// `apply` would finish the job.
+    pub fn can_resume(&self, target: &Path, backup_dir: Option<&Path>) -> bool {
+        if !Self::has_backup(target, backup_dir) || self.is_patched(target) {
+            return false;
+        }
+        let Ok(install_dir) = patch::install_dir(&self.manifest, target) else {
+            return false;
+        };
+        let target = install_dir.as_path();
+        let Ok(mut manifest) = self.resolve(target) else {
+            return false;
+        };
+        patch::remove_applied_entries(&mut manifest.entries, target, manifest.hash_algorithm);
+        patch::validate_path_restrictions(&manifest, target).is_ok()
+            && patch::validate_entries(&manifest.entries, target, manifest.hash_algorithm, None::<fn(Progress)>).is_ok()
+    }
+
+    /// The backup directory for `target`: `custom` if given, otherwise
+    /// `.patch-backup` inside the target.
+    pub fn backup_dir(target: &Path, custom: Option<&Path>) -> PathBuf {
+        custom.map_or_else(|| target.join(BACKUP_DIR), Path::to_path_buf)
+    }
+
+    /// Check if the backup directory for target exists
+    pub fn has_backup(target: &Path, backup_dir: Option<&Path>) -> bool {
+        Self::backup_dir(target, backup_dir).exists()
+    }
+
+    /// Check if the backup in `.patch-backup` inside target is intact, so
+    /// `rollback` won't stop partway on a missing or damaged backup file
+    pub fn can_rollback(&self, target: &Path) -> bool {
+        let Ok(install_dir) = patch::install_dir(&self.manifest, target) else {
+            return false;
+        };
+        let Ok(manifest) = self.applied(&install_dir, &target.join(BACKUP_DIR)) else {
+            return false;
+        };
+        patch::rollback_available(target, &manifest).unwrap_or(false)
     }
 
-    /// Check if backup directory exists in target
-    pub fn has_backup(target: &Path) -> bool {
-        target.join(BACKUP_DIR).exists()
+    /// The manifest with just the entries the backup in `backup_dir` records
+    /// as applied to `target`, or for a backup that doesn't record them,
+    /// those not matched by `.graftignore`.
+    fn applied(&self, target: &Path, backup_dir: &Path) -> Result<Manifest, PatchError> {
+        match patch::applied_entries(backup_dir)? {
+            Some(entries) => Ok(Manifest {
+                entries,
+                ..self.manifest.clone()
+            }),
+            None => self.unignored(target).map(|(manifest, _)| manifest),
+        }
     }
 
     /// Perform rollback with validation and progress reporting
@@ -221,11 +990,22 @@ impl PatchRunner {
     /// If `force` is false, validates that target files are in expected patched state first.
     /// If target files have been modified, returns TargetModified event and does not rollback.
     /// Always validates backup integrity before proceeding.
-    pub fn rollback<F>(&self, target: &Path, force: bool, mut on_progress: F) -> Result<(), PatchError>
+    ///
+    /// Target files are compared again immediately before restoring. With `force`,
+    /// files that changed are reported via OverwritingModified and then overwritten.
+    ///
+    /// `backup_dir` must be the one the patch was applied with.
+    pub fn rollback<F>(
+        &self,
+        target: &Path,
+        backup_dir: Option<&Path>,
+        force: bool,
+        mut on_progress: F,
+    ) -> Result<(), PatchError>
     where
         F: FnMut(RollbackEvent),
     {
-        let backup_dir = target.join(BACKUP_DIR);
+        let backup_dir = Self::backup_dir(target, backup_dir);
 
         // Check backup exists
         if !backup_dir.exists() {
@@ -237,12 +1017,35 @@ impl PatchRunner {
             });
         }
 
+        let install_dir = match patch::install_dir(&self.manifest, target) {
+            Ok(dir) => dir,
+            Err(e) => {
+                on_progress(RollbackEvent::Error {
+                    message: e.to_string(),
+                });
+                return Err(e);
+            }
+        };
+        let target = install_dir.as_path();
+
+        // Undo exactly what was applied, whatever .graftignore says now
+        let manifest = match self.applied(target, &backup_dir) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                on_progress(RollbackEvent::Error {
+                    message: e.to_string(),
+                });
+                return Err(e);
+            }
+        };
+
         // Validate target (patched files) unless force
         if !force {
             on_progress(RollbackEvent::ValidatingTarget);
             if let Err(e) = patch::validate_patched_entries(
-                &self.manifest.entries,
+                &manifest.entries,
                 target,
+                manifest.hash_algorithm,
                 None::<fn(Progress)>,
             ) {
                 on_progress(RollbackEvent::TargetModified {
@@ -254,15 +1057,30 @@ impl PatchRunner {
 
         // Always validate backup integrity
         on_progress(RollbackEvent::ValidatingBackup);
-        if let Err(e) = patch::validate_backup(&self.manifest.entries, &backup_dir, None::<fn(Progress)>) {
+        if let Err(e) = patch::validate_backup(&manifest.entries, &backup_dir, manifest.hash_algorithm, None::<fn(Progress)>) {
             on_progress(RollbackEvent::Error {
                 message: format!("Backup validation failed: {}", e),
             });
             return Err(e);
         }
 
+        // Compare the target again right before restoring, in case files
+        // changed since the check above (or it was skipped with force)
+        let modified = patch::find_modified_entries(&manifest.entries, target, manifest.hash_algorithm);
+        if let Some(first) = modified.first() {
+            if !force {
+                on_progress(RollbackEvent::TargetModified {
+                    reason: first.to_string(),
+                });
+                return Err(first.clone());
+            }
+            on_progress(RollbackEvent::OverwritingModified {
+                files: modified.iter().map(|e| e.to_string()).collect(),
+            });
+        }
+
         // Perform rollback
-        let entries: Vec<_> = self.manifest.entries.iter().collect();
+        let entries: Vec<_> = manifest.entries.iter().collect();
         let total = entries.len();
         patch::rollback(&entries, target, &backup_dir, Some(|p: Progress| {
             on_progress(RollbackEvent::Rolling {
@@ -281,8 +1099,8 @@ impl PatchRunner {
     }
 
     /// Delete the backup directory
-    pub fn delete_backup(target: &Path) -> std::io::Result<()> {
-        let backup_dir = target.join(BACKUP_DIR);
+    pub fn delete_backup(target: &Path, backup_dir: Option<&Path>) -> std::io::Result<()> {
+        let backup_dir = Self::backup_dir(target, backup_dir);
         if backup_dir.exists() {
             remove_dir_all_robust(&backup_dir)?;
         }
@@ -340,6 +1158,18 @@ fn remove_file_robust(path: &Path) -> std::io::Result<()> {
     }
 }
 
+/// Create a temp directory for the patch's files inside `temp_base`, or in
+/// the system temp directory if `None`.
+fn create_temp_dir(temp_base: Option<&Path>) -> Result<TempDir, PatchRunnerError> {
+    match temp_base {
+        Some(base) => tempfile::tempdir_in(base).map_err(|e| {
+            PatchRunnerError::ExtractionFailed(format!("Failed to create temp directory in {}: {}", base.display(), e))
+        }),
+        None => tempfile::tempdir()
+            .map_err(|e| PatchRunnerError::ExtractionFailed(format!("Failed to create temp directory: {}", e))),
+    }
+}
+
 /// Errors specific to the patch runner
 #[derive(Debug, Clone)]
 pub enum PatchRunnerError {
@@ -357,3 +1187,565 @@ impl std::fmt::Display for PatchRunnerError {
 }
 
 impl std::error::Error for PatchRunnerError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graft_core::archive::{create_archive_bytes, create_archive_bytes_compressed, ArchiveCompression, Codec};
+    use graft_core::utils::dir_scan::list_files_recursive;
+    use graft_core::utils::hash::hash_bytes;
+    use graft_core::utils::manifest::with_manifest_hash;
+    use tempfile::tempdir;
+
+    fn runner_for(manifest: &str) -> PatchRunner<'static> {
+        let patch_dir = tempdir().unwrap();
+        fs::write(patch_dir.path().join(patch::MANIFEST_FILENAME), with_manifest_hash(manifest)).unwrap();
+        let data = create_archive_bytes(patch_dir.path()).unwrap();
+        PatchRunner::new(&data).unwrap()
+    }
+
+    #[test]
+    fn extracted_patch_is_removed_with_runner() {
+        let runner = runner_for(r#"{"version": 1, "name": "Test", "entries": []}"#);
+        let patch_dir = runner.patch_dir.clone();
+        assert!(patch_dir.join(patch::MANIFEST_FILENAME).exists());
+
+        drop(runner);
+        assert!(!patch_dir.exists());
+    }
+
+    #[test]
+    fn new_in_extracts_to_caller_directory() {
+        let patch_dir = tempdir().unwrap();
+        fs::write(
+            patch_dir.path().join(patch::MANIFEST_FILENAME),
+            with_manifest_hash(r#"{"version": 1, "name": "Test", "entries": []}"#),
+        )
+        .unwrap();
+        let data = create_archive_bytes(patch_dir.path()).unwrap();
+        let scratch = tempdir().unwrap();
+        let dir = scratch.path().join("extracted");
+
+        let runner = PatchRunner::new_in(&data, &dir).unwrap();
+        drop(runner);
+
+        assert!(dir.join(patch::MANIFEST_FILENAME).exists());
+    }
+
+    #[test]
+    fn temp_dir_is_created_in_chosen_base() {
+        let patch_dir = tempdir().unwrap();
+        fs::write(
+            patch_dir.path().join(patch::MANIFEST_FILENAME),
+            with_manifest_hash(r#"{"version": 1, "name": "Test", "entries": []}"#),
+        )
+        .unwrap();
+        let data = create_archive_bytes(patch_dir.path()).unwrap();
+        let base = tempdir().unwrap();
+
+        let runner = PatchRunner::new_with_temp_dir(&data, Some(base.path())).unwrap();
+        assert!(runner.patch_dir.starts_with(base.path()));
+        drop(runner);
+        assert_eq!(fs::read_dir(base.path()).unwrap().count(), 0);
+
+        let missing = base.path().join("missing");
+        let err = PatchRunner::new_with_temp_dir(&data, Some(&missing)).err().unwrap();
+        assert!(err.to_string().contains(&missing.display().to_string()));
+    }
+
+    #[test]
+    fn progress_events_serialize_as_json() {
+        let operation = ProgressEvent::Operation {
+            file: "data/strings.txt".to_string(),
+            label: None,
+            index: 0,
+            total: 2,
+            action: ProgressAction::CheckingNotExists,
+        };
+        assert_eq!(
+            operation.to_json(),
+            r#"{"action":"checking_not_exists","event":"operation","file":"data/strings.txt","index":0,"total":2}"#
+        );
+        let labelled = ProgressEvent::Operation {
+            file: "data0.bin".to_string(),
+            label: Some("Spanish dialogue".to_string()),
+            index: 1,
+            total: 2,
+            action: ProgressAction::Patching,
+        };
+        assert_eq!(
+            labelled.to_json(),
+            r#"{"action":"patching","event":"operation","file":"data0.bin","index":1,"label":"Spanish dialogue","total":2}"#
+        );
+        assert_eq!(
+            ProgressEvent::PhaseStarted { phase: Phase::BackingUp }.to_json(),
+            r#"{"event":"phase_started","phase":"backing_up"}"#
+        );
+        assert_eq!(
+            ProgressEvent::BytesProcessed {
+                done: 512,
+                total: 2048,
+                throughput_bytes_per_sec: None,
+                eta_secs: None,
+            }
+            .to_json(),
+            r#"{"done":512,"eta_secs":null,"event":"bytes_processed","throughput_bytes_per_sec":null,"total":2048}"#
+        );
+        assert_eq!(
+            ProgressEvent::Done { files_patched: 2 }.to_json(),
+            r#"{"event":"done","files_patched":2}"#
+        );
+        let error = ProgressEvent::Error {
+            message: "Apply failed".to_string(),
+            details: None,
+        };
+        assert_eq!(error.to_json(), r#"{"details":null,"event":"error","message":"Apply failed"}"#);
+    }
+
+    #[test]
+    fn estimates_rate_over_recent_updates() {
+        let mut estimator = RateEstimator::default();
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        const MB: u64 = 1024 * 1024;
+
+        // Not enough to go by yet
+        assert_eq!(estimator.update(at(0), 0, 100 * MB), (None, None));
+        assert_eq!(estimator.update(at(500), 5 * MB, 100 * MB), (None, None));
+
+        // 10 MB/s, 90 MB left
+        assert_eq!(estimator.update(at(1000), 10 * MB, 100 * MB), (Some(10 * MB), Some(9)));
+
+        // Only the last WINDOW counts: after a stall, the rate drops to what
+        // was written in it
+        estimator.update(at(2000), 20 * MB, 100 * MB);
+        assert_eq!(estimator.update(at(8000), 20 * MB, 100 * MB), (Some(0), None));
+
+        assert_eq!(format_estimate(Some(120 * MB), Some(45)).unwrap(), "~45s remaining, 120.0 MB/s");
+        assert_eq!(format_estimate(Some(MB), Some(90)).unwrap(), "~1m 30s remaining, 1.0 MB/s");
+        assert_eq!(format_estimate(None, None), None);
+    }
+
+    #[test]
+    fn detects_up_to_date_target() {
+        let target = tempdir().unwrap();
+        fs::write(target.path().join("new.bin"), b"new content").unwrap();
+
+        let runner = runner_for(&format!(
+            r#"{{"version": 1, "name": "TestPatcher", "entries": [
+                {{"operation": "add", "file": "new.bin", "final_hash": "{}"}},
+                {{"operation": "delete", "file": "old.bin", "original_hash": "x"}}
+            ]}}"#,
+            hash_bytes(b"new content")
+        ));
+
+        assert!(runner.validate_target(target.path()).is_err());
+        assert!(runner.is_up_to_date(target.path()));
+    }
+
+    #[test]
+    fn unpatched_target_is_not_up_to_date() {
+        let target = tempdir().unwrap();
+        fs::write(target.path().join("old.bin"), b"old content").unwrap();
+
+        let runner = runner_for(
+            r#"{"version": 1, "name": "TestPatcher", "entries": [
+                {"operation": "delete", "file": "old.bin", "original_hash": "x"}
+            ]}"#,
+        );
+
+        assert!(!runner.is_up_to_date(target.path()));
+    }
+
+    #[test]
+    fn interrupted_apply_can_be_resumed() {
+        let patch_dir = tempdir().unwrap();
+        fs::create_dir_all(patch_dir.path().join(patch::FILES_DIR)).unwrap();
+        for file in ["a.bin", "b.bin"] {
+            fs::write(patch_dir.path().join(patch::FILES_DIR).join(file), file).unwrap();
+        }
+        fs::write(
+            patch_dir.path().join(patch::MANIFEST_FILENAME),
+            with_manifest_hash(&format!(
+                r#"{{"version": 1, "name": "TestPatcher", "entries": [
+                    {{"operation": "add", "file": "a.bin", "final_hash": "{}"}},
+                    {{"operation": "add", "file": "b.bin", "final_hash": "{}"}}
+                ]}}"#,
+                hash_bytes(b"a.bin"),
+                hash_bytes(b"b.bin")
+            )),
+        )
+        .unwrap();
+        let runner = PatchRunner::new(&create_archive_bytes(patch_dir.path()).unwrap()).unwrap();
+
+        // The first run added a.bin and was closed before b.bin
+        let target = tempdir().unwrap();
+        fs::write(target.path().join("a.bin"), b"a.bin").unwrap();
+        assert!(!runner.can_resume(target.path(), None));
+        fs::create_dir_all(target.path().join(BACKUP_DIR)).unwrap();
+        assert!(runner.can_resume(target.path(), None));
+
+        runner.apply(target.path(), None, false, |_| {}).unwrap();
+
+        assert_eq!(fs::read(target.path().join("b.bin")).unwrap(), b"b.bin");
+        assert!(runner.is_patched(target.path()));
+        assert!(!runner.can_resume(target.path(), None));
+    }
+
+    #[test]
+    fn apply_warns_about_an_unhashed_manifest() {
+        let patch_dir = tempdir().unwrap();
+        fs::write(
+            patch_dir.path().join(patch::MANIFEST_FILENAME),
+            r#"{"version": 1, "name": "Test", "entries": []}"#,
+        )
+        .unwrap();
+        let data = create_archive_bytes(patch_dir.path()).unwrap();
+        let runner = PatchRunner::new(&data).unwrap();
+
+        let target = tempdir().unwrap();
+        let mut warnings = Vec::new();
+        runner
+            .apply(target.path(), None, false, |event| {
+                if let ProgressEvent::Warning { message } = event {
+                    warnings.push(message);
+                }
+            })
+            .unwrap();
+        assert_eq!(warnings, [MISSING_HASH_WARNING]);
+    }
+
+    #[test]
+    fn apply_and_rollback_leave_ignored_files_alone() {
+        let runner = runner_for(&format!(
+            r#"{{"version": 1, "name": "TestPatcher", "entries": [
+                {{"operation": "delete", "file": "old.bin", "original_hash": "{}"}},
+                {{"operation": "delete", "file": "saves/slot1.sav", "original_hash": "{}"}}
+            ]}}"#,
+            hash_bytes(b"old"),
+            hash_bytes(b"save")
+        ));
+
+        let target = tempdir().unwrap();
+        fs::create_dir(target.path().join("saves")).unwrap();
+        fs::write(target.path().join("old.bin"), b"old").unwrap();
+        fs::write(target.path().join("saves/slot1.sav"), b"save").unwrap();
+        fs::write(target.path().join(IGNORE_FILENAME), "saves/\n").unwrap();
+
+        let mut warnings = Vec::new();
+        runner
+            .apply(target.path(), None, false, |event| {
+                if let ProgressEvent::Warning { message } = event {
+                    warnings.push(message);
+                }
+            })
+            .unwrap();
+        assert!(!target.path().join("old.bin").exists());
+        assert!(target.path().join("saves/slot1.sav").exists());
+        assert!(warnings.iter().any(|w| w.contains("saves/slot1.sav")), "{:?}", warnings);
+        assert!(runner.is_patched(target.path()));
+        assert!(runner.can_rollback(target.path()));
+
+        runner.rollback(target.path(), None, false, |_| {}).unwrap();
+        assert_eq!(fs::read(target.path().join("old.bin")).unwrap(), b"old");
+        assert_eq!(fs::read(target.path().join("saves/slot1.sav")).unwrap(), b"save");
+    }
+
+    #[test]
+    fn rollback_undoes_what_was_applied_whatever_graftignore_says_now() {
+        let runner = runner_for(&format!(
+            r#"{{"version": 1, "name": "TestPatcher", "entries": [
+                {{"operation": "delete", "file": "old.bin", "original_hash": "{}"}},
+                {{"operation": "delete", "file": "saves/slot1.sav", "original_hash": "{}"}}
+            ]}}"#,
+            hash_bytes(b"old"),
+            hash_bytes(b"save")
+        ));
+        let game = || {
+            let target = tempdir().unwrap();
+            fs::create_dir(target.path().join("saves")).unwrap();
+            fs::write(target.path().join("old.bin"), b"old").unwrap();
+            fs::write(target.path().join("saves/slot1.sav"), b"save").unwrap();
+            target
+        };
+
+        // Pattern removed after applying: the save was never touched, so
+        // rollback leaves the player's newer one alone
+        let target = game();
+        fs::write(target.path().join(IGNORE_FILENAME), "saves/\n").unwrap();
+        let mut done = None;
+        runner
+            .apply(target.path(), None, false, |event| {
+                if let ProgressEvent::Done { files_patched } = event {
+                    done = Some(files_patched);
+                }
+            })
+            .unwrap();
+        assert_eq!(done, Some(1));
+        fs::remove_file(target.path().join(IGNORE_FILENAME)).unwrap();
+        fs::write(target.path().join("saves/slot1.sav"), b"newer save").unwrap();
+        assert!(runner.can_rollback(target.path()));
+        runner.rollback(target.path(), None, false, |_| {}).unwrap();
+        assert_eq!(fs::read(target.path().join("old.bin")).unwrap(), b"old");
+        assert_eq!(fs::read(target.path().join("saves/slot1.sav")).unwrap(), b"newer save");
+
+        // Pattern added after applying: the deleted save still comes back
+        let target = game();
+        runner.apply(target.path(), None, false, |_| {}).unwrap();
+        assert!(!target.path().join("saves/slot1.sav").exists());
+        fs::write(target.path().join(IGNORE_FILENAME), "saves/\n").unwrap();
+        runner.rollback(target.path(), None, false, |_| {}).unwrap();
+        assert_eq!(fs::read(target.path().join("saves/slot1.sav")).unwrap(), b"save");
+    }
+
+    #[test]
+    fn apply_can_delete_backup_on_success() {
+        let runner = runner_for(&format!(
+            r#"{{"version": 1, "name": "TestPatcher", "entries": [
+                {{"operation": "delete", "file": "old.bin", "original_hash": "{}"}}
+            ]}}"#,
+            hash_bytes(b"old")
+        ));
+
+        let target = tempdir().unwrap();
+        fs::write(target.path().join("old.bin"), b"old").unwrap();
+        runner.apply(target.path(), None, true, |_| {}).unwrap();
+
+        assert!(!target.path().join("old.bin").exists());
+        assert!(!PatchRunner::has_backup(target.path(), None));
+    }
+
+    #[test]
+    fn apply_is_logged() {
+        let log_dir = tempdir().unwrap();
+        let log_path = log_dir.path().join(LOG_FILENAME);
+        let runner = runner_for(&format!(
+            r#"{{"version": 1, "name": "TestPatcher", "entries": [
+                {{"operation": "delete", "file": "old.bin", "original_hash": "{}"}}
+            ]}}"#,
+            hash_bytes(b"old")
+        ))
+        .with_log(&log_path);
+
+        let target = tempdir().unwrap();
+        fs::write(target.path().join("old.bin"), b"old").unwrap();
+        runner.apply(target.path(), None, false, |_| {}).unwrap();
+        fs::write(target.path().join("old.bin"), b"changed").unwrap();
+        fs::remove_dir_all(target.path().join(BACKUP_DIR)).unwrap();
+        runner.apply(target.path(), None, false, |_| {}).unwrap_err();
+
+        let lines: Vec<serde_json::Value> = fs::read_to_string(&log_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let events: Vec<&str> = lines.iter().map(|line| line["event"].as_str().unwrap()).collect();
+        assert_eq!(events[0], "started");
+        assert_eq!(lines[0]["patch"], "TestPatcher");
+        assert!(events.contains(&"operation"));
+        assert_eq!(lines.iter().filter(|line| line["event"] == "finished").count(), 2);
+
+        let last = lines.last().unwrap();
+        assert_eq!(last["ok"], false);
+        assert!(last["error"].as_str().unwrap().contains("old.bin"));
+        assert!(events.contains(&"error"));
+    }
+
+    #[test]
+    fn patch_with_hooks_is_refused_unless_applied_without_them() {
+        let manifest = format!(
+            r#"{{"version": 1, "name": "TestPatcher", "pre_commands": [["touch", "ran"]], "entries": [
+                {{"operation": "delete", "file": "old.bin", "original_hash": "{}"}}
+            ]}}"#,
+            hash_bytes(b"old")
+        );
+        let target = tempdir().unwrap();
+        fs::write(target.path().join("old.bin"), b"old").unwrap();
+
+        let err = runner_for(&manifest).apply(target.path(), None, false, |_| {}).unwrap_err();
+        assert!(matches!(err, PatchError::HooksNotAllowed));
+        assert!(target.path().join("old.bin").exists());
+
+        let mut warnings = Vec::new();
+        runner_for(&manifest)
+            .without_hooks()
+            .apply(target.path(), None, false, |event| {
+                if let ProgressEvent::Warning { message } = event {
+                    warnings.push(message);
+                }
+            })
+            .unwrap();
+        assert!(!target.path().join("old.bin").exists());
+        assert!(!target.path().join("ran").exists());
+        assert!(warnings.iter().any(|w| w.contains("touch ran")));
+    }
+
+    #[test]
+    fn apply_is_refused_while_target_is_locked() {
+        let runner = runner_for(&format!(
+            r#"{{"version": 1, "name": "TestPatcher", "entries": [
+                {{"operation": "delete", "file": "old.bin", "original_hash": "{}"}}
+            ]}}"#,
+            hash_bytes(b"old")
+        ));
+        let target = tempdir().unwrap();
+        fs::write(target.path().join("old.bin"), b"old").unwrap();
+
+        let lock = patch::PatchLock::acquire(target.path()).unwrap();
+        let err = runner.apply(target.path(), None, false, |_| {}).unwrap_err();
+        assert!(matches!(err, PatchError::AlreadyInProgress { .. }));
+        assert!(target.path().join("old.bin").exists());
+
+        drop(lock);
+        runner.apply(target.path(), None, false, |_| {}).unwrap();
+        assert!(!target.path().join("old.bin").exists());
+    }
+
+    #[test]
+    fn parallel_apply_adds_every_file() {
+        let patch_dir = tempdir().unwrap();
+        fs::create_dir_all(patch_dir.path().join(patch::FILES_DIR)).unwrap();
+        let mut entries = Vec::new();
+        for i in 0..50 {
+            let file = format!("clip_{:02}.ogg", i);
+            fs::write(patch_dir.path().join(patch::FILES_DIR).join(&file), &file).unwrap();
+            entries.push(format!(
+                r#"{{"operation": "add", "file": "{}", "final_hash": "{}"}}"#,
+                file,
+                hash_bytes(file.as_bytes())
+            ));
+        }
+        fs::write(
+            patch_dir.path().join(patch::MANIFEST_FILENAME),
+            with_manifest_hash(&format!(r#"{{"version": 1, "name": "TestPatcher", "entries": [{}]}}"#, entries.join(","))),
+        )
+        .unwrap();
+        let runner = PatchRunner::new(&create_archive_bytes(patch_dir.path()).unwrap())
+            .unwrap()
+            .with_jobs(4);
+
+        let target = tempdir().unwrap();
+        let mut operations = 0;
+        runner
+            .apply(target.path(), None, false, |event| {
+                if let ProgressEvent::Operation { action: ProgressAction::Adding, .. } = event {
+                    operations += 1;
+                }
+            })
+            .unwrap();
+
+        assert_eq!(operations, 50);
+        assert!(runner.is_patched(target.path()));
+    }
+
+    #[test]
+    fn streaming_apply_reads_files_from_archive() {
+        let patch_dir = tempdir().unwrap();
+        fs::create_dir_all(patch_dir.path().join(patch::FILES_DIR)).unwrap();
+        let mut entries = Vec::new();
+        for file in ["b.bin", "a.bin", "c.bin"] {
+            fs::write(patch_dir.path().join(patch::FILES_DIR).join(file), file).unwrap();
+            entries.push(format!(
+                r#"{{"operation": "add", "file": "{}", "final_hash": "{}"}}"#,
+                file,
+                hash_bytes(file.as_bytes())
+            ));
+        }
+        fs::write(
+            patch_dir.path().join(patch::MANIFEST_FILENAME),
+            with_manifest_hash(&format!(r#"{{"version": 1, "name": "TestPatcher", "entries": [{}]}}"#, entries.join(","))),
+        )
+        .unwrap();
+        let data = create_archive_bytes(patch_dir.path()).unwrap();
+
+        let temp_base = tempdir().unwrap();
+        let runner = PatchRunner::new_streaming(&data, Some(temp_base.path())).unwrap();
+        assert!(!runner.patch_dir.join(patch::FILES_DIR).exists());
+
+        let target = tempdir().unwrap();
+        runner.apply(target.path(), None, false, |_| {}).unwrap();
+        for file in ["a.bin", "b.bin", "c.bin"] {
+            assert_eq!(fs::read_to_string(target.path().join(file)).unwrap(), file);
+        }
+        assert!(runner.is_patched(target.path()));
+
+        // Each file was removed once applied, and the directory goes with
+        // the runner
+        assert_eq!(list_files_recursive(&runner.patch_dir).unwrap(), Vec::<String>::new());
+        drop(runner);
+        assert_eq!(fs::read_dir(temp_base.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn previews_text_files_from_extracted_or_streamed_patch() {
+        let patch_dir = tempdir().unwrap();
+        fs::create_dir_all(patch_dir.path().join(patch::FILES_DIR)).unwrap();
+        fs::write(patch_dir.path().join(patch::FILES_DIR).join("readme.txt"), "Hola\n").unwrap();
+        fs::write(patch_dir.path().join(patch::FILES_DIR).join("logo.png"), b"\x89PNG\0").unwrap();
+        fs::write(
+            patch_dir.path().join(patch::MANIFEST_FILENAME),
+            with_manifest_hash(&format!(
+                r#"{{"version": 1, "name": "TestPatcher", "entries": [
+                    {{"operation": "add", "file": "readme.txt", "final_hash": "{}"}},
+                    {{"operation": "add", "file": "logo.png", "final_hash": "{}"}}
+                ]}}"#,
+                hash_bytes(b"Hola\n"),
+                hash_bytes(b"\x89PNG\0")
+            )),
+        )
+        .unwrap();
+        let data = create_archive_bytes(patch_dir.path()).unwrap();
+        let target = tempdir().unwrap();
+
+        for runner in [PatchRunner::new(&data).unwrap(), PatchRunner::new_streaming(&data, None).unwrap()] {
+            let previews = runner.preview(target.path(), 100).unwrap();
+            assert_eq!(previews.len(), 1);
+            assert_eq!(previews[0].0, "readme.txt");
+            assert!(previews[0].1.contains("+Hola\n"), "{}", previews[0].1);
+        }
+    }
+
+    #[test]
+    fn forced_rollback_reports_files_changed_since_patching() {
+        let target = tempdir().unwrap();
+        fs::create_dir_all(target.path().join(BACKUP_DIR)).unwrap();
+        fs::write(target.path().join("new.bin"), b"edited by hand").unwrap();
+
+        let runner = runner_for(&format!(
+            r#"{{"version": 1, "name": "TestPatcher", "entries": [
+                {{"operation": "add", "file": "new.bin", "final_hash": "{}"}}
+            ]}}"#,
+            hash_bytes(b"new content")
+        ));
+
+        let mut overwritten = Vec::new();
+        runner
+            .rollback(target.path(), None, true, |event| {
+                if let RollbackEvent::OverwritingModified { files } = event {
+                    overwritten.extend(files);
+                }
+            })
+            .unwrap();
+
+        assert_eq!(overwritten.len(), 1);
+        assert!(overwritten[0].contains("new.bin"));
+        assert!(!target.path().join("new.bin").exists());
+    }
+
+    #[test]
+    fn extracts_archives_of_every_codec() {
+        let patch_dir = tempdir().unwrap();
+        fs::write(
+            patch_dir.path().join(patch::MANIFEST_FILENAME),
+            with_manifest_hash(r#"{"version": 3, "name": "TestPatcher", "entries": []}"#),
+        )
+        .unwrap();
+
+        for codec in [Codec::Gzip, Codec::Zstd, Codec::Store, Codec::Zip] {
+            let compression = ArchiveCompression::new(codec, None).unwrap();
+            let data = create_archive_bytes_compressed(patch_dir.path(), compression).unwrap();
+            let runner = PatchRunner::new(&data).unwrap();
+            assert_eq!(runner.manifest.version, 3, "{}", codec);
+        }
+    }
+}
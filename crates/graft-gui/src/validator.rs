@@ -1,8 +1,7 @@
-use flate2::read::GzDecoder;
+use graft_core::archive;
 use graft_core::patch;
+use graft_core::signing::{self, SignatureError, VerifyingKey};
 use graft_core::utils::manifest::Manifest;
-use std::io::Read;
-use tar::Archive;
 
 // Re-export PatchInfo for use by other modules in this crate
 pub use graft_core::utils::manifest::PatchInfo;
@@ -13,47 +12,73 @@ pub struct PatchValidator;
 impl PatchValidator {
     /// Validate patch data and extract info by reading only the manifest.
     /// Does NOT extract files to disk - just reads manifest from archive.
-    pub fn validate(data: &[u8]) -> Result<PatchInfo, PatchValidationError> {
-        let decoder = GzDecoder::new(data);
-        let mut archive = Archive::new(decoder);
-
-        let entries = archive.entries().map_err(|e| {
-            PatchValidationError::DecompressionFailed(format!("Failed to read archive: {}", e))
+    ///
+    /// The archive is first checked against its checksum (see
+    /// `verify_checksum`). With `expected_key` (see `expected_key`), the
+    /// archive must be signed by that key. Otherwise a signed archive is
+    /// checked against the key it carries, which only shows it wasn't
+    /// damaged, and the signer is reported in `signed_by` for the user to
+    /// compare.
+    ///
+    /// Both checks hash each file as it's decompressed, and only the
+    /// manifest is read whole, so validating doesn't hold the decompressed
+    /// patch in memory; `--no-extract` relies on that.
+    pub fn validate(data: &[u8], expected_key: Option<&VerifyingKey>) -> Result<PatchInfo, PatchValidationError> {
+        Self::verify_checksum(data)?;
+
+        let signed_by = signing::verify_archive(data, expected_key).map_err(|e| match e {
+            SignatureError::Archive(e) => {
+                PatchValidationError::DecompressionFailed(format!("Failed to read archive: {}", e))
+            }
+            e => PatchValidationError::SignatureInvalid(e.to_string()),
         })?;
 
-        for entry in entries {
-            let mut entry = entry.map_err(|e| {
-                PatchValidationError::DecompressionFailed(format!("Failed to read entry: {}", e))
-            })?;
-
-            let path = entry.path().map_err(|e| {
-                PatchValidationError::DecompressionFailed(format!("Failed to read path: {}", e))
-            })?;
-
-            if path.ends_with(patch::MANIFEST_FILENAME) {
-                let mut content = String::new();
-                entry.read_to_string(&mut content).map_err(|e| {
-                    PatchValidationError::ManifestInvalid(format!("Failed to read manifest: {}", e))
-                })?;
+        let content = archive::read_archive_file(data, patch::MANIFEST_FILENAME)
+            .map_err(|e| PatchValidationError::DecompressionFailed(format!("Failed to read archive: {}", e)))?
+            .ok_or(PatchValidationError::ManifestNotFound)?;
+        let content = String::from_utf8(content)
+            .map_err(|e| PatchValidationError::ManifestInvalid(format!("Failed to read manifest: {}", e)))?;
 
-                let manifest: Manifest = serde_json::from_str(&content).map_err(|e| {
-                    PatchValidationError::ManifestInvalid(format!("Invalid manifest JSON: {}", e))
-                })?;
+        let manifest = Manifest::parse(&content)
+            .map_err(|e| PatchValidationError::ManifestInvalid(format!("Invalid manifest: {}", e)))?;
 
-                return Ok(PatchInfo::from_manifest(&manifest));
-            }
-        }
+        let mut info = PatchInfo::from_manifest(&manifest);
+        info.signed_by = signed_by;
+        Ok(info)
+    }
 
-        Err(PatchValidationError::ManifestNotFound)
+    /// Check the archive against the checksum recorded when the patcher was
+    /// built, without reading the manifest.
+    ///
+    /// Fails with `Corrupt` if the contents don't match or the archive can't
+    /// be read to the end, as when the patcher was truncated during download.
+    /// Returns false for archives built without a checksum.
+    pub fn verify_checksum(data: &[u8]) -> Result<bool, PatchValidationError> {
+        archive::verify_checksum(data).map_err(|e| PatchValidationError::Corrupt(e.to_string()))
     }
 }
 
+/// The key patches must be signed with: `flag` (from `--expect-key`) if
+/// given, else the one the patcher was compiled with (`GRAFT_PUBLIC_KEY`, as
+/// hex), else none.
+pub fn expected_key(flag: Option<&str>) -> Result<Option<VerifyingKey>, SignatureError> {
+    flag.or(option_env!("GRAFT_PUBLIC_KEY"))
+        .map(signing::parse_public_key)
+        .transpose()
+}
+
 /// Errors from patch validation
 #[derive(Debug, Clone)]
 pub enum PatchValidationError {
     DecompressionFailed(String),
     ManifestNotFound,
     ManifestInvalid(String),
+    /// The patch is signed but the signature doesn't check out, so its
+    /// contents can't be trusted.
+    SignatureInvalid(String),
+    /// The patch data is damaged, e.g. the patcher was only partly
+    /// downloaded.
+    Corrupt(String),
 }
 
 impl std::fmt::Display for PatchValidationError {
@@ -64,6 +89,12 @@ impl std::fmt::Display for PatchValidationError {
             }
             PatchValidationError::ManifestNotFound => write!(f, "Manifest not found in archive"),
             PatchValidationError::ManifestInvalid(msg) => write!(f, "Invalid manifest: {}", msg),
+            PatchValidationError::SignatureInvalid(msg) => {
+                write!(f, "Signature verification failed: {}", msg)
+            }
+            PatchValidationError::Corrupt(msg) => {
+                write!(f, "The patch data is corrupt ({}); the patcher may not have downloaded completely, so download it again", msg)
+            }
         }
     }
 }
@@ -73,7 +104,11 @@ impl std::error::Error for PatchValidationError {}
 #[cfg(test)]
 mod tests {
     use super::*;
-    use graft_core::archive::create_archive_bytes;
+    use graft_core::archive::{
+        create_archive_bytes, create_archive_from_entries, read_archive_bytes, ArchiveCompression,
+    };
+    use graft_core::signing::SigningKey;
+    use graft_core::utils::manifest::with_manifest_hash;
     use std::fs;
     use tempfile::tempdir;
 
@@ -84,7 +119,7 @@ mod tests {
         // Create manifest with name and title
         fs::write(
             patch_dir.path().join("manifest.json"),
-            r#"{"version": 1, "name": "TestPatcher", "title": "My Test Title", "entries": []}"#,
+            with_manifest_hash(r#"{"version": 1, "name": "TestPatcher", "title": "My Test Title", "entries": []}"#),
         )
         .unwrap();
 
@@ -92,8 +127,128 @@ mod tests {
         let archive_data = create_archive_bytes(patch_dir.path()).unwrap();
 
         // Validate and check title
-        let info = PatchValidator::validate(&archive_data).unwrap();
+        let info = PatchValidator::validate(&archive_data, None).unwrap();
         assert_eq!(info.name, "TestPatcher");
         assert_eq!(info.title, Some("My Test Title".to_string()));
     }
+
+    #[test]
+    fn extracts_metadata_from_archive() {
+        let patch_dir = tempdir().unwrap();
+        fs::write(
+            patch_dir.path().join("manifest.json"),
+            with_manifest_hash(r#"{"version": 1, "name": "TestPatcher", "description": "Spanish translation", "author": "Fans", "target_game_version": "1.4", "entries": []}"#),
+        )
+        .unwrap();
+
+        let archive_data = create_archive_bytes(patch_dir.path()).unwrap();
+
+        let info = PatchValidator::validate(&archive_data, None).unwrap();
+        assert_eq!(info.description.as_deref(), Some("Spanish translation"));
+        assert_eq!(info.author.as_deref(), Some("Fans"));
+        assert_eq!(info.target_game_version.as_deref(), Some("1.4"));
+    }
+
+    fn signed_archive() -> (Vec<u8>, SigningKey) {
+        let patch_dir = tempdir().unwrap();
+        fs::write(
+            patch_dir.path().join("manifest.json"),
+            with_manifest_hash(r#"{"version": 1, "name": "TestPatcher", "entries": []}"#),
+        )
+        .unwrap();
+        fs::write(patch_dir.path().join("notes.txt"), "original").unwrap();
+
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let archive_data = create_archive_bytes(patch_dir.path()).unwrap();
+        let signed = signing::sign_archive(&archive_data, &key, ArchiveCompression::default()).unwrap();
+        (signed, key)
+    }
+
+    #[test]
+    fn reports_signer_of_signed_archive() {
+        let (archive_data, key) = signed_archive();
+
+        let info = PatchValidator::validate(&archive_data, None).unwrap();
+        assert_eq!(info.signed_by, Some(signing::public_key_hex(&key)));
+    }
+
+    #[test]
+    fn expected_key_rejects_other_signers_and_unsigned_archives() {
+        let (archive_data, key) = signed_archive();
+        let expected = expected_key(Some(&signing::public_key_hex(&key))).unwrap().unwrap();
+        assert!(PatchValidator::validate(&archive_data, Some(&expected)).is_ok());
+
+        let other = SigningKey::from_bytes(&[8; 32]);
+        let resigned = signing::sign_archive(&archive_data, &other, ArchiveCompression::default()).unwrap();
+        assert!(matches!(
+            PatchValidator::validate(&resigned, Some(&expected)),
+            Err(PatchValidationError::SignatureInvalid(_))
+        ));
+
+        let mut contents = read_archive_bytes(&archive_data).unwrap();
+        contents.remove(patch::SIGNATURE_FILENAME);
+        let stripped = create_archive_from_entries(&contents, ArchiveCompression::default()).unwrap();
+        assert!(PatchValidator::validate(&stripped, None).is_ok());
+        assert!(matches!(
+            PatchValidator::validate(&stripped, Some(&expected)),
+            Err(PatchValidationError::SignatureInvalid(_))
+        ));
+    }
+
+    #[test]
+    fn unsigned_archive_has_no_signer() {
+        let patch_dir = tempdir().unwrap();
+        fs::write(
+            patch_dir.path().join("manifest.json"),
+            with_manifest_hash(r#"{"version": 1, "name": "TestPatcher", "entries": []}"#),
+        )
+        .unwrap();
+
+        let archive_data = create_archive_bytes(patch_dir.path()).unwrap();
+
+        let info = PatchValidator::validate(&archive_data, None).unwrap();
+        assert_eq!(info.signed_by, None);
+    }
+
+    #[test]
+    fn rejects_tampered_signed_archive() {
+        let (archive_data, _) = signed_archive();
+        let mut contents = read_archive_bytes(&archive_data).unwrap();
+        contents.insert("notes.txt".to_string(), b"tampered".to_vec());
+        let tampered = create_archive_from_entries(&contents, ArchiveCompression::default()).unwrap();
+
+        assert!(matches!(
+            PatchValidator::validate(&tampered, None),
+            Err(PatchValidationError::SignatureInvalid(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_or_corrupt_archive() {
+        let patch_dir = tempdir().unwrap();
+        fs::write(
+            patch_dir.path().join("manifest.json"),
+            with_manifest_hash(r#"{"version": 1, "name": "TestPatcher", "entries": []}"#),
+        )
+        .unwrap();
+        let compression = ArchiveCompression::default();
+        let archive_data = create_archive_bytes(patch_dir.path()).unwrap();
+        let archive_data = archive::add_checksum(&archive_data, compression).unwrap();
+        assert!(PatchValidator::verify_checksum(&archive_data).unwrap());
+        assert!(PatchValidator::validate(&archive_data, None).is_ok());
+
+        let truncated = &archive_data[..archive_data.len() / 2];
+        assert!(matches!(
+            PatchValidator::validate(truncated, None),
+            Err(PatchValidationError::Corrupt(_))
+        ));
+
+        let mut contents = read_archive_bytes(&archive_data).unwrap();
+        contents.insert("notes.txt".to_string(), b"extra".to_vec());
+        let tampered = create_archive_from_entries(&contents, compression).unwrap();
+        assert!(matches!(
+            PatchValidator::validate(&tampered, None),
+            Err(PatchValidationError::Corrupt(_))
+        ));
+    }
 }
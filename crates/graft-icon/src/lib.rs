@@ -0,0 +1,86 @@
+//! Icon conversion shared by the `graft-icon` binary and the graft-gui
+//! build script, which embeds the default icon in the Windows patcher.
+
+use std::fmt;
+use std::io;
+
+/// ICO sizes used when none are requested.
+pub const DEFAULT_ICO_SIZES: [u32; 6] = [256, 128, 64, 48, 32, 16];
+
+/// Error type for icon conversion.
+#[derive(Debug)]
+pub enum IconError {
+    /// The output file couldn't be created or written
+    Io { context: &'static str, source: io::Error },
+    /// The input image couldn't be read or decoded
+    PngDecode(image::ImageError),
+    /// An icon at the given size couldn't be encoded
+    Encode { size: u32, source: io::Error },
+    /// An icon size is larger than the (square) input image and upscaling
+    /// wasn't allowed
+    UnsupportedSize { size: u32, side: u32 },
+}
+
+impl fmt::Display for IconError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IconError::Io { context, source } => write!(f, "{}: {}", context, source),
+            IconError::PngDecode(e) => write!(f, "Failed to load image: {}", e),
+            IconError::Encode { size, source } => {
+                write!(f, "Failed to encode icon at size {}: {}", size, source)
+            }
+            IconError::UnsupportedSize { size, side } => write!(
+                f,
+                "Icon size {} is larger than the input image ({}x{}); use --upscale to allow it",
+                size, side, side
+            ),
+        }
+    }
+}
+
+impl std::error::Error for IconError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IconError::Io { source, .. } | IconError::Encode { source, .. } => Some(source),
+            IconError::PngDecode(e) => Some(e),
+            IconError::UnsupportedSize { .. } => None,
+        }
+    }
+}
+
+/// Build an ICO holding `img` resized to each of `sizes`, in that order.
+///
+/// `img` should be square; it is stretched to each size as is.
+pub fn png_to_ico(img: &image::DynamicImage, sizes: &[u32]) -> Result<ico::IconDir, IconError> {
+    let mut icon_dir = ico::IconDir::new(ico::ResourceType::Icon);
+
+    // Create icons at multiple sizes for best display
+    for &size in sizes {
+        let resized = img.resize_exact(size, size, image::imageops::FilterType::Lanczos3);
+        let rgba = resized.to_rgba8();
+        let icon_image = ico::IconImage::from_rgba_data(size, size, rgba.into_raw());
+        icon_dir.add_entry(
+            ico::IconDirEntry::encode(&icon_image)
+                .map_err(|source| IconError::Encode { size, source })?
+        );
+    }
+
+    Ok(icon_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ico_has_requested_sizes_in_order() {
+        let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            64,
+            64,
+            image::Rgba([0, 255, 0, 255]),
+        ));
+        let icon_dir = png_to_ico(&img, &DEFAULT_ICO_SIZES).unwrap();
+        let sizes: Vec<_> = icon_dir.entries().iter().map(|e| e.width()).collect();
+        assert_eq!(sizes, DEFAULT_ICO_SIZES);
+    }
+}
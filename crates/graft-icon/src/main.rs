@@ -1,18 +1,20 @@
 //! Icon conversion utility for graft.
 //!
-//! Converts PNG icons to platform-specific formats:
+//! Converts images (PNG, WebP, BMP, or anything else the `image` crate
+//! reads) to platform-specific icon formats:
 //! - ICNS for macOS
 //! - ICO for Windows
 
 use clap::{Parser, Subcommand};
+use graft_core::exit_code::ExitCode;
+use graft_icon::{png_to_ico, IconError, DEFAULT_ICO_SIZES};
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::BufWriter;
 use std::path::PathBuf;
-use std::process;
 
 #[derive(Parser)]
 #[command(name = "graft-icon")]
-#[command(about = "Convert PNG icons to platform-specific formats")]
+#[command(about = "Convert images to platform-specific icon formats")]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
@@ -20,19 +22,29 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Convert PNG to macOS ICNS format
+    /// Convert an image to macOS ICNS format
     Icns {
-        /// Input PNG file
+        /// Input image (PNG, WebP, BMP, ...); non-square images are center-cropped
         input: PathBuf,
         /// Output ICNS file
         output: PathBuf,
+        /// Also fill slots larger than the input image, by upscaling it
+        #[arg(long)]
+        upscale: bool,
     },
-    /// Convert PNG to Windows ICO format
+    /// Convert an image to Windows ICO format
     Ico {
-        /// Input PNG file
+        /// Input image (PNG, WebP, BMP, ...); non-square images are center-cropped
         input: PathBuf,
         /// Output ICO file
         output: PathBuf,
+        /// Sizes to include, comma-separated (e.g. 32,16); each 1-256.
+        /// Defaults to 256,128,64,48,32,16
+        #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u32).range(1..=256))]
+        sizes: Option<Vec<u32>>,
+        /// Allow --sizes larger than the input image
+        #[arg(long)]
+        upscale: bool,
     },
 }
 
@@ -40,61 +52,228 @@ fn main() {
     let cli = Cli::parse();
 
     let result = match cli.command {
-        Commands::Icns { input, output } => convert_to_icns(&input, &output),
-        Commands::Ico { input, output } => convert_to_ico(&input, &output),
+        Commands::Icns {
+            input,
+            output,
+            upscale,
+        } => convert_to_icns(&input, &output, upscale),
+        Commands::Ico {
+            input,
+            output,
+            sizes,
+            upscale,
+        } => convert_to_ico(&input, &output, sizes.as_deref(), upscale),
     };
 
     if let Err(e) = result {
         eprintln!("Error: {}", e);
-        process::exit(1);
+        exit_code(&e).exit();
     }
 }
 
-/// Convert PNG to macOS ICNS format.
-fn convert_to_icns(input: &PathBuf, output: &PathBuf) -> Result<(), String> {
-    let file = File::open(input)
-        .map_err(|e| format!("Failed to open input file: {}", e))?;
-    let reader = BufReader::new(file);
+/// The exit code for a failed conversion.
+fn exit_code(error: &IconError) -> ExitCode {
+    match error {
+        IconError::Io { source, .. } => ExitCode::from(source),
+        IconError::PngDecode(image::ImageError::IoError(source)) => ExitCode::from(source),
+        IconError::PngDecode(_) | IconError::UnsupportedSize { .. } => ExitCode::Validation,
+        IconError::Encode { .. } => ExitCode::Failure,
+    }
+}
+
+/// ICNS slots to fill: 16, 32, 128, 256 and 512 points at 1x and 2x.
+const ICNS_TYPES: [icns::IconType; 10] = [
+    icns::IconType::RGBA32_16x16,
+    icns::IconType::RGBA32_16x16_2x,
+    icns::IconType::RGBA32_32x32,
+    icns::IconType::RGBA32_32x32_2x,
+    icns::IconType::RGBA32_128x128,
+    icns::IconType::RGBA32_128x128_2x,
+    icns::IconType::RGBA32_256x256,
+    icns::IconType::RGBA32_256x256_2x,
+    icns::IconType::RGBA32_512x512,
+    icns::IconType::RGBA32_512x512_2x,
+];
 
-    let image = icns::Image::read_png(reader)
-        .map_err(|e| format!("Failed to read PNG: {}", e))?;
+/// Convert an image to macOS ICNS format with multiple resolutions.
+///
+/// Slots larger than the input are skipped unless `upscale` is set.
+fn convert_to_icns(input: &PathBuf, output: &PathBuf, upscale: bool) -> Result<(), IconError> {
+    let img = load_square(input)?;
 
     let mut icon_family = icns::IconFamily::new();
-    icon_family.add_icon(&image)
-        .map_err(|e| format!("Failed to add icon: {}", e))?;
+
+    for icon_type in ICNS_TYPES {
+        let size = icon_type.pixel_width();
+        if size > img.width() && !upscale {
+            continue;
+        }
+        let resized = img.resize_exact(size, size, image::imageops::FilterType::Lanczos3);
+        let image = icns::Image::from_data(icns::PixelFormat::RGBA, size, size, resized.to_rgba8().into_raw())
+            .map_err(|source| IconError::Encode { size, source })?;
+        icon_family.add_icon_with_type(&image, icon_type)
+            .map_err(|source| IconError::Encode { size, source })?;
+    }
+
+    if icon_family.is_empty() {
+        return Err(IconError::UnsupportedSize {
+            size: ICNS_TYPES[0].pixel_width(),
+            side: img.width(),
+        });
+    }
 
     let output_file = File::create(output)
-        .map_err(|e| format!("Failed to create output file: {}", e))?;
-    icon_family.write(output_file)
-        .map_err(|e| format!("Failed to write ICNS: {}", e))?;
+        .map_err(|source| IconError::Io { context: "Failed to create output file", source })?;
+    icon_family.write(BufWriter::new(output_file))
+        .map_err(|source| IconError::Io { context: "Failed to write ICNS", source })?;
 
     println!("Created {}", output.display());
     Ok(())
 }
 
-/// Convert PNG to Windows ICO format with multiple sizes.
-fn convert_to_ico(input: &PathBuf, output: &PathBuf) -> Result<(), String> {
-    let img = image::open(input)
-        .map_err(|e| format!("Failed to load PNG: {}", e))?;
+/// Convert an image to Windows ICO format with multiple sizes.
+///
+/// `sizes` overrides the default set. Requested sizes larger than the input
+/// are rejected unless `upscale` is set.
+fn convert_to_ico(
+    input: &PathBuf,
+    output: &PathBuf,
+    sizes: Option<&[u32]>,
+    upscale: bool,
+) -> Result<(), IconError> {
+    let img = load_square(input)?;
 
-    let mut icon_dir = ico::IconDir::new(ico::ResourceType::Icon);
-
-    // Create icons at multiple sizes for best display
-    for size in [256, 128, 64, 48, 32, 16] {
-        let resized = img.resize_exact(size, size, image::imageops::FilterType::Lanczos3);
-        let rgba = resized.to_rgba8();
-        let icon_image = ico::IconImage::from_rgba_data(size, size, rgba.into_raw());
-        icon_dir.add_entry(
-            ico::IconDirEntry::encode(&icon_image)
-                .map_err(|e| format!("Failed to encode icon at size {}: {}", size, e))?
-        );
+    if let Some(sizes) = sizes
+        && !upscale
+    {
+        check_sizes_fit(sizes, img.width())?;
     }
 
+    let icon_dir = png_to_ico(&img, sizes.unwrap_or(&DEFAULT_ICO_SIZES))?;
+
     let file = File::create(output)
-        .map_err(|e| format!("Failed to create output file: {}", e))?;
+        .map_err(|source| IconError::Io { context: "Failed to create output file", source })?;
     icon_dir.write(BufWriter::new(file))
-        .map_err(|e| format!("Failed to write ICO: {}", e))?;
+        .map_err(|source| IconError::Io { context: "Failed to write ICO", source })?;
 
     println!("Created {}", output.display());
     Ok(())
 }
+
+/// Load an image in any supported format, center-cropping it to a square
+/// so icons aren't stretched.
+fn load_square(input: &PathBuf) -> Result<image::DynamicImage, IconError> {
+    let img = image::open(input).map_err(IconError::PngDecode)?;
+    let side = img.width().min(img.height());
+    if img.width() == img.height() {
+        return Ok(img);
+    }
+    println!(
+        "Input is {}x{}; cropping to the centered {}x{} square",
+        img.width(),
+        img.height(),
+        side,
+        side
+    );
+    Ok(img.crop_imm((img.width() - side) / 2, (img.height() - side) / 2, side, side))
+}
+
+/// Reject sizes that would need the (square) input image to be scaled up.
+fn check_sizes_fit(sizes: &[u32], side: u32) -> Result<(), IconError> {
+    match sizes.iter().find(|&&size| size > side) {
+        Some(&size) => Err(IconError::UnsupportedSize { size, side }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_larger_than_source_are_rejected() {
+        assert!(check_sizes_fit(&[64, 32, 16], 64).is_ok());
+        assert!(check_sizes_fit(&[64], 48).is_err());
+        assert!(check_sizes_fit(&[128, 16], 64).is_err());
+    }
+
+    #[test]
+    fn errors_are_typed() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("tiny.png");
+        let output = dir.path().join("icon.icns");
+
+        let missing = convert_to_icns(&input, &output, false).unwrap_err();
+        assert!(matches!(missing, IconError::PngDecode(_)));
+        assert_eq!(exit_code(&missing), ExitCode::Io);
+
+        image::RgbaImage::from_pixel(8, 8, image::Rgba([255, 0, 0, 255])).save(&input).unwrap();
+        let too_small = convert_to_icns(&input, &output, false).unwrap_err();
+        assert!(matches!(too_small, IconError::UnsupportedSize { size: 16, side: 8 }));
+        assert_eq!(exit_code(&too_small), ExitCode::Validation);
+        assert_eq!(
+            too_small.to_string(),
+            "Icon size 16 is larger than the input image (8x8); use --upscale to allow it"
+        );
+
+        let unwritable = dir.path().join("missing").join("icon.ico");
+        let err = convert_to_ico(&input, &unwritable, Some(&[8]), false).unwrap_err();
+        assert!(matches!(err, IconError::Io { .. }));
+    }
+
+    #[test]
+    fn sizes_flag_is_parsed_and_range_checked() {
+        let cli = Cli::try_parse_from(["graft-icon", "ico", "in.png", "out.ico", "--sizes", "32,16"]).unwrap();
+        let Commands::Ico { sizes, .. } = cli.command else {
+            panic!("expected ico command");
+        };
+        assert_eq!(sizes, Some(vec![32, 16]));
+
+        for bad in ["0", "257", "32,abc"] {
+            assert!(Cli::try_parse_from(["graft-icon", "ico", "in.png", "out.ico", "--sizes", bad]).is_err());
+        }
+    }
+
+    #[test]
+    fn icns_skips_slots_larger_than_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("icon.png");
+        let output = dir.path().join("icon.icns");
+        image::RgbaImage::from_pixel(64, 64, image::Rgba([255, 0, 0, 255])).save(&input).unwrap();
+
+        convert_to_icns(&input, &output, false).unwrap();
+        let family = icns::IconFamily::read(File::open(&output).unwrap()).unwrap();
+        let mut sizes: Vec<_> = family.available_icons().iter().map(|t| t.pixel_width()).collect();
+        sizes.sort();
+        assert_eq!(sizes, [16, 32, 32, 64]);
+
+        convert_to_icns(&input, &output, true).unwrap();
+        let family = icns::IconFamily::read(File::open(&output).unwrap()).unwrap();
+        assert_eq!(family.available_icons().len(), ICNS_TYPES.len());
+    }
+
+    #[test]
+    fn non_square_and_non_png_inputs_are_cropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("art.bmp");
+        // Red 20 px bands left and right of a blue 40x40 center
+        let art = image::RgbaImage::from_fn(80, 40, |x, _| {
+            if (20..60).contains(&x) {
+                image::Rgba([0, 0, 255, 255])
+            } else {
+                image::Rgba([255, 0, 0, 255])
+            }
+        });
+        art.save(&input).unwrap();
+
+        let square = load_square(&input).unwrap();
+        assert_eq!((square.width(), square.height()), (40, 40));
+        assert!(square.to_rgba8().pixels().all(|p| *p == image::Rgba([0, 0, 255, 255])));
+
+        let output = dir.path().join("icon.ico");
+        convert_to_ico(&input, &output, Some(&[32, 16]), false).unwrap();
+        let icon_dir = ico::IconDir::read(File::open(&output).unwrap()).unwrap();
+        let sizes: Vec<_> = icon_dir.entries().iter().map(|e| e.width()).collect();
+        assert_eq!(sizes, [32, 16]);
+    }
+}
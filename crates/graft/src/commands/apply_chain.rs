@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use graft_core::patch::{install_dir, resolve_by_content, PatchError, IGNORE_FILENAME, MANIFEST_FILENAME};
+use graft_core::utils::hash::Algorithm;
+use graft_core::utils::ignore::IgnoreList;
+use graft_core::utils::manifest::{Manifest, ManifestEntry};
+
+use crate::commands::{patch_apply, patch_rollback};
+
+/// What a file holds at some point in the chain, as far as the manifests
+/// tell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FileState {
+    Absent,
+    /// Content with this hash, in the given algorithm
+    Hash(Algorithm, String),
+    /// Present, but the patch that wrote it accepts several results
+    Unknown,
+}
+
+impl FileState {
+    /// Whether a file in this state matches `hash` (in `algorithm`).
+    /// Hashes in another algorithm can't be compared and are accepted;
+    /// validation right before that patch is applied still checks them.
+    fn has_hash(&self, algorithm: Algorithm, hash: &str) -> bool {
+        match self {
+            FileState::Absent => false,
+            FileState::Hash(a, h) => *a != algorithm || h == hash,
+            FileState::Unknown => true,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            FileState::Absent => "it won't exist".to_string(),
+            FileState::Hash(_, hash) => format!("it will have hash {}", hash),
+            FileState::Unknown => "it will exist".to_string(),
+        }
+    }
+}
+
+/// Expected file states as the chain is walked, for files an earlier patch
+/// has changed; files not in `files` are as they are on disk.
+#[derive(Default)]
+struct ChainState {
+    files: HashMap<PathBuf, FileState>,
+}
+
+impl ChainState {
+    fn get(&self, path: &Path, algorithm: Algorithm) -> Result<FileState, String> {
+        if let Some(state) = self.files.get(path) {
+            return Ok(state.clone());
+        }
+        if !path.exists() {
+            return Ok(FileState::Absent);
+        }
+        algorithm
+            .hash_file(path)
+            .map(|hash| FileState::Hash(algorithm, hash))
+            .map_err(|e| format!("failed to read file: {}", e))
+    }
+
+    /// Check `entry` (with paths under `dir`) against the state the chain
+    /// leaves it in so far, then record what applying it changes.
+    fn step(&mut self, entry: &ManifestEntry, dir: &Path, algorithm: Algorithm) -> Result<(), String> {
+        match entry {
+            ManifestEntry::Patch {
+                file,
+                original_hash,
+                final_hash,
+                final_hashes,
+                ..
+            } => {
+                let path = dir.join(file);
+                let state = self.get(&path, algorithm)?;
+                if !state.has_hash(algorithm, original_hash) {
+                    return Err(format!("expects hash {}, but {}", original_hash, state.describe()));
+                }
+                let patched = if final_hashes.is_empty() {
+                    FileState::Hash(algorithm, final_hash.clone())
+                } else {
+                    FileState::Unknown
+                };
+                self.files.insert(path, patched);
+            }
+            ManifestEntry::Add {
+                file,
+                final_hash,
+                original_hash,
+                ..
+            } => {
+                let path = dir.join(file);
+                let state = self.get(&path, algorithm)?;
+                match original_hash {
+                    _ if state == FileState::Absent => {}
+                    Some(original_hash) if state.has_hash(algorithm, original_hash) => {}
+                    Some(original_hash) => {
+                        return Err(format!("replaces hash {}, but {}", original_hash, state.describe()));
+                    }
+                    None => return Err("adds the file, but it will already exist".to_string()),
+                }
+                self.files.insert(path, FileState::Hash(algorithm, final_hash.clone()));
+            }
+            ManifestEntry::Delete { file, original_hash } => {
+                let path = dir.join(file);
+                let state = self.get(&path, algorithm)?;
+                if state != FileState::Absent && !state.has_hash(algorithm, original_hash) {
+                    return Err(format!("deletes hash {}, but {}", original_hash, state.describe()));
+                }
+                self.files.insert(path, FileState::Absent);
+            }
+            ManifestEntry::Rename { from, to, hash } => {
+                let from = dir.join(from);
+                let to = dir.join(to);
+                let state = self.get(&from, algorithm)?;
+                if !state.has_hash(algorithm, hash) {
+                    return Err(format!("renames a file with hash {}, but {}", hash, state.describe()));
+                }
+                if self.get(&to, algorithm)? != FileState::Absent {
+                    return Err("renames onto a file that will already exist".to_string());
+                }
+                self.files.insert(from, FileState::Absent);
+                self.files.insert(to, state);
+            }
+            ManifestEntry::Symlink { .. } | ManifestEntry::MkDir { .. } | ManifestEntry::RmDir { .. } => {
+                // Checked against the target when their patch is applied
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that each patch in a chain expects the files as the patches
+/// before it leave them, before anything is changed.
+///
+/// The first patch is checked against `target_dir` as it is; each later
+/// one against the hashes the earlier ones produce. Symlink and directory
+/// entries are only checked when their patch is applied.
+pub fn validate_chain(target_dir: &Path, patches: &[(PathBuf, Manifest)]) -> Result<(), PatchError> {
+    let mut state = ChainState::default();
+    for (index, (patch_dir, manifest)) in patches.iter().enumerate() {
+        let mut manifest = manifest.clone();
+        let dir = install_dir(&manifest, target_dir)?;
+        let ignore = IgnoreList::load(&dir).map_err(|e| PatchError::ManifestError {
+            reason: format!("failed to read {}: {}", IGNORE_FILENAME, e),
+        })?;
+        ignore.filter_entries(&mut manifest.entries);
+        if index == 0 {
+            // Renamed files can only be located on disk before the chain starts
+            resolve_by_content(&mut manifest, &dir)?;
+        }
+        for entry in &manifest.entries {
+            state
+                .step(entry, &dir, manifest.hash_algorithm)
+                .map_err(|reason| PatchError::ValidationFailed {
+                    file: entry.file().to_string(),
+                    reason: format!("patch {} ({}) {}", index + 1, patch_dir.display(), reason),
+                })?;
+        }
+    }
+    Ok(())
+}
+
+/// The backup directory for the patch at `index` in a chain, inside
+/// `backup_root`.
+pub fn chain_backup_dir(backup_root: &Path, index: usize) -> PathBuf {
+    backup_root.join(format!("chain-{}", index + 1))
+}
+
+/// Apply several patches to a target in order, e.g. a release and the
+/// updates that follow it.
+///
+/// Workflow:
+/// 1. Load every manifest and check the whole chain up front (see
+///    `validate_chain`), so a patch listed out of order fails before
+///    anything is written
+/// 2. Apply each patch as `patch_apply::run` does, with `options`, backing
+///    it up to its own directory (see `chain_backup_dir`) inside
+///    `options.backup_dir` or `BACKUP_DIR` in the target
+/// 3. If a patch fails, it is rolled back by apply, then the patches before
+///    it are rolled back newest first and the backups removed, leaving the
+///    target as it was
+///
+/// Refuses a target that already has a backup. With `dry_run`, stops after
+/// step 1.
+pub fn run(target_dir: &Path, patch_dirs: &[PathBuf], options: &patch_apply::ApplyOptions) -> Result<(), PatchError> {
+    let mut patches = Vec::new();
+    for patch_dir in patch_dirs {
+        let manifest = Manifest::load(&patch_dir.join(MANIFEST_FILENAME)).map_err(|e| PatchError::ManifestError {
+            reason: format!("{}: {}", patch_dir.display(), e),
+        })?;
+        patches.push((patch_dir.clone(), manifest));
+    }
+
+    validate_chain(target_dir, &patches)?;
+    println!("Chain of {} patches validated", patches.len());
+    if options.dry_run {
+        println!("Dry run: nothing was written");
+        return Ok(());
+    }
+
+    let backup_root = patch_apply::backup_dir_for(target_dir, options.backup_dir);
+    if backup_root.exists() {
+        return Err(PatchError::ValidationFailed {
+            file: backup_root.display().to_string(),
+            reason: "a backup from an earlier apply exists; roll it back or remove it first".to_string(),
+        });
+    }
+
+    for (index, (patch_dir, manifest)) in patches.iter().enumerate() {
+        println!();
+        println!("Applying patch {}/{}: {}", index + 1, patches.len(), manifest.title.as_deref().unwrap_or(&manifest.name));
+        let backup_dir = chain_backup_dir(&backup_root, index);
+        let patch_options = patch_apply::ApplyOptions {
+            backup_dir: Some(&backup_dir),
+            delete_backup: false,
+            ..*options
+        };
+        if let Err(e) = patch_apply::run(target_dir, patch_dir, &patch_options) {
+            unwind(target_dir, &patches[..index], &backup_root).map_err(|rollback_error| {
+                PatchError::RollbackFailed {
+                    reason: format!("{} (while undoing the chain after: {})", rollback_error, e),
+                }
+            })?;
+            return Err(e);
+        }
+    }
+
+    if options.delete_backup {
+        match fs::remove_dir_all(&backup_root) {
+            Ok(()) => println!("Removed backup {}", backup_root.display()),
+            Err(e) => eprintln!("Warning: failed to remove backup {}: {}", backup_root.display(), e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Roll back the already applied `patches`, newest first, then remove the
+/// chain's backups.
+fn unwind(target_dir: &Path, patches: &[(PathBuf, Manifest)], backup_root: &Path) -> Result<(), PatchError> {
+    for (index, (patch_dir, _)) in patches.iter().enumerate().rev() {
+        println!("Rolling back patch {}: {}", index + 1, patch_dir.display());
+        let backup_dir = chain_backup_dir(backup_root, index);
+        patch_rollback::run(target_dir, &patch_dir.join(MANIFEST_FILENAME), false, Some(&backup_dir))?;
+    }
+    if let Err(e) = fs::remove_dir_all(backup_root) {
+        eprintln!("Warning: failed to remove backup {}: {}", backup_root.display(), e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::patch_create;
+    use graft_core::patch::{Progress, BACKUP_DIR, DIFFS_DIR, DIFF_EXTENSION};
+    use tempfile::{tempdir, TempDir};
+
+    const OPTIONS: patch_create::CreateOptions<'static> =
+        patch_create::CreateOptions { allow_restricted: true, ..patch_create::CreateOptions::new(1, "TestPatcher") };
+
+    /// A patch from each of `versions` of `strings.txt` to the next, and
+    /// the temp directories holding them.
+    fn chain(versions: &[&str]) -> (Vec<TempDir>, Vec<PathBuf>) {
+        let mut dirs = Vec::new();
+        for version in versions {
+            let dir = tempdir().unwrap();
+            fs::write(dir.path().join("strings.txt"), version).unwrap();
+            dirs.push(dir);
+        }
+        let mut patches = Vec::new();
+        for pair in dirs.windows(2) {
+            let patch_dir = tempdir().unwrap();
+            patch_create::run(pair[0].path(), pair[1].path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+            patches.push(patch_dir);
+        }
+        let paths = patches.iter().map(|dir| dir.path().to_path_buf()).collect();
+        dirs.extend(patches);
+        (dirs, paths)
+    }
+
+    #[test]
+    fn applies_patches_in_order() {
+        let (_dirs, patches) = chain(&["v0 text", "v1 text", "v2 text, longer"]);
+        let target = tempdir().unwrap();
+        fs::write(target.path().join("strings.txt"), "v0 text").unwrap();
+
+        run(target.path(), &patches, &patch_apply::ApplyOptions::default()).unwrap();
+
+        assert_eq!(fs::read_to_string(target.path().join("strings.txt")).unwrap(), "v2 text, longer");
+        let backup_root = target.path().join(BACKUP_DIR);
+        assert_eq!(fs::read_to_string(chain_backup_dir(&backup_root, 0).join("strings.txt")).unwrap(), "v0 text");
+        assert_eq!(fs::read_to_string(chain_backup_dir(&backup_root, 1).join("strings.txt")).unwrap(), "v1 text");
+    }
+
+    #[test]
+    fn out_of_order_chain_fails_before_writing() {
+        let (_dirs, patches) = chain(&["v0 text", "v1 text", "v2 text"]);
+        let target = tempdir().unwrap();
+        fs::write(target.path().join("strings.txt"), "v0 text").unwrap();
+
+        let reversed = [patches[1].clone(), patches[0].clone()];
+        let err = run(target.path(), &reversed, &patch_apply::ApplyOptions::default()).unwrap_err();
+        assert!(matches!(err, PatchError::ValidationFailed { .. }));
+        assert!(err.to_string().contains("patch 1"), "{}", err);
+
+        // The second patch of a chain is checked against what the first
+        // leaves, not against the target
+        let repeated = [patches[0].clone(), patches[0].clone()];
+        let err = run(target.path(), &repeated, &patch_apply::ApplyOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("patch 2"), "{}", err);
+
+        assert_eq!(fs::read_to_string(target.path().join("strings.txt")).unwrap(), "v0 text");
+        assert!(!target.path().join(BACKUP_DIR).exists());
+    }
+
+    #[test]
+    fn failed_patch_unwinds_the_whole_chain() {
+        let (_dirs, patches) = chain(&["v0 text", "v1 text", "v2 text"]);
+        let target = tempdir().unwrap();
+        fs::write(target.path().join("strings.txt"), "v0 text").unwrap();
+
+        // Passes validation (the manifest still matches) but produces the
+        // wrong file
+        let manifest_path = patches[1].join(MANIFEST_FILENAME);
+        let mut manifest = Manifest::load(&manifest_path).unwrap();
+        let garbage = graft_core::utils::diff::create_diff(b"v1 text", b"not v2").unwrap();
+        fs::write(patches[1].join(DIFFS_DIR).join(format!("strings.txt{}", DIFF_EXTENSION)), &garbage).unwrap();
+        if let ManifestEntry::Patch { diff_hash, .. } = &mut manifest.entries[0] {
+            *diff_hash = manifest.hash_algorithm.hash_bytes(&garbage);
+        }
+        manifest.save(&manifest_path).unwrap();
+
+        let err = run(target.path(), &patches, &patch_apply::ApplyOptions::default()).unwrap_err();
+        assert!(matches!(err, PatchError::VerificationFailed { .. }), "{}", err);
+        assert_eq!(fs::read_to_string(target.path().join("strings.txt")).unwrap(), "v0 text");
+        assert!(!target.path().join(BACKUP_DIR).exists());
+    }
+}
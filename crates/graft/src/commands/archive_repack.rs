@@ -0,0 +1,291 @@
+//! Repack an existing patch archive into a different container.
+//!
+//! The archive is read into memory, its contents are checked against the
+//! manifest, and the same files are written out in the requested format and
+//! compression level. The output is read back and compared byte-for-byte.
+
+use graft_core::archive::{create_archive_from_entries, read_archive_bytes, ArchiveCompression, Codec};
+use graft_core::exit_code::ExitCode;
+use graft_core::patch::payload::{added_name, decompress, diff_name};
+use graft_core::patch::MANIFEST_FILENAME;
+use graft_core::utils::manifest::{Manifest, ManifestEntry};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+/// Highest supported compression level.
+pub const MAX_COMPRESSION: u32 = 9;
+
+/// Container format for a patch archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// Gzip-compressed tar (the format patchers read)
+    TarGz,
+    /// Deflate-compressed zip
+    Zip,
+}
+
+/// Parse a format name string into an ArchiveFormat.
+pub fn parse_format(name: &str) -> Option<ArchiveFormat> {
+    match name.to_lowercase().as_str() {
+        "tar.gz" | "tgz" | "gzip" => Some(ArchiveFormat::TarGz),
+        "zip" => Some(ArchiveFormat::Zip),
+        _ => None,
+    }
+}
+
+/// Errors from repacking an archive.
+#[derive(Debug)]
+pub enum RepackError {
+    /// Failed to read the input archive.
+    ReadFailed(io::Error),
+    /// Input is neither a patch archive nor a zip archive.
+    UnknownFormat,
+    /// Invalid format name specified.
+    InvalidFormat(String),
+    /// Compression level out of range.
+    InvalidCompression(u32),
+    /// Archive contents don't match the manifest.
+    InvalidPatch(String),
+    /// Failed to write the output archive.
+    WriteFailed(io::Error),
+    /// Repacked archive doesn't contain the same files as the input.
+    RoundTripMismatch(String),
+}
+
+impl std::fmt::Display for RepackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RepackError::ReadFailed(e) => write!(f, "Failed to read archive: {}", e),
+            RepackError::UnknownFormat => write!(f, "Input is not a patch archive or zip archive"),
+            RepackError::InvalidFormat(name) => {
+                write!(f, "Invalid format: {} (expected tar.gz or zip)", name)
+            }
+            RepackError::InvalidCompression(level) => write!(
+                f,
+                "Invalid compression level: {} (expected 0-{})",
+                level, MAX_COMPRESSION
+            ),
+            RepackError::InvalidPatch(msg) => write!(f, "Invalid patch archive: {}", msg),
+            RepackError::WriteFailed(e) => write!(f, "Failed to write archive: {}", e),
+            RepackError::RoundTripMismatch(msg) => {
+                write!(f, "Repacked archive does not match input: {}", msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RepackError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RepackError::ReadFailed(e) => Some(e),
+            RepackError::WriteFailed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<&RepackError> for ExitCode {
+    fn from(error: &RepackError) -> Self {
+        match error {
+            RepackError::ReadFailed(e) | RepackError::WriteFailed(e) => ExitCode::from(e),
+            RepackError::UnknownFormat
+            | RepackError::InvalidFormat(_)
+            | RepackError::InvalidCompression(_)
+            | RepackError::InvalidPatch(_) => ExitCode::Validation,
+            RepackError::RoundTripMismatch(_) => ExitCode::Verification,
+        }
+    }
+}
+
+/// Repack the patch archive at `input` into `output`.
+///
+/// Returns the number of files in the archive.
+pub fn run(
+    input: &Path,
+    output: &Path,
+    format: ArchiveFormat,
+    compression: u32,
+) -> Result<usize, RepackError> {
+    if compression > MAX_COMPRESSION {
+        return Err(RepackError::InvalidCompression(compression));
+    }
+
+    let data = fs::read(input).map_err(RepackError::ReadFailed)?;
+    let contents = read_contents(&data)?;
+    verify_contents(&contents)?;
+
+    let codec = match format {
+        ArchiveFormat::TarGz => Codec::Gzip,
+        ArchiveFormat::Zip => Codec::Zip,
+    };
+    let packed = create_archive_from_entries(&contents, ArchiveCompression { codec, level: compression })
+        .map_err(RepackError::WriteFailed)?;
+
+    // Read back what we're about to write and make sure nothing changed
+    let repacked = read_contents(&packed)
+        .map_err(|e| RepackError::RoundTripMismatch(e.to_string()))?;
+    if let Some(path) = first_difference(&contents, &repacked) {
+        return Err(RepackError::RoundTripMismatch(format!("'{}' differs", path)));
+    }
+
+    fs::write(output, &packed).map_err(RepackError::WriteFailed)?;
+
+    Ok(contents.len())
+}
+
+/// Read all files from a patch archive of any codec, zip included, detected
+/// by magic bytes.
+fn read_contents(data: &[u8]) -> Result<BTreeMap<String, Vec<u8>>, RepackError> {
+    if Codec::detect(data).is_some() {
+        read_archive_bytes(data).map_err(RepackError::ReadFailed)
+    } else {
+        Err(RepackError::UnknownFormat)
+    }
+}
+
+/// Check the manifest parses and every diff/added file it references is
+/// present with the expected hash.
+fn verify_contents(contents: &BTreeMap<String, Vec<u8>>) -> Result<(), RepackError> {
+    let manifest_data = contents
+        .get(MANIFEST_FILENAME)
+        .ok_or_else(|| RepackError::InvalidPatch(format!("{} not found", MANIFEST_FILENAME)))?;
+    let manifest = Manifest::parse(&String::from_utf8_lossy(manifest_data))
+        .map_err(|e| RepackError::InvalidPatch(format!("invalid manifest: {}", e)))?;
+
+    for entry in &manifest.entries {
+        let (path, expected, compressed) = match entry {
+            ManifestEntry::Patch { file, diff_hash, compressed, .. } => {
+                (diff_name(file, *compressed), diff_hash, false)
+            }
+            ManifestEntry::Add { file, final_hash, compressed, .. } => {
+                (added_name(file, *compressed), final_hash, *compressed)
+            }
+            ManifestEntry::Delete { .. }
+            | ManifestEntry::Rename { .. }
+            | ManifestEntry::Symlink { .. }
+            | ManifestEntry::MkDir { .. }
+            | ManifestEntry::RmDir { .. } => continue,
+        };
+
+        let data = contents
+            .get(&path)
+            .ok_or_else(|| RepackError::InvalidPatch(format!("'{}' not found", path)))?;
+        // Added files are hashed by their decompressed content
+        let actual = if compressed {
+            let data = decompress(data)
+                .map_err(|e| RepackError::InvalidPatch(format!("'{}' is not valid gzip: {}", path, e)))?;
+            manifest.hash_algorithm.hash_bytes(&data)
+        } else {
+            manifest.hash_algorithm.hash_bytes(data)
+        };
+        if &actual != expected {
+            return Err(RepackError::InvalidPatch(format!(
+                "hash mismatch for '{}': expected {}, got {}",
+                path, expected, actual
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// First path whose presence or contents differ between two archives.
+fn first_difference(
+    a: &BTreeMap<String, Vec<u8>>,
+    b: &BTreeMap<String, Vec<u8>>,
+) -> Option<String> {
+    a.keys()
+        .chain(b.keys())
+        .find(|path| a.get(*path) != b.get(*path))
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::patch_create::{self, CreateOptions};
+    use graft_core::archive::create_archive_bytes;
+    use graft_core::patch::{Progress, FILES_DIR};
+    use tempfile::tempdir;
+
+    const OPTIONS: CreateOptions<'static> = CreateOptions::new(1, "TestPatcher");
+
+    /// Create a tar.gz patch archive with one diff and one added file.
+    fn sample_archive(dir: &Path) -> Vec<u8> {
+        let orig_dir = dir.join("orig");
+        let new_dir = dir.join("new");
+        let patch_dir = dir.join("patch");
+        fs::create_dir_all(&orig_dir).unwrap();
+        fs::create_dir_all(&new_dir).unwrap();
+
+        fs::write(orig_dir.join("modified.bin"), b"original content").unwrap();
+        fs::write(new_dir.join("modified.bin"), b"modified content").unwrap();
+        fs::write(new_dir.join("added.bin"), b"added").unwrap();
+        patch_create::run(&orig_dir, &new_dir, &patch_dir, &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        create_archive_bytes(&patch_dir).unwrap()
+    }
+
+    #[test]
+    fn parses_format_names() {
+        assert_eq!(parse_format("tar.gz"), Some(ArchiveFormat::TarGz));
+        assert_eq!(parse_format("TGZ"), Some(ArchiveFormat::TarGz));
+        assert_eq!(parse_format("zip"), Some(ArchiveFormat::Zip));
+        assert_eq!(parse_format("rar"), None);
+    }
+
+    #[test]
+    fn repacks_tar_gz_to_zip_and_back() {
+        let dir = tempdir().unwrap();
+        let original = sample_archive(dir.path());
+        let input = dir.path().join("patch.tar.gz");
+        let zipped = dir.path().join("patch.zip");
+        let restored = dir.path().join("restored.tar.gz");
+        fs::write(&input, &original).unwrap();
+
+        run(&input, &zipped, ArchiveFormat::Zip, 9).unwrap();
+        assert_eq!(Codec::detect(&fs::read(&zipped).unwrap()), Some(Codec::Zip));
+
+        run(&zipped, &restored, ArchiveFormat::TarGz, 9).unwrap();
+
+        assert_eq!(
+            read_archive_bytes(&fs::read(&restored).unwrap()).unwrap(),
+            read_archive_bytes(&original).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_corrupted_content() {
+        let dir = tempdir().unwrap();
+        let mut contents = read_archive_bytes(&sample_archive(dir.path())).unwrap();
+        contents.insert(format!("{}/added.bin", FILES_DIR), b"tampered".to_vec());
+
+        let input = dir.path().join("patch.tar.gz");
+        fs::write(&input, create_archive_from_entries(&contents, ArchiveCompression::default()).unwrap()).unwrap();
+
+        let result = run(&input, &dir.path().join("out.zip"), ArchiveFormat::Zip, 6);
+
+        assert!(matches!(result, Err(RepackError::InvalidPatch(_))));
+        assert!(!dir.path().join("out.zip").exists());
+    }
+
+    #[test]
+    fn rejects_unknown_input_format() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("patch.bin");
+        fs::write(&input, b"not an archive").unwrap();
+
+        let result = run(&input, &dir.path().join("out.zip"), ArchiveFormat::Zip, 6);
+
+        assert!(matches!(result, Err(RepackError::UnknownFormat)));
+    }
+
+    #[test]
+    fn rejects_invalid_compression_level() {
+        let dir = tempdir().unwrap();
+        let result = run(&dir.path().join("in"), &dir.path().join("out"), ArchiveFormat::Zip, 10);
+
+        assert!(matches!(result, Err(RepackError::InvalidCompression(10))));
+    }
+}
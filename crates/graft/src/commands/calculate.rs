@@ -1,12 +1,22 @@
-use std::fs;
-use std::io;
+use std::fs::File;
+use std::io::{self, BufReader};
 use std::path::Path;
 
-use graft_core::utils::hash::hash_bytes;
+use graft_core::utils::hash::hash_reader_with_progress;
 
 pub fn run(file: &Path) -> io::Result<String> {
-    let data = fs::read(file)?;
-    Ok(hash_bytes(&data))
+    run_with_progress(file, |_, _| {})
+}
+
+/// Hash a file as a stream, calling `on_progress` with the bytes hashed so
+/// far and the file size.
+pub fn run_with_progress<F>(file: &Path, mut on_progress: F) -> io::Result<String>
+where
+    F: FnMut(u64, u64),
+{
+    let file = File::open(file)?;
+    let total = file.metadata()?.len();
+    hash_reader_with_progress(BufReader::new(file), |hashed| on_progress(hashed, total))
 }
 
 #[cfg(test)]
@@ -28,5 +38,15 @@ mod tests {
 
         assert_eq!(hash.as_str(), "6ae8a75555209fd6c44157c0aed8016e763ff435a19cf186f76863140143ff72");
     }
-}
 
+    #[test]
+    fn reports_progress_up_to_file_size() {
+        let file = create_temp_file(b"test content");
+        let mut last = None;
+
+        let hash = run_with_progress(file.path(), |hashed, total| last = Some((hashed, total))).unwrap();
+
+        assert_eq!(hash, run(file.path()).unwrap());
+        assert_eq!(last, Some((12, 12)));
+    }
+}
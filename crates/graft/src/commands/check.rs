@@ -3,12 +3,42 @@ use std::io;
 use std::path::Path;
 
 use graft_core::utils::hash::hash_bytes;
+use serde::Serialize;
 
 pub enum CheckResult {
     Match,
     NoMatch { actual: String },
 }
 
+/// Machine-readable outcome of a check, printed by `--json`.
+#[derive(Debug, Serialize)]
+pub struct CheckReport {
+    pub file: String,
+    pub expected: String,
+    pub actual: String,
+    #[serde(rename = "match")]
+    pub matches: bool,
+}
+
+impl CheckReport {
+    pub fn new(expected: &str, file: &Path, result: &CheckResult) -> Self {
+        let (actual, matches) = match result {
+            CheckResult::Match => (expected.to_string(), true),
+            CheckResult::NoMatch { actual } => (actual.clone(), false),
+        };
+        CheckReport {
+            file: file.display().to_string(),
+            expected: expected.to_string(),
+            actual,
+            matches,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("check report serializes")
+    }
+}
+
 pub fn run(expected: &str, file: &Path) -> io::Result<CheckResult> {
     let data = fs::read(file)?;
     let actual = hash_bytes(&data);
@@ -56,6 +86,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn report_serializes_with_match_field() {
+        let file = create_temp_file(b"test content");
+        let wrong_hash = "0000000000000000000000000000000000000000000000000000000000000000";
+
+        let result = run(wrong_hash, file.path()).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&CheckReport::new(wrong_hash, file.path(), &result).to_json()).unwrap();
+
+        assert_eq!(json["file"], file.path().display().to_string());
+        assert_eq!(json["expected"], wrong_hash);
+        assert_eq!(json["actual"], hash_bytes(b"test content"));
+        assert_eq!(json["match"], false);
+    }
+
     #[test]
     fn nonexistent_file_returns_error() {
         let nonexistent = Path::new("/nonexistent/file.bin");
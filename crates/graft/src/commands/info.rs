@@ -0,0 +1,168 @@
+use std::fs;
+use std::path::Path;
+
+use graft_core::patch::payload::{added_name, diff_name};
+use graft_core::patch::{self, PatchError};
+use graft_core::utils::manifest::{ManifestEntry, PatchInfo};
+use serde::Serialize;
+
+/// What a patch directory contains, printed by `graft info`.
+#[derive(Debug, Serialize)]
+pub struct PatchReport {
+    #[serde(flatten)]
+    pub info: PatchInfo,
+    /// Total size of the files the patch adds
+    pub added_bytes: u64,
+    /// Total size of the diffs for patched files
+    pub diff_bytes: u64,
+    pub files: Vec<FileReport>,
+}
+
+/// One manifest entry in a `PatchReport`.
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub operation: &'static str,
+    pub file: String,
+    /// Original path, for renames
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// Size of the diff or added file shipped in the patch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl PatchReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("patch report serializes")
+    }
+
+    /// Print the report for a person to read.
+    pub fn print(&self) {
+        let info = &self.info;
+        println!("{} v{}", info.title.as_deref().unwrap_or(&info.name), info.version);
+        if let Some(author) = &info.author {
+            println!("Author: {}", author);
+        }
+        if let Some(version) = &info.target_game_version {
+            println!("Target game version: {}", version);
+        }
+        if let Some(description) = &info.description {
+            println!("{}", description);
+        }
+        println!();
+        println!("{} entries:", info.entry_count);
+        for (label, count) in [
+            ("patched", info.patches),
+            ("added", info.additions),
+            ("deleted", info.deletions),
+            ("renamed", info.renames),
+            ("symlinks", info.symlinks),
+            ("directories", info.directories),
+        ] {
+            if count > 0 {
+                println!("  {:<12} {}", label, count);
+            }
+        }
+        println!("Added files: {} bytes", self.added_bytes);
+        println!("Diffs: {} bytes", self.diff_bytes);
+        println!();
+        for file in &self.files {
+            let path = match &file.from {
+                Some(from) => format!("{} -> {}", from, file.file),
+                None => file.file.clone(),
+            };
+            match file.size {
+                Some(size) => println!("{:<8} {} ({} bytes)", file.operation, path, size),
+                None => println!("{:<8} {}", file.operation, path),
+            }
+        }
+    }
+}
+
+/// Describe the patch in `patch_dir` without applying or building it.
+///
+/// The patch directory is validated first, so every diff and added file the
+/// manifest references is known to be present.
+pub fn run(patch_dir: &Path) -> Result<PatchReport, PatchError> {
+    let manifest = patch::validate_patch_dir(patch_dir)?;
+    let size = |path: &Path, file: &str| {
+        fs::metadata(path)
+            .map(|m| m.len())
+            .map_err(|_| PatchError::FileNotFound(file.to_string()))
+    };
+
+    let mut added_bytes = 0;
+    let mut diff_bytes = 0;
+    let mut files = Vec::new();
+    for entry in &manifest.entries {
+        let (from, size) = match entry {
+            ManifestEntry::Patch { file, compressed, .. } => {
+                let bytes = size(&patch_dir.join(diff_name(file, *compressed)), file)?;
+                diff_bytes += bytes;
+                (None, Some(bytes))
+            }
+            ManifestEntry::Add { file, compressed, .. } => {
+                let bytes = size(&patch_dir.join(added_name(file, *compressed)), file)?;
+                added_bytes += bytes;
+                (None, Some(bytes))
+            }
+            ManifestEntry::Rename { from, .. } => (Some(from.clone()), None),
+            ManifestEntry::Delete { .. }
+            | ManifestEntry::Symlink { .. }
+            | ManifestEntry::MkDir { .. }
+            | ManifestEntry::RmDir { .. } => (None, None),
+        };
+        files.push(FileReport {
+            operation: entry.operation(),
+            file: entry.file().to_string(),
+            from,
+            size,
+        });
+    }
+
+    Ok(PatchReport {
+        info: PatchInfo::from_manifest(&manifest),
+        added_bytes,
+        diff_bytes,
+        files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::patch_create;
+    use graft_core::patch::Progress;
+    use tempfile::tempdir;
+
+    #[test]
+    fn reports_counts_sizes_and_files() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("game.dat"), b"original").unwrap();
+        fs::write(new_dir.path().join("game.dat"), b"modified").unwrap();
+        fs::write(new_dir.path().join("fonts.pak"), b"0123456789").unwrap();
+        fs::write(orig_dir.path().join("intro.bik"), b"old video").unwrap();
+        let options = patch_create::CreateOptions {
+            author: Some("Someone"),
+            ..patch_create::CreateOptions::new(3, "Translation")
+        };
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &options, None::<fn(Progress)>).unwrap();
+
+        let report = run(patch_dir.path()).unwrap();
+
+        assert_eq!(report.info.version, 3);
+        assert_eq!((report.info.patches, report.info.additions, report.info.deletions), (1, 1, 1));
+        assert_eq!(report.added_bytes, 10);
+        let operations: Vec<_> = report.files.iter().map(|f| (f.operation, f.file.as_str())).collect();
+        assert_eq!(operations, vec![("add", "fonts.pak"), ("patch", "game.dat"), ("delete", "intro.bik")]);
+        assert_eq!(report.files[1].size, Some(report.diff_bytes));
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["name"], "Translation");
+        assert_eq!(json["added_bytes"], 10);
+        assert_eq!(json["files"][2], serde_json::json!({"operation": "delete", "file": "intro.bik"}));
+    }
+}
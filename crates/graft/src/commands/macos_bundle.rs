@@ -3,7 +3,6 @@
 //! Creates proper macOS application bundles with icons and Info.plist.
 //! Also supports modifying existing stub bundles.
 
-use graft_core::patch::{ASSETS_DIR, ICON_FILENAME};
 use icns::{IconFamily, Image};
 use std::fs::{self, File};
 use std::io::{self, BufReader};
@@ -81,14 +80,14 @@ impl std::error::Error for BundleError {
 /// # Arguments
 /// * `output_path` - Path for the .app bundle (e.g., "MyApp.app")
 /// * `executable_data` - The patcher executable bytes
-/// * `patch_dir` - Path to the patch directory (for reading icon)
+/// * `icon_path` - PNG icon to convert, used if it exists
 /// * `app_name` - Name for the app (used in executable and plist)
 /// * `title` - Display title for the app (from manifest, or defaults to app_name)
 /// * `version` - Version string for the app
 pub fn create_bundle(
     output_path: &Path,
     executable_data: &[u8],
-    patch_dir: &Path,
+    icon_path: &Path,
     app_name: &str,
     title: Option<&str>,
     version: &str,
@@ -125,10 +124,9 @@ pub fn create_bundle(
     }
 
     // Convert and write icon
-    let icon_path = patch_dir.join(ASSETS_DIR).join(ICON_FILENAME);
     if icon_path.exists() {
         let icns_path = resources_dir.join("AppIcon.icns");
-        convert_png_to_icns(&icon_path, &icns_path)?;
+        convert_png_to_icns(icon_path, &icns_path)?;
     }
 
     // Write Info.plist
@@ -179,14 +177,14 @@ pub fn convert_png_to_icns(png_path: &Path, icns_path: &Path) -> Result<(), Bund
 /// * `stub_bundle_path` - Path to the stub .app bundle
 /// * `output_path` - Path for the output .app bundle
 /// * `archive_data` - The patch archive data to append
-/// * `patch_dir` - Path to the patch directory (for reading custom icon)
+/// * `icon_path` - PNG icon to replace the stub's with, used if it exists
 /// * `title` - Display title for the app (from manifest)
 /// * `version` - Version string for the app
 pub fn modify_bundle(
     stub_bundle_path: &Path,
     output_path: &Path,
     archive_data: &[u8],
-    patch_dir: &Path,
+    icon_path: &Path,
     title: Option<&str>,
     version: &str,
 ) -> Result<usize, BundleError> {
@@ -194,7 +192,7 @@ pub fn modify_bundle(
     copy_dir_recursive(stub_bundle_path, output_path)?;
 
     // Finalize the bundle
-    finalize_bundle(output_path, archive_data, patch_dir, title, version)
+    finalize_bundle(output_path, archive_data, icon_path, title, version)
 }
 
 /// Finalize a bundle that is already at output_path.
@@ -205,13 +203,13 @@ pub fn modify_bundle(
 /// # Arguments
 /// * `output_path` - Path to the .app bundle (must already exist)
 /// * `archive_data` - The patch archive data to add
-/// * `patch_dir` - Path to the patch directory (for reading custom icon)
+/// * `icon_path` - PNG icon to replace the stub's with, used if it exists
 /// * `title` - Display title for the app (from manifest)
 /// * `version` - Version string for the app
 pub fn finalize_bundle(
     output_path: &Path,
     archive_data: &[u8],
-    patch_dir: &Path,
+    icon_path: &Path,
     title: Option<&str>,
     version: &str,
 ) -> Result<usize, BundleError> {
@@ -255,11 +253,10 @@ pub fn finalize_bundle(
     let plist_path = contents_dir.join("Info.plist");
     fs::write(&plist_path, plist_content).map_err(BundleError::FileWrite)?;
 
-    // Replace icon if patch has custom icon
-    let custom_icon_path = patch_dir.join(ASSETS_DIR).join(ICON_FILENAME);
-    if custom_icon_path.exists() {
+    // Replace icon if there is a custom one
+    if icon_path.exists() {
         let icns_path = resources_dir.join("AppIcon.icns");
-        convert_png_to_icns(&custom_icon_path, &icns_path)?;
+        convert_png_to_icns(icon_path, &icns_path)?;
     }
     // Otherwise keep the stub's default icon
 
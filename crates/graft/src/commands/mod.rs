@@ -1,11 +1,17 @@
+pub mod apply_chain;
+pub mod archive_repack;
 pub mod build;
 pub mod calculate;
 pub mod check;
 pub mod compare;
 pub mod diff_apply;
 pub mod diff_create;
+pub mod info;
 pub mod macos_bundle;
 pub mod patch_apply;
 pub mod patch_create;
+pub mod patch_rehash;
 pub mod patch_rollback;
+pub mod selftest;
+pub mod verify;
 pub mod windows_icon;
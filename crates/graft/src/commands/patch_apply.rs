@@ -1,10 +1,17 @@
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use graft_core::patch::{
-    apply_entries, backup_entries, validate_entries, validate_path_restrictions, PatchError,
-    Progress, ProgressAction, BACKUP_DIR, MANIFEST_FILENAME,
+    apply_entries, apply_entries_parallel, backup_entries, check_space, check_writable,
+    format_command, install_dir, remove_applied_entries, resolve_by_content, run_hook,
+    set_streaming_threshold, validate_entries, validate_entries_forced, validate_entries_parallel,
+    validate_game_version, validate_patch_files, validate_path_restrictions, ByteProgress,
+    PatchError, PatchLock, Progress, ProgressAction, Verification, BACKUP_DIR,
+    DEFAULT_STREAMING_THRESHOLD, IGNORE_FILENAME, MANIFEST_FILENAME,
 };
-use graft_core::utils::manifest::Manifest;
+use graft_core::utils::ignore::IgnoreList;
+use graft_core::utils::manifest::{Manifest, ManifestEntry, MISSING_HASH_WARNING};
+use graft_core::utils::temp_file::remove_stray_temp_files;
 
 fn format_action(action: ProgressAction) -> &'static str {
     match action {
@@ -15,55 +22,495 @@ fn format_action(action: ProgressAction) -> &'static str {
         ProgressAction::Patching => "Patching",
         ProgressAction::Adding => "Adding",
         ProgressAction::Deleting => "Deleting",
+        ProgressAction::Renaming => "Renaming",
+        ProgressAction::Linking => "Linking",
+        ProgressAction::Creating => "Creating",
         ProgressAction::Restoring => "Restoring",
         ProgressAction::Removing => "Removing",
+        ProgressAction::Diffing => "Diffing",
+        ProgressAction::Copying => "Copying",
+        ProgressAction::Recording => "Recording",
+    }
+}
+
+/// What `run` does with the commands a manifest asks to run before and
+/// after applying (`pre_commands` and `post_commands`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Hooks {
+    /// Refuse to apply a patch that declares any, since they run arbitrary
+    /// programs
+    #[default]
+    Refuse,
+    /// Run them
+    Run,
+    /// Apply the patch without running them
+    Skip,
+}
+
+/// How `run` applies a patch.
+#[derive(Debug, Clone, Copy)]
+pub struct ApplyOptions<'a> {
+    /// How thoroughly entries are verified after applying
+    pub verification: Verification,
+    /// Number of entries to validate and apply at once (1 handles them in order)
+    pub jobs: usize,
+    /// Only apply entries with these operations ("patch", "add", "delete",
+    /// "rename", "symlink"); empty applies everything
+    pub operations: &'a [String],
+    /// Validate and print what would change, without backing up or writing
+    /// anything
+    pub dry_run: bool,
+    /// Where to keep backups; `None` uses `BACKUP_DIR` inside the target
+    pub backup_dir: Option<&'a Path>,
+    /// Remove the backup once every entry is applied and verified, to
+    /// reclaim disk space; the patch can't be rolled back afterwards
+    pub delete_backup: bool,
+    /// Patch files larger than this many bytes as a stream instead of in
+    /// memory (see `set_streaming_threshold`)
+    pub streaming_threshold: u64,
+    /// Don't check that files to patch match their original hashes, e.g. to
+    /// apply over an already modified install. The diffs may then produce
+    /// the wrong result, so every entry is verified after applying whatever
+    /// `verification` says, and any mismatch rolls the patch back
+    pub force: bool,
+    /// Whether the manifest's hook commands may run
+    pub hooks: Hooks,
+}
+
+impl Default for ApplyOptions<'_> {
+    fn default() -> Self {
+        ApplyOptions {
+            verification: Verification::Full,
+            jobs: 1,
+            operations: &[],
+            dry_run: false,
+            backup_dir: None,
+            delete_backup: false,
+            streaming_threshold: DEFAULT_STREAMING_THRESHOLD,
+            force: false,
+            hooks: Hooks::Refuse,
+        }
     }
 }
 
 /// Apply a patch to a target directory.
 ///
+/// Entries are applied under the manifest's `base_dir` inside `target_dir`,
+/// if it names one; the backup still defaults to `BACKUP_DIR` in `target_dir`.
+///
+/// Unless `dry_run`, `target_dir` is locked for the whole run (see
+/// `PatchLock`), and `AlreadyInProgress` is returned if another patcher
+/// holds the lock.
+///
+/// A manifest with hook commands is refused with `HooksNotAllowed` unless
+/// `hooks` says to run or skip them. Pre-commands run in the install
+/// directory after validation, so a failing one stops the apply before
+/// anything is changed; post-commands run once every entry is applied. A
+/// failing post-command leaves the patch applied, with its backup, and
+/// returns `HookFailed`.
+///
 /// Workflow:
-/// 1. Load and parse manifest
-/// 2. Validate all entries (files exist, hashes match)
-/// 3. Backup all files that will be modified/deleted
-/// 4. Apply each entry, verifying immediately after
-/// 5. On any failure, rollback to original state
-pub fn run(target_dir: &Path, patch_dir: &Path) -> Result<(), PatchError> {
+/// 1. Load and parse manifest, dropping entries matched by the target's `.graftignore`
+///    or not in `operations`, and, for `match_by_content` patches, locating renamed
+///    files by hash. If a backup from an earlier run exists, entries already in their
+///    patched state are dropped too, so an interrupted apply can be resumed
+/// 2. Check the patch's diffs and added files against their manifest hashes, and
+///    validate all entries (files exist, hashes match unless `force`), on up to `jobs` threads, then
+///    remove temp files left by an earlier crashed run
+///    (with `dry_run`, print the planned operations and stop here)
+/// 3. Backup all files that will be modified/deleted, to `backup_dir` if set
+/// 4. Apply each entry, verifying immediately after (or a sample, per `verification`),
+///    on up to `jobs` threads
+/// 5. On any failure, rollback to original state; otherwise, with `delete_backup`,
+///    remove the backup
+pub fn run(target_dir: &Path, patch_dir: &Path, options: &ApplyOptions) -> Result<(), PatchError> {
     // Load manifest
     let manifest_path = patch_dir.join(MANIFEST_FILENAME);
-    let manifest = Manifest::load(&manifest_path).map_err(|e| PatchError::ManifestError {
+    let mut manifest = Manifest::load(&manifest_path).map_err(|e| PatchError::ManifestError {
         reason: e.to_string(),
     })?;
+    if manifest.unhashed {
+        eprintln!("Warning: {}", MISSING_HASH_WARNING);
+    }
+
+    // Hooks run arbitrary programs, so a patch from someone else never gets
+    // to run them without being asked
+    if manifest.has_hooks() && options.hooks == Hooks::Refuse {
+        return Err(PatchError::HooksNotAllowed);
+    }
+    if manifest.has_hooks() && options.hooks == Hooks::Skip {
+        println!("Skipping the patch's hook commands (--no-hooks)");
+    }
+
+    // Keep a second patcher off the target until this one is done
+    let _lock = if options.dry_run { None } else { Some(PatchLock::acquire(target_dir)?) };
+
+    // Backups stay in the chosen directory, but entries are relative to the
+    // patch's base_dir inside it, if it has one
+    let backup_dir = backup_dir_for(target_dir, options.backup_dir);
+    let target_dir = &install_dir(&manifest, target_dir)?;
+
+    // Leave files the user has asked us not to touch (mods, saves) alone
+    skip_ignored(&mut manifest, target_dir)?;
+
+    if !options.operations.is_empty() {
+        let total = manifest.entries.len();
+        manifest
+            .entries
+            .retain(|e| options.operations.iter().any(|op| op == e.operation()));
+        println!(
+            "Applying {} of {} entries (only {}); the rest are deferred",
+            manifest.entries.len(),
+            total,
+            options.operations.join(", ")
+        );
+    }
+
+    let located = resolve_by_content(&mut manifest, target_dir)?;
+    if located > 0 {
+        println!("{} files located by content", located);
+    }
 
     // Check path restrictions (unless allow_restricted is set in manifest)
     validate_path_restrictions(&manifest, target_dir)?;
 
+    // Refuse a build the patch wasn't made for
+    validate_game_version(&manifest, target_dir)?;
+
+    // A backup means an earlier run got at least as far as backing up; skip
+    // whatever it finished so a re-run picks up where it stopped
+    if backup_dir.exists() {
+        let applied = remove_applied_entries(&mut manifest.entries, target_dir, manifest.hash_algorithm);
+        if !applied.is_empty() {
+            println!(
+                "Resuming: {} entries already applied by an earlier run, {} left",
+                applied.len(),
+                manifest.entries.len()
+            );
+        }
+    }
+
+    let print_progress = |p: Progress| {
+        println!("{} [{}/{}]: {}", format_action(p.action), p.index + 1, p.total, p.label.unwrap_or(p.file));
+    };
+
+    // Check the patch's own files, so a corrupted download fails before
+    // anything is backed up
+    validate_patch_files(&manifest.entries, patch_dir, manifest.hash_algorithm, None::<fn(Progress)>)?;
+
     // Validate all entries before making any changes
-    validate_entries(&manifest.entries, target_dir, Some(|p: Progress| {
-        println!("{} [{}/{}]: {}", format_action(p.action), p.index + 1, p.total, p.file);
-    }))?;
+    if options.force {
+        println!("WARNING: --force skips checking files against the patch's original hashes");
+        validate_entries_forced(
+            &manifest.entries,
+            target_dir,
+            manifest.hash_algorithm,
+            options.jobs,
+            Some(print_progress),
+        )?;
+    } else if options.jobs > 1 {
+        validate_entries_parallel(
+            &manifest.entries,
+            target_dir,
+            manifest.hash_algorithm,
+            options.jobs,
+            Some(print_progress),
+        )?;
+    } else {
+        validate_entries(&manifest.entries, target_dir, manifest.hash_algorithm, Some(print_progress))?;
+    }
+
+    // Fail now rather than halfway through when the disk fills up
+    check_space(&manifest.entries, target_dir, patch_dir, &backup_dir)?;
+
+    // A read-only install would otherwise only fail after part of the backup is taken
+    check_writable(&manifest.entries, target_dir, &backup_dir)?;
+
+    let run_hooks = options.hooks == Hooks::Run;
+    if options.dry_run {
+        if run_hooks {
+            for command in &manifest.pre_commands {
+                println!("Would run before applying: {}", format_command(command));
+            }
+        }
+        print_plan(&manifest);
+        if run_hooks {
+            for command in &manifest.post_commands {
+                println!("Would run after applying: {}", format_command(command));
+            }
+        }
+        return Ok(());
+    }
+
+    if run_hooks {
+        for command in &manifest.pre_commands {
+            println!("Running: {}", format_command(command));
+            run_hook(command, target_dir)?;
+        }
+    }
+
+    // Clear out temp files left behind by an earlier run that crashed mid-write
+    let stray = remove_stray_temp_files(target_dir).map_err(|e| PatchError::ValidationFailed {
+        file: target_dir.display().to_string(),
+        reason: format!("failed to remove stray temp files: {}", e),
+    })?;
+    if stray > 0 {
+        println!("Removed {} stray temp files from an earlier run", stray);
+    }
 
     // Backup all files that will be modified/deleted
-    let backup_dir = target_dir.join(BACKUP_DIR);
-    backup_entries(&manifest.entries, target_dir, &backup_dir, Some(|p: Progress| {
-        println!("{} [{}/{}]: {}", format_action(p.action), p.index + 1, p.total, p.file);
-    }))?;
+    backup_entries(&manifest.entries, target_dir, &backup_dir, manifest.hash_algorithm, Some(print_progress))?;
+
+    // Apply each entry with automatic rollback on failure. A forced apply
+    // can't rely on the originals, so nothing goes unverified
+    set_streaming_threshold(options.streaming_threshold);
+    let verification = if options.force { Verification::Full } else { options.verification };
+    if options.jobs > 1 {
+        apply_entries_parallel(
+            &manifest.entries,
+            target_dir,
+            patch_dir,
+            &backup_dir,
+            verification,
+            manifest.hash_algorithm,
+            options.jobs,
+            Some(print_progress),
+            None::<fn(ByteProgress)>,
+        )?;
+    } else {
+        apply_entries(
+            &manifest.entries,
+            target_dir,
+            patch_dir,
+            &backup_dir,
+            verification,
+            manifest.hash_algorithm,
+            Some(print_progress),
+            None::<fn(ByteProgress)>,
+        )?;
+    }
+
+    // The patch stays applied if one of these fails; the backup is kept so
+    // it can still be rolled back
+    if run_hooks {
+        for command in &manifest.post_commands {
+            println!("Running: {}", format_command(command));
+            run_hook(command, target_dir)?;
+        }
+    }
+
+    // Only now that everything is applied and verified is the backup no
+    // longer needed to roll back a failure. Failing to remove it doesn't
+    // undo a successful apply
+    if options.delete_backup {
+        match fs::remove_dir_all(&backup_dir) {
+            Ok(()) => println!("Removed backup {}", backup_dir.display()),
+            Err(e) => eprintln!("Warning: failed to remove backup {}: {}", backup_dir.display(), e),
+        }
+    }
+
+    Ok(())
+}
 
-    // Apply each entry with automatic rollback on failure
-    apply_entries(&manifest.entries, target_dir, patch_dir, &backup_dir, Some(|p: Progress| {
-        println!("{} [{}/{}]: {}", format_action(p.action), p.index + 1, p.total, p.file);
-    }))?;
+/// The backup directory for `target_dir`: `custom` if given, otherwise
+/// `BACKUP_DIR` inside the target.
+pub(crate) fn backup_dir_for(target_dir: &Path, custom: Option<&Path>) -> PathBuf {
+    custom.map_or_else(|| target_dir.join(BACKUP_DIR), Path::to_path_buf)
+}
+
+/// Remove entries matched by the target's `.graftignore` from the manifest,
+/// reporting each one skipped.
+pub(crate) fn skip_ignored(manifest: &mut Manifest, target_dir: &Path) -> Result<(), PatchError> {
+    let ignore = IgnoreList::load(target_dir).map_err(|e| PatchError::ManifestError {
+        reason: format!("failed to read {}: {}", IGNORE_FILENAME, e),
+    })?;
+
+    let skipped = ignore.filter_entries(&mut manifest.entries);
+    for entry in &skipped {
+        println!("Skipping (matched {}): {}", IGNORE_FILENAME, entry.file());
+    }
+    if !skipped.is_empty() {
+        println!("{} entries skipped by {}", skipped.len(), IGNORE_FILENAME);
+    }
 
     Ok(())
 }
 
+/// Print the operation each entry would perform, for a dry run.
+fn print_plan(manifest: &Manifest) {
+    for entry in &manifest.entries {
+        match entry {
+            ManifestEntry::Patch { file, .. } => println!("Would patch: {}", file),
+            ManifestEntry::Add { file, original_hash: Some(_), .. } => println!("Would add or replace: {}", file),
+            ManifestEntry::Add { file, .. } => println!("Would add: {}", file),
+            ManifestEntry::Delete { file, .. } => println!("Would delete: {}", file),
+            ManifestEntry::Rename { from, to, .. } => println!("Would rename: {} -> {}", from, to),
+            ManifestEntry::Symlink { file, target: Some(target), .. } => {
+                println!("Would link: {} -> {}", file, target)
+            }
+            ManifestEntry::Symlink { file, target: None, .. } => println!("Would remove link: {}", file),
+            ManifestEntry::MkDir { dir } => println!("Would create directory: {}", dir),
+            ManifestEntry::RmDir { dir } => println!("Would remove directory: {}", dir),
+        }
+    }
+    println!();
+    println!("Dry run: {} entries validated, nothing was written", manifest.entries.len());
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::commands::patch_create;
+    use graft_core::patch::{DIFFS_DIR, DIFF_EXTENSION, FILES_DIR};
+    use graft_core::utils::hash::Algorithm;
+    use graft_core::utils::temp_file::{is_temp_file, TEMP_PREFIX};
     use std::fs;
     use tempfile::tempdir;
 
+    const OPTIONS: patch_create::CreateOptions<'static> =
+        patch_create::CreateOptions { allow_restricted: true, ..patch_create::CreateOptions::new(1, "TestPatcher") };
+
+    /// Replace the diff for `file` with garbage, updating the manifest to
+    /// match, so the patch passes validation but fails while applying.
+    fn corrupt_diff(patch_dir: &Path, file: &str) {
+        let data = b"corrupted";
+        fs::write(patch_dir.join(DIFFS_DIR).join(format!("{}{}", file, DIFF_EXTENSION)), data).unwrap();
+        let manifest_path = patch_dir.join(MANIFEST_FILENAME);
+        let mut manifest = Manifest::load(&manifest_path).unwrap();
+        for entry in &mut manifest.entries {
+            if let ManifestEntry::Patch { file: f, diff_hash, .. } = entry
+                && f == file
+            {
+                *diff_hash = manifest.hash_algorithm.hash_bytes(data);
+            }
+        }
+        manifest.save(&manifest_path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn apply_keeps_executables_executable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("game"), b"original binary").unwrap();
+        fs::write(new_dir.path().join("game"), b"patched binary").unwrap();
+        fs::write(new_dir.path().join("launcher"), b"launcher").unwrap();
+        for file in [new_dir.path().join("game"), new_dir.path().join("launcher")] {
+            fs::set_permissions(file, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        // The target copy has lost its executable bit, and the added file
+        // lands without one too
+        fs::write(target_dir.path().join("game"), b"original binary").unwrap();
+        fs::set_permissions(target_dir.path().join("game"), fs::Permissions::from_mode(0o644)).unwrap();
+        fs::set_permissions(
+            patch_dir.path().join("files/launcher"),
+            fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+
+        run(target_dir.path(), patch_dir.path(), &ApplyOptions::default()).unwrap();
+
+        for file in ["game", "launcher"] {
+            let mode = fs::metadata(target_dir.path().join(file)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755, "{}", file);
+        }
+    }
+
+    #[test]
+    fn apply_is_relative_to_base_dir() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("strings.txt"), b"hello").unwrap();
+        fs::write(new_dir.path().join("strings.txt"), b"hola").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+        let manifest_path = patch_dir.path().join(MANIFEST_FILENAME);
+        let mut manifest = Manifest::load(&manifest_path).unwrap();
+        manifest.base_dir = Some("localization".to_string());
+        manifest.save(&manifest_path).unwrap();
+
+        let install_dir = target_dir.path().join("localization");
+        fs::create_dir(&install_dir).unwrap();
+        fs::write(install_dir.join("strings.txt"), b"hello").unwrap();
+
+        run(target_dir.path(), patch_dir.path(), &ApplyOptions::default()).unwrap();
+
+        assert_eq!(fs::read(install_dir.join("strings.txt")).unwrap(), b"hola");
+        assert!(!target_dir.path().join("strings.txt").exists());
+        assert!(target_dir.path().join(BACKUP_DIR).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hooks_only_run_when_allowed() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("strings.txt"), b"hello").unwrap();
+        fs::write(new_dir.path().join("strings.txt"), b"hola").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+        let manifest_path = patch_dir.path().join(MANIFEST_FILENAME);
+        let mut manifest = Manifest::load(&manifest_path).unwrap();
+        manifest.pre_commands = vec![vec!["touch".to_string(), "pre".to_string()]];
+        manifest.post_commands = vec![vec!["touch".to_string(), "post".to_string()]];
+        manifest.save(&manifest_path).unwrap();
+        fs::write(target_dir.path().join("strings.txt"), b"hello").unwrap();
+
+        assert_eq!(
+            run(target_dir.path(), patch_dir.path(), &ApplyOptions::default()),
+            Err(PatchError::HooksNotAllowed)
+        );
+        assert_eq!(fs::read(target_dir.path().join("strings.txt")).unwrap(), b"hello");
+
+        let run_hooks = ApplyOptions { hooks: Hooks::Run, ..Default::default() };
+        run(target_dir.path(), patch_dir.path(), &run_hooks).unwrap();
+        assert_eq!(fs::read(target_dir.path().join("strings.txt")).unwrap(), b"hola");
+        assert!(target_dir.path().join("pre").exists());
+        assert!(target_dir.path().join("post").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failing_pre_hook_stops_before_any_change() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("strings.txt"), b"hello").unwrap();
+        fs::write(new_dir.path().join("strings.txt"), b"hola").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+        let manifest_path = patch_dir.path().join(MANIFEST_FILENAME);
+        let mut manifest = Manifest::load(&manifest_path).unwrap();
+        manifest.pre_commands = vec![vec!["false".to_string()]];
+        manifest.save(&manifest_path).unwrap();
+        fs::write(target_dir.path().join("strings.txt"), b"hello").unwrap();
+
+        let run_hooks = ApplyOptions { hooks: Hooks::Run, ..Default::default() };
+        assert!(matches!(
+            run(target_dir.path(), patch_dir.path(), &run_hooks),
+            Err(PatchError::HookFailed { .. })
+        ));
+        assert_eq!(fs::read(target_dir.path().join("strings.txt")).unwrap(), b"hello");
+        assert!(!target_dir.path().join(BACKUP_DIR).exists());
+
+        // --no-hooks applies the patch without them
+        let skip_hooks = ApplyOptions { hooks: Hooks::Skip, ..Default::default() };
+        run(target_dir.path(), patch_dir.path(), &skip_hooks).unwrap();
+        assert_eq!(fs::read(target_dir.path().join("strings.txt")).unwrap(), b"hola");
+    }
+
     #[test]
     fn successful_apply_modifies_target() {
         let orig_dir = tempdir().unwrap();
@@ -78,14 +525,14 @@ mod tests {
         fs::write(orig_dir.path().join("deleted.bin"), b"to delete").unwrap();
 
         // Create patch
-        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), 1, "TestPatcher", None, true).unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
 
         // Set up target (copy of original)
         fs::write(target_dir.path().join("modified.bin"), b"original").unwrap();
         fs::write(target_dir.path().join("deleted.bin"), b"to delete").unwrap();
 
         // Apply patch
-        run(target_dir.path(), patch_dir.path()).unwrap();
+        run(target_dir.path(), patch_dir.path(), &ApplyOptions::default()).unwrap();
 
         // Verify results
         assert_eq!(
@@ -99,6 +546,30 @@ mod tests {
         assert!(!target_dir.path().join("deleted.bin").exists());
     }
 
+    #[test]
+    fn blake3_patch_applies() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("modified.bin"), b"original").unwrap();
+        fs::write(new_dir.path().join("modified.bin"), b"modified").unwrap();
+        fs::write(new_dir.path().join("added.bin"), b"new file").unwrap();
+        let options = patch_create::CreateOptions {
+            hash_algorithm: Algorithm::Blake3,
+            ..OPTIONS
+        };
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &options, None::<fn(Progress)>).unwrap();
+
+        fs::write(target_dir.path().join("modified.bin"), b"original").unwrap();
+
+        // Validation, verification and rollback all hash with BLAKE3
+        run(target_dir.path(), patch_dir.path(), &ApplyOptions::default()).unwrap();
+        assert_eq!(fs::read(target_dir.path().join("modified.bin")).unwrap(), b"modified");
+        assert_eq!(fs::read(target_dir.path().join("added.bin")).unwrap(), b"new file");
+    }
+
     #[test]
     fn validation_rejects_missing_file() {
         let orig_dir = tempdir().unwrap();
@@ -109,10 +580,10 @@ mod tests {
         // Create a patch that modifies a file
         fs::write(orig_dir.path().join("file.bin"), b"original").unwrap();
         fs::write(new_dir.path().join("file.bin"), b"modified").unwrap();
-        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), 1, "TestPatcher", None, true).unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
 
         // Target is missing the file
-        let result = run(target_dir.path(), patch_dir.path());
+        let result = run(target_dir.path(), patch_dir.path(), &ApplyOptions::default());
 
         assert!(matches!(result, Err(PatchError::ValidationFailed { .. })));
     }
@@ -127,16 +598,59 @@ mod tests {
         // Create a patch
         fs::write(orig_dir.path().join("file.bin"), b"original").unwrap();
         fs::write(new_dir.path().join("file.bin"), b"modified").unwrap();
-        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), 1, "TestPatcher", None, true).unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
 
         // Target has different content
         fs::write(target_dir.path().join("file.bin"), b"different").unwrap();
 
-        let result = run(target_dir.path(), patch_dir.path());
+        let result = run(target_dir.path(), patch_dir.path(), &ApplyOptions::default());
 
         assert!(matches!(result, Err(PatchError::ValidationFailed { .. })));
     }
 
+    #[test]
+    fn dry_run_leaves_target_untouched() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("modified.bin"), b"original").unwrap();
+        fs::write(new_dir.path().join("modified.bin"), b"modified").unwrap();
+        fs::write(new_dir.path().join("added.bin"), b"new file").unwrap();
+        fs::write(orig_dir.path().join("deleted.bin"), b"to delete").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        fs::write(target_dir.path().join("modified.bin"), b"original").unwrap();
+        fs::write(target_dir.path().join("deleted.bin"), b"to delete").unwrap();
+
+        let options = ApplyOptions { dry_run: true, ..ApplyOptions::default() };
+        run(target_dir.path(), patch_dir.path(), &options).unwrap();
+
+        assert_eq!(fs::read(target_dir.path().join("modified.bin")).unwrap(), b"original");
+        assert!(target_dir.path().join("deleted.bin").exists());
+        assert!(!target_dir.path().join("added.bin").exists());
+        assert!(!target_dir.path().join(BACKUP_DIR).exists());
+    }
+
+    #[test]
+    fn dry_run_reports_validation_failures() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("file.bin"), b"original").unwrap();
+        fs::write(new_dir.path().join("file.bin"), b"modified").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+        fs::write(target_dir.path().join("file.bin"), b"different").unwrap();
+
+        let options = ApplyOptions { dry_run: true, ..ApplyOptions::default() };
+        let result = run(target_dir.path(), patch_dir.path(), &options);
+
+        assert!(matches!(result, Err(PatchError::ValidationFailed { file, .. }) if file == "file.bin"));
+    }
+
     #[test]
     fn validation_rejects_existing_add_target() {
         let orig_dir = tempdir().unwrap();
@@ -146,12 +660,12 @@ mod tests {
 
         // Create a patch that adds a file
         fs::write(new_dir.path().join("new.bin"), b"new content").unwrap();
-        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), 1, "TestPatcher", None, true).unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
 
         // Target already has that file
         fs::write(target_dir.path().join("new.bin"), b"existing").unwrap();
 
-        let result = run(target_dir.path(), patch_dir.path());
+        let result = run(target_dir.path(), patch_dir.path(), &ApplyOptions::default());
 
         assert!(matches!(result, Err(PatchError::ValidationFailed { .. })));
     }
@@ -165,14 +679,82 @@ mod tests {
 
         // Create a patch that deletes a file
         fs::write(orig_dir.path().join("deleted.bin"), b"content").unwrap();
-        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), 1, "TestPatcher", None, true).unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
 
         // Target doesn't have the file (already deleted)
-        let result = run(target_dir.path(), patch_dir.path());
+        let result = run(target_dir.path(), patch_dir.path(), &ApplyOptions::default());
 
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn preserved_mtimes_are_applied_and_rolled_back() {
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+        let set_mtime = |path: &Path, secs: u64| {
+            let time = UNIX_EPOCH + Duration::from_secs(secs);
+            fs::File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+            time
+        };
+        let mtime = |path: &Path| fs::metadata(path).unwrap().modified().unwrap();
+
+        fs::write(orig_dir.path().join("game.dat"), b"original").unwrap();
+        fs::write(new_dir.path().join("game.dat"), b"modified").unwrap();
+        fs::write(new_dir.path().join("fonts.pak"), b"fonts").unwrap();
+        let patched_time = set_mtime(&new_dir.path().join("game.dat"), 1_600_000_000);
+        let added_time = set_mtime(&new_dir.path().join("fonts.pak"), 1_600_000_100);
+        let options = patch_create::CreateOptions { preserve_mtime: true, ..OPTIONS };
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &options, None::<fn(Progress)>).unwrap();
+
+        let target = target_dir.path().join("game.dat");
+        fs::write(&target, b"original").unwrap();
+        let original_time = set_mtime(&target, 1_500_000_000);
+
+        run(target_dir.path(), patch_dir.path(), &ApplyOptions::default()).unwrap();
+        assert_eq!(mtime(&target), patched_time);
+        assert_eq!(mtime(&target_dir.path().join("fonts.pak")), added_time);
+
+        crate::commands::patch_rollback::run(target_dir.path(), &patch_dir.path().join(MANIFEST_FILENAME), false, None)
+            .unwrap();
+        assert_eq!(mtime(&target), original_time);
+
+        // Without the option, patched files get the time they were written
+        let plain_dir = tempdir().unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), plain_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+        run(target_dir.path(), plain_dir.path(), &ApplyOptions::default()).unwrap();
+        assert!(mtime(&target) > SystemTime::now() - Duration::from_secs(600));
+    }
+
+    #[test]
+    fn compressed_payloads_are_applied() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        let strings = "Bonjour, aventurier !\n".repeat(200);
+        fs::write(orig_dir.path().join("game.dat"), b"original").unwrap();
+        fs::write(new_dir.path().join("game.dat"), b"modified").unwrap();
+        fs::write(new_dir.path().join("strings.txt"), &strings).unwrap();
+        let options = patch_create::CreateOptions { compress_payloads: true, ..OPTIONS };
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &options, None::<fn(Progress)>).unwrap();
+
+        let added = patch_dir.path().join(FILES_DIR).join("strings.txt.gz");
+        assert!(fs::metadata(&added).unwrap().len() < strings.len() as u64);
+        assert!(patch_dir.path().join(DIFFS_DIR).join("game.dat.diff.gz").exists());
+        assert!(!patch_dir.path().join(FILES_DIR).join("strings.txt").exists());
+
+        fs::write(target_dir.path().join("game.dat"), b"original").unwrap();
+        run(target_dir.path(), patch_dir.path(), &ApplyOptions::default()).unwrap();
+
+        assert_eq!(fs::read(target_dir.path().join("game.dat")).unwrap(), b"modified");
+        assert_eq!(fs::read_to_string(target_dir.path().join("strings.txt")).unwrap(), strings);
+    }
+
     #[test]
     fn rollback_restores_on_failure() {
         let orig_dir = tempdir().unwrap();
@@ -185,17 +767,16 @@ mod tests {
         fs::write(new_dir.path().join("a.bin"), b"modified a").unwrap();
         fs::write(orig_dir.path().join("b.bin"), b"original b").unwrap();
         fs::write(new_dir.path().join("b.bin"), b"modified b").unwrap();
-        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), 1, "TestPatcher", None, true).unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
 
         // Set up target correctly for first file, but corrupt the diff for second
         fs::write(target_dir.path().join("a.bin"), b"original a").unwrap();
         fs::write(target_dir.path().join("b.bin"), b"original b").unwrap();
 
         // Corrupt the second diff file to cause apply failure
-        let diffs_dir = patch_dir.path().join("diffs");
-        fs::write(diffs_dir.join("b.bin.diff"), b"corrupted diff data").unwrap();
+        corrupt_diff(patch_dir.path(), "b.bin");
 
-        let result = run(target_dir.path(), patch_dir.path());
+        let result = run(target_dir.path(), patch_dir.path(), &ApplyOptions::default());
 
         // Should fail
         assert!(result.is_err());
@@ -207,6 +788,309 @@ mod tests {
         );
     }
 
+    #[test]
+    fn corrupted_patch_fails_before_backup() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("a.bin"), b"original a").unwrap();
+        fs::write(new_dir.path().join("a.bin"), b"modified a").unwrap();
+        fs::write(target_dir.path().join("a.bin"), b"original a").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+        fs::write(patch_dir.path().join("diffs/a.bin.diff"), b"truncated download").unwrap();
+
+        let result = run(target_dir.path(), patch_dir.path(), &ApplyOptions::default());
+
+        assert!(matches!(result, Err(PatchError::ValidationFailed { file, .. }) if file == "a.bin"));
+        assert!(!target_dir.path().join(BACKUP_DIR).exists());
+        assert_eq!(fs::read(target_dir.path().join("a.bin")).unwrap(), b"original a");
+    }
+
+    #[test]
+    fn nested_files_with_same_name_roll_back_separately() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        for dir in [orig_dir.path(), new_dir.path(), target_dir.path()] {
+            fs::create_dir_all(dir.join("en")).unwrap();
+            fs::create_dir_all(dir.join("fr")).unwrap();
+        }
+        for lang in ["en", "fr"] {
+            let file = format!("{}/strings.txt", lang);
+            fs::write(orig_dir.path().join(&file), format!("original {}", lang)).unwrap();
+            fs::write(new_dir.path().join(&file), format!("modified {}", lang)).unwrap();
+            fs::write(target_dir.path().join(&file), format!("original {}", lang)).unwrap();
+        }
+        fs::write(new_dir.path().join("fr/fonts.pak"), b"fonts").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+        corrupt_diff(patch_dir.path(), "fr/strings.txt");
+
+        let result = run(target_dir.path(), patch_dir.path(), &ApplyOptions::default());
+
+        assert!(result.is_err());
+        for lang in ["en", "fr"] {
+            assert_eq!(
+                fs::read_to_string(target_dir.path().join(format!("{}/strings.txt", lang))).unwrap(),
+                format!("original {}", lang)
+            );
+        }
+        assert!(!target_dir.path().join("fr/fonts.pak").exists());
+    }
+
+    #[test]
+    fn parallel_apply_rolls_back_on_failure() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        for i in 0..10 {
+            let name = format!("file{}.bin", i);
+            fs::write(orig_dir.path().join(&name), format!("original {}", i)).unwrap();
+            fs::write(new_dir.path().join(&name), format!("modified {}", i)).unwrap();
+            fs::write(target_dir.path().join(&name), format!("original {}", i)).unwrap();
+        }
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        // Several entries fail at once
+        for i in [2, 5, 7] {
+            corrupt_diff(patch_dir.path(), &format!("file{}.bin", i));
+        }
+
+        let options = ApplyOptions { jobs: 4, ..ApplyOptions::default() };
+        let result = run(target_dir.path(), patch_dir.path(), &options);
+
+        assert!(matches!(result, Err(PatchError::ApplyFailed { .. })));
+        for i in 0..10 {
+            assert_eq!(
+                fs::read_to_string(target_dir.path().join(format!("file{}.bin", i))).unwrap(),
+                format!("original {}", i)
+            );
+        }
+    }
+
+    #[test]
+    fn operations_filter_stages_apply() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("modified.bin"), b"original").unwrap();
+        fs::write(new_dir.path().join("modified.bin"), b"modified").unwrap();
+        fs::write(orig_dir.path().join("deleted.bin"), b"deleted").unwrap();
+        fs::write(new_dir.path().join("added.bin"), b"added").unwrap();
+        fs::write(target_dir.path().join("modified.bin"), b"original").unwrap();
+        fs::write(target_dir.path().join("deleted.bin"), b"deleted").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        // Only the additions first
+        let only_add = ["add".to_string()];
+        let options = ApplyOptions { operations: &only_add, ..ApplyOptions::default() };
+        run(target_dir.path(), patch_dir.path(), &options).unwrap();
+
+        assert_eq!(fs::read(target_dir.path().join("added.bin")).unwrap(), b"added");
+        assert_eq!(fs::read(target_dir.path().join("modified.bin")).unwrap(), b"original");
+        assert!(target_dir.path().join("deleted.bin").exists());
+
+        // Then the rest
+        let rest = ["patch".to_string(), "delete".to_string()];
+        let options = ApplyOptions { operations: &rest, ..ApplyOptions::default() };
+        run(target_dir.path(), patch_dir.path(), &options).unwrap();
+
+        assert_eq!(fs::read(target_dir.path().join("modified.bin")).unwrap(), b"modified");
+        assert!(!target_dir.path().join("deleted.bin").exists());
+    }
+
+    #[test]
+    fn rerun_resumes_interrupted_apply() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("a.bin"), b"original a").unwrap();
+        fs::write(new_dir.path().join("a.bin"), b"patched a").unwrap();
+        fs::write(orig_dir.path().join("b.bin"), b"original b").unwrap();
+        fs::write(new_dir.path().join("b.bin"), b"patched b").unwrap();
+        fs::write(orig_dir.path().join("deleted.bin"), b"deleted").unwrap();
+        fs::write(new_dir.path().join("added.bin"), b"added").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        // An earlier run backed everything up, then was killed after
+        // applying every entry except the patch to b.bin
+        for file in ["a.bin", "b.bin", "deleted.bin"] {
+            fs::copy(orig_dir.path().join(file), target_dir.path().join(file)).unwrap();
+        }
+        let manifest = Manifest::load(&patch_dir.path().join(MANIFEST_FILENAME)).unwrap();
+        let backup_dir = target_dir.path().join(BACKUP_DIR);
+        backup_entries(&manifest.entries, target_dir.path(), &backup_dir, manifest.hash_algorithm, None::<fn(Progress)>).unwrap();
+        fs::write(target_dir.path().join("a.bin"), b"patched a").unwrap();
+        fs::write(target_dir.path().join("added.bin"), b"added").unwrap();
+        fs::remove_file(target_dir.path().join("deleted.bin")).unwrap();
+
+        run(target_dir.path(), patch_dir.path(), &ApplyOptions::default()).unwrap();
+
+        for file in ["a.bin", "b.bin", "added.bin"] {
+            assert_eq!(
+                fs::read(target_dir.path().join(file)).unwrap(),
+                fs::read(new_dir.path().join(file)).unwrap()
+            );
+        }
+        assert!(!target_dir.path().join("deleted.bin").exists());
+
+        // The backup covers both runs, so rollback restores everything
+        crate::commands::patch_rollback::run(target_dir.path(), &patch_dir.path().join(MANIFEST_FILENAME), false, None)
+            .unwrap();
+        for file in ["a.bin", "b.bin", "deleted.bin"] {
+            assert_eq!(
+                fs::read(target_dir.path().join(file)).unwrap(),
+                fs::read(orig_dir.path().join(file)).unwrap()
+            );
+        }
+        assert!(!target_dir.path().join("added.bin").exists());
+    }
+
+    #[test]
+    fn already_patched_files_fail_validation_without_backup() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("a.bin"), b"original a").unwrap();
+        fs::write(new_dir.path().join("a.bin"), b"patched a").unwrap();
+        fs::write(orig_dir.path().join("b.bin"), b"original b").unwrap();
+        fs::write(new_dir.path().join("b.bin"), b"patched b").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        // Half-patched, but not by graft: nothing to resume from
+        fs::write(target_dir.path().join("a.bin"), b"patched a").unwrap();
+        fs::write(target_dir.path().join("b.bin"), b"original b").unwrap();
+
+        let result = run(target_dir.path(), patch_dir.path(), &ApplyOptions::default());
+
+        assert!(matches!(result, Err(PatchError::ValidationFailed { .. })));
+        assert_eq!(fs::read(target_dir.path().join("b.bin")).unwrap(), b"original b");
+    }
+
+    #[test]
+    fn custom_backup_dir_is_used_for_apply_and_rollback() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+        let elsewhere = tempdir().unwrap();
+        let backup_dir = elsewhere.path().join("game-backup");
+
+        for dir in [orig_dir.path(), new_dir.path(), target_dir.path()] {
+            fs::create_dir_all(dir.join("en")).unwrap();
+            fs::create_dir_all(dir.join("fr")).unwrap();
+        }
+        for (dir, prefix) in [(orig_dir.path(), "original"), (new_dir.path(), "patched")] {
+            fs::write(dir.join("en/strings.bin"), format!("{} en", prefix)).unwrap();
+            fs::write(dir.join("fr/strings.bin"), format!("{} fr", prefix)).unwrap();
+        }
+        fs::write(target_dir.path().join("en/strings.bin"), "original en").unwrap();
+        fs::write(target_dir.path().join("fr/strings.bin"), "original fr").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        let options = ApplyOptions { backup_dir: Some(&backup_dir), ..ApplyOptions::default() };
+        run(target_dir.path(), patch_dir.path(), &options).unwrap();
+
+        assert!(!target_dir.path().join(BACKUP_DIR).exists());
+        assert_eq!(fs::read_to_string(backup_dir.join("en/strings.bin")).unwrap(), "original en");
+        assert_eq!(fs::read_to_string(backup_dir.join("fr/strings.bin")).unwrap(), "original fr");
+
+        let manifest_path = patch_dir.path().join(MANIFEST_FILENAME);
+        crate::commands::patch_rollback::run(target_dir.path(), &manifest_path, false, Some(&backup_dir)).unwrap();
+
+        assert_eq!(fs::read_to_string(target_dir.path().join("en/strings.bin")).unwrap(), "original en");
+        assert_eq!(fs::read_to_string(target_dir.path().join("fr/strings.bin")).unwrap(), "original fr");
+    }
+
+    #[test]
+    fn parallel_apply_leaves_no_temp_files() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        for i in 0..20 {
+            let name = format!("file{}.bin", i);
+            fs::write(orig_dir.path().join(&name), format!("original {}", i)).unwrap();
+            fs::write(new_dir.path().join(&name), format!("modified {}", i)).unwrap();
+            fs::write(target_dir.path().join(&name), format!("original {}", i)).unwrap();
+        }
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+        let stray = target_dir.path().join(format!("{}4294967295-0-0", TEMP_PREFIX));
+        fs::write(&stray, b"half written").unwrap();
+
+        let options = ApplyOptions { jobs: 8, ..ApplyOptions::default() };
+        run(target_dir.path(), patch_dir.path(), &options).unwrap();
+
+        let leftovers = fs::read_dir(target_dir.path())
+            .unwrap()
+            .filter(|e| is_temp_file(&e.as_ref().unwrap().file_name().to_string_lossy()))
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn force_applies_over_different_original_and_can_roll_back() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        // Nothing of the original survives, so the diff rebuilds the new
+        // file from any original
+        fs::write(orig_dir.path().join("file.bin"), b"original").unwrap();
+        fs::write(new_dir.path().join("file.bin"), b"ZYXWVUTS").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+        fs::write(target_dir.path().join("file.bin"), b"regional").unwrap();
+
+        let err = run(target_dir.path(), patch_dir.path(), &ApplyOptions::default()).unwrap_err();
+        assert!(matches!(err, PatchError::ValidationFailed { .. }));
+
+        let options = ApplyOptions { force: true, ..ApplyOptions::default() };
+        run(target_dir.path(), patch_dir.path(), &options).unwrap();
+        assert_eq!(fs::read(target_dir.path().join("file.bin")).unwrap(), b"ZYXWVUTS");
+
+        crate::commands::patch_rollback::run(target_dir.path(), &patch_dir.path().join(MANIFEST_FILENAME), false, None)
+            .unwrap();
+        assert_eq!(fs::read(target_dir.path().join("file.bin")).unwrap(), b"regional");
+    }
+
+    #[test]
+    fn force_still_rolls_back_when_result_does_not_verify() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("file.bin"), b"hello world, hello world").unwrap();
+        fs::write(new_dir.path().join("file.bin"), b"hello world, hello there").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+        fs::write(target_dir.path().join("file.bin"), b"HELLO WORLD, HELLO WORLD").unwrap();
+
+        let options = ApplyOptions {
+            force: true,
+            verification: Verification::EveryNth(100),
+            ..ApplyOptions::default()
+        };
+        let err = run(target_dir.path(), patch_dir.path(), &options).unwrap_err();
+        assert!(matches!(err, PatchError::VerificationFailed { .. }), "{}", err);
+        assert_eq!(
+            fs::read(target_dir.path().join("file.bin")).unwrap(),
+            b"HELLO WORLD, HELLO WORLD"
+        );
+    }
+
     #[test]
     fn backup_preserved_on_success() {
         let orig_dir = tempdir().unwrap();
@@ -216,11 +1100,11 @@ mod tests {
 
         fs::write(orig_dir.path().join("file.bin"), b"original").unwrap();
         fs::write(new_dir.path().join("file.bin"), b"modified").unwrap();
-        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), 1, "TestPatcher", None, true).unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
 
         fs::write(target_dir.path().join("file.bin"), b"original").unwrap();
 
-        run(target_dir.path(), patch_dir.path()).unwrap();
+        run(target_dir.path(), patch_dir.path(), &ApplyOptions::default()).unwrap();
 
         // Backup directory should exist with original file
         let backup_dir = target_dir.path().join(BACKUP_DIR);
@@ -228,13 +1112,129 @@ mod tests {
         assert_eq!(fs::read(backup_dir.join("file.bin")).unwrap(), b"original");
     }
 
+    #[test]
+    fn backup_deleted_on_success_when_asked() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("file.bin"), b"original").unwrap();
+        fs::write(new_dir.path().join("file.bin"), b"modified").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        fs::write(target_dir.path().join("file.bin"), b"original").unwrap();
+
+        let options = ApplyOptions { delete_backup: true, ..ApplyOptions::default() };
+        run(target_dir.path(), patch_dir.path(), &options).unwrap();
+
+        assert_eq!(fs::read(target_dir.path().join("file.bin")).unwrap(), b"modified");
+        assert!(!target_dir.path().join(BACKUP_DIR).exists());
+    }
+
+    #[test]
+    fn backup_kept_on_failure_even_when_deleting() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("a.bin"), b"original a").unwrap();
+        fs::write(new_dir.path().join("a.bin"), b"modified a").unwrap();
+        fs::write(orig_dir.path().join("b.bin"), b"original b").unwrap();
+        fs::write(new_dir.path().join("b.bin"), b"modified b").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        fs::write(target_dir.path().join("a.bin"), b"original a").unwrap();
+        fs::write(target_dir.path().join("b.bin"), b"original b").unwrap();
+        corrupt_diff(patch_dir.path(), "b.bin");
+
+        let options = ApplyOptions { delete_backup: true, ..ApplyOptions::default() };
+        assert!(run(target_dir.path(), patch_dir.path(), &options).is_err());
+
+        assert_eq!(fs::read(target_dir.path().join("a.bin")).unwrap(), b"original a");
+        assert!(target_dir.path().join(BACKUP_DIR).join("a.bin").exists());
+    }
+
     #[test]
     fn missing_manifest_returns_error() {
         let target_dir = tempdir().unwrap();
         let patch_dir = tempdir().unwrap();
 
-        let result = run(target_dir.path(), patch_dir.path());
+        let result = run(target_dir.path(), patch_dir.path(), &ApplyOptions::default());
 
         assert!(matches!(result, Err(PatchError::ManifestError { .. })));
     }
+
+    #[test]
+    fn graftignore_in_target_skips_matching_entries() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("modified.bin"), b"original").unwrap();
+        fs::write(new_dir.path().join("modified.bin"), b"modified").unwrap();
+        fs::write(orig_dir.path().join("slot1.sav"), b"old save").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+
+        // Player's save would be deleted by the patch, but is ignored
+        fs::write(target_dir.path().join("modified.bin"), b"original").unwrap();
+        fs::write(target_dir.path().join("slot1.sav"), b"my save").unwrap();
+        fs::write(target_dir.path().join(".graftignore"), "*.sav\n").unwrap();
+
+        run(target_dir.path(), patch_dir.path(), &ApplyOptions::default()).unwrap();
+
+        assert_eq!(
+            fs::read(target_dir.path().join("modified.bin")).unwrap(),
+            b"modified"
+        );
+        assert_eq!(
+            fs::read(target_dir.path().join("slot1.sav")).unwrap(),
+            b"my save"
+        );
+
+        // Rollback undoes what was applied even once the pattern is gone
+        fs::remove_file(target_dir.path().join(".graftignore")).unwrap();
+        let manifest_path = patch_dir.path().join(MANIFEST_FILENAME);
+        crate::commands::patch_rollback::run(target_dir.path(), &manifest_path, false, None).unwrap();
+        assert_eq!(
+            fs::read(target_dir.path().join("modified.bin")).unwrap(),
+            b"original"
+        );
+        assert_eq!(
+            fs::read(target_dir.path().join("slot1.sav")).unwrap(),
+            b"my save"
+        );
+    }
+
+    #[test]
+    fn match_by_content_handles_renamed_files() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("data.bin"), b"original").unwrap();
+        fs::write(new_dir.path().join("data.bin"), b"modified").unwrap();
+        fs::write(orig_dir.path().join("voice_01.bin"), b"voice").unwrap();
+        fs::write(new_dir.path().join("a7f3.bin"), b"voice").unwrap();
+        let options = patch_create::CreateOptions {
+            match_by_content: true,
+            ..OPTIONS
+        };
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &options, None::<fn(Progress)>).unwrap();
+
+        // This copy of the game has data.bin under a different name
+        fs::write(target_dir.path().join("shuffled.bin"), b"original").unwrap();
+        fs::write(target_dir.path().join("voice_01.bin"), b"voice").unwrap();
+
+        run(target_dir.path(), patch_dir.path(), &ApplyOptions::default()).unwrap();
+
+        assert_eq!(fs::read(target_dir.path().join("data.bin")).unwrap(), b"modified");
+        assert_eq!(fs::read(target_dir.path().join("a7f3.bin")).unwrap(), b"voice");
+        assert!(!target_dir.path().join("shuffled.bin").exists());
+        assert!(!target_dir.path().join("voice_01.bin").exists());
+        assert!(!patch_dir.path().join("files").join("a7f3.bin").exists());
+    }
 }
@@ -1,275 +1,187 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::io;
 use std::path::Path;
 
-use graft_core::patch::{ASSETS_DIR, DIFFS_DIR, DIFF_EXTENSION, FILES_DIR, ICON_FILENAME, MANIFEST_FILENAME};
-use graft_core::utils::diff::create_diff;
-use graft_core::utils::dir_scan::{categorize_files, FileChange};
-use graft_core::utils::hash::hash_bytes;
-use graft_core::utils::manifest::{Manifest, ManifestEntry};
+use graft_core::patch::{self, Progress, ASSETS_DIR, ICON_FILENAME, MANIFEST_FILENAME};
+use graft_core::utils::dir_scan::ScanSummary;
+use graft_core::utils::hash::Algorithm;
+use graft_core::utils::manifest::{Manifest, PatchInfo};
+
+pub use graft_core::patch::CreateOptions;
 
 /// Default icon embedded at compile time
 const DEFAULT_ICON: &[u8] = include_bytes!("../../assets/default_icon.png");
 
-/// Create a patch from two directories.
-/// Outputs a patch directory containing manifest.json, diffs/, and files/.
+/// Detect changes between two directories without creating a patch.
+/// Writes the list of changes as JSON to `changes_path` so it can be
+/// reviewed and later passed to `run_approved`.
 ///
-/// If `allow_restricted` is true, the resulting manifest will allow patching
-/// restricted paths (system directories, executables). Default is false for security.
-pub fn run(
+/// Returns the scan summary, with the changes written and the files found
+/// identical.
+pub fn detect(
     orig_dir: &Path,
     new_dir: &Path,
-    output_dir: &Path,
-    version: u32,
-    name: &str,
-    title: Option<&str>,
-    allow_restricted: bool,
-) -> io::Result<()> {
-    let changes = categorize_files(orig_dir, new_dir)?;
+    changes_path: &Path,
+    case_insensitive: bool,
+    algorithm: Algorithm,
+    text_extensions: &[String],
+    text_encoding: Option<&str>,
+) -> io::Result<ScanSummary> {
+    patch::detect_changes(orig_dir, new_dir, changes_path, case_insensitive, algorithm, text_extensions, text_encoding)
+}
 
-    // Create output directory structure
-    fs::create_dir_all(output_dir)?;
-    let diffs_dir = output_dir.join(DIFFS_DIR);
-    let files_dir = output_dir.join(FILES_DIR);
+/// Load a labels file: a JSON object mapping relative paths to the label
+/// players see while that file is applied, e.g.
+/// `{"data0.bin": "Spanish dialogue"}`.
+pub fn load_labels(path: &Path) -> io::Result<BTreeMap<String, String>> {
+    let content = fs::read_to_string(path)?;
+    serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
 
-    // Only create subdirs if we need them
-    let has_diffs = changes.iter().any(|c| matches!(c, FileChange::Diff { .. }));
-    let has_new = changes.iter().any(|c| matches!(c, FileChange::New { .. }));
+/// Load a file list: one relative path per line, with `/` separators.
+/// Blank lines and lines starting with `#` are skipped.
+pub fn load_file_list(path: &Path) -> io::Result<Vec<String>> {
+    let content = fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
 
-    if has_diffs {
-        fs::create_dir_all(&diffs_dir)?;
-    }
-    if has_new {
-        fs::create_dir_all(&files_dir)?;
+/// Create a patch from two directories with `patch::create`, and give it
+/// the default patcher icon (unless only the manifest is written).
+pub fn run<F>(
+    orig_dir: &Path,
+    new_dir: &Path,
+    output_dir: &Path,
+    options: &CreateOptions,
+    on_progress: Option<F>,
+) -> io::Result<()>
+where
+    F: FnMut(Progress),
+{
+    patch::create(orig_dir, new_dir, output_dir, options, on_progress)?;
+    if options.manifest_only {
+        return Ok(());
     }
+    write_default_icon(output_dir)
+}
 
-    let mut manifest = Manifest::new(version, name.to_string(), title.map(|s| s.to_string()));
-    manifest.allow_restricted = allow_restricted;
-
-    for change in changes {
-        let entry = match change {
-            FileChange::Diff {
-                file,
-                original_hash,
-                final_hash,
-            } => {
-                // Read files and create diff
-                let orig_data = fs::read(orig_dir.join(&file))?;
-                let new_data = fs::read(new_dir.join(&file))?;
-                let diff_data = create_diff(&orig_data, &new_data)?;
-
-                // Write diff file
-                let diff_path = diffs_dir.join(format!("{}{}", file, DIFF_EXTENSION));
-                fs::write(&diff_path, &diff_data)?;
-
-                // Compute diff hash
-                let diff_hash = hash_bytes(&diff_data);
-
-                ManifestEntry::Patch {
-                    file,
-                    original_hash,
-                    diff_hash,
-                    final_hash,
-                }
-            }
-            FileChange::New { file, final_hash } => {
-                // Copy new file to files/
-                let src_path = new_dir.join(&file);
-                let dest_path = files_dir.join(&file);
-                fs::copy(&src_path, &dest_path)?;
-
-                ManifestEntry::Add { file, final_hash }
-            }
-            FileChange::Old {
-                file,
-                original_hash,
-            } => {
-                // Nothing to write, just record in manifest
-                ManifestEntry::Delete { file, original_hash }
-            }
-        };
-
-        manifest.entries.push(entry);
+/// Create a patch from the approved changes in `changes_path` with
+/// `patch::create_approved`, and give it the default patcher icon (unless
+/// only the manifest is written).
+pub fn run_approved<F>(
+    orig_dir: &Path,
+    new_dir: &Path,
+    output_dir: &Path,
+    changes_path: &Path,
+    options: &CreateOptions,
+    on_progress: Option<F>,
+) -> io::Result<()>
+where
+    F: FnMut(Progress),
+{
+    patch::create_approved(orig_dir, new_dir, output_dir, changes_path, options, on_progress)?;
+    if options.manifest_only {
+        return Ok(());
     }
+    write_default_icon(output_dir)
+}
 
-    // Sort entries by filename for consistent output
-    manifest.entries.sort_by(|a, b| a.file().cmp(b.file()));
+/// Summarize the changes in the patch written to `output_dir`, one line per
+/// kind of change, e.g. "Patched: 3".
+pub fn summary(output_dir: &Path) -> io::Result<String> {
+    let manifest = Manifest::load(&output_dir.join(MANIFEST_FILENAME))?;
+    let info = PatchInfo::from_manifest(&manifest);
+    if info.entry_count == 0 {
+        return Ok("No changes".to_string());
+    }
 
-    // Write manifest
-    let manifest_path = output_dir.join(MANIFEST_FILENAME);
-    manifest.save(&manifest_path)?;
+    let mut lines = Vec::new();
+    for (label, count) in [
+        ("Patched", info.patches),
+        ("Added", info.additions),
+        ("Deleted", info.deletions),
+        ("Renamed", info.renames),
+        ("Symlinks", info.symlinks),
+        ("Directories", info.directories),
+    ] {
+        if count > 0 {
+            lines.push(format!("  {:<12} {}", format!("{}:", label), count));
+        }
+    }
+    Ok(format!("{} changes:\n{}", info.entry_count, lines.join("\n")))
+}
 
-    // Create assets directory with default icon
+/// Create the assets directory with the default icon.
+fn write_default_icon(output_dir: &Path) -> io::Result<()> {
     let assets_dir = output_dir.join(ASSETS_DIR);
     fs::create_dir_all(&assets_dir)?;
-    fs::write(assets_dir.join(ICON_FILENAME), DEFAULT_ICON)?;
-
-    Ok(())
+    fs::write(assets_dir.join(ICON_FILENAME), DEFAULT_ICON)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use graft_core::utils::diff::apply_diff;
     use tempfile::tempdir;
 
     #[test]
-    fn creates_directory_structure() {
-        let orig_dir = tempdir().unwrap();
-        let new_dir = tempdir().unwrap();
-        let output_dir = tempdir().unwrap();
-
-        // Create a modified file (triggers diffs/ creation)
-        fs::write(orig_dir.path().join("modified.bin"), b"old").unwrap();
-        fs::write(new_dir.path().join("modified.bin"), b"new").unwrap();
-
-        // Create a new file (triggers files/ creation)
-        fs::write(new_dir.path().join("added.bin"), b"added").unwrap();
-
-        run(orig_dir.path(), new_dir.path(), output_dir.path(), 1, "TestPatcher", None, false).unwrap();
-
-        assert!(output_dir.path().join("manifest.json").exists());
-        assert!(output_dir.path().join("diffs").exists());
-        assert!(output_dir.path().join("files").exists());
-    }
-
-    #[test]
-    fn creates_valid_diffs() {
-        let orig_dir = tempdir().unwrap();
-        let new_dir = tempdir().unwrap();
-        let output_dir = tempdir().unwrap();
-
-        let orig_content = b"original content here";
-        let new_content = b"modified content here";
-
-        fs::write(orig_dir.path().join("file.bin"), orig_content).unwrap();
-        fs::write(new_dir.path().join("file.bin"), new_content).unwrap();
-
-        run(orig_dir.path(), new_dir.path(), output_dir.path(), 1, "TestPatcher", None, false).unwrap();
-
-        // Read the diff and apply it
-        let diff_data = fs::read(output_dir.path().join("diffs").join("file.bin.diff")).unwrap();
-        let result = apply_diff(orig_content, &diff_data).unwrap();
-
-        assert_eq!(result, new_content);
-    }
-
-    #[test]
-    fn copies_new_files() {
-        let orig_dir = tempdir().unwrap();
-        let new_dir = tempdir().unwrap();
-        let output_dir = tempdir().unwrap();
-
-        let content = b"new file content";
-        fs::write(new_dir.path().join("new.bin"), content).unwrap();
-
-        run(orig_dir.path(), new_dir.path(), output_dir.path(), 1, "TestPatcher", None, false).unwrap();
-
-        let copied = fs::read(output_dir.path().join("files").join("new.bin")).unwrap();
-        assert_eq!(copied, content);
-    }
-
-    #[test]
-    fn manifest_contains_correct_entries() {
+    fn run_adds_default_icon() {
         let orig_dir = tempdir().unwrap();
         let new_dir = tempdir().unwrap();
         let output_dir = tempdir().unwrap();
-
-        // Modified file
-        fs::write(orig_dir.path().join("modified.bin"), b"old").unwrap();
-        fs::write(new_dir.path().join("modified.bin"), b"new").unwrap();
-
-        // New file
         fs::write(new_dir.path().join("added.bin"), b"added").unwrap();
+        let options = CreateOptions::new(1, "TestPatcher");
 
-        // Deleted file
-        fs::write(orig_dir.path().join("deleted.bin"), b"deleted").unwrap();
-
-        // Unchanged file (should not appear in manifest)
-        fs::write(orig_dir.path().join("unchanged.bin"), b"same").unwrap();
-        fs::write(new_dir.path().join("unchanged.bin"), b"same").unwrap();
-
-        run(orig_dir.path(), new_dir.path(), output_dir.path(), 1, "TestPatcher", None, false).unwrap();
+        run(orig_dir.path(), new_dir.path(), output_dir.path(), &options, None::<fn(Progress)>).unwrap();
 
-        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
-
-        assert_eq!(manifest.entries.len(), 3);
-
-        // Check each entry type exists
-        assert!(manifest
-            .entries
-            .iter()
-            .any(|e| matches!(e, ManifestEntry::Patch { file, .. } if file == "modified.bin")));
-        assert!(manifest
-            .entries
-            .iter()
-            .any(|e| matches!(e, ManifestEntry::Add { file, .. } if file == "added.bin")));
-        assert!(manifest
-            .entries
-            .iter()
-            .any(|e| matches!(e, ManifestEntry::Delete { file, .. } if file == "deleted.bin")));
-    }
-
-    #[test]
-    fn manifest_has_correct_hashes() {
-        let orig_dir = tempdir().unwrap();
-        let new_dir = tempdir().unwrap();
-        let output_dir = tempdir().unwrap();
-
-        let orig_content = b"original";
-        let new_content = b"modified";
-
-        fs::write(orig_dir.path().join("file.bin"), orig_content).unwrap();
-        fs::write(new_dir.path().join("file.bin"), new_content).unwrap();
-
-        run(orig_dir.path(), new_dir.path(), output_dir.path(), 1, "TestPatcher", None, false).unwrap();
-
-        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
-
-        if let ManifestEntry::Patch {
-            original_hash,
-            diff_hash,
-            final_hash,
-            ..
-        } = &manifest.entries[0]
-        {
-            assert_eq!(original_hash, &hash_bytes(orig_content));
-            assert_eq!(final_hash, &hash_bytes(new_content));
-
-            // Verify diff_hash matches the actual diff file
-            let diff_data =
-                fs::read(output_dir.path().join("diffs").join("file.bin.diff")).unwrap();
-            assert_eq!(diff_hash, &hash_bytes(&diff_data));
-        } else {
-            panic!("Expected Patch entry");
-        }
+        let icon = fs::read(output_dir.path().join(ASSETS_DIR).join(ICON_FILENAME)).unwrap();
+        assert_eq!(icon, DEFAULT_ICON);
+        assert!(output_dir.path().join("files").join("added.bin").exists());
     }
 
     #[test]
-    fn empty_directories_creates_empty_manifest() {
+    fn labels_file_names_entries() {
         let orig_dir = tempdir().unwrap();
         let new_dir = tempdir().unwrap();
         let output_dir = tempdir().unwrap();
-
-        run(orig_dir.path(), new_dir.path(), output_dir.path(), 1, "TestPatcher", None, false).unwrap();
-
-        let manifest = Manifest::load(&output_dir.path().join("manifest.json")).unwrap();
-        assert!(manifest.entries.is_empty());
+        fs::write(orig_dir.path().join("data0.bin"), b"hello").unwrap();
+        fs::write(new_dir.path().join("data0.bin"), b"hola").unwrap();
+        fs::write(new_dir.path().join("data1.bin"), b"menu").unwrap();
+        let labels_path = output_dir.path().join("labels.json");
+        fs::write(&labels_path, r#"{"data0.bin": "Spanish dialogue"}"#).unwrap();
+
+        let labels = load_labels(&labels_path).unwrap();
+        let options = CreateOptions { labels: Some(&labels), ..CreateOptions::new(1, "TestPatcher") };
+        let manifest = patch::create(orig_dir.path(), new_dir.path(), &output_dir.path().join("patch"), &options, None::<fn(Progress)>)
+            .unwrap();
+
+        let labels: Vec<_> = manifest.entries.iter().map(|e| e.label()).collect();
+        assert_eq!(labels, vec![Some("Spanish dialogue"), None]);
+
+        fs::write(&labels_path, "[]").unwrap();
+        assert_eq!(load_labels(&labels_path).unwrap_err().kind(), io::ErrorKind::InvalidData);
     }
 
     #[test]
-    fn skips_unnecessary_subdirs() {
+    fn summary_counts_changes_by_kind() {
         let orig_dir = tempdir().unwrap();
         let new_dir = tempdir().unwrap();
         let output_dir = tempdir().unwrap();
-
-        // Only a deleted file - no diffs/ or files/ needed
-        fs::write(orig_dir.path().join("deleted.bin"), b"deleted").unwrap();
-
-        run(orig_dir.path(), new_dir.path(), output_dir.path(), 1, "TestPatcher", None, false).unwrap();
-
-        assert!(output_dir.path().join("manifest.json").exists());
-        assert!(!output_dir.path().join("diffs").exists());
-        assert!(!output_dir.path().join("files").exists());
+        fs::write(orig_dir.path().join("dialogue.bin"), b"hello").unwrap();
+        fs::write(new_dir.path().join("dialogue.bin"), b"hola").unwrap();
+        fs::write(orig_dir.path().join("old.bin"), b"old").unwrap();
+        fs::write(new_dir.path().join("menu.bin"), b"menu").unwrap();
+        fs::write(new_dir.path().join("font.bin"), b"font").unwrap();
+        let options = CreateOptions::new(1, "TestPatcher");
+        run(orig_dir.path(), new_dir.path(), output_dir.path(), &options, None::<fn(Progress)>).unwrap();
+
+        assert_eq!(
+            summary(output_dir.path()).unwrap(),
+            "4 changes:\n  Patched:     1\n  Added:       2\n  Deleted:     1"
+        );
     }
 }
@@ -0,0 +1,49 @@
+use std::path::Path;
+
+use graft_core::patch::{PatchError, MANIFEST_FILENAME};
+use graft_core::utils::manifest::Manifest;
+
+/// Record the current `manifest_hash` in the manifest of `patch_dir`, after
+/// it was edited by hand or saved by a version that didn't record one.
+///
+/// Only the manifest is rewritten; the patch files it refers to aren't
+/// checked. Returns the recorded hash.
+pub fn run(patch_dir: &Path) -> Result<String, PatchError> {
+    let manifest = Manifest::rehash(&patch_dir.join(MANIFEST_FILENAME)).map_err(|e| PatchError::ManifestError {
+        reason: e.to_string(),
+    })?;
+    manifest.content_hash().map_err(|e| PatchError::ManifestError {
+        reason: e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn hand_edited_manifest_loads_after_rehash() {
+        let patch_dir = tempdir().unwrap();
+        let manifest_path = patch_dir.path().join(MANIFEST_FILENAME);
+        Manifest::new(1, "Patcher".to_string(), Some("Old title".to_string()))
+            .save(&manifest_path)
+            .unwrap();
+        let edited = fs::read_to_string(&manifest_path).unwrap().replace("Old title", "New title");
+        fs::write(&manifest_path, edited).unwrap();
+        assert!(Manifest::load(&manifest_path).is_err());
+
+        let hash = run(patch_dir.path()).unwrap();
+
+        let manifest = Manifest::load(&manifest_path).unwrap();
+        assert_eq!(manifest.title.as_deref(), Some("New title"));
+        assert!(fs::read_to_string(&manifest_path).unwrap().contains(&hash));
+    }
+
+    #[test]
+    fn missing_manifest_is_an_error() {
+        let patch_dir = tempdir().unwrap();
+        assert!(matches!(run(patch_dir.path()), Err(PatchError::ManifestError { .. })));
+    }
+}
@@ -1,11 +1,13 @@
 use std::path::Path;
 
 use graft_core::patch::{
-    rollback, validate_backup, validate_patched_entries, PatchError, Progress, ProgressAction,
-    BACKUP_DIR,
+    applied_entries, find_modified_entries, install_dir, rollback, validate_backup,
+    validate_patched_entries, PatchError, Progress, ProgressAction,
 };
 use graft_core::utils::manifest::Manifest;
 
+use crate::commands::patch_apply::{backup_dir_for, skip_ignored};
+
 fn format_action(action: ProgressAction) -> &'static str {
     match action {
         ProgressAction::Validating => "Validating",
@@ -15,43 +17,79 @@ fn format_action(action: ProgressAction) -> &'static str {
         ProgressAction::Patching => "Patching",
         ProgressAction::Adding => "Adding",
         ProgressAction::Deleting => "Deleting",
+        ProgressAction::Renaming => "Renaming",
+        ProgressAction::Linking => "Linking",
+        ProgressAction::Creating => "Creating",
         ProgressAction::Restoring => "Restoring",
         ProgressAction::Removing => "Removing",
+        ProgressAction::Diffing => "Diffing",
+        ProgressAction::Copying => "Copying",
+        ProgressAction::Recording => "Recording",
     }
 }
 
 /// Rollback a previously applied patch using the backup directory.
 ///
-/// This restores files from `.patch-backup` to their original state.
+/// This restores files from `.patch-backup` (or `backup_dir`, if the patch
+/// was applied with one) to their original state.
+/// Only the entries the backup records as applied are rolled back; for a
+/// backup that doesn't record them, entries matched by the target's
+/// `.graftignore` are left untouched, as in apply.
 ///
 /// If `force` is false, validates that patched files are in expected state first.
 /// If `force` is true, skips patched files validation (but still validates backups).
-pub fn run(target_dir: &Path, manifest_path: &Path, force: bool) -> Result<(), PatchError> {
+/// Target files are compared again immediately before restoring; with `force`,
+/// any that changed are listed in a warning before being overwritten.
+pub fn run(target_dir: &Path, manifest_path: &Path, force: bool, backup_dir: Option<&Path>) -> Result<(), PatchError> {
     // Load manifest
-    let manifest = Manifest::load(manifest_path).map_err(|e| PatchError::ManifestError {
+    let mut manifest = Manifest::load(manifest_path).map_err(|e| PatchError::ManifestError {
         reason: e.to_string(),
     })?;
 
-    // Get backup directory
-    let backup_dir = target_dir.join(BACKUP_DIR);
+    // Get backup directory, then the directory entries are relative to
+    let backup_dir = backup_dir_for(target_dir, backup_dir);
+    let target_dir = &install_dir(&manifest, target_dir)?;
+
     if !backup_dir.exists() {
         return Err(PatchError::RollbackFailed {
             reason: format!("backup directory not found: {}", backup_dir.display()),
         });
     }
 
+    // Undo exactly what was applied, whatever .graftignore says now
+    match applied_entries(&backup_dir)? {
+        Some(entries) => manifest.entries = entries,
+        None => skip_ignored(&mut manifest, target_dir)?,
+    }
+
     // Validate patched files are in expected state (skip if --force)
     if !force {
-        validate_patched_entries(&manifest.entries, target_dir, Some(|p: Progress| {
+        validate_patched_entries(&manifest.entries, target_dir, manifest.hash_algorithm, Some(|p: Progress| {
             println!("{} [{}/{}]: {}", format_action(p.action), p.index + 1, p.total, p.file);
         }))?;
     }
 
     // Validate backup integrity before rolling back (always required)
-    validate_backup(&manifest.entries, &backup_dir, Some(|p: Progress| {
+    validate_backup(&manifest.entries, &backup_dir, manifest.hash_algorithm, Some(|p: Progress| {
         println!("{} [{}/{}]: {}", format_action(p.action), p.index + 1, p.total, p.file);
     }))?;
 
+    // Files may have changed since the check above (or it was skipped with
+    // --force), so compare again right before anything is overwritten
+    let modified = find_modified_entries(&manifest.entries, target_dir, manifest.hash_algorithm);
+    if let Some(first) = modified.first() {
+        if !force {
+            return Err(first.clone());
+        }
+        eprintln!(
+            "WARNING: {} files were changed outside graft since the patch was applied and will be overwritten:",
+            modified.len()
+        );
+        for e in &modified {
+            eprintln!("  {}", e);
+        }
+    }
+
     // Rollback all entries (treat all as "applied")
     let entries: Vec<_> = manifest.entries.iter().collect();
     rollback(&entries, target_dir, &backup_dir, Some(|p: Progress| {
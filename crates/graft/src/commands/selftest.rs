@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use graft_core::patch::{Progress, MANIFEST_FILENAME};
+use graft_core::utils::dir_scan::list_files_recursive;
+
+use crate::commands::patch_apply::{self, ApplyOptions};
+use crate::commands::{patch_create, patch_rollback};
+
+/// Game files before the test patch: one to patch, one to delete, and one
+/// left alone.
+const ORIGINAL: &[(&str, &[u8])] = &[
+    ("data/strings.txt", b"Hello, world!\nGoodbye!\n"),
+    ("data/old.bin", b"\x00\x01\x02\x03 removed by the patch"),
+    ("game.dat", b"unchanged game data"),
+];
+
+/// Game files after the test patch: one patched, one deleted, and one added.
+const MODIFIED: &[(&str, &[u8])] = &[
+    ("data/strings.txt", b"Hola, mundo!\nAdios!\n"),
+    ("data/new/voice.bin", b"\x10\x20\x30 added by the patch"),
+    ("game.dat", b"unchanged game data"),
+];
+
+const OPTIONS: patch_create::CreateOptions<'static> = patch_create::CreateOptions::new(1, "Selftest");
+
+/// Round-trip a small patch through create, apply and rollback in
+/// `work_dir` (which should be empty), checking that the target matches the
+/// modified files after applying and the original files after rolling back.
+///
+/// Each phase's result is printed as it finishes. The first failure or
+/// discrepancy is returned as a message naming the phase.
+pub fn run(work_dir: &Path) -> Result<(), String> {
+    let orig_dir = work_dir.join("original");
+    let new_dir = work_dir.join("modified");
+    let patch_dir = work_dir.join("patch");
+    let target_dir = work_dir.join("target");
+
+    write_files(&orig_dir, ORIGINAL)
+        .and_then(|()| write_files(&new_dir, MODIFIED))
+        .and_then(|()| write_files(&target_dir, ORIGINAL))
+        .map_err(|e| format!("setup: failed to write test files: {}", e))?;
+
+    patch_create::run(&orig_dir, &new_dir, &patch_dir, &OPTIONS, None::<fn(Progress)>)
+        .map_err(|e| format!("create: {}", e))?;
+    println!("create: OK");
+
+    patch_apply::run(&target_dir, &patch_dir, &ApplyOptions::default()).map_err(|e| format!("apply: {}", e))?;
+    compare(&target_dir, MODIFIED).map_err(|e| format!("apply: {}", e))?;
+    println!("apply: OK, target matches the modified files");
+
+    patch_rollback::run(&target_dir, &patch_dir.join(MANIFEST_FILENAME), false, None)
+        .map_err(|e| format!("rollback: {}", e))?;
+    compare(&target_dir, ORIGINAL).map_err(|e| format!("rollback: {}", e))?;
+    println!("rollback: OK, target matches the original files");
+
+    Ok(())
+}
+
+fn write_files(dir: &Path, files: &[(&str, &[u8])]) -> io::Result<()> {
+    for (file, content) in files {
+        let path = dir.join(file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, content)?;
+    }
+    Ok(())
+}
+
+/// Check that `dir` holds exactly `expected` (ignoring the backup
+/// directory), naming every file that is missing, different or extra.
+fn compare(dir: &Path, expected: &[(&str, &[u8])]) -> Result<(), String> {
+    let expected: BTreeMap<&str, &[u8]> = expected.iter().copied().collect();
+    let actual = list_files_recursive(dir).map_err(|e| format!("failed to list target files: {}", e))?;
+
+    let mut problems = Vec::new();
+    for file in &actual {
+        match expected.get(file.as_str()) {
+            None => problems.push(format!("unexpected file {}", file)),
+            Some(content) => match fs::read(dir.join(file)) {
+                Ok(data) if data == *content => {}
+                Ok(_) => problems.push(format!("wrong content in {}", file)),
+                Err(e) => problems.push(format!("failed to read {}: {}", file, e)),
+            },
+        }
+    }
+    for file in expected.keys() {
+        if !actual.iter().any(|f| f == file) {
+            problems.push(format!("missing file {}", file));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("target differs: {}", problems.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn round_trip_passes() {
+        let work_dir = tempdir().unwrap();
+        run(work_dir.path()).unwrap();
+    }
+
+    #[test]
+    fn compare_names_every_difference() {
+        let dir = tempdir().unwrap();
+        write_files(dir.path(), &[("game.dat", b"other"), ("extra.bin", b"x")]).unwrap();
+
+        let err = compare(dir.path(), ORIGINAL).unwrap_err();
+        assert!(err.contains("unexpected file extra.bin"));
+        assert!(err.contains("wrong content in game.dat"));
+        assert!(err.contains("missing file data/strings.txt"));
+    }
+}
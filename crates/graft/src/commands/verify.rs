@@ -0,0 +1,162 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use graft_core::patch::{install_dir, verify_entry, PatchError, IGNORE_FILENAME, LOCK_FILENAME};
+use graft_core::utils::dir_scan::list_files_recursive;
+use graft_core::utils::ignore::IgnoreList;
+use graft_core::utils::manifest::{Manifest, ManifestEntry};
+
+use crate::commands::patch_apply::skip_ignored;
+
+/// Outcome of checking a target against a manifest.
+pub struct VerifyReport {
+    /// Number of entries checked
+    pub total: usize,
+    /// One error per entry not in its post-patch state
+    pub failures: Vec<PatchError>,
+    /// Files in the target the manifest doesn't mention, if they were
+    /// looked for
+    pub extra: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn passed(&self) -> usize {
+        self.total - self.failures.len()
+    }
+}
+
+/// Check that every entry of a manifest is in its post-patch state in
+/// `target_dir`, without changing anything.
+///
+/// Unlike apply, this doesn't stop at the first mismatch: every entry is
+/// checked and all failures are returned. Entries matched by the target's
+/// `.graftignore` are skipped, as in apply.
+///
+/// With `find_extra`, files in the target that no entry mentions (mods,
+/// screenshots, leftovers) are also listed, as warnings. Files matched by
+/// `.graftignore`, the backup directory and the patch lock aren't counted.
+pub fn run(target_dir: &Path, manifest_path: &Path, find_extra: bool) -> Result<VerifyReport, PatchError> {
+    let mut manifest = Manifest::load(manifest_path).map_err(|e| PatchError::ManifestError {
+        reason: e.to_string(),
+    })?;
+
+    let target_dir = &install_dir(&manifest, target_dir)?;
+    skip_ignored(&mut manifest, target_dir)?;
+
+    let total = manifest.entries.len();
+    let mut failures = Vec::new();
+    for (index, entry) in manifest.entries.iter().enumerate() {
+        match verify_entry(entry, target_dir, manifest.hash_algorithm) {
+            Ok(()) => println!("OK [{}/{}]: {}", index + 1, total, entry.file()),
+            Err(e) => {
+                println!("FAILED [{}/{}]: {}", index + 1, total, e);
+                failures.push(e);
+            }
+        }
+    }
+
+    let extra = if find_extra { extra_files(&manifest, target_dir)? } else { Vec::new() };
+    for file in &extra {
+        println!("WARNING: not in manifest: {}", file);
+    }
+
+    Ok(VerifyReport { total, failures, extra })
+}
+
+/// Files in `target_dir` that no entry of `manifest` mentions, sorted.
+fn extra_files(manifest: &Manifest, target_dir: &Path) -> Result<Vec<String>, PatchError> {
+    let ignore = IgnoreList::load(target_dir).map_err(|e| PatchError::ManifestError {
+        reason: format!("failed to read {}: {}", IGNORE_FILENAME, e),
+    })?;
+    let mut known: BTreeSet<&str> = manifest.entries.iter().map(ManifestEntry::file).collect();
+    for entry in &manifest.entries {
+        if let ManifestEntry::Rename { from, .. } = entry {
+            known.insert(from);
+        }
+    }
+
+    let files = list_files_recursive(target_dir).map_err(|e| PatchError::ValidationFailed {
+        file: target_dir.display().to_string(),
+        reason: format!("failed to list files: {}", e),
+    })?;
+    Ok(files
+        .into_iter()
+        .filter(|f| f != LOCK_FILENAME && !ignore.is_ignored(f) && !known.contains(f.as_str()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{patch_apply, patch_create};
+    use graft_core::patch::{Progress, MANIFEST_FILENAME};
+    use std::fs;
+    use tempfile::tempdir;
+
+    const OPTIONS: patch_create::CreateOptions<'static> =
+        patch_create::CreateOptions { allow_restricted: true, ..patch_create::CreateOptions::new(1, "TestPatcher") };
+
+    #[test]
+    fn reports_every_entry_not_in_patched_state() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("modified.bin"), b"original").unwrap();
+        fs::write(new_dir.path().join("modified.bin"), b"modified").unwrap();
+        fs::write(new_dir.path().join("added.bin"), b"new file").unwrap();
+        fs::write(orig_dir.path().join("deleted.bin"), b"to delete").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+        let manifest_path = patch_dir.path().join(MANIFEST_FILENAME);
+
+        fs::write(target_dir.path().join("modified.bin"), b"original").unwrap();
+        fs::write(target_dir.path().join("deleted.bin"), b"to delete").unwrap();
+
+        // Unpatched: nothing is in its final state
+        let report = run(target_dir.path(), &manifest_path, false).unwrap();
+        assert_eq!(report.total, 3);
+        assert_eq!(report.failures.len(), 3);
+
+        patch_apply::run(target_dir.path(), patch_dir.path(), &patch_apply::ApplyOptions::default()).unwrap();
+        let report = run(target_dir.path(), &manifest_path, false).unwrap();
+        assert_eq!(report.passed(), 3);
+        assert!(report.failures.is_empty());
+
+        // Tampering after the patch shows up as a single failure
+        fs::write(target_dir.path().join("added.bin"), b"edited").unwrap();
+        let report = run(target_dir.path(), &manifest_path, false).unwrap();
+        assert_eq!(report.passed(), 2);
+        assert!(matches!(
+            &report.failures[..],
+            [PatchError::VerificationFailed { file, .. }] if file == "added.bin"
+        ));
+    }
+
+    #[test]
+    fn lists_files_the_manifest_does_not_mention() {
+        let orig_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let patch_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+
+        fs::write(orig_dir.path().join("modified.bin"), b"original").unwrap();
+        fs::write(new_dir.path().join("modified.bin"), b"modified").unwrap();
+        patch_create::run(orig_dir.path(), new_dir.path(), patch_dir.path(), &OPTIONS, None::<fn(Progress)>).unwrap();
+        let manifest_path = patch_dir.path().join(MANIFEST_FILENAME);
+
+        fs::write(target_dir.path().join("modified.bin"), b"original").unwrap();
+        patch_apply::run(target_dir.path(), patch_dir.path(), &patch_apply::ApplyOptions::default()).unwrap();
+        fs::create_dir(target_dir.path().join("mods")).unwrap();
+        fs::write(target_dir.path().join("mods/extra.pak"), b"mod").unwrap();
+        fs::write(target_dir.path().join("shot.png"), b"png").unwrap();
+        fs::write(target_dir.path().join(".graftignore"), "*.png\n").unwrap();
+
+        let report = run(target_dir.path(), &manifest_path, false).unwrap();
+        assert!(report.extra.is_empty());
+
+        let report = run(target_dir.path(), &manifest_path, true).unwrap();
+        assert!(report.failures.is_empty());
+        assert_eq!(report.extra, ["mods/extra.pak"]);
+    }
+}
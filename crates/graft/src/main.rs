@@ -2,7 +2,13 @@ use std::path::PathBuf;
 use std::process;
 
 use clap::{Parser, Subcommand};
-use graft::commands::check::CheckResult;
+use graft::commands::check::{CheckReport, CheckResult};
+use graft::commands::patch_apply::Hooks;
+use graft_core::archive::{ArchiveCompression, Codec};
+use graft_core::exit_code::ExitCode;
+use graft_core::patch::{Progress, ProgressAction, Verification, BACKUP_DIR, MANIFEST_FILENAME};
+use graft_core::utils::hash::Algorithm;
+use graft_core::utils::text_encoding::TextEncoding;
 
 fn version_string() -> &'static str {
     #[cfg(feature = "embedded-stubs")]
@@ -44,6 +50,63 @@ enum Commands {
     },
     /// Build standalone patcher executables
     Build(BuildArgs),
+    /// Patch archive operations
+    Archive {
+        #[command(subcommand)]
+        command: ArchiveCommands,
+    },
+    /// Check that a target is in the state a patch leaves it in
+    #[command(visible_alias = "verify-install")]
+    Verify {
+        /// Target directory to check
+        target: PathBuf,
+        /// Path to manifest.json (from the patch, or written with --manifest-only)
+        manifest: PathBuf,
+        /// Also warn about files in the target that the manifest doesn't mention
+        #[arg(long)]
+        extra: bool,
+        /// Like --extra, but exit with an error if there are any
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Create a patch from two directories (same as `patch create`)
+    Create(CreateArgs),
+    /// Apply several patches in order (e.g. a release, then its updates), undoing them all if one fails
+    ApplyChain {
+        /// Target directory to patch
+        target: PathBuf,
+        /// Patch directories, in the order to apply them
+        #[arg(required = true, num_args = 1..)]
+        patches: Vec<PathBuf>,
+        /// Number of entries to validate and apply in parallel
+        #[arg(short = 'j', long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        jobs: u64,
+        /// Check that the patches line up with each other and the target, without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Keep backups in this directory instead of .patch-backup inside the target
+        #[arg(long, value_name = "DIR")]
+        backup_dir: Option<PathBuf>,
+        /// Patch files larger than this many MiB as a stream, to bound memory use
+        #[arg(long, value_name = "MIB", default_value_t = 256)]
+        stream_above: u64,
+        /// Run the commands the patches declare before and after applying (only for patches you trust)
+        #[arg(long, conflicts_with = "no_hooks")]
+        allow_hooks: bool,
+        /// Apply the patches without running the commands they declare
+        #[arg(long)]
+        no_hooks: bool,
+    },
+    /// Show what a patch directory contains
+    Info {
+        /// Path to the patch directory (containing manifest.json)
+        patch_dir: PathBuf,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check that graft works on this system by creating, applying and rolling back a test patch
+    Selftest,
 }
 
 /// Build arguments for production mode (with embedded stubs)
@@ -62,9 +125,37 @@ struct BuildArgs {
     stub_dir: Option<PathBuf>,
 
     /// Target platform(s) to build for. Repeat for multiple.
-    /// Available: linux-x64, linux-arm64, windows-x64, macos-x64, macos-arm64
+    /// Available: linux-x64, linux-arm64, linux-x64-musl, linux-arm64-musl, windows-x64, windows-x86, macos-x64, macos-arm64, or all
     #[arg(short, long)]
     target: Vec<String>,
+
+    /// Custom output names per target, e.g. windows=Setup,linux-x64=patcher
+    #[arg(long, value_name = "TARGET=NAME,...")]
+    name_map: Option<String>,
+
+    /// Compression for the embedded patch archive: gzip, zstd, store or zip
+    #[arg(long, value_name = "CODEC", default_value = "gzip", value_parser = parse_codec)]
+    compression: Codec,
+
+    /// Compression level (gzip and zip 0-9, zstd 1-22; defaults to 6 for gzip and zip, 3 for zstd)
+    #[arg(long, value_name = "LEVEL")]
+    compression_level: Option<u32>,
+
+    /// Sign the embedded archive with this Ed25519 private key (PEM)
+    #[arg(long, value_name = "KEY")]
+    sign_key: Option<PathBuf>,
+
+    /// PNG to use as the patcher's icon instead of the patch's assets/icon.png
+    #[arg(long, value_name = "PNG")]
+    icon: Option<PathBuf>,
+
+    /// Validate the patch and stubs and show what would be built, without writing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Also write checksums.txt to the output directory, with the SHA-256 of each patcher (as sha256sum prints)
+    #[arg(long)]
+    checksums: bool,
 }
 
 /// Build arguments for development mode (no embedded stubs)
@@ -83,9 +174,37 @@ struct BuildArgs {
     stub_dir: PathBuf,
 
     /// Target platform(s) to build for. Repeat for multiple.
-    /// Available: linux-x64, linux-arm64, windows-x64, macos-x64, macos-arm64
+    /// Available: linux-x64, linux-arm64, linux-x64-musl, linux-arm64-musl, windows-x64, windows-x86, macos-x64, macos-arm64, or all
     #[arg(short, long)]
     target: Vec<String>,
+
+    /// Custom output names per target, e.g. windows=Setup,linux-x64=patcher
+    #[arg(long, value_name = "TARGET=NAME,...")]
+    name_map: Option<String>,
+
+    /// Compression for the embedded patch archive: gzip, zstd, store or zip
+    #[arg(long, value_name = "CODEC", default_value = "gzip", value_parser = parse_codec)]
+    compression: Codec,
+
+    /// Compression level (gzip and zip 0-9, zstd 1-22; defaults to 6 for gzip and zip, 3 for zstd)
+    #[arg(long, value_name = "LEVEL")]
+    compression_level: Option<u32>,
+
+    /// Sign the embedded archive with this Ed25519 private key (PEM)
+    #[arg(long, value_name = "KEY")]
+    sign_key: Option<PathBuf>,
+
+    /// PNG to use as the patcher's icon instead of the patch's assets/icon.png
+    #[arg(long, value_name = "PNG")]
+    icon: Option<PathBuf>,
+
+    /// Validate the patch and stubs and show what would be built, without writing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Also write checksums.txt to the output directory, with the SHA-256 of each patcher (as sha256sum prints)
+    #[arg(long)]
+    checksums: bool,
 }
 
 #[derive(Subcommand)]
@@ -110,12 +229,46 @@ enum DiffCommands {
     },
 }
 
+#[derive(Subcommand)]
+enum ArchiveCommands {
+    /// Repack a patch archive into a different format or compression level
+    Repack {
+        /// Input archive (tar.gz or zip)
+        input: PathBuf,
+        /// Path to write the repacked archive to
+        output: PathBuf,
+        /// Output format: tar.gz or zip
+        #[arg(long, default_value = "tar.gz")]
+        format: String,
+        /// Compression level (0-9)
+        #[arg(long, default_value_t = 6)]
+        compression: u32,
+    },
+    /// Write a patch directory's archive to a file, without building a patcher
+    Bundle {
+        /// Path to the patch directory (containing manifest.json)
+        patch_dir: PathBuf,
+        /// Path to write the archive to (e.g. patch.tar.gz)
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Compression for the archive: gzip, zstd, store or zip
+        #[arg(long, value_name = "CODEC", default_value = "gzip", value_parser = parse_codec)]
+        compression: Codec,
+        /// Compression level (gzip and zip 0-9, zstd 1-22; defaults to 6 for gzip and zip, 3 for zstd)
+        #[arg(long, value_name = "LEVEL")]
+        compression_level: Option<u32>,
+    },
+}
+
 #[derive(Subcommand)]
 enum HashCommands {
     /// Calculate the SHA-256 hash of a file
     Calculate {
         /// File to hash
         file: PathBuf,
+        /// Show hashing progress (useful for very large files)
+        #[arg(long)]
+        progress: bool,
     },
     /// Compare two files by their SHA-256 hash
     Compare {
@@ -130,31 +283,109 @@ enum HashCommands {
         hash: String,
         /// File to compare
         file: PathBuf,
+        /// Print the result as JSON
+        #[arg(long)]
+        json: bool,
     },
 }
 
+/// Arguments for `graft patch create` (and its shortcut, `graft create`)
+#[derive(clap::Args, Debug)]
+struct CreateArgs {
+    /// Original directory
+    orig: PathBuf,
+    /// Modified directory
+    new: PathBuf,
+    /// Output directory for patch files
+    output: PathBuf,
+    /// Manifest version number
+    #[arg(short = 'v', long)]
+    version: u32,
+    /// Base name for patcher executable (e.g., "MyPatcher")
+    #[arg(long)]
+    name: String,
+    /// Window title for the patcher application
+    #[arg(long)]
+    title: Option<String>,
+    /// Description of the patch shown before applying
+    #[arg(long)]
+    description: Option<String>,
+    /// Author of the patch
+    #[arg(long)]
+    author: Option<String>,
+    /// Version of the game the patch applies to (e.g., "1.4")
+    #[arg(long, value_name = "VERSION")]
+    target_game_version: Option<String>,
+    /// Game file (relative to the original directory) whose hash identifies the game version
+    #[arg(long, value_name = "FILE")]
+    version_file: Option<String>,
+    /// Allow patching restricted paths (system dirs, executables)
+    #[arg(long)]
+    allow_restricted: bool,
+    /// Also find files to patch by content when they are missing from the target (handles renamed files)
+    #[arg(long)]
+    match_by_content: bool,
+    /// Treat paths that differ only in case as the same file (for case-insensitive targets such as Windows)
+    #[arg(long)]
+    case_insensitive: bool,
+    /// Hash algorithm to record in the manifest (sha256 or blake3)
+    #[arg(long, value_name = "ALGORITHM", default_value = "sha256", value_parser = parse_algorithm)]
+    hash_algorithm: Algorithm,
+    /// Give patched and added files their modification time from the modified directory
+    #[arg(long)]
+    preserve_mtime: bool,
+    /// Store each diff and added file gzip-compressed
+    #[arg(long)]
+    compress_payloads: bool,
+    /// Convert modified files with these extensions (e.g. txt,json,ini) to the original's line endings before diffing
+    #[arg(long, value_name = "EXTS", value_delimiter = ',')]
+    text_extensions: Vec<String>,
+    /// Encoding of --text-extensions originals without a byte order mark (e.g. shift_jis);
+    /// modified files are converted to it
+    #[arg(long, value_name = "ENCODING", value_parser = parse_encoding)]
+    text_encoding: Option<String>,
+    /// Only compare the files listed in this file (one relative path per line) instead of scanning both directories
+    #[arg(long, value_name = "LIST", conflicts_with_all = ["approved", "case_insensitive"])]
+    files: Option<PathBuf>,
+    /// JSON file mapping relative paths to labels shown to players while applying
+    #[arg(long, value_name = "FILE")]
+    labels: Option<PathBuf>,
+    /// Ship this modified file (relative path, repeatable) whole, so it is created on installs that lack it
+    #[arg(long, value_name = "FILE")]
+    upsert: Vec<String>,
+    /// Only include changes from a reviewed changes file (from `patch detect`)
+    #[arg(long, value_name = "CHANGES")]
+    approved: Option<PathBuf>,
+    /// Only write manifest.json, to check installs against with `graft verify-install`
+    #[arg(long)]
+    manifest_only: bool,
+}
+
 #[derive(Subcommand)]
 enum PatchCommands {
     /// Create a patch from two directories
-    Create {
+    Create(Box<CreateArgs>),
+    /// Detect changes between two directories without creating a patch
+    Detect {
         /// Original directory
         orig: PathBuf,
         /// Modified directory
         new: PathBuf,
-        /// Output directory for patch files
-        output: PathBuf,
-        /// Manifest version number
-        #[arg(short = 'v', long)]
-        version: u32,
-        /// Base name for patcher executable (e.g., "MyPatcher")
-        #[arg(long)]
-        name: String,
-        /// Window title for the patcher application
-        #[arg(long)]
-        title: Option<String>,
-        /// Allow patching restricted paths (system dirs, executables)
+        /// Path to write the changes JSON to
+        changes: PathBuf,
+        /// Treat paths that differ only in case as the same file (for case-insensitive targets such as Windows)
         #[arg(long)]
-        allow_restricted: bool,
+        case_insensitive: bool,
+        /// Hash algorithm to record in the manifest (sha256 or blake3)
+        #[arg(long, value_name = "ALGORITHM", default_value = "sha256", value_parser = parse_algorithm)]
+        hash_algorithm: Algorithm,
+        /// Convert modified files with these extensions (e.g. txt,json,ini) to the original's line endings before diffing
+        #[arg(long, value_name = "EXTS", value_delimiter = ',')]
+        text_extensions: Vec<String>,
+        /// Encoding of --text-extensions originals without a byte order mark (e.g. shift_jis);
+        /// modified files are converted to it
+        #[arg(long, value_name = "ENCODING", value_parser = parse_encoding)]
+        text_encoding: Option<String>,
     },
     /// Apply a patch to a target directory
     Apply {
@@ -162,6 +393,42 @@ enum PatchCommands {
         target: PathBuf,
         /// Directory containing patch files
         patch: PathBuf,
+        /// Only verify every Nth entry after applying (critical entries are always verified)
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["verify_percent", "force"])]
+        verify_every: Option<u64>,
+        /// Only verify about this percentage of entries after applying (0-100)
+        #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(0..=100), conflicts_with = "force")]
+        verify_percent: Option<u8>,
+        /// Number of entries to validate and apply in parallel
+        #[arg(short = 'j', long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        jobs: u64,
+        /// Only apply entries with these operations (comma-separated); the rest are left for a later run
+        #[arg(long, value_name = "OPS", value_delimiter = ',', value_parser = ["patch", "add", "delete", "rename", "symlink", "mkdir", "rmdir"])]
+        only: Vec<String>,
+        /// Validate the target and show what would change, without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Keep backups in this directory instead of .patch-backup inside the target
+        #[arg(long, value_name = "DIR")]
+        backup_dir: Option<PathBuf>,
+        /// Keep the backup after a successful apply, so the patch can be rolled back (default)
+        #[arg(long, overrides_with = "delete_backup")]
+        keep_backup: bool,
+        /// Delete the backup once the patch is applied and verified, to reclaim disk space
+        #[arg(long, overrides_with = "keep_backup")]
+        delete_backup: bool,
+        /// Patch files larger than this many MiB as a stream, to bound memory use
+        #[arg(long, value_name = "MIB", default_value_t = 256)]
+        stream_above: u64,
+        /// Patch files even if they don't match the patch's original hashes (every entry is still verified after applying)
+        #[arg(long)]
+        force: bool,
+        /// Run the commands the patch declares before and after applying (only for patches you trust)
+        #[arg(long, conflicts_with = "no_hooks")]
+        allow_hooks: bool,
+        /// Apply the patch without running the commands it declares
+        #[arg(long)]
+        no_hooks: bool,
     },
     /// Rollback a previously applied patch using backup
     Rollback {
@@ -172,7 +439,141 @@ enum PatchCommands {
         /// Skip validation of patched files (use when files have been modified)
         #[arg(long, short)]
         force: bool,
+        /// Directory the backups were written to, if not .patch-backup inside the target
+        #[arg(long, value_name = "DIR")]
+        backup_dir: Option<PathBuf>,
     },
+    /// Record the manifest hash again after editing a patch's manifest.json by hand
+    Rehash {
+        /// Directory containing patch files
+        patch_dir: PathBuf,
+    },
+}
+
+/// What to do with a patch's hook commands, from `--allow-hooks` and
+/// `--no-hooks`.
+fn hooks_option(allow_hooks: bool, no_hooks: bool) -> Hooks {
+    if allow_hooks {
+        Hooks::Run
+    } else if no_hooks {
+        Hooks::Skip
+    } else {
+        Hooks::Refuse
+    }
+}
+
+fn parse_codec(name: &str) -> Result<Codec, String> {
+    Codec::parse(name).ok_or_else(|| format!("unknown compression '{}' (expected gzip, zstd, store or zip)", name))
+}
+
+fn parse_algorithm(name: &str) -> Result<Algorithm, String> {
+    Algorithm::parse(name).ok_or_else(|| format!("unknown hash algorithm '{}' (expected sha256 or blake3)", name))
+}
+
+fn parse_encoding(label: &str) -> Result<String, String> {
+    TextEncoding::for_label(label).map(|_| label.to_string()).map_err(|e| e.to_string())
+}
+
+/// Create a patch for `graft patch create` or `graft create`, then print a
+/// summary of the changes it holds.
+fn run_create(args: CreateArgs) {
+    let CreateArgs {
+        orig,
+        new,
+        output,
+        version,
+        name,
+        title,
+        description,
+        author,
+        target_game_version,
+        version_file,
+        allow_restricted,
+        match_by_content,
+        case_insensitive,
+        hash_algorithm,
+        preserve_mtime,
+        compress_payloads,
+        text_extensions,
+        text_encoding,
+        files,
+        labels,
+        upsert,
+        approved,
+        manifest_only,
+    } = args;
+    let files = match files.as_deref().map(graft::commands::patch_create::load_file_list) {
+        Some(Ok(files)) => Some(files),
+        Some(Err(e)) => {
+            eprintln!("Error: failed to read file list: {}", e);
+            ExitCode::from(&e).exit();
+        }
+        None => None,
+    };
+    let labels = match labels.as_deref().map(graft::commands::patch_create::load_labels) {
+        Some(Ok(labels)) => Some(labels),
+        Some(Err(e)) => {
+            eprintln!("Error: failed to read labels: {}", e);
+            ExitCode::from(&e).exit();
+        }
+        None => None,
+    };
+    let options = graft::commands::patch_create::CreateOptions {
+        version,
+        name: &name,
+        title: title.as_deref(),
+        description: description.as_deref(),
+        author: author.as_deref(),
+        target_game_version: target_game_version.as_deref(),
+        version_file: version_file.as_deref(),
+        allow_restricted,
+        match_by_content,
+        case_insensitive,
+        hash_algorithm,
+        preserve_mtime,
+        compress_payloads,
+        text_extensions: &text_extensions,
+        text_encoding: text_encoding.as_deref(),
+        files: files.as_deref(),
+        labels: labels.as_ref(),
+        upsert: &upsert,
+        manifest_only,
+    };
+    let print_progress = |p: Progress| {
+        let action = match p.action {
+            ProgressAction::Diffing => "Diffing",
+            ProgressAction::Copying => "Copying",
+            _ => "Recording",
+        };
+        println!("{} [{}/{}]: {}", action, p.index + 1, p.total, p.file);
+    };
+    let result = match approved {
+        Some(changes) => graft::commands::patch_create::run_approved(
+            &orig,
+            &new,
+            &output,
+            &changes,
+            &options,
+            Some(print_progress),
+        ),
+        None => graft::commands::patch_create::run(&orig, &new, &output, &options, Some(print_progress)),
+    };
+    match result {
+        Ok(()) if manifest_only => {
+            println!("Manifest written to {}", output.display());
+        }
+        Ok(()) => {
+            println!("Patch created at {}", output.display());
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(&e).exit();
+        }
+    }
+    match graft::commands::patch_create::summary(&output) {
+        Ok(summary) => println!("{}", summary),
+        Err(e) => eprintln!("Warning: failed to summarize the patch: {}", e),
+    }
 }
 
 fn main() {
@@ -187,7 +588,7 @@ fn main() {
                     }
                     Err(e) => {
                         eprintln!("Error: {}", e);
-                        process::exit(2);
+                        ExitCode::from(&e).exit();
                     }
                 }
             }
@@ -198,20 +599,34 @@ fn main() {
                     }
                     Err(e) => {
                         eprintln!("Error: {}", e);
-                        process::exit(2);
+                        ExitCode::from(&e).exit();
                     }
                 }
             }
         }
         Commands::Hash { command } => match command {
-            HashCommands::Calculate { file } => {
-                match graft::commands::calculate::run(&file) {
+            HashCommands::Calculate { file, progress } => {
+                let result = if progress {
+                    let mut last_percent = None;
+                    let result = graft::commands::calculate::run_with_progress(&file, |hashed, total| {
+                        let percent = (hashed * 100).checked_div(total).unwrap_or(100);
+                        if last_percent != Some(percent) {
+                            last_percent = Some(percent);
+                            eprint!("\rHashing: {}% ({} / {} bytes)", percent, hashed, total);
+                        }
+                    });
+                    eprintln!();
+                    result
+                } else {
+                    graft::commands::calculate::run(&file)
+                };
+                match result {
                     Ok(result) => {
                         println!("Hash for file {}: {}", file.display(), result);
                     }
                     Err(e) => {
                         eprintln!("Error: {}", e);
-                        process::exit(2);
+                        ExitCode::from(&e).exit();
                     }
                 }
             }
@@ -224,78 +639,306 @@ fn main() {
                             println!("Files match");
                         } else {
                             println!("Files differ");
-                            process::exit(1);
+                            ExitCode::Verification.exit();
                         }
                     }
                     Err(e) => {
                         eprintln!("Error: {}", e);
-                        process::exit(2);
+                        ExitCode::from(&e).exit();
                     }
                 }
             }
-            HashCommands::Check { hash, file } => {
+            HashCommands::Check { hash, file, json } => {
                 match graft::commands::check::run(&hash, &file) {
-                    Ok(result) => match result {
-                        CheckResult::Match => {
-                            println!("Hash match");
+                    Ok(result) => {
+                        let matched = matches!(result, CheckResult::Match);
+                        if json {
+                            println!("{}", CheckReport::new(&hash, &file, &result).to_json());
+                        } else {
+                            match result {
+                                CheckResult::Match => {
+                                    println!("Hash match");
+                                }
+                                CheckResult::NoMatch { actual } => {
+                                    println!("Hashes differ");
+                                    println!("Expected hash: {}", hash);
+                                    println!("Actual hash: {}", actual);
+                                }
+                            }
                         }
-                        CheckResult::NoMatch { actual } => {
-                            println!("Hashes differ");
-                            println!("Expected hash: {}", hash);
-                            println!("Actual hash: {}", actual);
+                        if !matched {
+                            ExitCode::Verification.exit();
                         }
-                    },
+                    }
                     Err(e) => {
                         eprintln!("Error: {}", e);
-                        process::exit(2);
+                        ExitCode::from(&e).exit();
                     }
                 }
             }
         }
         Commands::Patch { command } => match command {
-            PatchCommands::Create {
+            PatchCommands::Create(args) => run_create(*args),
+            PatchCommands::Detect {
                 orig,
                 new,
-                output,
-                version,
-                name,
-                title,
-                allow_restricted,
+                changes,
+                case_insensitive,
+                hash_algorithm,
+                text_extensions,
+                text_encoding,
             } => {
-                match graft::commands::patch_create::run(&orig, &new, &output, version, &name, title.as_deref(), allow_restricted) {
-                    Ok(()) => {
-                        println!("Patch created at {}", output.display());
+                match graft::commands::patch_create::detect(
+                    &orig,
+                    &new,
+                    &changes,
+                    case_insensitive,
+                    hash_algorithm,
+                    &text_extensions,
+                    text_encoding.as_deref(),
+                ) {
+                    Ok(summary) => {
+                        println!(
+                            "Scanned {} files: {} changed, {} identical",
+                            summary.files_scanned,
+                            summary.files_changed(),
+                            summary.unchanged.len()
+                        );
+                        println!("{} changes written to {}", summary.changes.len(), changes.display());
                     }
                     Err(e) => {
                         eprintln!("Error: {}", e);
-                        process::exit(2);
+                        ExitCode::from(&e).exit();
                     }
                 }
             }
-            PatchCommands::Apply { target, patch } => {
-                match graft::commands::patch_apply::run(&target, &patch) {
+            PatchCommands::Apply {
+                target,
+                patch,
+                verify_every,
+                verify_percent,
+                jobs,
+                only,
+                dry_run,
+                backup_dir,
+                keep_backup: _,
+                delete_backup,
+                stream_above,
+                force,
+                allow_hooks,
+                no_hooks,
+            } => {
+                let verification = match (verify_every, verify_percent) {
+                    (Some(n), _) => Verification::EveryNth(n as usize),
+                    (None, Some(percent)) => Verification::Percent(percent),
+                    (None, None) => Verification::Full,
+                };
+                let options = graft::commands::patch_apply::ApplyOptions {
+                    verification,
+                    jobs: jobs as usize,
+                    operations: &only,
+                    dry_run,
+                    backup_dir: backup_dir.as_deref(),
+                    delete_backup,
+                    streaming_threshold: stream_above.saturating_mul(1024 * 1024),
+                    force,
+                    hooks: hooks_option(allow_hooks, no_hooks),
+                };
+                match graft::commands::patch_apply::run(&target, &patch, &options) {
                     Ok(()) => {
-                        println!("Patch applied successfully");
+                        if !dry_run {
+                            println!("Patch applied successfully");
+                        }
                     }
                     Err(e) => {
                         eprintln!("Error: {}", e);
-                        process::exit(2);
+                        ExitCode::from(&e).exit();
                     }
                 }
             }
-            PatchCommands::Rollback { target, manifest, force } => {
-                match graft::commands::patch_rollback::run(&target, &manifest, force) {
+            PatchCommands::Rollback {
+                target,
+                manifest,
+                force,
+                backup_dir,
+            } => {
+                match graft::commands::patch_rollback::run(&target, &manifest, force, backup_dir.as_deref()) {
                     Ok(()) => {
                         println!("Rollback complete");
                     }
                     Err(e) => {
                         eprintln!("Error: {}", e);
-                        process::exit(2);
+                        ExitCode::from(&e).exit();
                     }
                 }
             }
+            PatchCommands::Rehash { patch_dir } => match graft::commands::patch_rehash::run(&patch_dir) {
+                Ok(hash) => println!("Recorded manifest hash {}", hash),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    ExitCode::from(&e).exit();
+                }
+            },
         },
+        Commands::Archive { command } => match command {
+            ArchiveCommands::Repack {
+                input,
+                output,
+                format,
+                compression,
+            } => {
+                let result = match graft::commands::archive_repack::parse_format(&format) {
+                    Some(format) => graft::commands::archive_repack::run(&input, &output, format, compression),
+                    None => Err(graft::commands::archive_repack::RepackError::InvalidFormat(format)),
+                };
+                match result {
+                    Ok(count) => {
+                        println!("Repacked {} files to {}", count, output.display());
+                    }
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        ExitCode::from(&e).exit();
+                    }
+                }
+            }
+            ArchiveCommands::Bundle {
+                patch_dir,
+                output,
+                compression,
+                compression_level,
+            } => {
+                let Some(compression) = ArchiveCompression::new(compression, compression_level) else {
+                    eprintln!(
+                        "Error: compression level {} is out of range for {} (0-{})",
+                        compression_level.unwrap_or_default(),
+                        compression,
+                        compression.max_level()
+                    );
+                    ExitCode::Validation.exit();
+                };
+                match graft::commands::build::bundle(&patch_dir, &output, compression) {
+                    Ok(_) => println!("Patch archive written to {}", output.display()),
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        ExitCode::from(&e).exit();
+                    }
+                }
+            }
+        },
+        Commands::Create(args) => run_create(args),
+        Commands::ApplyChain {
+            target,
+            patches,
+            jobs,
+            dry_run,
+            backup_dir,
+            stream_above,
+            allow_hooks,
+            no_hooks,
+        } => {
+            let options = graft::commands::patch_apply::ApplyOptions {
+                jobs: jobs as usize,
+                dry_run,
+                backup_dir: backup_dir.as_deref(),
+                streaming_threshold: stream_above.saturating_mul(1024 * 1024),
+                hooks: hooks_option(allow_hooks, no_hooks),
+                ..Default::default()
+            };
+            match graft::commands::apply_chain::run(&target, &patches, &options) {
+                Ok(()) if dry_run => {}
+                Ok(()) => {
+                    println!("\nAll {} patches applied successfully", patches.len());
+                    println!("To roll back, roll back each patch, last one first:");
+                    let backup_root = backup_dir.unwrap_or_else(|| target.join(BACKUP_DIR));
+                    for (index, patch) in patches.iter().enumerate().rev() {
+                        println!(
+                            "  graft patch rollback {} {} --backup-dir {}",
+                            target.display(),
+                            patch.join(MANIFEST_FILENAME).display(),
+                            graft::commands::apply_chain::chain_backup_dir(&backup_root, index).display()
+                        );
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    ExitCode::from(&e).exit();
+                }
+            }
+        }
+        Commands::Verify {
+            target,
+            manifest,
+            extra,
+            strict,
+        } => {
+            match graft::commands::verify::run(&target, &manifest, extra || strict) {
+                Ok(report) => {
+                    println!(
+                        "{} of {} entries verified, {} failed",
+                        report.passed(),
+                        report.total,
+                        report.failures.len()
+                    );
+                    if extra || strict {
+                        println!("{} files not in the manifest", report.extra.len());
+                    }
+                    if !report.failures.is_empty() || (strict && !report.extra.is_empty()) {
+                        ExitCode::Verification.exit();
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    ExitCode::from(&e).exit();
+                }
+            }
+        }
+        Commands::Info { patch_dir, json } => match graft::commands::info::run(&patch_dir) {
+            Ok(report) if json => println!("{}", report.to_json()),
+            Ok(report) => report.print(),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ExitCode::from(&e).exit();
+            }
+        },
+        Commands::Selftest => {
+            let work_dir = std::env::temp_dir().join(format!("graft-selftest-{}", process::id()));
+            let result = graft::commands::selftest::run(&work_dir);
+            let _ = std::fs::remove_dir_all(&work_dir);
+            match result {
+                Ok(()) => println!("Selftest passed"),
+                Err(e) => {
+                    eprintln!("Selftest FAILED: {}", e);
+                    ExitCode::Failure.exit();
+                }
+            }
+        }
         Commands::Build(args) => {
+            let name_map = match args.name_map.as_deref().map(graft::commands::build::NameMap::parse) {
+                Some(Ok(map)) => map,
+                Some(Err(e)) => {
+                    eprintln!("Error: {}", e);
+                    ExitCode::from(&e).exit();
+                }
+                None => Default::default(),
+            };
+            let Some(compression) = ArchiveCompression::new(args.compression, args.compression_level) else {
+                eprintln!(
+                    "Error: compression level {} is out of range for {} (0-{})",
+                    args.compression_level.unwrap_or_default(),
+                    args.compression,
+                    args.compression.max_level()
+                );
+                ExitCode::Validation.exit();
+            };
+
+            let options = graft::commands::build::BuildOptions {
+                compression,
+                sign_key: args.sign_key.as_deref(),
+                icon: args.icon.as_deref(),
+                dry_run: args.dry_run,
+                checksums: args.checksums,
+            };
+
             #[cfg(feature = "embedded-stubs")]
             {
                 match graft::commands::build::run(
@@ -303,11 +946,13 @@ fn main() {
                     &args.output,
                     args.stub_dir.as_deref(),
                     &args.target,
+                    &name_map,
+                    &options,
                 ) {
                     Ok(()) => {}
                     Err(e) => {
                         eprintln!("Error: {}", e);
-                        process::exit(2);
+                        ExitCode::from(&e).exit();
                     }
                 }
             }
@@ -319,14 +964,30 @@ fn main() {
                     &args.output,
                     &args.stub_dir,
                     &args.target,
+                    &name_map,
+                    &options,
                 ) {
                     Ok(()) => {}
                     Err(e) => {
                         eprintln!("Error: {}", e);
-                        process::exit(2);
+                        ExitCode::from(&e).exit();
                     }
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_conflicts_with_case_insensitive() {
+        let args = ["graft", "create", "orig", "new", "out", "-v", "1", "--name", "Patch", "--files", "list.txt"];
+        assert!(Cli::try_parse_from(args).is_ok());
+
+        let result = Cli::try_parse_from(args.iter().chain(&["--case-insensitive"]));
+        assert!(result.is_err_and(|e| e.kind() == clap::error::ErrorKind::ArgumentConflict));
+    }
+}
@@ -203,11 +203,26 @@ pub fn get_embedded_stub(target: &Target) -> Result<Vec<u8>, StubError> {
             "/graft-gui-stub-linux-arm64"
         ))
         .to_vec()),
+        "linux-x64-musl" => Ok(include_bytes!(concat!(
+            env!("GRAFT_STUBS_DIR"),
+            "/graft-gui-stub-linux-x64-musl"
+        ))
+        .to_vec()),
+        "linux-arm64-musl" => Ok(include_bytes!(concat!(
+            env!("GRAFT_STUBS_DIR"),
+            "/graft-gui-stub-linux-arm64-musl"
+        ))
+        .to_vec()),
         "windows-x64" => Ok(include_bytes!(concat!(
             env!("GRAFT_STUBS_DIR"),
             "/graft-gui-stub-windows-x64.exe"
         ))
         .to_vec()),
+        "windows-x86" => Ok(include_bytes!(concat!(
+            env!("GRAFT_STUBS_DIR"),
+            "/graft-gui-stub-windows-x86.exe"
+        ))
+        .to_vec()),
         // macOS uses bundle stubs, not binary stubs
         _ => Err(StubError::TargetNotAvailable(target.name.to_string())),
     }
@@ -35,6 +35,20 @@ pub const LINUX_ARM64: Target = Target {
     stub_is_bundle: false,
 };
 
+pub const LINUX_X64_MUSL: Target = Target {
+    name: "linux-x64-musl",
+    triple: "x86_64-unknown-linux-musl",
+    binary_suffix: "",
+    stub_is_bundle: false,
+};
+
+pub const LINUX_ARM64_MUSL: Target = Target {
+    name: "linux-arm64-musl",
+    triple: "aarch64-unknown-linux-musl",
+    binary_suffix: "",
+    stub_is_bundle: false,
+};
+
 pub const WINDOWS_X64: Target = Target {
     name: "windows-x64",
     triple: "x86_64-pc-windows-gnu",
@@ -42,6 +56,13 @@ pub const WINDOWS_X64: Target = Target {
     stub_is_bundle: false,
 };
 
+pub const WINDOWS_X86: Target = Target {
+    name: "windows-x86",
+    triple: "i686-pc-windows-gnu",
+    binary_suffix: ".exe",
+    stub_is_bundle: false,
+};
+
 pub const MACOS_X64: Target = Target {
     name: "macos-x64",
     triple: "x86_64-apple-darwin",
@@ -60,7 +81,10 @@ pub const MACOS_ARM64: Target = Target {
 pub const ALL_TARGETS: &[Target] = &[
     LINUX_X64,
     LINUX_ARM64,
+    LINUX_X64_MUSL,
+    LINUX_ARM64_MUSL,
     WINDOWS_X64,
+    WINDOWS_X86,
     MACOS_X64,
     MACOS_ARM64,
 ];
@@ -70,24 +94,36 @@ pub fn parse_target(name: &str) -> Option<Target> {
     match name.to_lowercase().as_str() {
         "linux-x64" | "linux-x86_64" => Some(LINUX_X64),
         "linux-arm64" | "linux-aarch64" => Some(LINUX_ARM64),
+        "linux-x64-musl" | "linux-x86_64-musl" => Some(LINUX_X64_MUSL),
+        "linux-arm64-musl" | "linux-aarch64-musl" => Some(LINUX_ARM64_MUSL),
         "windows-x64" | "windows" => Some(WINDOWS_X64),
+        "windows-x86" | "i686" => Some(WINDOWS_X86),
         "macos-x64" | "macos-x86_64" | "darwin-x64" => Some(MACOS_X64),
-        "macos-arm64" | "macos-aarch64" | "darwin-arm64" => Some(MACOS_ARM64),
+        "macos-arm64" | "macos-aarch64" | "darwin-arm64" | "macos" => Some(MACOS_ARM64),
         _ => None,
     }
 }
 
 /// Get the current platform's target.
 pub fn current_target() -> Option<Target> {
-    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[cfg(all(target_os = "linux", target_arch = "x86_64", not(target_env = "musl")))]
     return Some(LINUX_X64);
 
-    #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+    #[cfg(all(target_os = "linux", target_arch = "aarch64", not(target_env = "musl")))]
     return Some(LINUX_ARM64);
 
+    #[cfg(all(target_os = "linux", target_arch = "x86_64", target_env = "musl"))]
+    return Some(LINUX_X64_MUSL);
+
+    #[cfg(all(target_os = "linux", target_arch = "aarch64", target_env = "musl"))]
+    return Some(LINUX_ARM64_MUSL);
+
     #[cfg(all(target_os = "windows", target_arch = "x86_64"))]
     return Some(WINDOWS_X64);
 
+    #[cfg(all(target_os = "windows", target_arch = "x86"))]
+    return Some(WINDOWS_X86);
+
     #[cfg(all(target_os = "macos", target_arch = "x86_64"))]
     return Some(MACOS_X64);
 
@@ -118,8 +154,14 @@ mod tests {
     fn parse_target_works() {
         assert_eq!(parse_target("linux-x64"), Some(LINUX_X64));
         assert_eq!(parse_target("LINUX-X64"), Some(LINUX_X64));
+        assert_eq!(parse_target("linux-x64-musl"), Some(LINUX_X64_MUSL));
+        assert_eq!(parse_target("linux-arm64-musl"), Some(LINUX_ARM64_MUSL));
         assert_eq!(parse_target("windows"), Some(WINDOWS_X64));
+        assert_eq!(parse_target("windows-x86"), Some(WINDOWS_X86));
+        assert_eq!(parse_target("i686"), Some(WINDOWS_X86));
         assert_eq!(parse_target("macos-arm64"), Some(MACOS_ARM64));
+        assert_eq!(parse_target("macos"), Some(MACOS_ARM64));
+        assert_eq!(parse_target("darwin-x64"), Some(MACOS_X64));
         assert_eq!(parse_target("invalid"), None);
     }
 
@@ -127,14 +169,16 @@ mod tests {
     fn current_target_returns_some() {
         // Should return Some on any supported platform
         // On Linux x64 (common CI), this should be Some
-        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        #[cfg(all(target_os = "linux", target_arch = "x86_64", not(target_env = "musl")))]
         assert_eq!(current_target(), Some(LINUX_X64));
     }
 
     #[test]
     fn stub_filename_formats_correctly() {
         assert_eq!(stub_filename(&LINUX_X64), "graft-gui-stub-linux-x64");
+        assert_eq!(stub_filename(&LINUX_X64_MUSL), "graft-gui-stub-linux-x64-musl");
         assert_eq!(stub_filename(&WINDOWS_X64), "graft-gui-stub-windows-x64.exe");
+        assert_eq!(stub_filename(&WINDOWS_X86), "graft-gui-stub-windows-x86.exe");
         assert_eq!(stub_filename(&MACOS_ARM64), "graft-gui-stub-macos-arm64.app.zip");
     }
 }
@@ -10,9 +10,11 @@ WORK_DIR="$SCRIPT_DIR/.e2e-work"
 
 # Parse arguments
 STUB_DIR=""
+TARGET=""
 while [[ $# -gt 0 ]]; do
     case $1 in
         --stub-dir) STUB_DIR="$2"; shift 2 ;;
+        --target) TARGET="$2"; shift 2 ;;
         *) echo "Unknown option: $1"; exit 1 ;;
     esac
 done
@@ -26,17 +28,20 @@ trap cleanup EXIT
 echo "=== Graft E2E Test ==="
 echo ""
 
-# Detect platform
-OS="$(uname -s)"
-ARCH="$(uname -m)"
-case "$OS-$ARCH" in
-    Linux-x86_64)       TARGET="linux-x64" ;;
-    Linux-aarch64)      TARGET="linux-arm64" ;;
-    Darwin-x86_64)      TARGET="macos-x64" ;;
-    Darwin-arm64)       TARGET="macos-arm64" ;;
-    MINGW*|MSYS*)       TARGET="windows-x64" ;;
-    *)                  echo "Unsupported platform: $OS-$ARCH"; exit 1 ;;
-esac
+# Detect platform, unless a target the host can run was given (e.g.
+# windows-x86 on 64-bit Windows)
+if [ -z "$TARGET" ]; then
+    OS="$(uname -s)"
+    ARCH="$(uname -m)"
+    case "$OS-$ARCH" in
+        Linux-x86_64)       TARGET="linux-x64" ;;
+        Linux-aarch64)      TARGET="linux-arm64" ;;
+        Darwin-x86_64)      TARGET="macos-x64" ;;
+        Darwin-arm64)       TARGET="macos-arm64" ;;
+        MINGW*|MSYS*)       TARGET="windows-x64" ;;
+        *)                  echo "Unsupported platform: $OS-$ARCH"; exit 1 ;;
+    esac
+fi
 echo "Platform: $TARGET"
 echo ""
 
@@ -128,7 +133,7 @@ echo "Step 7: Applying patch..."
 # Step 8: Verify patch applied
 echo "Step 8: Verifying patch..."
 # Compare test-target with exampleTarget (excluding .patch-backup)
-DIFF_OUTPUT=$(diff -rq "$WORK_DIR/test-target" "$SCRIPT_DIR/exampleTarget" 2>&1 | grep -v ".patch-backup" || true)
+DIFF_OUTPUT=$(diff -rq "$WORK_DIR/test-target" "$SCRIPT_DIR/exampleTarget" 2>&1 | grep -v -e ".patch-backup" -e ".patch-lock" || true)
 if [ -z "$DIFF_OUTPUT" ]; then
     echo "  Patch applied correctly!"
 else
@@ -143,7 +148,7 @@ echo "y" | "$PATCHER" headless rollback "$WORK_DIR/test-target"
 
 # Step 10: Verify rollback
 echo "Step 10: Verifying rollback..."
-DIFF_OUTPUT=$(diff -rq "$WORK_DIR/test-target" "$SCRIPT_DIR/exampleOrig" 2>&1 | grep -v ".patch-backup" || true)
+DIFF_OUTPUT=$(diff -rq "$WORK_DIR/test-target" "$SCRIPT_DIR/exampleOrig" 2>&1 | grep -v -e ".patch-backup" -e ".patch-lock" || true)
 if [ -z "$DIFF_OUTPUT" ]; then
     echo "  Rollback successful!"
 else